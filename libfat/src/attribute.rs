@@ -5,8 +5,7 @@
 pub struct Attributes(u8);
 
 impl Attributes {
-    /// The filesystem will not allow a file to be opened for modification.
-    // TODO: Follow this behaviour.
+    /// The filesystem will not allow a file to be opened for modification, deleted or renamed.
     pub const READ_ONLY: u8 = 0x01;
 
     /// Hides files or directories from normal directory views.
@@ -80,4 +79,13 @@ impl Attributes {
     pub fn get_value(self) -> u8 {
         self.0
     }
+
+    /// Set or clear the given attribute bits.
+    pub fn set(&mut self, mask: u8, value: bool) {
+        if value {
+            self.0 |= mask;
+        } else {
+            self.0 &= !mask;
+        }
+    }
 }
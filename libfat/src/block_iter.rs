@@ -19,6 +19,9 @@ pub struct BlockIndexClusterIter<'a, T> {
 
     /// The current iteration point in the cluster.
     counter: usize,
+
+    /// The count of blocks in the last cluster used.
+    block_count: usize,
 }
 
 impl<'a, T> BlockIndexClusterIter<'a, T>
@@ -31,19 +34,27 @@ where
         cluster: Cluster,
         block_index: Option<BlockIndex>,
     ) -> Self {
-        let blocks_per_cluster = fs.boot_record.blocks_per_cluster() as usize;
+        let blocks_per_cluster = cluster.block_count(fs) as usize;
+        let mut cluster_iter = FatClusterIter::new(fs, cluster);
 
-        let (cluster, block_index) = if let Some(block_index) = block_index {
+        let block_index = if let Some(block_index) = block_index {
+            // The clusters of a chain aren't contiguous on disk, follow the chain up to the cluster holding the block
             let cluster_offset = block_index.0 / blocks_per_cluster as u32;
-            let block_index = BlockIndex(block_index.0 % blocks_per_cluster as u32);
-            (Cluster(cluster.0 + cluster_offset), Some(block_index))
+            for _ in 0..cluster_offset {
+                if cluster_iter.next().is_none() {
+                    break;
+                }
+            }
+
+            Some(BlockIndex(block_index.0 % blocks_per_cluster as u32))
         } else {
-            (cluster, block_index)
+            block_index
         };
 
         BlockIndexClusterIter {
-            counter: blocks_per_cluster,
-            cluster_iter: FatClusterIter::new(fs, cluster),
+            counter: 0,
+            block_count: 0,
+            cluster_iter,
             block_index,
             last_cluster: None,
         }
@@ -56,15 +67,15 @@ where
 {
     type Item = Cluster;
     fn next(&mut self) -> Option<Cluster> {
-        let cluster_opt =
-            if self.counter == self.cluster_iter.fs.boot_record.blocks_per_cluster() as usize {
-                self.counter = self.block_index.or(Some(BlockIndex(0)))?.0 as usize;
-                self.block_index = None;
-                self.last_cluster = self.cluster_iter.next();
-                self.last_cluster
-            } else {
-                self.last_cluster
-            };
+        let cluster_opt = if self.counter == self.block_count {
+            self.counter = self.block_index.or(Some(BlockIndex(0)))?.0 as usize;
+            self.block_index = None;
+            self.last_cluster = self.cluster_iter.next();
+            self.block_count = self.last_cluster?.block_count(self.cluster_iter.fs) as usize;
+            self.last_cluster
+        } else {
+            self.last_cluster
+        };
 
         let cluster = cluster_opt?;
 
@@ -1,6 +1,7 @@
 //! FAT cluster.
 
 use super::FatFileSystem;
+use super::FatFsType;
 use libfs::block::{Block, BlockDevice, BlockIndex};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -8,18 +9,49 @@ use libfs::block::{Block, BlockDevice, BlockIndex};
 pub struct Cluster(pub u32);
 
 impl Cluster {
+    /// Check if the cluster represents the fixed root directory region of FAT12/FAT16 filesystems.
+    pub fn is_fixed_root_dir<T>(self, fs: &FatFileSystem<T>) -> bool
+    where
+        T: BlockDevice,
+    {
+        self.0 == 0 && fs.boot_record.fat_type != FatFsType::Fat32
+    }
+
+    /// Compute the count of blocks held by the cluster.
+    pub fn block_count<T>(self, fs: &FatFileSystem<T>) -> u32
+    where
+        T: BlockDevice,
+    {
+        if self.is_fixed_root_dir(fs) {
+            fs.boot_record.root_dir_blocks()
+        } else {
+            fs.boot_record.blocks_per_cluster()
+        }
+    }
+
     /// Compute the offset of the data from the cluster position.
     pub fn to_data_block_index<T>(self, fs: &FatFileSystem<T>) -> BlockIndex
     where
         T: BlockDevice,
     {
-        let first_block_of_cluster = (self.0 - 2) * u32::from(fs.boot_record.blocks_per_cluster());
+        if self.is_fixed_root_dir(fs) {
+            return BlockIndex(fs.boot_record.root_dir_offset());
+        }
+
+        let first_block_of_cluster = (self.0 - 2) * fs.boot_record.blocks_per_cluster();
         BlockIndex(fs.first_data_offset.0 + first_block_of_cluster)
     }
 
     /// Compute the offset in the cluster map of the cluster chain.
-    pub fn to_fat_offset(self) -> u32 {
-        self.0 * 4
+    pub fn to_fat_offset<T>(self, fs: &FatFileSystem<T>) -> u32
+    where
+        T: BlockDevice,
+    {
+        match fs.boot_record.fat_type {
+            FatFsType::Fat12 => self.0 + (self.0 / 2),
+            FatFsType::Fat16 => self.0 * 2,
+            FatFsType::Fat32 | FatFsType::ExFat => self.0 * 4,
+        }
     }
 
     /// Compute the block index of a cluster in the cluster map.
@@ -27,10 +59,9 @@ impl Cluster {
     where
         T: BlockDevice,
     {
-        let fat_offset = self.to_fat_offset();
+        let fat_offset = self.to_fat_offset(fs);
 
-        let fat_block_index =
-            u32::from(fs.boot_record.reserved_block_count()) + (fat_offset / Block::LEN_U32);
+        let fat_block_index = fs.boot_record.reserved_block_count() + (fat_offset / Block::LEN_U32);
         BlockIndex(fat_block_index)
     }
 }
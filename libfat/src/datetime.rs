@@ -1,7 +1,9 @@
 //! FAT datetime.
 
+use core::convert::TryFrom;
+
 /// Represent a FAT date time
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct FatDateTime {
     /// The year of the datetime.
     year: u16,
@@ -21,24 +23,33 @@ pub struct FatDateTime {
     /// The seconds of the datetime.
     seconds: u8,
 
-    /// The tenths of second of the datetime.
+    /// The 10ms units of the datetime (0-99).
     tenths: u8,
+
+    /// The offset from UTC of the datetime in 15 minutes increments, if known.
+    /// NOTE: If unknown, the datetime is considered to be UTC.
+    utc_offset: Option<i8>,
 }
 
 impl FatDateTime {
-    /// The amount of days in every leap year indexed per months.
-    const DAYS: [[u16; 12]; 4] = [
-        [0, 31, 60, 91, 121, 152, 182, 213, 244, 274, 305, 335],
-        [366, 397, 425, 456, 486, 517, 547, 578, 609, 639, 670, 700],
-        [
-            731, 762, 790, 821, 851, 882, 912, 943, 974, 1004, 1035, 1065,
-        ],
-        [
-            1096, 1127, 1155, 1186, 1216, 1247, 1277, 1308, 1339, 1369, 1400, 1430,
-        ],
-    ];
-
-    /// Create a new datetime
+    /// The first year that can be represented.
+    const MIN_YEAR: u16 = 1980;
+
+    /// The last year that can be represented.
+    const MAX_YEAR: u16 = 2107;
+
+    /// The count of days between 0000-03-01 and 1970-01-01.
+    const UNIX_EPOCH_DAYS: u64 = 719_468;
+
+    /// The count of days in 400 years.
+    const DAYS_PER_ERA: u64 = 146_097;
+
+    /// The exFAT flag marking a UTC offset as valid.
+    const EXFAT_UTC_OFFSET_VALID: u8 = 0x80;
+
+    /// Create a new datetime.
+    /// NOTE: ``tenths`` is in 10ms units and may exceed a second as FAT creation times do (0-199).
+    /// NOTE: Seconds past 59.99, as found on corrupted volumes, are clamped.
     pub fn new(
         year: u16,
         month: u8,
@@ -48,42 +59,217 @@ impl FatDateTime {
         seconds: u8,
         tenths: u8,
     ) -> Self {
+        // The 10ms units since the start of the minute
+        let units = u16::from(seconds.min(59)) * 100 + u16::from(tenths.min(199));
+        let units = units.min(5999);
+
         FatDateTime {
             year,
             month,
             day,
             hour,
             minutes,
+            seconds: (units / 100) as u8,
+            tenths: (units % 100) as u8,
+            utc_offset: None,
+        }
+    }
+
+    /// Decode a FAT date, a FAT time and the 10ms units of the creation time fine resolution field.
+    pub fn from_fat_date_time(date: u16, time: u16, tenths: u8) -> Self {
+        let seconds = ((time & 0x1f) << 1) as u8;
+        let minutes = ((time >> 5) & 0x3f) as u8;
+        let hour = ((time >> 11) & 0x1f) as u8;
+
+        let day = (date & 0x1f) as u8;
+        let month = ((date >> 5) & 0xf) as u8;
+        let year = (date >> 9) & 0x7f;
+
+        FatDateTime::new(
+            Self::MIN_YEAR + year,
+            month,
+            day,
+            hour,
+            minutes,
             seconds,
             tenths,
+        )
+    }
+
+    /// Create a datetime from a UNIX timestamp, expressed in the local time of the given UTC offset.
+    /// NOTE: Timestamps outside the 1980-2107 range are clamped.
+    pub fn from_unix_time(timestamp: u64, utc_offset: Option<i8>) -> Self {
+        Self::from_unix_time_millis(timestamp.saturating_mul(1000), utc_offset)
+    }
+
+    /// Create a datetime from a UNIX timestamp in milliseconds, expressed in the local time of the given UTC offset.
+    /// NOTE: Timestamps outside the 1980-2107 range are clamped.
+    pub fn from_unix_time_millis(timestamp: u64, utc_offset: Option<i8>) -> Self {
+        let min = FatDateTime::new(Self::MIN_YEAR, 1, 1, 0, 0, 0, 0).to_unix_time_millis();
+        let max = FatDateTime::new(Self::MAX_YEAR, 12, 31, 23, 59, 59, 99).to_unix_time_millis();
+
+        let local_time = Self::shift_utc_offset(timestamp, utc_offset, true)
+            .max(min)
+            .min(max);
+
+        let days = local_time / 86_400_000;
+        let millis_of_day = local_time % 86_400_000;
+        let (year, month, day) = Self::civil_from_days(days);
+
+        let mut res = FatDateTime::new(
+            year,
+            month,
+            day,
+            (millis_of_day / 3_600_000) as u8,
+            (millis_of_day / 60_000 % 60) as u8,
+            (millis_of_day / 1000 % 60) as u8,
+            (millis_of_day % 1000 / 10) as u8,
+        );
+        res.utc_offset = utc_offset;
+
+        res
+    }
+
+    /// Get the offset from UTC of the datetime in 15 minutes increments, if known.
+    pub fn utc_offset(&self) -> Option<i8> {
+        self.utc_offset
+    }
+
+    /// Set the offset from UTC of the datetime in 15 minutes increments.
+    /// NOTE: This doesn't change the local time fields.
+    pub fn set_utc_offset(&mut self, utc_offset: Option<i8>) {
+        self.utc_offset = utc_offset;
+    }
+
+    /// Decode an exFAT UTC offset field.
+    pub fn utc_offset_from_exfat(raw: u8) -> Option<i8> {
+        if (raw & Self::EXFAT_UTC_OFFSET_VALID) == 0 {
+            return None;
         }
+
+        // sign extend the 7 bits value
+        let value = i16::from(raw & 0x7f);
+        let value = if value >= 0x40 { value - 0x80 } else { value };
+
+        i8::try_from(value).ok()
+    }
+
+    /// Encode a UTC offset as an exFAT UTC offset field.
+    pub fn utc_offset_to_exfat(utc_offset: Option<i8>) -> u8 {
+        match utc_offset {
+            Some(offset) => {
+                Self::EXFAT_UTC_OFFSET_VALID | u8::try_from(i16::from(offset) & 0x7f).unwrap_or(0)
+            }
+            None => 0,
+        }
+    }
+
+    /// Encode the date in the FAT date format.
+    /// NOTE: Years outside the 1980-2107 range are clamped.
+    pub fn to_fat_date(&self) -> u16 {
+        let year = self.year.max(Self::MIN_YEAR).min(Self::MAX_YEAR) - Self::MIN_YEAR;
+
+        (year << 9) | (u16::from(self.month & 0xf) << 5) | u16::from(self.day & 0x1f)
+    }
+
+    /// Encode the time in the FAT time format, with a 2 seconds resolution.
+    pub fn to_fat_time(&self) -> u16 {
+        (u16::from(self.hour & 0x1f) << 11)
+            | (u16::from(self.minutes & 0x3f) << 5)
+            | u16::from((self.seconds / 2) & 0x1f)
+    }
+
+    /// Get the 10ms units lost by the 2 seconds resolution of ``to_fat_time``.
+    pub fn to_fat_time_fine_resolution(&self) -> u8 {
+        (self.seconds % 2) * 100 + self.tenths
+    }
+
+    /// Check that the date is a valid one in the 1980-2107 range.
+    fn is_valid(&self) -> bool {
+        self.year >= Self::MIN_YEAR
+            && self.year <= Self::MAX_YEAR
+            && self.month >= 1
+            && self.month <= 12
+            && self.day >= 1
+            && self.day <= 31
     }
 
     /// Convert the FAT datetime to a UNIX timestamp.
-    /// NOTE: This only support the 2000-2099 range. If something outside this range is provided, it will return an UNIX epoch.
+    /// NOTE: If the date is invalid, it will return an UNIX epoch.
     pub fn to_unix_time(&self) -> u64 {
-        // TODO: support other ranges than 2000-2099
-        if self.year > 2099 || self.year < 2000 {
+        self.to_unix_time_millis() / 1000
+    }
+
+    /// Convert the FAT datetime to a UNIX timestamp in milliseconds.
+    /// NOTE: If the date is invalid, it will return an UNIX epoch.
+    pub fn to_unix_time_millis(&self) -> u64 {
+        if !self.is_valid() {
             return 0;
         }
 
-        let year = u64::from(self.year) % 100;
-        let month = u64::from(self.month) - 1;
-        let day = u64::from(self.day) - 1;
-
-        let hour = u64::from(self.hour);
-        let minutes = u64::from(self.minutes);
-        let seconds = u64::from(self.seconds);
-
-        946_684_800
-            + (((year / 4 * (365 * 4 + 1)
-                + u64::from(Self::DAYS[year as usize % 4][month as usize])
-                + day)
-                * 24
-                + hour)
-                * 60
-                + minutes)
-                * 60
-            + seconds
+        let days = Self::days_from_civil(self.year, self.month, self.day);
+
+        let local_time = (((days * 24 + u64::from(self.hour)) * 60 + u64::from(self.minutes)) * 60
+            + u64::from(self.seconds))
+            * 1000
+            + u64::from(self.tenths) * 10;
+
+        Self::shift_utc_offset(local_time, self.utc_offset, false)
+    }
+
+    /// Shift a UNIX timestamp in milliseconds by a UTC offset, to local time if ``to_local`` is set or to UTC otherwise.
+    fn shift_utc_offset(timestamp: u64, utc_offset: Option<i8>, to_local: bool) -> u64 {
+        let offset_millis = i64::from(utc_offset.unwrap_or(0)) * 15 * 60 * 1000;
+        let offset_millis = if to_local {
+            offset_millis
+        } else {
+            -offset_millis
+        };
+
+        let timestamp = i64::try_from(timestamp).unwrap_or(i64::max_value());
+        u64::try_from(timestamp.saturating_add(offset_millis)).unwrap_or(0)
+    }
+
+    /// Compute the count of days since the UNIX epoch of a date of the proleptic Gregorian calendar.
+    fn days_from_civil(year: u16, month: u8, day: u8) -> u64 {
+        // Years start on the first of March to put the leap day at the end
+        let month = u64::from(month);
+        let year = u64::from(year) - if month <= 2 { 1 } else { 0 };
+
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * shifted_month + 2) / 5 + u64::from(day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+        era * Self::DAYS_PER_ERA + day_of_era - Self::UNIX_EPOCH_DAYS
+    }
+
+    /// Compute the date of the proleptic Gregorian calendar from a count of days since the UNIX epoch.
+    fn civil_from_days(days: u64) -> (u16, u8, u8) {
+        let days = days + Self::UNIX_EPOCH_DAYS;
+
+        let era = days / Self::DAYS_PER_ERA;
+        let day_of_era = days - era * Self::DAYS_PER_ERA;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        (year as u16, month as u8, day as u8)
     }
 }
+
+/// A source of the current local time, used to stamp directory entries.
+pub trait TimeProvider {
+    /// Get the current local time.
+    fn now(&self) -> FatDateTime;
+}
@@ -1,9 +1,12 @@
 //! High level directory entry representation.
 use arrayvec::ArrayString;
+use core::iter::Peekable;
 
 use crate::attribute::Attributes;
 use crate::block_iter::BlockIndexClusterIter;
 use crate::cluster::Cluster;
+use crate::datetime::FatDateTime;
+use crate::extent::ExtentMap;
 use crate::filesystem::FatFileSystem;
 use crate::table;
 use crate::utils;
@@ -75,6 +78,51 @@ impl DirectoryEntry {
     ) -> FileSystemResult<u64>
     where
         T: BlockDevice,
+    {
+        let start_cluster = self.start_cluster;
+
+        self.read_blocks(fs, offset, buf, |block_index| {
+            BlockIndexClusterIter::new(fs, start_cluster, Some(block_index))
+        })
+    }
+
+    /// Read at a given offset of the file into a given buffer, locating clusters with ``extent_map``.
+    ///
+    /// The map is filled on first use and must only be used with this entry.
+    pub fn read_with_extents<'a, T>(
+        &mut self,
+        fs: &'a FatFileSystem<T>,
+        extent_map: &mut ExtentMap,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> FileSystemResult<u64>
+    where
+        T: BlockDevice,
+    {
+        extent_map.sync(fs, self.start_cluster, self.cluster_count(fs))?;
+
+        let blocks_per_cluster = fs.boot_record.blocks_per_cluster();
+        let extent_map = &*extent_map;
+
+        self.read_blocks(fs, offset, buf, |block_index| {
+            extent_map.block_clusters_from(blocks_per_cluster, block_index)
+        })
+    }
+
+    /// Read at a given offset of the file into a given buffer.
+    ///
+    /// ``block_clusters`` gives an iterator over the cluster of every block of the file starting at the given block.
+    fn read_blocks<T, F, I>(
+        &mut self,
+        fs: &FatFileSystem<T>,
+        offset: u64,
+        buf: &mut [u8],
+        block_clusters: F,
+    ) -> FileSystemResult<u64>
+    where
+        T: BlockDevice,
+        F: FnOnce(BlockIndex) -> I,
+        I: Iterator<Item = Cluster>,
     {
         if offset >= 0xFFFF_FFFF {
             return Ok(0);
@@ -86,57 +134,56 @@ impl DirectoryEntry {
 
         let device: &T = &fs.block_device;
 
+        // Never read past the end of the file
+        let file_left = (u64::from(self.file_size) - offset) as usize;
+        let buf_len = buf.len().min(file_left);
+        let buf = &mut buf[..buf_len];
+
         let mut raw_tmp_offset = offset as u32;
-        let mut cluster_offset = BlockIndex(raw_tmp_offset / Block::LEN_U32);
-        let mut cluster_block_iterator =
-            BlockIndexClusterIter::new(fs, self.start_cluster, Some(cluster_offset));
-        let blocks_per_cluster = u32::from(fs.boot_record.blocks_per_cluster());
+        let mut data_blocks = Self::data_blocks(fs, raw_tmp_offset, block_clusters).peekable();
 
-        let mut read_size = 0u64;
+        let mut read_size = 0usize;
         let mut blocks = [Block::new()];
 
-        while read_size < buf.len() as u64 {
-            let cluster_opt = cluster_block_iterator.next();
-            if cluster_opt.is_none() {
-                break;
-            }
+        while read_size < buf.len() {
+            let block_index = match data_blocks.next() {
+                Some(block_index) => block_index,
+                None => break,
+            };
 
-            cluster_offset = BlockIndex(raw_tmp_offset / Block::LEN_U32);
+            let tmp_offset = (raw_tmp_offset % Block::LEN_U32) as usize;
+            let buf_slice = &mut buf[read_size..];
 
-            let cluster = cluster_opt.unwrap();
-            let block_start_index = cluster.to_data_block_index(fs);
-            let tmp_index = cluster_offset.0 % blocks_per_cluster;
-            let tmp_offset = raw_tmp_offset % Block::LEN_U32;
+            let buf_limit = if tmp_offset == 0 && buf_slice.len() >= Block::LEN {
+                // Read whole blocks contiguous on disk straight into the buffer
+                let block_count =
+                    Self::take_run(&mut data_blocks, block_index, buf_slice.len() / Block::LEN);
+                let buf_limit = block_count * Block::LEN;
+                let buf_blocks = Block::slice_from_bytes_mut(&mut buf_slice[..buf_limit]).unwrap();
 
-            device
-                .read(
-                    &mut blocks,
-                    fs.partition_start,
-                    BlockIndex(block_start_index.0 + tmp_index),
-                )
-                .or(Err(FileSystemError::ReadFailed))?;
+                device
+                    .read(buf_blocks, fs.partition_start, block_index)
+                    .or(Err(FileSystemError::ReadFailed))?;
 
-            let buf_slice = &mut buf[read_size as usize..];
-            let mut buf_limit = if buf_slice.len() >= Block::LEN {
-                Block::LEN
+                buf_limit
             } else {
-                buf_slice.len()
-            };
+                device
+                    .read(&mut blocks, fs.partition_start, block_index)
+                    .or(Err(FileSystemError::ReadFailed))?;
 
-            let bytes_left = (u64::from(self.file_size) - read_size - offset) as usize;
-            if bytes_left < buf_limit {
-                buf_limit = bytes_left;
-            }
+                // Never go past the end of the current block
+                let buf_limit = buf_slice.len().min(Block::LEN - tmp_offset);
+                buf_slice[..buf_limit]
+                    .copy_from_slice(&blocks[0][tmp_offset..tmp_offset + buf_limit]);
 
-            for (index, buf_entry) in buf_slice.iter_mut().take(buf_limit).enumerate() {
-                *buf_entry = blocks[0][tmp_offset as usize + index];
-            }
+                buf_limit
+            };
 
             raw_tmp_offset += buf_limit as u32;
-            read_size += buf_limit as u64;
+            read_size += buf_limit;
         }
 
-        Ok(read_size)
+        Ok(read_size as u64)
     }
 
     /// Write the given buffer at a given offset of the file.
@@ -147,6 +194,52 @@ impl DirectoryEntry {
         buf: &[u8],
         appendable: bool,
     ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        self.grow_for_write(fs, offset, buf.len(), appendable)?;
+
+        let start_cluster = self.start_cluster;
+
+        self.write_blocks(fs, offset, buf, |block_index| {
+            BlockIndexClusterIter::new(fs, start_cluster, Some(block_index))
+        })
+    }
+
+    /// Write the given buffer at a given offset of the file, locating clusters with ``extent_map``.
+    ///
+    /// The map is filled on first use and must only be used with this entry.
+    pub fn write_with_extents<'a, T>(
+        &mut self,
+        fs: &'a FatFileSystem<T>,
+        extent_map: &mut ExtentMap,
+        offset: u64,
+        buf: &[u8],
+        appendable: bool,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        // Growing only appends clusters, which are mapped by the following sync
+        self.grow_for_write(fs, offset, buf.len(), appendable)?;
+        extent_map.sync(fs, self.start_cluster, self.cluster_count(fs))?;
+
+        let blocks_per_cluster = fs.boot_record.blocks_per_cluster();
+        let extent_map = &*extent_map;
+
+        self.write_blocks(fs, offset, buf, |block_index| {
+            extent_map.block_clusters_from(blocks_per_cluster, block_index)
+        })
+    }
+
+    /// Check that ``size`` bytes can be written at ``offset`` and grow the file if needed and ``appendable`` is set.
+    fn grow_for_write<T>(
+        &mut self,
+        fs: &FatFileSystem<T>,
+        offset: u64,
+        size: usize,
+        appendable: bool,
+    ) -> FileSystemResult<()>
     where
         T: BlockDevice,
     {
@@ -154,7 +247,7 @@ impl DirectoryEntry {
             return Err(FileSystemError::AccessDenied);
         }
 
-        let min_size = offset + buf.len() as u64;
+        let min_size = offset + size as u64;
         if min_size > u64::from(self.file_size) {
             if appendable {
                 self.set_len(fs, min_size)?;
@@ -163,64 +256,251 @@ impl DirectoryEntry {
             }
         }
 
+        Ok(())
+    }
+
+    /// Write the given buffer at a given offset of the file, which must be large enough to hold it.
+    ///
+    /// ``block_clusters`` gives an iterator over the cluster of every block of the file starting at the given block.
+    fn write_blocks<T, F, I>(
+        &mut self,
+        fs: &FatFileSystem<T>,
+        offset: u64,
+        buf: &[u8],
+        block_clusters: F,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+        F: FnOnce(BlockIndex) -> I,
+        I: Iterator<Item = Cluster>,
+    {
+        fs.mark_dirty()?;
+
         let device: &T = &fs.block_device;
 
         let mut raw_tmp_offset = offset as u32;
-        let mut cluster_offset = BlockIndex(raw_tmp_offset / Block::LEN_U32);
-        let mut cluster_block_iterator =
-            BlockIndexClusterIter::new(fs, self.start_cluster, Some(cluster_offset));
-        let blocks_per_cluster = u32::from(fs.boot_record.blocks_per_cluster());
+        let mut data_blocks = Self::data_blocks(fs, raw_tmp_offset, block_clusters).peekable();
 
-        let mut write_size = 0u64;
+        let mut write_size = 0usize;
         let mut blocks = [Block::new()];
 
-        while write_size < buf.len() as u64 {
-            let cluster = cluster_block_iterator
-                .next()
-                .ok_or(FileSystemError::WriteFailed)?;
+        while write_size < buf.len() {
+            let block_index = data_blocks.next().ok_or(FileSystemError::WriteFailed)?;
 
-            cluster_offset = BlockIndex(raw_tmp_offset / Block::LEN_U32);
+            let tmp_offset = (raw_tmp_offset % Block::LEN_U32) as usize;
+            let buf_slice = &buf[write_size..];
 
-            let block_start_index = cluster.to_data_block_index(fs);
-            let tmp_index = cluster_offset.0 % blocks_per_cluster;
-            let tmp_offset = raw_tmp_offset % Block::LEN_U32;
+            let buf_limit = if tmp_offset == 0 && buf_slice.len() >= Block::LEN {
+                // Write whole blocks contiguous on disk straight from the buffer, their old content doesn't matter
+                let block_count =
+                    Self::take_run(&mut data_blocks, block_index, buf_slice.len() / Block::LEN);
+                let buf_limit = block_count * Block::LEN;
+                let buf_blocks = Block::slice_from_bytes(&buf_slice[..buf_limit]).unwrap();
 
-            device
-                .read(
-                    &mut blocks,
-                    fs.partition_start,
-                    BlockIndex(block_start_index.0 + tmp_index),
-                )
-                .or(Err(FileSystemError::ReadFailed))?;
+                device
+                    .write(buf_blocks, fs.partition_start, block_index)
+                    .or(Err(FileSystemError::WriteFailed))?;
 
-            let buf_slice = &buf[write_size as usize..];
-            let buf_limit = if buf_slice.len() >= Block::LEN {
-                Block::LEN
+                buf_limit
             } else {
-                buf_slice.len()
-            };
+                device
+                    .read(&mut blocks, fs.partition_start, block_index)
+                    .or(Err(FileSystemError::ReadFailed))?;
 
-            let block_slice = &mut blocks[0][tmp_offset as usize..];
+                // Never go past the end of the current block
+                let buf_limit = buf_slice.len().min(Block::LEN - tmp_offset);
+                blocks[0][tmp_offset..tmp_offset + buf_limit]
+                    .copy_from_slice(&buf_slice[..buf_limit]);
 
-            for (index, buf_entry) in block_slice.iter_mut().take(buf_limit).enumerate() {
-                *buf_entry = buf_slice[index];
-            }
+                device
+                    .write(&blocks, fs.partition_start, block_index)
+                    .or(Err(FileSystemError::WriteFailed))?;
 
-            device
-                .write(
-                    &blocks,
-                    fs.partition_start,
-                    BlockIndex(block_start_index.0 + tmp_index),
-                )
-                .or(Err(FileSystemError::WriteFailed))?;
+                buf_limit
+            };
 
             raw_tmp_offset += buf_limit as u32;
-            write_size += buf_limit as u64;
+            write_size += buf_limit;
         }
 
         Ok(())
     }
 
+    /// Iterate over the index of every block of the file on disk, starting at the block holding ``offset``.
+    ///
+    /// ``block_clusters`` gives an iterator over the cluster of every block of the file starting at the given block.
+    fn data_blocks<'b, T, F, I>(
+        fs: &'b FatFileSystem<T>,
+        offset: u32,
+        block_clusters: F,
+    ) -> impl Iterator<Item = BlockIndex> + 'b
+    where
+        T: BlockDevice,
+        F: FnOnce(BlockIndex) -> I,
+        I: Iterator<Item = Cluster> + 'b,
+    {
+        let blocks_per_cluster = fs.boot_record.blocks_per_cluster();
+        let first_block = offset / Block::LEN_U32;
+
+        block_clusters(BlockIndex(first_block))
+            .zip(first_block..)
+            .map(move |(cluster, file_block)| {
+                BlockIndex(cluster.to_data_block_index(fs).0 + file_block % blocks_per_cluster)
+            })
+    }
+
+    /// Take the blocks following ``first_block`` on disk out of ``data_blocks``, up to ``max_count`` blocks in total.
+    ///
+    /// Return the count of blocks of the run, ``first_block`` included.
+    fn take_run<I>(
+        data_blocks: &mut Peekable<I>,
+        first_block: BlockIndex,
+        max_count: usize,
+    ) -> usize
+    where
+        I: Iterator<Item = BlockIndex>,
+    {
+        let mut block_count = 1;
+
+        while block_count < max_count
+            && data_blocks.peek() == Some(&BlockIndex(first_block.0 + block_count as u32))
+        {
+            data_blocks.next();
+            block_count += 1;
+        }
+
+        block_count
+    }
+
+    /// Set the attributes of the entry.
+    /// NOTE: This doesn't check that the directory and volume bits are left untouched.
+    pub fn set_attribute<T>(
+        &mut self,
+        fs: &FatFileSystem<T>,
+        attribute: Attributes,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        // The root directory doesn't have any attributes
+        let raw_info = self.raw_info.ok_or(FileSystemError::AccessDenied)?;
+        let mut raw_dir_entry = raw_info.get_dir_entry(fs)?;
+
+        raw_dir_entry.set_attribute(attribute);
+        raw_dir_entry.flush(fs)?;
+
+        self.attribute = attribute;
+
+        Ok(())
+    }
+
+    /// Set the creation, last modification and last access timestamps of the entry. ``None`` timestamps are left untouched.
+    /// NOTE: FAT stores the creation time with a 10ms resolution, the last modification time with a 2 seconds resolution and only the date of the last access.
+    pub fn set_timestamps<T>(
+        &mut self,
+        fs: &FatFileSystem<T>,
+        creation: Option<FatDateTime>,
+        modification: Option<FatDateTime>,
+        access: Option<FatDateTime>,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        // The root directory doesn't have any timestamps
+        let raw_info = self.raw_info.ok_or(FileSystemError::AccessDenied)?;
+        let mut raw_dir_entry = raw_info.get_dir_entry(fs)?;
+
+        if let Some(creation) = creation {
+            raw_dir_entry.set_creation_datetime(&creation);
+        }
+
+        if let Some(modification) = modification {
+            raw_dir_entry.set_modification_datetime(&modification);
+        }
+
+        if let Some(access) = access {
+            raw_dir_entry.set_last_access_date(&access);
+        }
+
+        raw_dir_entry.flush(fs)?;
+
+        self.creation_timestamp = raw_dir_entry.get_creation_datetime().to_unix_time();
+        self.last_access_timestamp = raw_dir_entry.get_last_access_date().to_unix_time();
+        self.last_modification_timestamp = raw_dir_entry.get_modification_datetime().to_unix_time();
+
+        Ok(())
+    }
+
+    /// Update the last access date of the entry, and its last modification time if ``is_modification`` is set.
+    ///
+    /// Reads and writes don't stamp the entry themselves, callers are expected to do it once per open file.
+    /// A last access date update alone doesn't mark the volume dirty.
+    /// NOTE: The entry is only written if something changed.
+    pub fn update_timestamps<T>(
+        &mut self,
+        fs: &FatFileSystem<T>,
+        is_modification: bool,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        // The root directory doesn't have any timestamps, and nothing is stamped without a time provider
+        let (raw_info, now) = match (self.raw_info, fs.now()) {
+            (Some(raw_info), Some(now)) => (raw_info, now),
+            _ => return Ok(()),
+        };
+
+        let mut raw_dir_entry = raw_info.get_dir_entry(fs)?;
+
+        if !is_modification
+            && raw_dir_entry.as_sfn_entry().last_access_date.to_int() == now.to_fat_date()
+        {
+            return Ok(());
+        }
+
+        raw_dir_entry.set_last_access_date(&now);
+        if is_modification {
+            raw_dir_entry.set_modification_datetime(&now);
+            raw_dir_entry.flush(fs)?;
+        } else {
+            raw_dir_entry.write(fs)?;
+        }
+
+        self.last_access_timestamp = raw_dir_entry.get_last_access_date().to_unix_time();
+        self.last_modification_timestamp = raw_dir_entry.get_modification_datetime().to_unix_time();
+
+        Ok(())
+    }
+
+    /// Set the file length, keeping ``extent_map`` in sync with the cluster chain.
+    pub fn set_len_with_extents<'a, T>(
+        &mut self,
+        fs: &'a FatFileSystem<T>,
+        extent_map: &mut ExtentMap,
+        size: u64,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        if let Err(error) = self.set_len(fs, size) {
+            // Some clusters might have been freed already
+            extent_map.clear();
+            return Err(error);
+        }
+
+        extent_map.sync(fs, self.start_cluster, self.cluster_count(fs))
+    }
+
+    /// Get the count of clusters needed to hold the file data.
+    fn cluster_count<T>(&self, fs: &FatFileSystem<T>) -> u32
+    where
+        T: BlockDevice,
+    {
+        let cluster_size = u64::from(fs.boot_record.cluster_size());
+
+        (utils::align_up(u64::from(self.file_size), cluster_size) / cluster_size) as u32
+    }
+
     /// Set the file length
     pub fn set_len<'a, T>(&mut self, fs: &'a FatFileSystem<T>, size: u64) -> FileSystemResult<()>
     where
@@ -236,11 +516,9 @@ impl DirectoryEntry {
         let raw_file_info = self.raw_info.ok_or(FileSystemError::Custom {
             name: "Raw Info is missing ON A FILE",
         })?;
-        let mut raw_dir_entry = raw_file_info.get_dir_entry(fs)?;
+        let raw_dir_entry = raw_file_info.get_dir_entry(fs)?;
 
-        let cluster_size = u64::from(
-            u16::from(fs.boot_record.blocks_per_cluster()) * fs.boot_record.bytes_per_block(),
-        );
+        let cluster_size = u64::from(fs.boot_record.cluster_size());
         let aligned_size = utils::align_up(size, cluster_size);
         let aligned_current_len = utils::align_up(current_len, cluster_size);
 
@@ -271,24 +549,132 @@ impl DirectoryEntry {
                 self.start_cluster = start_cluster.unwrap();
             }
         } else {
-            let diff_size = current_len - size;
-            let mut cluster_to_remove_count = (aligned_current_len - aligned_size) / cluster_size;
-
-            while cluster_to_remove_count != 0 {
-                let (last_cluster, previous_cluster) =
-                    table::get_last_and_previous_cluster(fs, self.start_cluster)?;
-                fs.free_cluster(last_cluster, previous_cluster)?;
-                cluster_to_remove_count -= 1;
+            return self.shrink(fs, raw_dir_entry, size as u32);
+        }
+
+        self.flush_len(fs, raw_dir_entry, new_size)
+    }
+
+    /// Shrink the file to ``new_size`` bytes, freeing the clusters it doesn't need anymore.
+    fn shrink<T>(
+        &mut self,
+        fs: &FatFileSystem<T>,
+        raw_dir_entry: FatDirEntry,
+        new_size: u32,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        let cluster_size = fs.boot_record.cluster_size();
+        let cluster_to_keep_count = (utils::align_up(u64::from(new_size), u64::from(cluster_size))
+            / u64::from(cluster_size)) as u32;
+        let old_start_cluster = self.start_cluster;
+        let old_cluster_count = self.cluster_count(fs);
+
+        if cluster_to_keep_count == 0 {
+            self.start_cluster = Cluster(0);
+        }
+
+        // The new size is written first so that an interruption only leaks the clusters removed
+        if let Err(error) = self.flush_len(fs, raw_dir_entry, new_size) {
+            self.start_cluster = old_start_cluster;
+            return Err(error);
+        }
+
+        if cluster_to_keep_count == old_cluster_count {
+            return Ok(());
+        }
+
+        if cluster_to_keep_count == 0 {
+            return fs.free_cluster(old_start_cluster, None);
+        }
+
+        let last_cluster = table::FatClusterIter::new(fs, old_start_cluster)
+            .nth(cluster_to_keep_count as usize - 1)
+            .ok_or(FileSystemError::WriteFailed)?;
+
+        match table::FatValue::get(fs, last_cluster)? {
+            table::FatValue::Data(next_cluster) => {
+                fs.free_cluster(Cluster(next_cluster), Some(last_cluster))
             }
+            _ => Ok(()),
+        }
+    }
+
+    /// Set the file length, allocating the clusters added as a single contiguous run.
+    ///
+    /// The run is placed right after the last cluster of the file, keeping the whole file contiguous. Empty files get
+    /// the first run of free clusters large enough.
+    /// If the clusters needed aren't free, ``NoContiguousSpaceLeft`` is returned and the file is left untouched.
+    pub fn set_len_contiguous<T>(
+        &mut self,
+        fs: &FatFileSystem<T>,
+        size: u64,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        let current_len = u64::from(self.file_size);
+        let cluster_size = u64::from(fs.boot_record.cluster_size());
+        let aligned_size = utils::align_up(size, cluster_size);
+        let aligned_current_len = utils::align_up(current_len, cluster_size);
+
+        // Shrinking the file or growing it inside its last cluster doesn't allocate anything
+        if size <= current_len || size > 0xFFFF_FFFF || aligned_size == aligned_current_len {
+            return self.set_len(fs, size);
+        }
+
+        let raw_file_info = self.raw_info.ok_or(FileSystemError::Custom {
+            name: "Raw Info is missing ON A FILE",
+        })?;
+        let raw_dir_entry = raw_file_info.get_dir_entry(fs)?;
+
+        let cluster_to_add_count = ((aligned_size - aligned_current_len) / cluster_size) as u32;
+        let last_cluster = if self.start_cluster.0 == 0 || self.file_size == 0 {
+            None
+        } else {
+            Some(table::get_last_cluster(fs, self.start_cluster)?)
+        };
+
+        let run_start = fs.alloc_contiguous_clusters(cluster_to_add_count, last_cluster)?;
+        let old_start_cluster = self.start_cluster;
+        if last_cluster.is_none() {
+            self.start_cluster = run_start;
+        }
+
+        let flush_res = self.flush_len(fs, raw_dir_entry, size as u32);
 
-            new_size = self.file_size - diff_size as u32;
+        if let Err(error) = flush_res {
+            // If it fail here, this can be catastrophic but at least we tried our best.
+            self.start_cluster = old_start_cluster;
+            fs.free_cluster(run_start, last_cluster)?;
+            return Err(error);
         }
-        // TODO: update modified date?
+
+        Ok(())
+    }
+
+    /// Write the start cluster and the given size of the file to its entry and update the timestamps if a time provider is set.
+    fn flush_len<T>(
+        &mut self,
+        fs: &FatFileSystem<T>,
+        mut raw_dir_entry: FatDirEntry,
+        new_size: u32,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
         raw_dir_entry.set_cluster(self.start_cluster);
         raw_dir_entry.set_file_size(new_size);
+        if let Some(now) = fs.now() {
+            raw_dir_entry.set_last_access_date(&now);
+            raw_dir_entry.set_modification_datetime(&now);
+        }
         raw_dir_entry.flush(fs)?;
 
         self.file_size = new_size;
+        self.last_access_timestamp = raw_dir_entry.get_last_access_date().to_unix_time();
+        self.last_modification_timestamp = raw_dir_entry.get_modification_datetime().to_unix_time();
 
         Ok(())
     }
@@ -25,6 +25,7 @@ pub(crate) mod raw_dir_entry_iterator;
 use dir_entry::{DirectoryEntry, DirectoryEntryRawInfo};
 
 use dir_entry_iterator::DirectoryEntryIterator;
+use raw_dir_entry::FatDirEntry;
 use raw_dir_entry_iterator::FatDirEntryIterator;
 
 #[derive(Copy)]
@@ -144,6 +145,11 @@ where
             }
         }
 
+        // the root directory of FAT12/FAT16 filesystems has a fixed size
+        if entry.start_cluster.is_fixed_root_dir(fs) {
+            return Err(FileSystemError::NoSpaceLeft);
+        }
+
         // if the directory is full, try to allocate a cluster and use it
         let last_cluster = table::get_last_cluster(fs, entry.start_cluster)?;
         let new_cluster = fs.alloc_cluster(Some(last_cluster))?;
@@ -159,8 +165,15 @@ where
         Ok(FatDirEntryIterator::new(fs, new_cluster, BlockIndex(0), 0))
     }
 
+    /// Allocate a single raw entry in the directory.
+    pub(crate) fn allocate_raw_entry(&self) -> FileSystemResult<FatDirEntry> {
+        Self::allocate_entries(&self.dir_info, self.fs, 1)?
+            .next()
+            .unwrap_or(Err(FileSystemError::NoSpaceLeft))
+    }
+
     /// Create a directory entry in a given parent directory.
-    fn create_dir_entry(
+    pub(crate) fn create_dir_entry(
         fs: &'a FatFileSystem<T>,
         parent_entry: &DirectoryEntry,
         attribute: Attributes,
@@ -215,6 +228,12 @@ where
         sfn_entry.set_cluster(cluster);
         sfn_entry.set_attribute(attribute);
 
+        if let Some(now) = fs.now() {
+            sfn_entry.set_creation_datetime(&now);
+            sfn_entry.set_modification_datetime(&now);
+            sfn_entry.set_last_access_date(&now);
+        }
+
         sfn_entry.set_short_name(&short_file_name);
         sfn_entry.flush(fs)?;
 
@@ -291,6 +310,14 @@ where
             return Err(error);
         }
 
+        // Fill the directory before linking it, so that a power loss never exposes it half initialized
+        let init_res = self.init_dir_cluster(cluster);
+
+        if let Err(error) = init_res {
+            self.fs.free_cluster(cluster, None)?;
+            return Err(error);
+        }
+
         let new_entry_res = Self::create_dir_entry(
             self.fs,
             &self.dir_info,
@@ -306,48 +333,39 @@ where
             return Err(err);
         }
 
-        let entry = new_entry_res?;
+        Ok(())
+    }
+
+    /// Write the "." and ".." entries of a new child directory starting at the given ``cluster``.
+    fn init_dir_cluster(&self, cluster: Cluster) -> FileSystemResult<()> {
+        let mut dir_info = self.dir_info;
+        dir_info.start_cluster = cluster;
+        dir_info.raw_info = None;
 
-        let res = Self::create_dir_entry(
+        Self::create_dir_entry(
             self.fs,
-            &entry,
+            &dir_info,
             Attributes::new(Attributes::DIRECTORY),
             ".",
-            entry.start_cluster,
+            cluster,
             0,
-        );
-
-        if let Err(err) = res {
-            // If it fail here, this can be catastrophic but at least we tried our best.
-            Self::delete_dir_entry(self.fs, &entry)?;
-            self.fs.free_cluster(cluster, None)?;
-            return Err(err);
-        }
-
-        let raw_info = entry.raw_info.unwrap();
+        )?;
 
         let parent_cluster =
-            if raw_info.parent_cluster == self.fs.get_root_directory().dir_info.start_cluster {
+            if self.dir_info.start_cluster == self.fs.get_root_directory().dir_info.start_cluster {
                 Cluster(0)
             } else {
-                raw_info.parent_cluster
+                self.dir_info.start_cluster
             };
 
-        let res = Self::create_dir_entry(
+        Self::create_dir_entry(
             self.fs,
-            &entry,
+            &dir_info,
             Attributes::new(Attributes::DIRECTORY),
             "..",
             parent_cluster,
             0,
-        );
-
-        if let Err(err) = res {
-            // If it fail here, this can be catastrophic but at least we tried our best.
-            Self::delete_dir_entry(self.fs, &entry)?;
-            self.fs.free_cluster(cluster, None)?;
-            return Err(err);
-        }
+        )?;
 
         Ok(())
     }
@@ -384,6 +402,11 @@ where
             return Err(FileSystemError::NotAFile);
         }
 
+        // Read only files cannot be deleted
+        if !is_dir && dir_entry.attribute.is_read_only() {
+            return Err(FileSystemError::AccessDenied);
+        }
+
         // Check for directory not being empty
         if dir_entry.attribute.is_directory()
             && Self::from_entry(fs, dir_entry).iter().nth(2).is_some()
@@ -465,6 +488,33 @@ where
             dir_entry.file_size,
         )?;
 
+        let res = self.move_dir_entry(&dir_entry, &new_entry, is_dir);
+
+        // Never leave two entries sharing the same clusters
+        if let Err(err) = res {
+            // If it fail here, this can be catastrophic but at least we tried our best.
+            Self::delete_dir_entry(self.fs, &new_entry)?;
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Finish a rename once ``new_entry`` got created: copy the metadata of ``old_entry``, update ".." and delete ``old_entry``.
+    fn move_dir_entry(
+        &self,
+        old_entry: &DirectoryEntry,
+        new_entry: &DirectoryEntry,
+        is_dir: bool,
+    ) -> FileSystemResult<()> {
+        let old_raw_info = old_entry.raw_info.unwrap();
+
+        // keep the timestamps of the old entry
+        let old_sfn_entry = old_raw_info.get_dir_entry(self.fs)?;
+        let mut new_sfn_entry = new_entry.raw_info.unwrap().get_dir_entry(self.fs)?;
+        new_sfn_entry.copy_timestamps(&old_sfn_entry);
+        new_sfn_entry.flush(self.fs)?;
+
         if is_dir {
             let new_raw_info = new_entry.raw_info.unwrap();
 
@@ -496,8 +546,7 @@ where
             }
         }
 
-        Self::delete_dir_entry(self.fs, &dir_entry)?;
-        Ok(())
+        Self::delete_dir_entry(self.fs, old_entry)
     }
 }
 
@@ -149,6 +149,16 @@ impl FatDirEntry {
 
     /// Write the raw data buffer to disk.
     pub fn flush<T>(&self, fs: &FatFileSystem<T>) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        fs.mark_dirty()?;
+        self.write(fs)
+    }
+
+    /// Write the raw data buffer to disk without marking the volume dirty.
+    /// Only meant for updates that can't leave the volume inconsistent, like the last access date.
+    pub(crate) fn write<T>(&self, fs: &FatFileSystem<T>) -> FileSystemResult<()>
     where
         T: BlockDevice,
     {
@@ -300,51 +310,52 @@ impl FatDirEntry {
     /// Retrieve the creation datetime of this 8.3 entry.
     pub fn get_creation_datetime(&self) -> FatDateTime {
         let entry = self.as_sfn_entry();
-        let raw_time = entry.creation_time.to_int();
-        let seconds = ((raw_time & 0x1f) << 1) as u8;
-        let minutes = ((raw_time >> 5) & 0x3f) as u8;
-        let hour = ((raw_time >> 11) & 0x1f) as u8;
-
-        let raw_date = entry.creation_date.to_int();
-        let day = (raw_date & 0x1f) as u8;
-        let month = ((raw_date >> 5) & 0xf) as u8;
-        let year = (raw_date >> 9) & 0x7f;
-        FatDateTime::new(
-            1980 + year,
-            month,
-            day,
-            hour,
-            minutes,
-            seconds,
-            self.data[13],
+
+        FatDateTime::from_fat_date_time(
+            entry.creation_date.to_int(),
+            entry.creation_time.to_int(),
+            entry.creation_tenths,
         )
     }
 
+    /// Set the creation datetime of this 8.3 entry.
+    pub fn set_creation_datetime(&mut self, datetime: &FatDateTime) {
+        self.data[13] = datetime.to_fat_time_fine_resolution();
+        LittleEndian::write_u16(&mut self.data[14..16], datetime.to_fat_time());
+        LittleEndian::write_u16(&mut self.data[16..18], datetime.to_fat_date());
+    }
+
     /// Retrieve the last access datetime of this 8.3 entry.
     pub fn get_last_access_date(&self) -> FatDateTime {
-        let entry = self.as_sfn_entry();
+        FatDateTime::from_fat_date_time(self.as_sfn_entry().last_access_date.to_int(), 0, 0)
+    }
 
-        let raw_date = entry.last_access_date.to_int();
-        let day = (raw_date & 0x1f) as u8;
-        let month = ((raw_date >> 5) & 0xf) as u8;
-        let year = (raw_date >> 9) & 0x7f;
-        FatDateTime::new(1980 + year, month, day, 0, 0, 0, 0)
+    /// Set the last access date of this 8.3 entry.
+    pub fn set_last_access_date(&mut self, datetime: &FatDateTime) {
+        LittleEndian::write_u16(&mut self.data[18..20], datetime.to_fat_date());
     }
 
     /// Retrieve the last modification datetime of this 8.3 entry.
     pub fn get_modification_datetime(&self) -> FatDateTime {
         let entry = self.as_sfn_entry();
 
-        let raw_time = entry.modification_time.to_int();
-        let seconds = ((raw_time & 0x1f) << 1) as u8;
-        let minutes = ((raw_time >> 5) & 0x3f) as u8;
-        let hour = ((raw_time >> 11) & 0x1f) as u8;
+        FatDateTime::from_fat_date_time(
+            entry.modification_date.to_int(),
+            entry.modification_time.to_int(),
+            0,
+        )
+    }
+
+    /// Copy the creation, last access and last modification timestamps of another 8.3 entry.
+    pub fn copy_timestamps(&mut self, other: &FatDirEntry) {
+        self.data[13..20].copy_from_slice(&other.data[13..20]);
+        self.data[22..26].copy_from_slice(&other.data[22..26]);
+    }
 
-        let raw_date = entry.modification_date.to_int();
-        let day = (raw_date & 0x1f) as u8;
-        let month = ((raw_date >> 5) & 0xf) as u8;
-        let year = (raw_date >> 9) & 0x7f;
-        FatDateTime::new(1980 + year, month, day, hour, minutes, seconds, 0)
+    /// Set the last modification datetime of this 8.3 entry.
+    pub fn set_modification_datetime(&mut self, datetime: &FatDateTime) {
+        LittleEndian::write_u16(&mut self.data[22..24], datetime.to_fat_time());
+        LittleEndian::write_u16(&mut self.data[24..26], datetime.to_fat_date());
     }
 }
 
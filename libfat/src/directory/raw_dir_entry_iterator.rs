@@ -62,9 +62,11 @@ where
                 self.counter = 0;
                 self.block_index += 1;
             }
-            self.block_index %= u32::from(fs.boot_record.blocks_per_cluster());
             self.is_first = false;
             self.last_cluster = self.cluster_iter.next();
+            if let Some(cluster) = self.last_cluster {
+                self.block_index %= cluster.block_count(fs);
+            }
             self.last_cluster
         } else {
             self.last_cluster
@@ -0,0 +1,220 @@
+//! exFAT allocation bitmap.
+
+use alloc::vec::Vec;
+
+use libfs::block::{Block, BlockDevice, BlockIndex};
+use libfs::FileSystemError;
+use libfs::FileSystemResult;
+
+use super::chain::{ClusterChain, ClusterChainIter};
+use super::filesystem::ExFatFileSystem;
+
+/// Represent the allocation bitmap, tracking the clusters in use on the volume.
+pub struct AllocationBitmap {
+    /// The clusters holding the bitmap.
+    clusters: Vec<u32>,
+}
+
+impl AllocationBitmap {
+    /// The amount of bits stored in a block.
+    const BITS_PER_BLOCK: u32 = Block::LEN_U32 * 8;
+
+    /// Create a bitmap that doesn't point to anything. Used until the real one is found.
+    pub fn empty() -> Self {
+        AllocationBitmap {
+            clusters: Vec::new(),
+        }
+    }
+
+    /// Import the bitmap stored at the given cluster.
+    pub fn new<T>(
+        fs: &ExFatFileSystem<T>,
+        first_cluster: u32,
+        length: u64,
+    ) -> FileSystemResult<Self>
+    where
+        T: BlockDevice,
+    {
+        let cluster_size = fs.boot_record.cluster_size();
+        let cluster_count = ((length + cluster_size - 1) / cluster_size) as u32;
+
+        // the bitmap must be able to hold a bit per cluster
+        if length * 8 < u64::from(fs.boot_record.cluster_count()) {
+            return Err(FileSystemError::InvalidPartition);
+        }
+
+        let chain = ClusterChain {
+            first_cluster,
+            no_fat_chain: false,
+            cluster_count: Some(cluster_count),
+        };
+
+        let clusters: Vec<u32> = ClusterChainIter::new(fs, chain, 0).collect();
+        if clusters.len() != cluster_count as usize {
+            return Err(FileSystemError::InvalidPartition);
+        }
+
+        Ok(AllocationBitmap { clusters })
+    }
+
+    /// Compute the block index of the n-th block of the bitmap.
+    fn block_index<T>(&self, fs: &ExFatFileSystem<T>, block_number: u32) -> BlockIndex
+    where
+        T: BlockDevice,
+    {
+        let blocks_per_cluster = fs.boot_record.blocks_per_cluster();
+        let cluster = self.clusters[(block_number / blocks_per_cluster) as usize];
+
+        BlockIndex(fs.cluster_to_block_index(cluster).0 + (block_number % blocks_per_cluster))
+    }
+
+    /// Read the n-th block of the bitmap.
+    fn read_block<T>(
+        &self,
+        fs: &ExFatFileSystem<T>,
+        block_number: u32,
+        blocks: &mut [Block; 1],
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        fs.block_device
+            .read(
+                blocks,
+                fs.partition_start,
+                self.block_index(fs, block_number),
+            )
+            .or(Err(FileSystemError::ReadFailed))
+    }
+
+    /// Check if a cluster is in use.
+    pub fn is_used<T>(&self, fs: &ExFatFileSystem<T>, cluster: u32) -> FileSystemResult<bool>
+    where
+        T: BlockDevice,
+    {
+        let bit = cluster - 2;
+        let mut blocks = [Block::new()];
+
+        self.read_block(fs, bit / Self::BITS_PER_BLOCK, &mut blocks)?;
+
+        let bit_in_block = bit % Self::BITS_PER_BLOCK;
+        Ok((blocks[0][(bit_in_block / 8) as usize] & (1 << (bit_in_block % 8))) != 0)
+    }
+
+    /// Mark a cluster as used or free.
+    pub fn set_used<T>(
+        &self,
+        fs: &ExFatFileSystem<T>,
+        cluster: u32,
+        used: bool,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        let bit = cluster - 2;
+        let block_number = bit / Self::BITS_PER_BLOCK;
+        let mut blocks = [Block::new()];
+
+        self.read_block(fs, block_number, &mut blocks)?;
+
+        let bit_in_block = bit % Self::BITS_PER_BLOCK;
+        let byte = &mut blocks[0][(bit_in_block / 8) as usize];
+        if used {
+            *byte |= 1 << (bit_in_block % 8);
+        } else {
+            *byte &= !(1 << (bit_in_block % 8));
+        }
+
+        fs.block_device
+            .write(
+                &blocks,
+                fs.partition_start,
+                self.block_index(fs, block_number),
+            )
+            .or(Err(FileSystemError::WriteFailed))
+    }
+
+    /// Search a free cluster, starting at ``start_cluster`` and wrapping around the end of the volume.
+    pub fn find_free<T>(
+        &self,
+        fs: &ExFatFileSystem<T>,
+        start_cluster: u32,
+    ) -> FileSystemResult<Option<u32>>
+    where
+        T: BlockDevice,
+    {
+        let bit_count = fs.boot_record.cluster_count();
+        let start_bit = if fs.is_valid_cluster(start_cluster) {
+            start_cluster - 2
+        } else {
+            0
+        };
+
+        let mut blocks = [Block::new()];
+
+        for (range_start, range_end) in &[(start_bit, bit_count), (0, start_bit)] {
+            let mut bit = *range_start;
+            while bit < *range_end {
+                let block_number = bit / Self::BITS_PER_BLOCK;
+                self.read_block(fs, block_number, &mut blocks)?;
+
+                let block_end =
+                    core::cmp::min(*range_end, (block_number + 1) * Self::BITS_PER_BLOCK);
+                while bit < block_end {
+                    let bit_in_block = bit % Self::BITS_PER_BLOCK;
+                    let byte = blocks[0][(bit_in_block / 8) as usize];
+
+                    // skip full bytes in one go
+                    if byte == 0xFF && bit_in_block % 8 == 0 {
+                        bit += 8;
+                        continue;
+                    }
+
+                    if (byte & (1 << (bit_in_block % 8))) == 0 {
+                        return Ok(Some(bit + 2));
+                    }
+
+                    bit += 1;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Compute the count of free clusters on the volume.
+    pub fn count_free<T>(&self, fs: &ExFatFileSystem<T>) -> FileSystemResult<u32>
+    where
+        T: BlockDevice,
+    {
+        let bit_count = fs.boot_record.cluster_count();
+        let mut blocks = [Block::new()];
+        let mut used = 0;
+
+        let block_count = (bit_count + Self::BITS_PER_BLOCK - 1) / Self::BITS_PER_BLOCK;
+        for block_number in 0..block_count {
+            self.read_block(fs, block_number, &mut blocks)?;
+
+            let bits_in_block = core::cmp::min(
+                Self::BITS_PER_BLOCK,
+                bit_count - block_number * Self::BITS_PER_BLOCK,
+            );
+
+            for (index, byte) in blocks[0].iter().enumerate() {
+                let byte_start = index as u32 * 8;
+                if byte_start >= bits_in_block {
+                    break;
+                }
+
+                let mask = if bits_in_block - byte_start >= 8 {
+                    0xFF
+                } else {
+                    (1u8 << (bits_in_block - byte_start)) - 1
+                };
+                used += (*byte & mask).count_ones();
+            }
+        }
+
+        Ok(bit_count - used)
+    }
+}
@@ -0,0 +1,102 @@
+//! exFAT cluster chains.
+
+use libfs::block::BlockDevice;
+
+use super::filesystem::ExFatFileSystem;
+
+/// Represent the clusters allocated to a file or a directory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClusterChain {
+    /// The first cluster of the chain. Zero if nothing is allocated.
+    pub first_cluster: u32,
+
+    /// Set if the clusters are contiguous and the FAT doesn't describe them.
+    pub no_fat_chain: bool,
+
+    /// The count of clusters in the chain. None if the FAT needs to be followed to know it (root directory).
+    pub cluster_count: Option<u32>,
+}
+
+impl ClusterChain {
+    /// Create a chain without any cluster allocated.
+    pub fn empty() -> Self {
+        ClusterChain {
+            first_cluster: 0,
+            no_fat_chain: false,
+            cluster_count: Some(0),
+        }
+    }
+}
+
+/// Util iterator used to simplify iteration over the clusters of a chain.
+pub struct ClusterChainIter<'a, T> {
+    /// The filesystem it belongs to.
+    pub(crate) fs: &'a ExFatFileSystem<T>,
+
+    /// The chain to iterate.
+    chain: ClusterChain,
+
+    /// The next cluster to return.
+    next_cluster: Option<u32>,
+
+    /// The position of the next cluster in the chain.
+    index: u32,
+}
+
+impl<'a, T> ClusterChainIter<'a, T>
+where
+    T: BlockDevice,
+{
+    /// Create a new iterator over ``chain`` starting at the cluster at position ``start_index``.
+    pub fn new(fs: &'a ExFatFileSystem<T>, chain: ClusterChain, start_index: u32) -> Self {
+        let mut res = ClusterChainIter {
+            fs,
+            chain,
+            next_cluster: Some(chain.first_cluster),
+            index: 0,
+        };
+
+        if chain.no_fat_chain {
+            // contiguous chains can be seeked directly
+            res.next_cluster = Some(chain.first_cluster + start_index);
+            res.index = start_index;
+        } else {
+            for _ in 0..start_index {
+                if res.next().is_none() {
+                    break;
+                }
+            }
+        }
+
+        res
+    }
+}
+
+impl<'a, T> Iterator for ClusterChainIter<'a, T>
+where
+    T: BlockDevice,
+{
+    type Item = u32;
+    fn next(&mut self) -> Option<u32> {
+        if let Some(cluster_count) = self.chain.cluster_count {
+            if self.index >= cluster_count {
+                return None;
+            }
+        }
+
+        let res = self.next_cluster?;
+        if !self.fs.is_valid_cluster(res) {
+            self.next_cluster = None;
+            return None;
+        }
+
+        self.next_cluster = if self.chain.no_fat_chain {
+            Some(res + 1)
+        } else {
+            self.fs.fat_get(res).ok().and_then(|value| value)
+        };
+        self.index += 1;
+
+        Some(res)
+    }
+}
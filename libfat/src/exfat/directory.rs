@@ -0,0 +1,844 @@
+//! exFAT directory managment.
+
+use alloc::vec::Vec;
+use arrayvec::ArrayString;
+
+use libfs::block::{Block, BlockDevice, BlockIndex};
+use libfs::FileSystemError;
+use libfs::FileSystemResult;
+
+use crate::attribute::Attributes;
+use crate::datetime::FatDateTime;
+use crate::utils;
+
+use super::chain::{ClusterChain, ClusterChainIter};
+use super::entry::{self, EntrySet, RawEntry, ENTRY_LEN};
+use super::filesystem::ExFatFileSystem;
+
+/// Encode a name as UTF-16, failing if it doesn't fit in an entry set.
+fn encode_name(name: &str) -> FileSystemResult<Vec<u16>> {
+    let res: Vec<u16> = name.encode_utf16().collect();
+
+    if res.len() > entry::MAX_NAME_LEN {
+        return Err(FileSystemError::PathTooLong);
+    }
+
+    Ok(res)
+}
+
+/// Util iterator used to walk the raw entries of a directory.
+pub(crate) struct RawEntryIterator<'a, T> {
+    /// The filesystem it belongs to.
+    fs: &'a ExFatFileSystem<T>,
+
+    /// The cluster iterator.
+    cluster_iter: ClusterChainIter<'a, T>,
+
+    /// The cluster holding the current entry.
+    cluster: Option<u32>,
+
+    /// The position in the directory of the current cluster.
+    cluster_number: Option<u32>,
+
+    /// The position in the directory of the block loaded.
+    block_number: Option<u32>,
+
+    /// The block holding the current entry.
+    blocks: [Block; 1],
+
+    /// The index of the next entry to read.
+    index: u32,
+}
+
+impl<'a, T> RawEntryIterator<'a, T>
+where
+    T: BlockDevice,
+{
+    /// The amount of entries in a block.
+    const ENTRIES_PER_BLOCK: u32 = (Block::LEN / ENTRY_LEN) as u32;
+
+    /// Create a new iterator over the entries of a directory starting at the entry at position ``start_index``.
+    pub fn new(fs: &'a ExFatFileSystem<T>, chain: ClusterChain, start_index: u32) -> Self {
+        let entries_per_cluster = Self::ENTRIES_PER_BLOCK * fs.boot_record.blocks_per_cluster();
+
+        RawEntryIterator {
+            fs,
+            cluster_iter: fs.cluster_iter(chain, start_index / entries_per_cluster),
+            cluster: None,
+            cluster_number: None,
+            block_number: None,
+            blocks: [Block::new()],
+            index: start_index,
+        }
+    }
+}
+
+impl<'a, T> Iterator for RawEntryIterator<'a, T>
+where
+    T: BlockDevice,
+{
+    type Item = FileSystemResult<(u32, RawEntry)>;
+    fn next(&mut self) -> Option<FileSystemResult<(u32, RawEntry)>> {
+        let blocks_per_cluster = self.fs.boot_record.blocks_per_cluster();
+        let entries_per_cluster = Self::ENTRIES_PER_BLOCK * blocks_per_cluster;
+
+        let cluster_number = self.index / entries_per_cluster;
+        if self.cluster_number != Some(cluster_number) {
+            self.cluster = Some(self.cluster_iter.next()?);
+            self.cluster_number = Some(cluster_number);
+        }
+
+        let block_number = self.index / Self::ENTRIES_PER_BLOCK;
+        if self.block_number != Some(block_number) {
+            let block_index = BlockIndex(
+                self.fs.cluster_to_block_index(self.cluster?).0
+                    + (block_number % blocks_per_cluster),
+            );
+
+            let read_res = self
+                .fs
+                .block_device
+                .read(&mut self.blocks, self.fs.partition_start, block_index)
+                .or(Err(FileSystemError::ReadFailed));
+
+            if let Err(error) = read_res {
+                return Some(Err(error));
+            }
+
+            self.block_number = Some(block_number);
+        }
+
+        let entry_start = ((self.index % Self::ENTRIES_PER_BLOCK) as usize) * ENTRY_LEN;
+        let mut raw_entry = [0x0u8; ENTRY_LEN];
+        raw_entry.copy_from_slice(&self.blocks[0][entry_start..entry_start + ENTRY_LEN]);
+
+        let index = self.index;
+        self.index += 1;
+
+        Some(Ok((index, raw_entry)))
+    }
+}
+
+/// Write raw entries in a directory starting at the entry at position ``start_index``.
+fn write_raw_entries<T>(
+    fs: &ExFatFileSystem<T>,
+    chain: ClusterChain,
+    start_index: u32,
+    entries: &[RawEntry],
+) -> FileSystemResult<()>
+where
+    T: BlockDevice,
+{
+    let entries_per_block = (Block::LEN / ENTRY_LEN) as u32;
+    let entries_per_cluster = entries_per_block * fs.boot_record.blocks_per_cluster();
+
+    let mut blocks = [Block::new()];
+    let mut loaded_block_index = None;
+
+    for (i, raw_entry) in entries.iter().enumerate() {
+        let index = start_index + i as u32;
+        let cluster = fs
+            .nth_cluster(chain, index / entries_per_cluster)
+            .ok_or(FileSystemError::NotFound)?;
+        let block_index = BlockIndex(
+            fs.cluster_to_block_index(cluster).0
+                + ((index % entries_per_cluster) / entries_per_block),
+        );
+
+        if loaded_block_index != Some(block_index) {
+            if let Some(loaded_block_index) = loaded_block_index {
+                fs.block_device
+                    .write(&blocks, fs.partition_start, loaded_block_index)
+                    .or(Err(FileSystemError::WriteFailed))?;
+            }
+
+            fs.block_device
+                .read(&mut blocks, fs.partition_start, block_index)
+                .or(Err(FileSystemError::ReadFailed))?;
+            loaded_block_index = Some(block_index);
+        }
+
+        let entry_start = ((index % entries_per_block) as usize) * ENTRY_LEN;
+        blocks[0][entry_start..entry_start + ENTRY_LEN].copy_from_slice(raw_entry);
+    }
+
+    if let Some(loaded_block_index) = loaded_block_index {
+        fs.block_device
+            .write(&blocks, fs.partition_start, loaded_block_index)
+            .or(Err(FileSystemError::WriteFailed))?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Represent the location of an entry set in its parent directory.
+pub(crate) struct DirectoryEntryRawInfo {
+    /// The cluster chain of the parent directory.
+    pub parent: ClusterChain,
+
+    /// The position of the File entry in the parent directory.
+    pub entry_index: u32,
+
+    /// The count of raw entries used by the entry set.
+    pub entry_count: u32,
+}
+
+impl DirectoryEntryRawInfo {
+    /// Read the entry set from the disk.
+    pub fn get_entry_set<T>(&self, fs: &ExFatFileSystem<T>) -> FileSystemResult<EntrySet>
+    where
+        T: BlockDevice,
+    {
+        let mut entries = Vec::with_capacity(self.entry_count as usize);
+
+        for raw_entry in
+            RawEntryIterator::new(fs, self.parent, self.entry_index).take(self.entry_count as usize)
+        {
+            entries.push(raw_entry?.1);
+        }
+
+        EntrySet::from_raw(entries).ok_or(FileSystemError::NotFound)
+    }
+}
+
+#[derive(Clone, Copy)]
+/// A high level representation of a directory/file in the directory.
+pub struct DirectoryEntry {
+    /// The clusters used by the entry.
+    pub(crate) chain: ClusterChain,
+
+    /// The location of the entry set inside its parent.
+    pub(crate) raw_info: Option<DirectoryEntryRawInfo>,
+
+    /// How far the file data was written. Everything after it reads as zeros.
+    pub(crate) valid_data_length: u64,
+
+    /// The creation UNIX timestamp of the entry.
+    pub creation_timestamp: u64,
+
+    /// The last access UNIX timestamp of the entry.
+    pub last_access_timestamp: u64,
+
+    /// The last modification UNIX timestamp of the entry.
+    pub last_modification_timestamp: u64,
+
+    /// The file size of the entry.
+    pub file_size: u64,
+
+    /// The file name of the entry.
+    pub file_name: ArrayString<[u8; Self::MAX_FILE_NAME_LEN_UNICODE]>,
+
+    /// The attributes of the entry.
+    pub attribute: Attributes,
+}
+
+impl DirectoryEntry {
+    /// The max size of an exFAT name encoded as Unicode.
+    pub const MAX_FILE_NAME_LEN_UNICODE: usize = 1024;
+
+    /// Create a directory entry from an entry set.
+    fn from_entry_set<T>(
+        fs: &ExFatFileSystem<T>,
+        entry_set: &EntrySet,
+        parent: ClusterChain,
+        entry_index: u32,
+    ) -> Self
+    where
+        T: BlockDevice,
+    {
+        let mut file_name = ArrayString::<[_; Self::MAX_FILE_NAME_LEN_UNICODE]>::new();
+        for c in core::char::decode_utf16(entry_set.name().iter().cloned()) {
+            file_name.push(c.unwrap_or(core::char::REPLACEMENT_CHARACTER));
+        }
+
+        let first_cluster = entry_set.first_cluster();
+        let file_size = entry_set.data_length();
+
+        DirectoryEntry {
+            chain: ClusterChain {
+                first_cluster,
+                no_fat_chain: entry_set.no_fat_chain(),
+                cluster_count: Some(if first_cluster == 0 {
+                    0
+                } else {
+                    fs.cluster_count_for_size(file_size)
+                }),
+            },
+            raw_info: Some(DirectoryEntryRawInfo {
+                parent,
+                entry_index,
+                entry_count: entry_set.entry_count(),
+            }),
+            valid_data_length: entry_set.valid_data_length(),
+            creation_timestamp: entry_set.get_creation_datetime().to_unix_time(),
+            last_access_timestamp: entry_set.get_last_access_datetime().to_unix_time(),
+            last_modification_timestamp: entry_set.get_modification_datetime().to_unix_time(),
+            file_size,
+            file_name,
+            attribute: entry_set.attribute(),
+        }
+    }
+
+    /// Write the cluster chain and the sizes of the entry to its entry set.
+    pub(crate) fn flush<T>(&self, fs: &ExFatFileSystem<T>) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        let raw_info = self.raw_info.ok_or(FileSystemError::Custom {
+            name: "Raw Info is missing ON A FILE",
+        })?;
+
+        let mut entry_set = raw_info.get_entry_set(fs)?;
+        entry_set.set_first_cluster(self.chain.first_cluster);
+        entry_set.set_no_fat_chain(self.chain.no_fat_chain);
+        entry_set.set_data_length(self.file_size);
+        entry_set.set_valid_data_length(self.valid_data_length);
+        entry_set.set_attribute(self.attribute);
+        entry_set.update_checksum();
+
+        write_raw_entries(
+            fs,
+            raw_info.parent,
+            raw_info.entry_index,
+            entry_set.as_entries(),
+        )
+    }
+
+    /// Set the attributes of the entry.
+    /// NOTE: This doesn't check that the directory bit is left untouched.
+    pub fn set_attribute<T>(
+        &mut self,
+        fs: &ExFatFileSystem<T>,
+        attribute: Attributes,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        // The root directory doesn't have any attributes
+        if self.raw_info.is_none() {
+            return Err(FileSystemError::AccessDenied);
+        }
+
+        self.attribute = attribute;
+        self.flush(fs)
+    }
+
+    /// Set the creation, last modification and last access timestamps of the entry. ``None`` timestamps are left untouched.
+    /// NOTE: exFAT stores the creation and last modification times with a 10ms resolution and the last access time with a 2 seconds resolution.
+    pub fn set_timestamps<T>(
+        &mut self,
+        fs: &ExFatFileSystem<T>,
+        creation: Option<FatDateTime>,
+        modification: Option<FatDateTime>,
+        access: Option<FatDateTime>,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        // The root directory doesn't have any timestamps
+        let raw_info = self.raw_info.ok_or(FileSystemError::AccessDenied)?;
+        let mut entry_set = raw_info.get_entry_set(fs)?;
+
+        if let Some(creation) = creation {
+            entry_set.set_creation_datetime(&creation);
+        }
+
+        if let Some(modification) = modification {
+            entry_set.set_modification_datetime(&modification);
+        }
+
+        if let Some(access) = access {
+            entry_set.set_last_access_datetime(&access);
+        }
+
+        entry_set.update_checksum();
+        write_raw_entries(
+            fs,
+            raw_info.parent,
+            raw_info.entry_index,
+            entry_set.as_entries(),
+        )?;
+
+        self.creation_timestamp = entry_set.get_creation_datetime().to_unix_time();
+        self.last_access_timestamp = entry_set.get_last_access_datetime().to_unix_time();
+        self.last_modification_timestamp = entry_set.get_modification_datetime().to_unix_time();
+
+        Ok(())
+    }
+
+    /// Read at a given offset of the file into a given buffer.
+    pub fn read<T>(
+        &mut self,
+        fs: &ExFatFileSystem<T>,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> FileSystemResult<u64>
+    where
+        T: BlockDevice,
+    {
+        if offset >= self.file_size {
+            return Ok(0);
+        }
+
+        let read_size = core::cmp::min(buf.len() as u64, self.file_size - offset) as usize;
+        let buf = &mut buf[..read_size];
+
+        // data after the valid data length was never written and must read as zeros
+        let valid_size = if offset >= self.valid_data_length {
+            0
+        } else {
+            core::cmp::min(read_size as u64, self.valid_data_length - offset) as usize
+        };
+
+        fs.read_chain(self.chain, offset, &mut buf[..valid_size])?;
+        for value in buf[valid_size..].iter_mut() {
+            *value = 0;
+        }
+
+        Ok(read_size as u64)
+    }
+
+    /// Write the given buffer at a given offset of the file.
+    pub fn write<T>(
+        &mut self,
+        fs: &ExFatFileSystem<T>,
+        offset: u64,
+        buf: &[u8],
+        appendable: bool,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        let min_size = offset + buf.len() as u64;
+        if min_size > self.file_size {
+            if appendable {
+                self.set_len(fs, min_size)?;
+            } else {
+                return Err(FileSystemError::AccessDenied);
+            }
+        }
+
+        // the data between the valid data length and the write offset must be zeroed
+        if offset > self.valid_data_length {
+            fs.write_chain(
+                self.chain,
+                self.valid_data_length,
+                None,
+                offset - self.valid_data_length,
+            )?;
+        }
+
+        fs.write_chain(self.chain, offset, Some(buf), buf.len() as u64)?;
+
+        if min_size > self.valid_data_length {
+            self.valid_data_length = min_size;
+            self.flush(fs)?;
+        }
+
+        Ok(())
+    }
+
+    /// Set the file length
+    pub fn set_len<T>(&mut self, fs: &ExFatFileSystem<T>, size: u64) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        if size == self.file_size {
+            return Ok(());
+        }
+
+        let current_cluster_count = self.chain.cluster_count.unwrap_or(0);
+        let cluster_count = fs.cluster_count_for_size(size);
+
+        if cluster_count > current_cluster_count {
+            let mut chain = self.chain;
+            let res = fs.extend_chain(&mut chain, cluster_count - current_cluster_count);
+
+            if let Err(error) = res {
+                // If it fail here, this can be catastrophic but at least we tried our best.
+                fs.truncate_chain(&mut chain, current_cluster_count)?;
+                return Err(error);
+            }
+
+            self.chain = chain;
+        } else if cluster_count < current_cluster_count {
+            fs.truncate_chain(&mut self.chain, cluster_count)?;
+        }
+
+        self.file_size = size;
+        if self.valid_data_length > size {
+            self.valid_data_length = size;
+        }
+
+        self.flush(fs)
+    }
+}
+
+/// Represent a directory entries iterator.
+pub struct DirectoryEntryIterator<'a, T> {
+    /// The raw directory entries iterator.
+    raw_iter: RawEntryIterator<'a, T>,
+
+    /// The cluster chain of the directory.
+    chain: ClusterChain,
+
+    /// Set when the end of the directory was reached.
+    is_done: bool,
+}
+
+impl<'a, T> Iterator for DirectoryEntryIterator<'a, T>
+where
+    T: BlockDevice,
+{
+    type Item = FileSystemResult<DirectoryEntry>;
+    fn next(&mut self) -> Option<FileSystemResult<DirectoryEntry>> {
+        if self.is_done {
+            return None;
+        }
+
+        while let Some(raw_entry) = self.raw_iter.next() {
+            let (index, raw_entry) = match raw_entry {
+                Ok(raw_entry) => raw_entry,
+                Err(error) => return Some(Err(error)),
+            };
+
+            match raw_entry[0] {
+                entry::END_OF_DIRECTORY => break,
+                entry::FILE => {
+                    let entry_count = usize::from(raw_entry[1]) + 1;
+                    let mut entries = Vec::with_capacity(entry_count);
+                    entries.push(raw_entry);
+
+                    for _ in 1..entry_count {
+                        match self.raw_iter.next() {
+                            Some(Ok((_, raw_entry))) => entries.push(raw_entry),
+                            Some(Err(error)) => return Some(Err(error)),
+                            None => break,
+                        }
+                    }
+
+                    // Broken entry sets are ignored
+                    if let Some(entry_set) = EntrySet::from_raw(entries) {
+                        return Some(Ok(DirectoryEntry::from_entry_set(
+                            self.raw_iter.fs,
+                            &entry_set,
+                            self.chain,
+                            index,
+                        )));
+                    }
+                }
+                // deleted entries, volume label, allocation bitmap, up-case table...
+                _ => {}
+            }
+        }
+
+        self.is_done = true;
+        None
+    }
+}
+
+#[derive(Copy)]
+/// Represent a Directory.
+pub struct Directory<'a, T> {
+    /// The information about this directory.
+    pub(crate) dir_info: DirectoryEntry,
+
+    /// A reference to the filesystem.
+    fs: &'a ExFatFileSystem<T>,
+}
+
+impl<'a, T> Clone for Directory<'a, T> {
+    fn clone(&self) -> Self {
+        Directory {
+            dir_info: self.dir_info,
+            fs: self.fs,
+        }
+    }
+}
+
+impl<'a, T> Directory<'a, T>
+where
+    T: BlockDevice,
+{
+    /// Create a directory from a filesystem reference and a directory entry.
+    pub fn from_entry(fs: &'a ExFatFileSystem<T>, dir_info: DirectoryEntry) -> Self {
+        Directory { dir_info, fs }
+    }
+
+    /// Create a directory entry iterator from the directory.
+    pub fn iter(self) -> DirectoryEntryIterator<'a, T> {
+        DirectoryEntryIterator {
+            raw_iter: RawEntryIterator::new(self.fs, self.dir_info.chain, 0),
+            chain: self.dir_info.chain,
+            is_done: false,
+        }
+    }
+
+    /// Search an entry inside the directory and if found return it.
+    pub fn find_entry(self, name: &str) -> FileSystemResult<DirectoryEntry> {
+        let name = encode_name(name)?;
+        let upcase_table = &self.fs.upcase_table;
+
+        for entry in self.iter() {
+            let entry = entry?;
+
+            let file_name: Vec<u16> = entry.file_name.as_str().encode_utf16().collect();
+            if upcase_table.name_eq(&file_name, &name) {
+                return Ok(entry);
+            }
+        }
+
+        Err(FileSystemError::NotFound)
+    }
+
+    /// Open a file a the given path.
+    pub fn open_file(self, path: &str) -> FileSystemResult<DirectoryEntry> {
+        let (name, rest_opt) = utils::split_path(path);
+        let fs = self.fs;
+
+        let child_entry = self.find_entry(name)?;
+
+        match rest_opt {
+            Some(rest) => {
+                if !child_entry.attribute.is_directory() {
+                    Err(FileSystemError::NotFound)
+                } else {
+                    Directory::from_entry(fs, child_entry).open_file(rest)
+                }
+            }
+            None => Ok(child_entry),
+        }
+    }
+
+    /// Open a directory at the given path.
+    pub fn open_dir(self, path: &str) -> FileSystemResult<Directory<'a, T>> {
+        let (name, rest_opt) = utils::split_path(path);
+
+        let fs = self.fs;
+
+        let child_entry = self.find_entry(name)?;
+
+        if !child_entry.attribute.is_directory() {
+            return Err(FileSystemError::NotFound);
+        }
+
+        match rest_opt {
+            Some(rest) => Directory::from_entry(fs, child_entry).open_dir(rest),
+            None => Ok(Directory::from_entry(fs, child_entry)),
+        }
+    }
+
+    /// Search ``count`` consecutive unused entries and return the position of the first one.
+    fn find_free_entries(&self, count: u32) -> FileSystemResult<Option<u32>> {
+        let mut run_start = 0;
+        let mut run_len = 0;
+
+        for raw_entry in RawEntryIterator::new(self.fs, self.dir_info.chain, 0) {
+            let (index, raw_entry) = raw_entry?;
+
+            if (raw_entry[0] & entry::IN_USE) == 0 {
+                if run_len == 0 {
+                    run_start = index;
+                }
+
+                run_len += 1;
+                if run_len == count {
+                    return Ok(Some(run_start));
+                }
+            } else {
+                run_len = 0;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Add a cluster at the end of the directory.
+    fn grow(&mut self) -> FileSystemResult<()> {
+        let fs = self.fs;
+        let mut chain = self.dir_info.chain;
+
+        let cluster_count = match chain.cluster_count {
+            Some(cluster_count) => cluster_count,
+            None => fs.cluster_iter(chain, 0).count() as u32,
+        };
+
+        fs.extend_chain(&mut chain, 1)?;
+
+        let clear_res = fs
+            .nth_cluster(chain, cluster_count)
+            .ok_or(FileSystemError::NotFound)
+            .and_then(|cluster| fs.clean_cluster_data(cluster));
+
+        if let Err(error) = clear_res {
+            // If it fail here, this can be catastrophic but at least we tried our best.
+            fs.truncate_chain(&mut chain, cluster_count)?;
+            return Err(error);
+        }
+
+        self.dir_info.chain = chain;
+
+        // the root directory size is only described by its chain
+        if self.dir_info.raw_info.is_some() {
+            self.dir_info.file_size += fs.boot_record.cluster_size();
+            self.dir_info.valid_data_length = self.dir_info.file_size;
+            self.dir_info.flush(fs)?;
+        }
+
+        Ok(())
+    }
+
+    /// Create an entry set in the directory.
+    fn create_dir_entry(&mut self, entry_set: &EntrySet) -> FileSystemResult<DirectoryEntry> {
+        let entry_count = entry_set.entry_count();
+
+        let entry_index = loop {
+            if let Some(entry_index) = self.find_free_entries(entry_count)? {
+                break entry_index;
+            }
+
+            // if the directory is full, try to allocate a cluster and use it
+            self.grow()?;
+        };
+
+        write_raw_entries(
+            self.fs,
+            self.dir_info.chain,
+            entry_index,
+            entry_set.as_entries(),
+        )?;
+
+        Ok(DirectoryEntry::from_entry_set(
+            self.fs,
+            entry_set,
+            self.dir_info.chain,
+            entry_index,
+        ))
+    }
+
+    /// Delete an entry set from its parent directory.
+    fn delete_dir_entry(
+        fs: &'a ExFatFileSystem<T>,
+        dir_entry: &DirectoryEntry,
+    ) -> FileSystemResult<()> {
+        if let Some(raw_info) = dir_entry.raw_info {
+            let mut entries = Vec::with_capacity(raw_info.entry_count as usize);
+
+            for raw_entry in RawEntryIterator::new(fs, raw_info.parent, raw_info.entry_index)
+                .take(raw_info.entry_count as usize)
+            {
+                let mut raw_entry = raw_entry?.1;
+                raw_entry[0] &= !entry::IN_USE;
+                entries.push(raw_entry);
+            }
+
+            write_raw_entries(fs, raw_info.parent, raw_info.entry_index, &entries)?;
+        }
+
+        Ok(())
+    }
+
+    /// Create a directory with the given name.
+    pub fn mkdir(&mut self, name: &str) -> FileSystemResult<()> {
+        let name = encode_name(name)?;
+        let fs = self.fs;
+
+        // Allocate a cluster for the directory entries
+        let mut chain = ClusterChain::empty();
+        fs.extend_chain(&mut chain, 1)?;
+
+        let clear_res = fs.clean_cluster_data(chain.first_cluster);
+
+        if let Err(error) = clear_res {
+            // If it fail here, this can be catastrophic but at least we tried our best.
+            fs.truncate_chain(&mut chain, 0)?;
+            return Err(error);
+        }
+
+        let mut entry_set = EntrySet::new(
+            &fs.upcase_table,
+            &name,
+            Attributes::new(Attributes::DIRECTORY),
+        );
+        entry_set.set_first_cluster(chain.first_cluster);
+        entry_set.set_no_fat_chain(chain.no_fat_chain);
+        entry_set.set_data_length(fs.boot_record.cluster_size());
+        entry_set.set_valid_data_length(fs.boot_record.cluster_size());
+        entry_set.update_checksum();
+
+        // Cannot create directory?
+        if let Err(err) = self.create_dir_entry(&entry_set) {
+            fs.truncate_chain(&mut chain, 0)?;
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Create a file with the given name.
+    pub fn touch(&mut self, name: &str) -> FileSystemResult<()> {
+        let name = encode_name(name)?;
+
+        let entry_set = EntrySet::new(&self.fs.upcase_table, &name, Attributes::new(0));
+        self.create_dir_entry(&entry_set)?;
+
+        Ok(())
+    }
+
+    /// Delete a directory or a file with the given name.
+    pub fn unlink(self, name: &str, is_dir: bool) -> FileSystemResult<()> {
+        let fs = self.fs;
+
+        let mut dir_entry = self.find_entry(name)?;
+
+        if dir_entry.attribute.is_directory() != is_dir {
+            if is_dir {
+                return Err(FileSystemError::NotADirectory);
+            }
+
+            return Err(FileSystemError::NotAFile);
+        }
+
+        // Read only files cannot be deleted
+        if !is_dir && dir_entry.attribute.is_read_only() {
+            return Err(FileSystemError::AccessDenied);
+        }
+
+        // Check for directory not being empty
+        if dir_entry.attribute.is_directory()
+            && Self::from_entry(fs, dir_entry).iter().next().is_some()
+        {
+            return Err(FileSystemError::AccessDenied);
+        }
+
+        Self::delete_dir_entry(fs, &dir_entry)?;
+
+        fs.truncate_chain(&mut dir_entry.chain, 0)
+    }
+
+    /// Rename a directory or a file to a new name in this directory.
+    pub fn rename(mut self, dir_entry: DirectoryEntry, new_name: &str) -> FileSystemResult<()> {
+        let new_name = encode_name(new_name)?;
+        let old_raw_info = dir_entry.raw_info.ok_or(FileSystemError::AccessDenied)?;
+
+        let entry_set = old_raw_info
+            .get_entry_set(self.fs)?
+            .with_name(&self.fs.upcase_table, &new_name);
+
+        // can we update in place?
+        if old_raw_info.entry_count == entry_set.entry_count()
+            && old_raw_info.parent == self.dir_info.chain
+        {
+            return write_raw_entries(
+                self.fs,
+                old_raw_info.parent,
+                old_raw_info.entry_index,
+                entry_set.as_entries(),
+            );
+        }
+
+        self.create_dir_entry(&entry_set)?;
+        Self::delete_dir_entry(self.fs, &dir_entry)
+    }
+}
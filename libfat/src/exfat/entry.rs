@@ -0,0 +1,325 @@
+//! exFAT raw directory entries.
+
+use alloc::vec::Vec;
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::attribute::Attributes;
+use crate::datetime::FatDateTime;
+
+use super::upcase::UpcaseTable;
+
+/// The length of an exFAT directory entry.
+pub const ENTRY_LEN: usize = 32;
+
+/// Represent a raw exFAT directory entry.
+pub type RawEntry = [u8; ENTRY_LEN];
+
+/// Marks the end of the directory. Every following entries are unused.
+pub const END_OF_DIRECTORY: u8 = 0x00;
+
+/// The bit set on the entry type of entries in use.
+pub const IN_USE: u8 = 0x80;
+
+/// Allocation Bitmap primary entry type.
+pub const ALLOCATION_BITMAP: u8 = 0x81;
+
+/// Up-case Table primary entry type.
+pub const UPCASE_TABLE: u8 = 0x82;
+
+/// File primary entry type.
+pub const FILE: u8 = 0x85;
+
+/// Stream Extension secondary entry type.
+pub const STREAM_EXTENSION: u8 = 0xC0;
+
+/// File Name secondary entry type.
+pub const FILE_NAME: u8 = 0xC1;
+
+/// The count of UTF-16 characters held by a File Name entry.
+pub const FILE_NAME_ENTRY_LEN: usize = 15;
+
+/// The max count of UTF-16 characters in a file name.
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Set in the general secondary flags when clusters can be allocated to the entry.
+const ALLOCATION_POSSIBLE: u8 = 0x01;
+
+/// Set in the general secondary flags when the allocated clusters are contiguous and the FAT doesn't describe them.
+const NO_FAT_CHAIN: u8 = 0x02;
+
+/// Represent a file directory entry set: one File entry, one Stream Extension entry and File Name entries.
+#[derive(Clone)]
+pub struct EntrySet {
+    /// The raw entries of the set.
+    entries: Vec<RawEntry>,
+}
+
+impl EntrySet {
+    /// Compute the count of entries needed to store a set with a name of the given length.
+    pub fn entry_count_for_name(name_len: usize) -> u32 {
+        2 + ((name_len + FILE_NAME_ENTRY_LEN - 1) / FILE_NAME_ENTRY_LEN) as u32
+    }
+
+    /// Create a new entry set with no cluster allocated.
+    pub fn new(upcase_table: &UpcaseTable, name: &[u16], attribute: Attributes) -> Self {
+        let entry_count = Self::entry_count_for_name(name.len()) as usize;
+        let mut entries = Vec::with_capacity(entry_count);
+
+        let mut file_entry = [0x0u8; ENTRY_LEN];
+        file_entry[0] = FILE;
+        file_entry[1] = (entry_count - 1) as u8;
+        entries.push(file_entry);
+
+        let mut stream_entry = [0x0u8; ENTRY_LEN];
+        stream_entry[0] = STREAM_EXTENSION;
+        stream_entry[1] = ALLOCATION_POSSIBLE;
+        entries.push(stream_entry);
+
+        for _ in 2..entry_count {
+            let mut name_entry = [0x0u8; ENTRY_LEN];
+            name_entry[0] = FILE_NAME;
+            entries.push(name_entry);
+        }
+
+        let mut res = EntrySet { entries };
+        res.set_attribute(attribute);
+        res.set_name(upcase_table, name);
+        res.update_checksum();
+        res
+    }
+
+    /// Import an entry set from raw entries, checking its consistency.
+    pub fn from_raw(entries: Vec<RawEntry>) -> Option<Self> {
+        if entries.len() < 3
+            || entries[0][0] != FILE
+            || entries[1][0] != STREAM_EXTENSION
+            || usize::from(entries[0][1]) + 1 != entries.len()
+        {
+            return None;
+        }
+
+        let res = EntrySet { entries };
+
+        if res.entries[2..].iter().any(|entry| entry[0] != FILE_NAME)
+            || res.name_len() > (res.entries.len() - 2) * FILE_NAME_ENTRY_LEN
+            || res.checksum() != LittleEndian::read_u16(&res.entries[0][2..4])
+        {
+            return None;
+        }
+
+        Some(res)
+    }
+
+    /// Return the raw entries of the set.
+    pub fn as_entries(&self) -> &[RawEntry] {
+        &self.entries
+    }
+
+    /// Return the count of entries in the set.
+    pub fn entry_count(&self) -> u32 {
+        self.entries.len() as u32
+    }
+
+    /// Compute the checksum of the entry set.
+    pub fn checksum(&self) -> u16 {
+        let mut checksum = 0u16;
+        for (entry_index, entry) in self.entries.iter().enumerate() {
+            for (index, value) in entry.iter().enumerate() {
+                // skip the checksum field itself
+                if entry_index == 0 && (index == 2 || index == 3) {
+                    continue;
+                }
+
+                checksum = (checksum << 15)
+                    .wrapping_add(checksum >> 1)
+                    .wrapping_add(u16::from(*value));
+            }
+        }
+        checksum
+    }
+
+    /// Update the stored checksum. Must be called after any modification of the set.
+    pub fn update_checksum(&mut self) {
+        let checksum = self.checksum();
+        LittleEndian::write_u16(&mut self.entries[0][2..4], checksum);
+    }
+
+    /// Return the attributes of the entry.
+    pub fn attribute(&self) -> Attributes {
+        Attributes::new(self.entries[0][4])
+    }
+
+    /// Set the attributes of the entry.
+    pub fn set_attribute(&mut self, attribute: Attributes) {
+        LittleEndian::write_u16(&mut self.entries[0][4..6], u16::from(attribute.get_value()));
+    }
+
+    /// Return the length of the name in UTF-16 characters.
+    pub fn name_len(&self) -> usize {
+        usize::from(self.entries[1][3])
+    }
+
+    /// Return the name of the entry as UTF-16 characters.
+    pub fn name(&self) -> Vec<u16> {
+        let name_len = self.name_len();
+        let mut res = Vec::with_capacity(name_len);
+
+        for entry in self.entries[2..].iter() {
+            for index in 0..FILE_NAME_ENTRY_LEN {
+                if res.len() == name_len {
+                    return res;
+                }
+
+                let offset = 2 + index * 2;
+                res.push(LittleEndian::read_u16(&entry[offset..offset + 2]));
+            }
+        }
+
+        res
+    }
+
+    /// Set the name of the entry. The set must have enough File Name entries to hold it.
+    fn set_name(&mut self, upcase_table: &UpcaseTable, name: &[u16]) {
+        self.entries[1][3] = name.len() as u8;
+        LittleEndian::write_u16(&mut self.entries[1][4..6], upcase_table.name_hash(name));
+
+        for (entry_index, entry) in self.entries[2..].iter_mut().enumerate() {
+            for index in 0..FILE_NAME_ENTRY_LEN {
+                let offset = 2 + index * 2;
+                let value = name
+                    .get(entry_index * FILE_NAME_ENTRY_LEN + index)
+                    .cloned()
+                    .unwrap_or(0);
+                LittleEndian::write_u16(&mut entry[offset..offset + 2], value);
+            }
+        }
+    }
+
+    /// Create a copy of this set with another name.
+    pub fn with_name(&self, upcase_table: &UpcaseTable, name: &[u16]) -> Self {
+        let mut res = Self::new(upcase_table, name, self.attribute());
+
+        // copy everything except the name related fields
+        res.entries[0][4..ENTRY_LEN].copy_from_slice(&self.entries[0][4..ENTRY_LEN]);
+        res.entries[1][1] = self.entries[1][1];
+        res.entries[1][8..ENTRY_LEN].copy_from_slice(&self.entries[1][8..ENTRY_LEN]);
+        res.update_checksum();
+        res
+    }
+
+    /// Return the first cluster allocated to the entry.
+    pub fn first_cluster(&self) -> u32 {
+        LittleEndian::read_u32(&self.entries[1][20..24])
+    }
+
+    /// Set the first cluster allocated to the entry.
+    pub fn set_first_cluster(&mut self, cluster: u32) {
+        LittleEndian::write_u32(&mut self.entries[1][20..24], cluster);
+    }
+
+    /// Return the size of the data of the entry.
+    pub fn data_length(&self) -> u64 {
+        LittleEndian::read_u64(&self.entries[1][24..32])
+    }
+
+    /// Set the size of the data of the entry.
+    pub fn set_data_length(&mut self, length: u64) {
+        LittleEndian::write_u64(&mut self.entries[1][24..32], length);
+    }
+
+    /// Return how far the data of the entry was written. Everything after it reads as zeros.
+    pub fn valid_data_length(&self) -> u64 {
+        LittleEndian::read_u64(&self.entries[1][8..16])
+    }
+
+    /// Set how far the data of the entry was written.
+    pub fn set_valid_data_length(&mut self, length: u64) {
+        LittleEndian::write_u64(&mut self.entries[1][8..16], length);
+    }
+
+    /// Check if the clusters of the entry are contiguous and not described by the FAT.
+    pub fn no_fat_chain(&self) -> bool {
+        (self.entries[1][1] & NO_FAT_CHAIN) == NO_FAT_CHAIN
+    }
+
+    /// Set if the clusters of the entry are contiguous and not described by the FAT.
+    pub fn set_no_fat_chain(&mut self, value: bool) {
+        if value {
+            self.entries[1][1] |= NO_FAT_CHAIN;
+        } else {
+            self.entries[1][1] &= !NO_FAT_CHAIN;
+        }
+    }
+
+    /// Decode an exFAT timestamp with its 10ms increment and its UTC offset.
+    fn decode_timestamp(raw: u32, increment: u8, raw_utc_offset: u8) -> FatDateTime {
+        let mut res = FatDateTime::from_fat_date_time((raw >> 16) as u16, raw as u16, increment);
+        res.set_utc_offset(FatDateTime::utc_offset_from_exfat(raw_utc_offset));
+
+        res
+    }
+
+    /// Encode an exFAT timestamp with its 10ms increment and its UTC offset.
+    fn encode_timestamp(datetime: &FatDateTime) -> (u32, u8, u8) {
+        let raw = (u32::from(datetime.to_fat_date()) << 16) | u32::from(datetime.to_fat_time());
+
+        (
+            raw,
+            datetime.to_fat_time_fine_resolution(),
+            FatDateTime::utc_offset_to_exfat(datetime.utc_offset()),
+        )
+    }
+
+    /// Retrieve the creation datetime of the entry.
+    pub fn get_creation_datetime(&self) -> FatDateTime {
+        Self::decode_timestamp(
+            LittleEndian::read_u32(&self.entries[0][8..12]),
+            self.entries[0][20],
+            self.entries[0][22],
+        )
+    }
+
+    /// Retrieve the last modification datetime of the entry.
+    pub fn get_modification_datetime(&self) -> FatDateTime {
+        Self::decode_timestamp(
+            LittleEndian::read_u32(&self.entries[0][12..16]),
+            self.entries[0][21],
+            self.entries[0][23],
+        )
+    }
+
+    /// Retrieve the last access datetime of the entry.
+    pub fn get_last_access_datetime(&self) -> FatDateTime {
+        Self::decode_timestamp(
+            LittleEndian::read_u32(&self.entries[0][16..20]),
+            0,
+            self.entries[0][24],
+        )
+    }
+
+    /// Set the creation datetime of the entry.
+    pub fn set_creation_datetime(&mut self, datetime: &FatDateTime) {
+        let (raw, increment, raw_utc_offset) = Self::encode_timestamp(datetime);
+
+        LittleEndian::write_u32(&mut self.entries[0][8..12], raw);
+        self.entries[0][20] = increment;
+        self.entries[0][22] = raw_utc_offset;
+    }
+
+    /// Set the last modification datetime of the entry.
+    pub fn set_modification_datetime(&mut self, datetime: &FatDateTime) {
+        let (raw, increment, raw_utc_offset) = Self::encode_timestamp(datetime);
+
+        LittleEndian::write_u32(&mut self.entries[0][12..16], raw);
+        self.entries[0][21] = increment;
+        self.entries[0][23] = raw_utc_offset;
+    }
+
+    /// Set the last access datetime of the entry. Only a 2 seconds resolution is available.
+    pub fn set_last_access_datetime(&mut self, datetime: &FatDateTime) {
+        let (raw, _, raw_utc_offset) = Self::encode_timestamp(datetime);
+
+        LittleEndian::write_u32(&mut self.entries[0][16..20], raw);
+        self.entries[0][24] = raw_utc_offset;
+    }
+}
@@ -0,0 +1,615 @@
+//! exFAT Filesystem.
+
+use alloc::vec::Vec;
+use arrayvec::ArrayString;
+use byteorder::{ByteOrder, LittleEndian};
+
+use libfs::block::{Block, BlockCount, BlockDevice, BlockIndex};
+use libfs::FileSystemError;
+use libfs::FileSystemResult;
+
+use crate::attribute::Attributes;
+use crate::utils;
+use crate::{FatFsType, VolumeStatistics};
+
+use super::bitmap::AllocationBitmap;
+use super::chain::{ClusterChain, ClusterChainIter};
+use super::directory::Directory;
+use super::directory::{DirectoryEntry, RawEntryIterator};
+use super::entry;
+use super::upcase::UpcaseTable;
+use super::ExFatBootRecord;
+
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+
+/// Represent an exFAT filesystem.
+#[allow(dead_code)]
+pub struct ExFatFileSystem<T> {
+    /// The block device of the filesystem.
+    pub(crate) block_device: T,
+
+    /// The block index of the start of the partition of this filesystem.
+    pub(crate) partition_start: BlockIndex,
+
+    /// The count of blocks that this partition contains.
+    pub(crate) partition_block_count: BlockCount,
+
+    /// The volume information of the filesystem.
+    pub(crate) boot_record: ExFatBootRecord,
+
+    /// The allocation bitmap of the filesystem.
+    pub(crate) bitmap: AllocationBitmap,
+
+    /// The up-case table used to compare names.
+    pub(crate) upcase_table: UpcaseTable,
+
+    /// The last allocated cluster on the filesystem.
+    last_cluster: AtomicU32,
+
+    /// The free cluster count on the filesystem.
+    free_cluster: AtomicU32,
+}
+
+impl<T> ExFatFileSystem<T>
+where
+    T: BlockDevice,
+{
+    /// The value of a FAT entry marking the end of a chain.
+    const END_OF_CHAIN: u32 = 0xFFFF_FFFF;
+
+    /// Create a new instance of ExFatFileSystem
+    /// TODO: ``init`` needs to be called after this
+    pub(crate) fn new(
+        block_device: T,
+        partition_start: BlockIndex,
+        partition_block_count: BlockCount,
+        boot_record: ExFatBootRecord,
+    ) -> ExFatFileSystem<T> {
+        ExFatFileSystem {
+            block_device,
+            partition_start,
+            partition_block_count,
+            boot_record,
+            bitmap: AllocationBitmap::empty(),
+            upcase_table: UpcaseTable::ascii(),
+            last_cluster: AtomicU32::new(2),
+            free_cluster: AtomicU32::new(0),
+        }
+    }
+
+    /// Initialize the filesystem.
+    pub(crate) fn init(&mut self) -> FileSystemResult<()> {
+        let mut bitmap_entry = None;
+        let mut upcase_entry = None;
+
+        // the allocation bitmap and the up-case table are described in the root directory
+        for raw_entry in RawEntryIterator::new(self, self.root_chain(), 0) {
+            let (_, raw_entry) = raw_entry?;
+
+            match raw_entry[0] {
+                entry::END_OF_DIRECTORY => break,
+                entry::ALLOCATION_BITMAP => {
+                    // TexFAT volumes have a bitmap per FAT
+                    if (raw_entry[1] & 0x1) == self.boot_record.active_fat() {
+                        bitmap_entry = Some(raw_entry);
+                    }
+                }
+                entry::UPCASE_TABLE => upcase_entry = Some(raw_entry),
+                _ => {}
+            }
+        }
+
+        let bitmap_entry = bitmap_entry.ok_or(FileSystemError::InvalidPartition)?;
+        let upcase_entry = upcase_entry.ok_or(FileSystemError::InvalidPartition)?;
+
+        let bitmap = AllocationBitmap::new(
+            self,
+            LittleEndian::read_u32(&bitmap_entry[20..24]),
+            LittleEndian::read_u64(&bitmap_entry[24..32]),
+        )?;
+
+        let upcase_length = LittleEndian::read_u64(&upcase_entry[24..32]);
+        let upcase_chain = ClusterChain {
+            first_cluster: LittleEndian::read_u32(&upcase_entry[20..24]),
+            no_fat_chain: false,
+            cluster_count: Some(self.cluster_count_for_size(upcase_length)),
+        };
+
+        // the table is at most 128KiB, 64k characters
+        if upcase_length > 0x20000 {
+            return Err(FileSystemError::InvalidPartition);
+        }
+
+        let mut upcase_data = Vec::new();
+        upcase_data.resize(upcase_length as usize, 0);
+        self.read_chain(upcase_chain, 0, &mut upcase_data)?;
+
+        if UpcaseTable::checksum(&upcase_data) != LittleEndian::read_u32(&upcase_entry[4..8]) {
+            return Err(FileSystemError::InvalidPartition);
+        }
+
+        self.bitmap = bitmap;
+        self.upcase_table = UpcaseTable::from_raw(&upcase_data);
+        self.free_cluster
+            .store(self.bitmap.count_free(self)?, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Return the cluster chain of the root directory.
+    pub(crate) fn root_chain(&self) -> ClusterChain {
+        ClusterChain {
+            first_cluster: self.boot_record.first_cluster_of_root_directory(),
+            no_fat_chain: false,
+            cluster_count: None,
+        }
+    }
+
+    /// Get the statistics of the volume.
+    pub fn statistics(&self) -> VolumeStatistics {
+        VolumeStatistics::new(
+            FatFsType::ExFat,
+            self.boot_record.volume_serial_number(),
+            self.boot_record.cluster_size(),
+            self.boot_record.cluster_count(),
+            self.free_cluster.load(Ordering::SeqCst),
+        )
+    }
+
+    /// Get the root directory of the filesystem.
+    pub fn get_root_directory(&self) -> Directory<'_, T> {
+        let dir_info = DirectoryEntry {
+            chain: self.root_chain(),
+            raw_info: None,
+            file_size: 0,
+            valid_data_length: 0,
+            creation_timestamp: 0,
+            last_access_timestamp: 0,
+            last_modification_timestamp: 0,
+            file_name: ArrayString::<[_; DirectoryEntry::MAX_FILE_NAME_LEN_UNICODE]>::new(),
+            attribute: Attributes::new(Attributes::DIRECTORY),
+        };
+
+        Directory::from_entry(self, dir_info)
+    }
+
+    /// Helper used to open the parent directory of a path.
+    fn get_parent_directory<'a>(
+        &'a self,
+        path: &'a str,
+    ) -> FileSystemResult<(Directory<'a, T>, &'a str)> {
+        let (parent_name, file_name) = utils::get_parent(path);
+        let parent_dir = if parent_name == "" {
+            self.get_root_directory()
+        } else {
+            self.get_root_directory().open_dir(parent_name)?
+        };
+
+        Ok((parent_dir, file_name))
+    }
+
+    /// Create a new directory at the given path.
+    pub fn mkdir(&self, path: &str) -> FileSystemResult<()> {
+        let (mut parent_dir, file_name) = self.get_parent_directory(path)?;
+
+        // precheck that it doesn't exist already
+        if parent_dir.clone().find_entry(file_name).is_ok() {
+            return Err(FileSystemError::FileExists);
+        }
+
+        parent_dir.mkdir(file_name)
+    }
+
+    /// Create a new file at the given path.
+    pub fn touch(&self, path: &str) -> FileSystemResult<()> {
+        let (mut parent_dir, file_name) = self.get_parent_directory(path)?;
+
+        // precheck that it doesn't exist already
+        if parent_dir.clone().find_entry(file_name).is_ok() {
+            return Err(FileSystemError::FileExists);
+        }
+
+        parent_dir.touch(file_name)
+    }
+
+    /// Delete a directory or a file at the given path.
+    pub fn unlink(&self, path: &str, is_dir: bool) -> FileSystemResult<()> {
+        let (parent_dir, file_name) = self.get_parent_directory(path)?;
+
+        parent_dir.unlink(file_name, is_dir)
+    }
+
+    /// Rename a directory or a file at the given path to a new path.
+    pub fn rename(&self, old_path: &str, new_path: &str, is_dir: bool) -> FileSystemResult<()> {
+        let (parent_old_dir, file_name) = self.get_parent_directory(old_path)?;
+
+        let old_entry = parent_old_dir.find_entry(file_name)?;
+
+        if old_entry.attribute.is_directory() != is_dir {
+            if is_dir {
+                return Err(FileSystemError::NotADirectory);
+            } else {
+                return Err(FileSystemError::NotAFile);
+            }
+        }
+
+        // Read only files cannot be renamed
+        if !is_dir && old_entry.attribute.is_read_only() {
+            return Err(FileSystemError::AccessDenied);
+        }
+
+        let (parent_new_dir, file_name) = self.get_parent_directory(new_path)?;
+
+        // names are case insensitive, the entry can be renamed to a name differing only by its case
+        if let Ok(new_entry) = parent_new_dir.clone().find_entry(file_name) {
+            let is_same_entry = match (new_entry.raw_info, old_entry.raw_info) {
+                (Some(new_info), Some(old_info)) => {
+                    new_info.parent == old_info.parent
+                        && new_info.entry_index == old_info.entry_index
+                }
+                _ => false,
+            };
+
+            if !is_same_entry {
+                return Err(FileSystemError::FileExists);
+            }
+        }
+
+        // moving a directory inside itself would detach it from the tree
+        if is_dir && self.is_inside(old_path, new_path) {
+            return Err(FileSystemError::AccessDenied);
+        }
+
+        parent_new_dir.rename(old_entry, file_name)
+    }
+
+    /// Check if ``path`` is below the directory at ``dir_path``, comparing the names through the up-case table.
+    fn is_inside(&self, dir_path: &str, path: &str) -> bool {
+        let mut dir_names = dir_path.split('/').filter(|name| !name.is_empty());
+        let mut names = path.split('/').filter(|name| !name.is_empty());
+
+        loop {
+            match (dir_names.next(), names.next()) {
+                (None, Some(_)) => return true,
+                (Some(dir_name), Some(name)) => {
+                    let dir_name: Vec<u16> = dir_name.encode_utf16().collect();
+                    let name: Vec<u16> = name.encode_utf16().collect();
+
+                    if !self.upcase_table.name_eq(&dir_name, &name) {
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    /// Check if a cluster is part of the cluster heap.
+    pub(crate) fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster - 2 < self.boot_record.cluster_count()
+    }
+
+    /// Compute the count of clusters needed to store ``size`` bytes.
+    pub(crate) fn cluster_count_for_size(&self, size: u64) -> u32 {
+        let cluster_size = self.boot_record.cluster_size();
+        ((size + cluster_size - 1) / cluster_size) as u32
+    }
+
+    /// Compute the block index of the first block of a cluster.
+    pub(crate) fn cluster_to_block_index(&self, cluster: u32) -> BlockIndex {
+        BlockIndex(
+            self.boot_record.cluster_heap_block_index().0
+                + (cluster - 2) * self.boot_record.blocks_per_cluster(),
+        )
+    }
+
+    /// Get the next cluster of a cluster in the FAT, None if the chain ends here.
+    pub(crate) fn fat_get(&self, cluster: u32) -> FileSystemResult<Option<u32>> {
+        let mut blocks = [Block::new()];
+        let fat_offset = cluster * 4;
+
+        self.block_device
+            .read(
+                &mut blocks,
+                self.partition_start,
+                BlockIndex(self.boot_record.fat_block_index().0 + fat_offset / Block::LEN_U32),
+            )
+            .or(Err(FileSystemError::ReadFailed))?;
+
+        let offset = (fat_offset % Block::LEN_U32) as usize;
+        let value = LittleEndian::read_u32(&blocks[0][offset..offset + 4]);
+
+        if self.is_valid_cluster(value) {
+            Ok(Some(value))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Set the next cluster of a cluster in the FAT, None marking the end of the chain.
+    pub(crate) fn fat_put(&self, cluster: u32, next_cluster: Option<u32>) -> FileSystemResult<()> {
+        let mut blocks = [Block::new()];
+        let fat_offset = cluster * 4;
+        let block_index =
+            BlockIndex(self.boot_record.fat_block_index().0 + fat_offset / Block::LEN_U32);
+
+        self.block_device
+            .read(&mut blocks, self.partition_start, block_index)
+            .or(Err(FileSystemError::ReadFailed))?;
+
+        let offset = (fat_offset % Block::LEN_U32) as usize;
+        LittleEndian::write_u32(
+            &mut blocks[0][offset..offset + 4],
+            next_cluster.unwrap_or(Self::END_OF_CHAIN),
+        );
+
+        self.block_device
+            .write(&blocks, self.partition_start, block_index)
+            .or(Err(FileSystemError::WriteFailed))
+    }
+
+    /// Create an iterator over the clusters of a chain starting at the cluster at position ``start_index``.
+    pub(crate) fn cluster_iter(
+        &self,
+        chain: ClusterChain,
+        start_index: u32,
+    ) -> ClusterChainIter<'_, T> {
+        ClusterChainIter::new(self, chain, start_index)
+    }
+
+    /// Read the data of a chain at the given offset.
+    /// The caller must ensure that the chain is big enough.
+    pub(crate) fn read_chain(
+        &self,
+        chain: ClusterChain,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> FileSystemResult<()> {
+        let cluster_size = self.boot_record.cluster_size();
+        let mut cluster_iter = self.cluster_iter(chain, (offset / cluster_size) as u32);
+        let mut cluster = None;
+        let mut blocks = [Block::new()];
+        let mut read_size = 0;
+
+        while read_size < buf.len() {
+            let position = offset + read_size as u64;
+            if cluster.is_none() || position % cluster_size == 0 {
+                cluster = Some(cluster_iter.next().ok_or(FileSystemError::ReadFailed)?);
+            }
+
+            let block_index = BlockIndex(
+                self.cluster_to_block_index(cluster.unwrap()).0
+                    + ((position % cluster_size) / Block::LEN as u64) as u32,
+            );
+            let block_offset = (position % Block::LEN as u64) as usize;
+            let size = core::cmp::min(Block::LEN - block_offset, buf.len() - read_size);
+
+            self.block_device
+                .read(&mut blocks, self.partition_start, block_index)
+                .or(Err(FileSystemError::ReadFailed))?;
+
+            buf[read_size..read_size + size]
+                .copy_from_slice(&blocks[0][block_offset..block_offset + size]);
+            read_size += size;
+        }
+
+        Ok(())
+    }
+
+    /// Write data in a chain at the given offset. If ``buf`` is None, ``len`` zeros are written.
+    /// The caller must ensure that the chain is big enough.
+    pub(crate) fn write_chain(
+        &self,
+        chain: ClusterChain,
+        offset: u64,
+        buf: Option<&[u8]>,
+        len: u64,
+    ) -> FileSystemResult<()> {
+        let cluster_size = self.boot_record.cluster_size();
+        let mut cluster_iter = self.cluster_iter(chain, (offset / cluster_size) as u32);
+        let mut cluster = None;
+        let mut blocks = [Block::new()];
+        let mut write_size = 0u64;
+
+        // The length is kept as u64 and only a block is handled at a time, as zero fills can exceed usize
+        while write_size < len {
+            let position = offset + write_size;
+            if cluster.is_none() || position % cluster_size == 0 {
+                cluster = Some(cluster_iter.next().ok_or(FileSystemError::WriteFailed)?);
+            }
+
+            let block_index = BlockIndex(
+                self.cluster_to_block_index(cluster.unwrap()).0
+                    + ((position % cluster_size) / Block::LEN as u64) as u32,
+            );
+            let block_offset = (position % Block::LEN as u64) as usize;
+            let size =
+                core::cmp::min((Block::LEN - block_offset) as u64, len - write_size) as usize;
+
+            // partial block write, we need the old content
+            if size != Block::LEN {
+                self.block_device
+                    .read(&mut blocks, self.partition_start, block_index)
+                    .or(Err(FileSystemError::ReadFailed))?;
+            }
+
+            let block_slice = &mut blocks[0][block_offset..block_offset + size];
+            match buf {
+                Some(buf) => {
+                    let buf_offset = write_size as usize;
+                    block_slice.copy_from_slice(&buf[buf_offset..buf_offset + size]);
+                }
+                None => block_slice.copy_from_slice(&[0; Block::LEN][..size]),
+            }
+
+            self.block_device
+                .write(&blocks, self.partition_start, block_index)
+                .or(Err(FileSystemError::WriteFailed))?;
+            write_size += size as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Get the cluster at the given position in a chain.
+    pub(crate) fn nth_cluster(&self, chain: ClusterChain, index: u32) -> Option<u32> {
+        self.cluster_iter(chain, index).next()
+    }
+
+    /// Clean cluster data.
+    /// Used when creating a new directory.
+    pub(crate) fn clean_cluster_data(&self, cluster: u32) -> FileSystemResult<()> {
+        let blocks = [Block::new()];
+        let block_start = self.cluster_to_block_index(cluster);
+
+        for block_index in 0..self.boot_record.blocks_per_cluster() {
+            self.block_device
+                .write(
+                    &blocks,
+                    self.partition_start,
+                    BlockIndex(block_start.0 + block_index),
+                )
+                .or(Err(FileSystemError::WriteFailed))?;
+        }
+
+        Ok(())
+    }
+
+    /// Allocate a cluster, trying to use the one following ``previous_cluster`` if specified.
+    pub(crate) fn alloc_cluster(&self, previous_cluster: Option<u32>) -> FileSystemResult<u32> {
+        if self.free_cluster.load(Ordering::SeqCst) == 0 {
+            return Err(FileSystemError::NoSpaceLeft);
+        }
+
+        let start_cluster = match previous_cluster {
+            Some(previous_cluster) => previous_cluster + 1,
+            None => self.last_cluster.load(Ordering::SeqCst),
+        };
+
+        let cluster = self
+            .bitmap
+            .find_free(self, start_cluster)?
+            .ok_or(FileSystemError::NoSpaceLeft)?;
+
+        self.bitmap.set_used(self, cluster, true)?;
+        self.last_cluster.store(cluster, Ordering::SeqCst);
+        self.free_cluster.fetch_sub(1, Ordering::SeqCst);
+
+        Ok(cluster)
+    }
+
+    /// Free a cluster.
+    pub(crate) fn free_cluster(&self, cluster: u32) -> FileSystemResult<()> {
+        self.bitmap.set_used(self, cluster, false)?;
+        self.free_cluster.fetch_add(1, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Add ``count`` clusters at the end of a chain.
+    ///
+    /// The chain is kept contiguous without using the FAT as long as possible.
+    /// On error, the chain reflects what was allocated so far.
+    pub(crate) fn extend_chain(
+        &self,
+        chain: &mut ClusterChain,
+        count: u32,
+    ) -> FileSystemResult<()> {
+        let mut current_count = match chain.cluster_count {
+            Some(cluster_count) => cluster_count,
+            None => self.cluster_iter(*chain, 0).count() as u32,
+        };
+
+        let mut last_cluster = if current_count == 0 {
+            None
+        } else {
+            Some(
+                self.nth_cluster(*chain, current_count - 1)
+                    .ok_or(FileSystemError::NotFound)?,
+            )
+        };
+
+        for _ in 0..count {
+            let new_cluster = match last_cluster {
+                None => {
+                    let new_cluster = self.alloc_cluster(None)?;
+                    chain.first_cluster = new_cluster;
+                    chain.no_fat_chain = true;
+                    new_cluster
+                }
+                Some(last_cluster) if chain.no_fat_chain => {
+                    let next_cluster = last_cluster + 1;
+                    if self.is_valid_cluster(next_cluster)
+                        && !self.bitmap.is_used(self, next_cluster)?
+                    {
+                        self.alloc_cluster(Some(last_cluster))?
+                    } else {
+                        let new_cluster = self.alloc_cluster(Some(last_cluster))?;
+
+                        // The chain isn't contiguous anymore, describe it in the FAT.
+                        self.fat_put(new_cluster, None)?;
+                        for index in 0..current_count {
+                            let cluster = chain.first_cluster + index;
+                            let next_cluster = if index + 1 == current_count {
+                                new_cluster
+                            } else {
+                                cluster + 1
+                            };
+                            self.fat_put(cluster, Some(next_cluster))?;
+                        }
+
+                        chain.no_fat_chain = false;
+                        new_cluster
+                    }
+                }
+                Some(last_cluster) => {
+                    let new_cluster = self.alloc_cluster(Some(last_cluster))?;
+                    self.fat_put(new_cluster, None)?;
+                    self.fat_put(last_cluster, Some(new_cluster))?;
+                    new_cluster
+                }
+            };
+
+            last_cluster = Some(new_cluster);
+            current_count += 1;
+            if chain.cluster_count.is_some() {
+                chain.cluster_count = Some(current_count);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Free every cluster of a chain after the first ``count`` clusters.
+    pub(crate) fn truncate_chain(
+        &self,
+        chain: &mut ClusterChain,
+        count: u32,
+    ) -> FileSystemResult<()> {
+        let mut last_kept_cluster = None;
+
+        for (index, cluster) in self.cluster_iter(*chain, 0).enumerate() {
+            if (index as u32) < count {
+                last_kept_cluster = Some(cluster);
+            } else {
+                self.free_cluster(cluster)?;
+            }
+        }
+
+        if count == 0 {
+            chain.first_cluster = 0;
+            chain.no_fat_chain = false;
+        } else if !chain.no_fat_chain {
+            if let Some(last_kept_cluster) = last_kept_cluster {
+                self.fat_put(last_kept_cluster, None)?;
+            }
+        }
+
+        if chain.cluster_count.is_some() {
+            chain.cluster_count = Some(count);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+//! exFAT filesystem.
+//!
+//! exFAT shares the FAT and the cluster heap layout with FAT32, but everything else differs:
+//! free clusters are tracked in an allocation bitmap, names are compared through an up-case table
+//! stored on the volume, files are described by entry sets and contiguous files may not use the FAT at all.
+
+use byteorder::{ByteOrder, LittleEndian};
+use libfs::block::{Block, BlockCount, BlockDevice, BlockIndex};
+use libfs::FileSystemError;
+
+pub(crate) mod bitmap;
+pub(crate) mod chain;
+pub mod directory;
+pub(crate) mod entry;
+pub mod filesystem;
+pub(crate) mod upcase;
+
+use crate::partition::PartitionInfo;
+use crate::FatFsType;
+use filesystem::ExFatFileSystem;
+
+/// Represent the exFAT Main Boot Sector.
+pub(crate) struct ExFatBootRecord {
+    /// The actual data of the boot sector.
+    data: Block,
+}
+
+#[allow(dead_code)]
+impl ExFatBootRecord {
+    /// The file system name of exFAT volumes.
+    pub const FILE_SYSTEM_NAME: &'static [u8] = b"EXFAT   ";
+
+    /// The count of sectors covered by the boot checksum.
+    const BOOT_CHECKSUM_SECTORS: u32 = 11;
+
+    /// Create a new exFAT boot record from raw data.
+    pub fn new(data: Block) -> ExFatBootRecord {
+        ExFatBootRecord { data }
+    }
+
+    /// Checks the validity of the boot record.
+    pub fn is_valid(&self) -> bool {
+        /// Offset of the boot signature.
+        const BOOTABLE_SIGNATURE: usize = 510;
+
+        // check boot signature
+        if LittleEndian::read_u16(&self.data[BOOTABLE_SIGNATURE..BOOTABLE_SIGNATURE + 2]) != 0xAA55
+        {
+            return false;
+        }
+
+        // check jump code and file system name
+        if self.data[0..3] != [0xEB, 0x76, 0x90] || &self.data[3..11] != Self::FILE_SYSTEM_NAME {
+            return false;
+        }
+
+        // this range overlaps the FAT BIOS parameter block and must be zero
+        if self.data[11..64].iter().any(|value| *value != 0) {
+            return false;
+        }
+
+        if self.bytes_per_sector_shift() < 9 || self.bytes_per_sector_shift() > 12 {
+            return false;
+        }
+
+        // clusters are limited to 32MiB
+        if u32::from(self.bytes_per_sector_shift()) + u32::from(self.sectors_per_cluster_shift())
+            > 25
+        {
+            return false;
+        }
+
+        if self.fats_count() != 1 && self.fats_count() != 2 {
+            return false;
+        }
+
+        self.cluster_count() != 0 && self.first_cluster_of_root_directory() >= 2
+    }
+
+    /// The sector offset of the volume on the media. Zero if unused.
+    pub fn partition_offset(&self) -> u64 {
+        LittleEndian::read_u64(&self.data[64..72])
+    }
+
+    /// The size of the volume in sectors.
+    pub fn volume_length(&self) -> u64 {
+        LittleEndian::read_u64(&self.data[72..80])
+    }
+
+    /// The sector offset of the first FAT.
+    pub fn fat_offset(&self) -> u32 {
+        LittleEndian::read_u32(&self.data[80..84])
+    }
+
+    /// The size of a FAT in sectors.
+    pub fn fat_length(&self) -> u32 {
+        LittleEndian::read_u32(&self.data[84..88])
+    }
+
+    /// The sector offset of the cluster heap.
+    pub fn cluster_heap_offset(&self) -> u32 {
+        LittleEndian::read_u32(&self.data[88..92])
+    }
+
+    /// The count of clusters in the cluster heap.
+    pub fn cluster_count(&self) -> u32 {
+        LittleEndian::read_u32(&self.data[92..96])
+    }
+
+    /// The first cluster of the root directory.
+    pub fn first_cluster_of_root_directory(&self) -> u32 {
+        LittleEndian::read_u32(&self.data[96..100])
+    }
+
+    /// The serial number of the volume.
+    pub fn volume_serial_number(&self) -> u32 {
+        LittleEndian::read_u32(&self.data[100..104])
+    }
+
+    /// The revision of the filesystem (major in the high byte).
+    pub fn file_system_revision(&self) -> u16 {
+        LittleEndian::read_u16(&self.data[104..106])
+    }
+
+    /// The volume flags.
+    pub fn volume_flags(&self) -> u16 {
+        LittleEndian::read_u16(&self.data[106..108])
+    }
+
+    /// The index of the active FAT and allocation bitmap.
+    pub fn active_fat(&self) -> u8 {
+        (self.volume_flags() & 0x1) as u8
+    }
+
+    /// The size of a sector as a power of two.
+    pub fn bytes_per_sector_shift(&self) -> u8 {
+        self.data[108]
+    }
+
+    /// The size of a cluster in sectors as a power of two.
+    pub fn sectors_per_cluster_shift(&self) -> u8 {
+        self.data[109]
+    }
+
+    /// The number of FAT present in the filesystem.
+    pub fn fats_count(&self) -> u8 {
+        self.data[110]
+    }
+
+    /// The amount of blocks per sector.
+    pub fn blocks_per_sector(&self) -> u32 {
+        1 << (u32::from(self.bytes_per_sector_shift()) - 9)
+    }
+
+    /// The amount of blocks per cluster.
+    pub fn blocks_per_cluster(&self) -> u32 {
+        self.blocks_per_sector() << u32::from(self.sectors_per_cluster_shift())
+    }
+
+    /// The size of a cluster in bytes.
+    pub fn cluster_size(&self) -> u64 {
+        u64::from(self.blocks_per_cluster()) * Block::LEN as u64
+    }
+
+    /// The block index of the active FAT.
+    pub fn fat_block_index(&self) -> BlockIndex {
+        BlockIndex(
+            (self.fat_offset() + u32::from(self.active_fat()) * self.fat_length())
+                * self.blocks_per_sector(),
+        )
+    }
+
+    /// The block index of the cluster heap.
+    pub fn cluster_heap_block_index(&self) -> BlockIndex {
+        BlockIndex(self.cluster_heap_offset() * self.blocks_per_sector())
+    }
+
+    /// Compute the checksum of the boot region.
+    ///
+    /// Volume flags and percent in use are skipped as they change during the volume life.
+    pub fn compute_boot_checksum<T>(
+        block_device: &T,
+        partition_start: BlockIndex,
+        blocks_per_sector: u32,
+    ) -> Result<u32, FileSystemError>
+    where
+        T: BlockDevice,
+    {
+        let mut blocks = [Block::new()];
+        let mut checksum = 0u32;
+
+        for block_index in 0..Self::BOOT_CHECKSUM_SECTORS * blocks_per_sector {
+            block_device
+                .read(&mut blocks, partition_start, BlockIndex(block_index))
+                .or(Err(FileSystemError::ReadFailed))?;
+
+            for (index, value) in blocks[0].iter().enumerate() {
+                if block_index == 0 && (index == 106 || index == 107 || index == 112) {
+                    continue;
+                }
+
+                checksum = (checksum << 31)
+                    .wrapping_add(checksum >> 1)
+                    .wrapping_add(u32::from(*value));
+            }
+        }
+
+        Ok(checksum)
+    }
+}
+
+/// Parse an exFAT boot record and return an ExFatFileSystem instance.
+fn parse_exfat_boot_record<T>(
+    block_device: T,
+    partition_start: BlockIndex,
+    partition_block_count: BlockCount,
+) -> Result<ExFatFileSystem<T>, FileSystemError>
+where
+    T: BlockDevice,
+{
+    let mut blocks = [Block::new()];
+
+    block_device
+        .read(&mut blocks, partition_start, BlockIndex(0))
+        .or(Err(FileSystemError::ReadFailed))?;
+
+    let boot_record = ExFatBootRecord::new(blocks[0].clone());
+
+    if !boot_record.is_valid() {
+        return Err(FileSystemError::InvalidPartition);
+    }
+
+    let checksum = ExFatBootRecord::compute_boot_checksum(
+        &block_device,
+        partition_start,
+        boot_record.blocks_per_sector(),
+    )?;
+
+    block_device
+        .read(
+            &mut blocks,
+            partition_start,
+            BlockIndex(ExFatBootRecord::BOOT_CHECKSUM_SECTORS * boot_record.blocks_per_sector()),
+        )
+        .or(Err(FileSystemError::ReadFailed))?;
+
+    if LittleEndian::read_u32(&blocks[0][0..4]) != checksum {
+        return Err(FileSystemError::InvalidPartition);
+    }
+
+    let mut file_system = ExFatFileSystem::new(
+        block_device,
+        partition_start,
+        partition_block_count,
+        boot_record,
+    );
+    file_system.init()?;
+    Ok(file_system)
+}
+
+/// Treat the block device directly as an exFAT filesystem.
+pub fn get_raw_partition<T>(block_device: T) -> Result<ExFatFileSystem<T>, FileSystemError>
+where
+    T: BlockDevice,
+{
+    parse_exfat_boot_record(block_device, BlockIndex(0), BlockCount(0))
+}
+
+/// Return an instance to the exFAT filesystem of a partition returned by ``partition::list_partitions``.
+pub fn open_partition<T>(
+    block_device: T,
+    partition: &PartitionInfo,
+) -> Result<ExFatFileSystem<T>, FileSystemError>
+where
+    T: BlockDevice,
+{
+    if partition.filesystem != Some(FatFsType::ExFat) {
+        return Err(FileSystemError::InvalidPartition);
+    }
+
+    parse_exfat_boot_record(block_device, partition.start, partition.block_count)
+}
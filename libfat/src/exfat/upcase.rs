@@ -0,0 +1,87 @@
+//! exFAT up-case table.
+
+use alloc::vec::Vec;
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Represent the up-case table used to compare and hash file names.
+pub struct UpcaseTable {
+    /// The up-cased value of every character covered by the table.
+    table: Vec<u16>,
+}
+
+impl UpcaseTable {
+    /// Marks a range of identity mapped characters in the compressed table.
+    const IDENTITY_MARKER: u16 = 0xFFFF;
+
+    /// Create a table that only up-cases ASCII characters.
+    pub fn ascii() -> Self {
+        UpcaseTable { table: Vec::new() }
+    }
+
+    /// Import a table from its on-disk representation, expanding compressed ranges.
+    pub fn from_raw(data: &[u8]) -> Self {
+        let mut table = Vec::new();
+        let mut values = data.chunks_exact(2).map(LittleEndian::read_u16);
+
+        while let Some(value) = values.next() {
+            if value == Self::IDENTITY_MARKER {
+                if let Some(count) = values.next() {
+                    for _ in 0..count {
+                        let character = table.len() as u16;
+                        table.push(character);
+                    }
+                    continue;
+                }
+            }
+
+            table.push(value);
+        }
+
+        UpcaseTable { table }
+    }
+
+    /// Compute the checksum of the on-disk representation of a table.
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut checksum = 0u32;
+        for value in data {
+            checksum = (checksum << 31)
+                .wrapping_add(checksum >> 1)
+                .wrapping_add(u32::from(*value));
+        }
+        checksum
+    }
+
+    /// Up-case a UTF-16 character.
+    pub fn to_upper(&self, character: u16) -> u16 {
+        match self.table.get(usize::from(character)) {
+            Some(value) => *value,
+            None if character >= u16::from(b'a') && character <= u16::from(b'z') => {
+                character - 0x20
+            }
+            None => character,
+        }
+    }
+
+    /// Compute the hash of a name as stored in the Stream Extension entry.
+    pub fn name_hash(&self, name: &[u16]) -> u16 {
+        let mut hash = 0u16;
+        for character in name {
+            let character = self.to_upper(*character);
+            for value in &[character as u8, (character >> 8) as u8] {
+                hash = (hash << 15)
+                    .wrapping_add(hash >> 1)
+                    .wrapping_add(u16::from(*value));
+            }
+        }
+        hash
+    }
+
+    /// Compare two names without case sensitivity.
+    pub fn name_eq(&self, left: &[u16], right: &[u16]) -> bool {
+        left.len() == right.len()
+            && left
+                .iter()
+                .zip(right.iter())
+                .all(|(left, right)| self.to_upper(*left) == self.to_upper(*right))
+    }
+}
@@ -0,0 +1,203 @@
+//! Extent map of a file cluster chain.
+
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::iter;
+
+use crate::cluster::Cluster;
+use crate::filesystem::FatFileSystem;
+use crate::table::FatValue;
+
+use libfs::block::{BlockDevice, BlockIndex};
+use libfs::FileSystemResult;
+
+/// Represent a run of contiguous clusters of a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extent {
+    /// The index of the first cluster of the run in the file.
+    pub file_cluster: u32,
+
+    /// The first cluster of the run on the volume.
+    pub start_cluster: u32,
+
+    /// The count of clusters in the run.
+    pub cluster_count: u32,
+}
+
+impl Extent {
+    /// Compare the run with the cluster of index ``file_cluster`` in the file.
+    fn cmp_file_cluster(&self, file_cluster: u32) -> Ordering {
+        if file_cluster < self.file_cluster {
+            Ordering::Greater
+        } else if file_cluster - self.file_cluster >= self.cluster_count {
+            Ordering::Less
+        } else {
+            Ordering::Equal
+        }
+    }
+}
+
+/// A cache of the cluster chain of a file stored as a list of contiguous runs.
+///
+/// Locating the cluster holding a given offset is a binary search instead of a walk of the chain in the FAT.
+/// The map only follows changes made through the entry it is used with.
+#[derive(Debug, Clone, Default)]
+pub struct ExtentMap {
+    /// The runs of the chain, sorted by position in the file.
+    extents: Vec<Extent>,
+}
+
+impl ExtentMap {
+    /// Create an empty map, filled on first use.
+    pub fn new() -> ExtentMap {
+        ExtentMap::default()
+    }
+
+    /// Get the runs of the chain, sorted by position in the file.
+    pub fn extents(&self) -> &[Extent] {
+        &self.extents
+    }
+
+    /// Get the count of clusters mapped.
+    pub fn cluster_count(&self) -> u32 {
+        self.extents
+            .last()
+            .map(|extent| extent.file_cluster + extent.cluster_count)
+            .unwrap_or(0)
+    }
+
+    /// Forget every run, forcing the chain to be read again on next use.
+    pub fn clear(&mut self) {
+        self.extents.clear();
+    }
+
+    /// Make the map cover the first ``cluster_count`` clusters of the chain starting at ``start_cluster``.
+    ///
+    /// Only the clusters not mapped yet are read from the FAT. The map stops early if the chain is shorter.
+    pub(crate) fn sync<T>(
+        &mut self,
+        fs: &FatFileSystem<T>,
+        start_cluster: Cluster,
+        cluster_count: u32,
+    ) -> FileSystemResult<()>
+    where
+        T: BlockDevice,
+    {
+        let is_same_chain = self
+            .extents
+            .first()
+            .map(|extent| extent.start_cluster == start_cluster.0)
+            .unwrap_or(false);
+
+        if !is_same_chain {
+            self.extents.clear();
+        }
+
+        self.truncate(cluster_count);
+
+        let mut mapped_count = self.cluster_count();
+        if mapped_count == cluster_count {
+            return Ok(());
+        }
+
+        let mut cluster = match self.extents.last() {
+            Some(extent) => {
+                let last_cluster = Cluster(extent.start_cluster + extent.cluster_count - 1);
+                match FatValue::get(fs, last_cluster)? {
+                    FatValue::Data(next_cluster) => Cluster(next_cluster),
+                    _ => return Ok(()),
+                }
+            }
+            None => start_cluster,
+        };
+
+        loop {
+            self.push(cluster);
+            mapped_count += 1;
+
+            if mapped_count == cluster_count {
+                return Ok(());
+            }
+
+            cluster = match FatValue::get(fs, cluster)? {
+                FatValue::Data(next_cluster) => Cluster(next_cluster),
+                _ => return Ok(()),
+            };
+        }
+    }
+
+    /// Append a cluster to the end of the map.
+    fn push(&mut self, cluster: Cluster) {
+        let file_cluster = self.cluster_count();
+
+        if let Some(extent) = self.extents.last_mut() {
+            if extent.start_cluster + extent.cluster_count == cluster.0 {
+                extent.cluster_count += 1;
+                return;
+            }
+        }
+
+        self.extents.push(Extent {
+            file_cluster,
+            start_cluster: cluster.0,
+            cluster_count: 1,
+        });
+    }
+
+    /// Drop the clusters past the first ``cluster_count`` ones.
+    fn truncate(&mut self, cluster_count: u32) {
+        while let Some(extent) = self.extents.last_mut() {
+            if extent.file_cluster >= cluster_count {
+                self.extents.pop();
+            } else {
+                extent.cluster_count = extent
+                    .cluster_count
+                    .min(cluster_count - extent.file_cluster);
+                break;
+            }
+        }
+    }
+
+    /// Get the index of the run holding the cluster of index ``file_cluster`` in the file.
+    fn find_index(&self, file_cluster: u32) -> Option<usize> {
+        self.extents
+            .binary_search_by(|extent| extent.cmp_file_cluster(file_cluster))
+            .ok()
+    }
+
+    /// Get the run holding the cluster of index ``file_cluster`` in the file.
+    pub fn find(&self, file_cluster: u32) -> Option<&Extent> {
+        self.find_index(file_cluster)
+            .map(|index| &self.extents[index])
+    }
+
+    /// Iterate over the clusters of the file starting at the one of index ``file_cluster``.
+    pub(crate) fn clusters_from(&self, file_cluster: u32) -> impl Iterator<Item = Cluster> + '_ {
+        let index = self.find_index(file_cluster).unwrap_or(self.extents.len());
+
+        self.extents[index..]
+            .iter()
+            .enumerate()
+            .flat_map(move |(i, extent)| {
+                let skipped_count = if i == 0 {
+                    file_cluster - extent.file_cluster
+                } else {
+                    0
+                };
+
+                (extent.start_cluster + skipped_count..extent.start_cluster + extent.cluster_count)
+                    .map(Cluster)
+            })
+    }
+
+    /// Iterate over the cluster of every block of the file starting at the block of index ``block_index``.
+    pub(crate) fn block_clusters_from(
+        &self,
+        blocks_per_cluster: u32,
+        block_index: BlockIndex,
+    ) -> impl Iterator<Item = Cluster> + '_ {
+        self.clusters_from(block_index.0 / blocks_per_cluster)
+            .flat_map(move |cluster| iter::repeat(cluster).take(blocks_per_cluster as usize))
+            .skip((block_index.0 % blocks_per_cluster) as usize)
+    }
+}
@@ -1,38 +1,46 @@
 //! FAT Filesystem.
 
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
 use arrayvec::ArrayString;
 use byteorder::{ByteOrder, LittleEndian};
 
 use super::attribute::Attributes;
 use super::block_iter::BlockIndexClusterIter;
-use super::directory::{dir_entry::DirectoryEntry, Directory};
+use super::directory::{dir_entry::DirectoryEntry, raw_dir_entry::FatDirEntry, Directory};
+use super::format;
+use super::free_bitmap::FreeClusterBitmap;
+use super::name::ShortFileName;
 use super::FatVolumeBootRecord;
 
 use super::cluster::Cluster;
+use super::datetime::{FatDateTime, TimeProvider};
 use super::table;
 use super::table::FatValue;
 use super::utils;
-use super::FatFsType;
+use super::{FatFsType, VolumeStatistics};
 use libfs::block::{Block, BlockCount, BlockDevice, BlockIndex};
 use libfs::FileSystemError;
 use libfs::FileSystemResult;
 
+use core::sync::atomic::AtomicBool;
 use core::sync::atomic::AtomicU32;
 use core::sync::atomic::Ordering;
 
 /// Reprsent the FS Info structure of FAT32.
-struct FatFileSystemInfo {
+pub(crate) struct FatFileSystemInfo {
     // TODO: select Ordering wisely on operations.
     /// The last allocated cluster on the filesystem.
-    last_cluster: AtomicU32,
+    pub(crate) last_cluster: AtomicU32,
 
     /// The free cluster count on the filesystem.
-    free_cluster: AtomicU32,
+    pub(crate) free_cluster: AtomicU32,
 }
 
 impl FatFileSystemInfo {
     /// Import FS Info from a FAT32 filesystem.
-    fn from_fs<T>(fs: &FatFileSystem<T>) -> FileSystemResult<Self>
+    pub(crate) fn from_fs<T>(fs: &FatFileSystem<T>) -> FileSystemResult<Self>
     where
         T: BlockDevice,
     {
@@ -45,7 +53,7 @@ impl FatFileSystemInfo {
             .read(
                 &mut blocks,
                 fs.partition_start,
-                BlockIndex(u32::from(fs.boot_record.fs_info_block())),
+                BlockIndex(fs.boot_record.fs_info_block()),
             )
             .or(Err(FileSystemError::ReadFailed))?;
 
@@ -101,7 +109,7 @@ impl FatFileSystemInfo {
             .write(
                 &blocks,
                 fs.partition_start,
-                BlockIndex(u32::from(fs.boot_record.fs_info_block())),
+                BlockIndex(fs.boot_record.fs_info_block()),
             )
             .or(Err(FileSystemError::ReadFailed))?;
 
@@ -109,6 +117,87 @@ impl FatFileSystemInfo {
     }
 }
 
+/// Represent the volume state bits stored in the second FAT entry of FAT16 and FAT32 filesystems.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VolumeState {
+    /// Set if the volume was cleanly unmounted.
+    pub clean_shutdown: bool,
+
+    /// Set if a disk I/O error was encountered on the volume.
+    pub hard_error: bool,
+}
+
+impl VolumeState {
+    /// Get the masks of the clean shutdown and no hard error bits of the second FAT entry.
+    ///
+    /// Return None on FAT12 as it doesn't have any volume state bits.
+    fn masks(fat_type: FatFsType) -> Option<(u32, u32)> {
+        match fat_type {
+            FatFsType::Fat16 => Some((0x8000, 0x4000)),
+            FatFsType::Fat32 => Some((0x0800_0000, 0x0400_0000)),
+            FatFsType::Fat12 | FatFsType::ExFat => None,
+        }
+    }
+}
+
+/// State of the search of a run of free clusters, fed with every cluster of the volume in order.
+struct FreeRunSearch {
+    /// The count of clusters of the run.
+    count: u32,
+
+    /// The cluster the run must start at, if any.
+    required_start: Option<Cluster>,
+
+    /// The first cluster of the current run of free clusters.
+    run_start: Cluster,
+
+    /// The length of the current run of free clusters.
+    run_len: u32,
+
+    /// The first run of free clusters large enough.
+    result: Option<Cluster>,
+}
+
+impl FreeRunSearch {
+    /// Start the search of a run of ``count`` clusters, starting at ``required_start`` if specified.
+    fn new(count: u32, required_start: Option<Cluster>) -> Self {
+        FreeRunSearch {
+            count,
+            required_start,
+            run_start: Cluster(0),
+            run_len: 0,
+            result: None,
+        }
+    }
+
+    /// Account for the state of the given cluster. Return true once the following clusters can't change the result.
+    fn visit(&mut self, cluster: Cluster, is_free: bool) -> bool {
+        if let Some(required_start) = self.required_start {
+            if cluster.0 < required_start.0 {
+                return false;
+            }
+        }
+
+        if !is_free {
+            self.run_len = 0;
+
+            // The run can't start at the required cluster anymore
+            return self.required_start.is_some();
+        }
+
+        if self.run_len == 0 {
+            self.run_start = cluster;
+        }
+
+        self.run_len += 1;
+        if self.run_len == self.count {
+            self.result = Some(self.run_start);
+        }
+
+        self.result.is_some()
+    }
+}
+
 /// Represent a FAT filesystem.
 #[allow(dead_code)]
 pub struct FatFileSystem<T> {
@@ -130,6 +219,23 @@ pub struct FatFileSystem<T> {
 
     /// The extra infos of the filesystem.
     fat_info: FatFileSystemInfo,
+
+    /// The volume state read at mount time.
+    mount_state: VolumeState,
+
+    /// Set if the dirty state is stored on disk.
+    is_dirty: AtomicBool,
+
+    /// Set if the clean shutdown bit can be restored on flush.
+    /// Volumes that weren't cleanly unmounted stay dirty until they are repaired.
+    can_mark_clean: AtomicBool,
+
+    /// The source of the time used to stamp directory entries.
+    /// Timestamps are left untouched when there is none.
+    time_provider: Option<Box<dyn TimeProvider>>,
+
+    /// The in-memory map of the free clusters, if enabled.
+    pub(crate) free_bitmap: Option<FreeClusterBitmap>,
 }
 
 impl<T> FatFileSystem<T>
@@ -155,6 +261,14 @@ where
                 last_cluster: AtomicU32::new(0xFFFF_FFFF),
                 free_cluster: AtomicU32::new(0xFFFF_FFFF),
             },
+            mount_state: VolumeState {
+                clean_shutdown: true,
+                hard_error: false,
+            },
+            is_dirty: AtomicBool::new(false),
+            can_mark_clean: AtomicBool::new(true),
+            time_provider: None,
+            free_bitmap: None,
         }
     }
 
@@ -171,9 +285,149 @@ where
                 .store(table::get_free_cluster_count(self)?, Ordering::SeqCst);
         }
 
+        // read the volume state
+        if let Some((clean_mask, no_error_mask)) = VolumeState::masks(self.boot_record.fat_type) {
+            let value = table::get_volume_state_entry(self)?;
+
+            self.mount_state = VolumeState {
+                clean_shutdown: (value & clean_mask) != 0,
+                hard_error: (value & no_error_mask) == 0,
+            };
+
+            let clean_shutdown = self.mount_state.clean_shutdown;
+            self.is_dirty.store(!clean_shutdown, Ordering::SeqCst);
+            self.can_mark_clean.store(clean_shutdown, Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+
+    /// Set the source of the time used to stamp directory entries.
+    /// Timestamps are left untouched until this is called.
+    pub fn set_time_provider(&mut self, time_provider: Box<dyn TimeProvider>) {
+        self.time_provider = Some(time_provider);
+    }
+
+    /// Keep an in-memory map of the free clusters, built by scanning the FAT.
+    ///
+    /// Allocations then find free clusters without reading the FAT, at the cost of one bit of memory per cluster.
+    /// The free cluster count is also recomputed from the map.
+    pub fn enable_free_cluster_bitmap(&mut self) -> FileSystemResult<()> {
+        let free_bitmap = FreeClusterBitmap::from_fs(self)?;
+
+        self.fat_info
+            .free_cluster
+            .store(free_bitmap.free_count(), Ordering::SeqCst);
+        self.free_bitmap = Some(free_bitmap);
+
+        Ok(())
+    }
+
+    /// Check if the free cluster map is enabled.
+    pub fn has_free_cluster_bitmap(&self) -> bool {
+        self.free_bitmap.is_some()
+    }
+
+    /// Get the current time from the time provider, if any.
+    pub(crate) fn now(&self) -> Option<FatDateTime> {
+        self.time_provider
+            .as_ref()
+            .map(|time_provider| time_provider.now())
+    }
+
+    /// Get the volume state as it was when the filesystem was mounted.
+    pub fn mount_state(&self) -> VolumeState {
+        self.mount_state
+    }
+
+    /// Return true if the volume is currently marked as dirty.
+    pub fn is_dirty(&self) -> bool {
+        self.is_dirty.load(Ordering::SeqCst)
+    }
+
+    /// Mark the volume as dirty before its first write.
+    pub(crate) fn mark_dirty(&self) -> FileSystemResult<()> {
+        if self.is_dirty.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        if let Some((clean_mask, _)) = VolumeState::masks(self.boot_record.fat_type) {
+            let value = table::get_volume_state_entry(self)?;
+            if let Err(error) = table::put_volume_state_entry(self, value & !clean_mask) {
+                self.is_dirty.store(false, Ordering::SeqCst);
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Allow the clean shutdown bit to be restored on the next flush.
+    /// Used once a volume that wasn't cleanly unmounted got repaired.
+    pub(crate) fn allow_mark_clean(&self) {
+        self.can_mark_clean.store(true, Ordering::SeqCst);
+    }
+
+    /// Flush the FS Info structure, leaving the volume state untouched.
+    ///
+    /// Unlike ``flush``, this is safe to call while other files are being updated.
+    pub fn flush_fs_info(&self) -> FileSystemResult<()> {
+        if !self.is_dirty.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        self.fat_info.flush(self)
+    }
+
+    /// Flush the FS Info structure and mark the volume as cleanly unmounted.
+    ///
+    /// This must be called before dropping the filesystem. The next write marks the volume as dirty again.
+    pub fn flush(&self) -> FileSystemResult<()> {
+        if !self.is_dirty.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        self.fat_info.flush(self)?;
+
+        if !self.can_mark_clean.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        if let Some((clean_mask, _)) = VolumeState::masks(self.boot_record.fat_type) {
+            let value = table::get_volume_state_entry(self)?;
+            table::put_volume_state_entry(self, value | clean_mask)?;
+        }
+
+        self.is_dirty.store(false, Ordering::SeqCst);
+
         Ok(())
     }
 
+    /// Recount the free clusters and write the count to the FS Info structure.
+    ///
+    /// Return the free cluster count.
+    pub(crate) fn rebuild_fs_info(&self) -> FileSystemResult<u32> {
+        let free_cluster_count = table::get_free_cluster_count(self)?;
+
+        self.fat_info
+            .free_cluster
+            .store(free_cluster_count, Ordering::SeqCst);
+        self.fat_info.flush(self)?;
+
+        Ok(free_cluster_count)
+    }
+
+    /// Get the statistics of the volume.
+    pub fn statistics(&self) -> VolumeStatistics {
+        VolumeStatistics::new(
+            self.boot_record.fat_type,
+            self.boot_record.volume_id().unwrap_or(0),
+            u64::from(self.boot_record.cluster_size()),
+            self.boot_record.cluster_count - 2,
+            self.fat_info.free_cluster.load(Ordering::SeqCst),
+        )
+    }
+
     /// Get the root directory of the filesystem.
     pub fn get_root_directory(&self) -> Directory<'_, T> {
         let dir_info = DirectoryEntry {
@@ -190,6 +444,124 @@ where
         Directory::from_entry(self, dir_info)
     }
 
+    /// Find the volume label entry of the root directory.
+    fn find_volume_label_entry(&self) -> FileSystemResult<Option<FatDirEntry>> {
+        for raw_dir_entry in self.get_root_directory().fat_dir_entry_iter() {
+            let raw_dir_entry = raw_dir_entry?;
+
+            if raw_dir_entry.is_free() {
+                break;
+            }
+
+            if !raw_dir_entry.is_deleted()
+                && !raw_dir_entry.is_long_file_name()
+                && raw_dir_entry.attribute().is_volume()
+            {
+                return Ok(Some(raw_dir_entry));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Read the boot record and, on FAT32, its backup from the disk.
+    fn read_boot_records(&self) -> FileSystemResult<Vec<(BlockIndex, FatVolumeBootRecord)>> {
+        let mut res = Vec::new();
+        let backup_block_index =
+            u32::from(self.boot_record.backup_boot_sector()) * self.boot_record.blocks_per_sector();
+
+        for block_index in &[0, backup_block_index] {
+            // The backup is optional
+            if !res.is_empty() && *block_index == 0 {
+                continue;
+            }
+
+            let mut blocks = [Block::new()];
+            self.block_device
+                .read(&mut blocks, self.partition_start, BlockIndex(*block_index))
+                .or(Err(FileSystemError::ReadFailed))?;
+
+            res.push((
+                BlockIndex(*block_index),
+                FatVolumeBootRecord::new(blocks[0].clone()),
+            ));
+        }
+
+        Ok(res)
+    }
+
+    /// Get the label of the volume, or None if it doesn't have any.
+    ///
+    /// The label of the root directory is used if present, the one of the boot record otherwise.
+    pub fn volume_label(&self) -> FileSystemResult<Option<String>> {
+        let raw_label = match self.find_volume_label_entry()? {
+            Some(raw_dir_entry) => Some(raw_dir_entry.as_sfn_entry().name),
+            None => self
+                .read_boot_records()?
+                .first()
+                .and_then(|(_, boot_record)| boot_record.volume_label())
+                .filter(|raw_label| raw_label != format::NO_NAME_LABEL),
+        };
+
+        let res = raw_label.map(|raw_label| {
+            let chars = ShortFileName::from_data(&raw_label).chars();
+            let res: String = chars.iter().collect();
+
+            String::from(res.trim_end_matches(' '))
+        });
+
+        Ok(res.filter(|label| !label.is_empty()))
+    }
+
+    /// Set the label of the volume in both the boot record and the root directory. An empty label removes it.
+    pub fn set_volume_label(&self, volume_label: &str) -> FileSystemResult<()> {
+        let raw_label = format::encode_volume_label(volume_label)?;
+
+        match (self.find_volume_label_entry()?, raw_label) {
+            (Some(mut raw_dir_entry), Some(raw_label)) => {
+                raw_dir_entry.set_short_name(&ShortFileName::from_data(&raw_label));
+                if let Some(now) = self.now() {
+                    raw_dir_entry.set_modification_datetime(&now);
+                }
+                raw_dir_entry.flush(self)?;
+            }
+            (Some(mut raw_dir_entry), None) => {
+                raw_dir_entry.set_deleted();
+                raw_dir_entry.flush(self)?;
+            }
+            (None, Some(raw_label)) => {
+                let mut raw_dir_entry = self.get_root_directory().allocate_raw_entry()?;
+
+                raw_dir_entry.clear();
+                raw_dir_entry.set_short_name(&ShortFileName::from_data(&raw_label));
+                raw_dir_entry.set_attribute(Attributes::new(Attributes::VOLUME));
+                if let Some(now) = self.now() {
+                    raw_dir_entry.set_modification_datetime(&now);
+                }
+                raw_dir_entry.flush(self)?;
+            }
+            (None, None) => {}
+        }
+
+        let raw_label = raw_label.unwrap_or(*format::NO_NAME_LABEL);
+
+        for (block_index, mut boot_record) in self.read_boot_records()? {
+            // Old boot records don't have any volume label field
+            if boot_record.set_volume_label(&raw_label) {
+                self.mark_dirty()?;
+                self.block_device
+                    .write(
+                        core::slice::from_ref(&boot_record.data),
+                        self.partition_start,
+                        block_index,
+                    )
+                    .or(Err(FileSystemError::WriteFailed))?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Create a new directory at the given path.
     pub fn mkdir(&self, path: &str) -> FileSystemResult<()> {
         let (parent_name, file_name) = utils::get_parent(path);
@@ -224,6 +596,25 @@ where
         parent_dir.touch(file_name)
     }
 
+    /// Create a new file at the given path, holding ``size`` bytes stored in a single run of contiguous clusters.
+    ///
+    /// If no run of free clusters is large enough, ``NoContiguousSpaceLeft`` is returned and no file is created.
+    pub fn create_contiguous_file(&self, path: &str, size: u64) -> FileSystemResult<()> {
+        self.touch(path)?;
+
+        let len_res = self
+            .get_root_directory()
+            .open_file(path)
+            .and_then(|mut file| file.set_len_contiguous(self, size));
+
+        if let Err(error) = len_res {
+            self.unlink(path, false)?;
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
     /// Delete a directory or a file at the given path.
     pub fn unlink(&self, path: &str, is_dir: bool) -> FileSystemResult<()> {
         let (parent_name, file_name) = utils::get_parent(path);
@@ -255,6 +646,11 @@ where
             }
         }
 
+        // Read only files cannot be renamed
+        if !is_dir && old_entry.attribute.is_read_only() {
+            return Err(FileSystemError::AccessDenied);
+        }
+
         let (parent_name, file_name) = utils::get_parent(new_path);
         let parent_new_dir = if parent_name == "" {
             self.get_root_directory()
@@ -276,7 +672,7 @@ where
         let mut block_index = 0;
 
         for cluster in BlockIndexClusterIter::new(self, cluster, None) {
-            block_index = (block_index + 1) % u32::from(self.boot_record.blocks_per_cluster());
+            block_index = (block_index + 1) % self.boot_record.blocks_per_cluster();
             self.block_device
                 .write(
                     &blocks,
@@ -329,9 +725,7 @@ where
                 number_cluster = 2;
             }
 
-            let value = FatValue::get(self, Cluster(number_cluster))?;
-
-            if value != FatValue::Free {
+            if !self.is_cluster_free(Cluster(number_cluster))? {
                 let new_start = Cluster(self.fat_info.last_cluster.load(Ordering::SeqCst));
                 if new_start.0 >= 2 && new_start.0 < self.boot_record.cluster_count {
                     start_cluster = new_start;
@@ -342,29 +736,21 @@ where
         }
 
         if number_cluster == 0 {
-            number_cluster = start_cluster.0;
-            loop {
-                number_cluster += 1;
-                if number_cluster >= self.boot_record.cluster_count {
-                    number_cluster = 2;
-                    if number_cluster > start_cluster.0 {
-                        return Err(FileSystemError::NoSpaceLeft);
-                    }
-                }
-
-                let value = FatValue::get(self, Cluster(number_cluster))?;
-
-                if value == FatValue::Free {
-                    break;
-                }
-
-                if number_cluster == start_cluster.0 {
-                    return Err(FileSystemError::NoSpaceLeft);
-                }
-            }
+            number_cluster = self
+                .find_free_cluster(start_cluster)?
+                .ok_or(FileSystemError::NoSpaceLeft)?
+                .0;
         }
 
-        let allocated_cluster = Cluster(number_cluster);
+        self.claim_cluster(Cluster(number_cluster), last_cluster_allocated_opt)
+    }
+
+    /// Mark the given free cluster as the end of a chain and link it after ``last_cluster_allocated_opt`` if specified.
+    fn claim_cluster(
+        &self,
+        allocated_cluster: Cluster,
+        last_cluster_allocated_opt: Option<Cluster>,
+    ) -> FileSystemResult<Cluster> {
         debug_assert!(FatValue::get(self, allocated_cluster)? == FatValue::Free);
         FatValue::put(self, allocated_cluster, FatValue::EndOfChain)?;
 
@@ -390,6 +776,105 @@ where
         Ok(allocated_cluster)
     }
 
+    /// Allocate ``count`` clusters forming a single run on the volume, linked after ``last_cluster_allocated_opt`` if
+    /// specified, and return the first cluster of the run.
+    ///
+    /// When extending a chain, the run must start right after ``last_cluster_allocated_opt`` to keep the chain
+    /// contiguous. Otherwise the first run of free clusters large enough is used.
+    pub(crate) fn alloc_contiguous_clusters(
+        &self,
+        count: u32,
+        last_cluster_allocated_opt: Option<Cluster>,
+    ) -> FileSystemResult<Cluster> {
+        debug_assert!(count != 0);
+
+        if self.fat_info.free_cluster.load(Ordering::SeqCst) < count {
+            return Err(FileSystemError::NoSpaceLeft);
+        }
+
+        let required_start = last_cluster_allocated_opt.map(|cluster| Cluster(cluster.0 + 1));
+        let run_start = self
+            .find_free_run(count, required_start)?
+            .ok_or(FileSystemError::NoContiguousSpaceLeft)?;
+
+        let mut last_cluster = last_cluster_allocated_opt;
+        for cluster in run_start.0..run_start.0 + count {
+            let claim_res = self.claim_cluster(Cluster(cluster), last_cluster);
+
+            if let Err(error) = claim_res {
+                // If it fail here, this can be catastrophic but at least we tried our best.
+                self.free_cluster(run_start, last_cluster_allocated_opt)?;
+                return Err(error);
+            }
+
+            last_cluster = Some(Cluster(cluster));
+        }
+
+        Ok(run_start)
+    }
+
+    /// Find a run of ``count`` free clusters, starting at ``required_start`` if specified.
+    fn find_free_run(
+        &self,
+        count: u32,
+        required_start: Option<Cluster>,
+    ) -> FileSystemResult<Option<Cluster>> {
+        let cluster_count = self.boot_record.cluster_count;
+
+        let first_cluster = match required_start {
+            Some(cluster) if u64::from(cluster.0) + u64::from(count) > u64::from(cluster_count) => {
+                return Ok(None);
+            }
+            Some(cluster) => cluster.0,
+            None => 2,
+        };
+
+        let mut search = FreeRunSearch::new(count, required_start);
+
+        match &self.free_bitmap {
+            Some(free_bitmap) => {
+                for cluster in first_cluster..cluster_count {
+                    let cluster = Cluster(cluster);
+                    if search.visit(cluster, free_bitmap.is_free(cluster)) {
+                        break;
+                    }
+                }
+            }
+            None => table::scan_fat(self, |cluster, value| {
+                search.visit(cluster, value == FatValue::Free)
+            })?,
+        }
+
+        Ok(search.result)
+    }
+
+    /// Check if the given cluster is free, using the free cluster map if enabled.
+    fn is_cluster_free(&self, cluster: Cluster) -> FileSystemResult<bool> {
+        match &self.free_bitmap {
+            Some(free_bitmap) => Ok(free_bitmap.is_free(cluster)),
+            None => Ok(FatValue::get(self, cluster)? == FatValue::Free),
+        }
+    }
+
+    /// Find the first free cluster after ``start_cluster``, wrapping around to the start of the volume and ending
+    /// with ``start_cluster`` itself.
+    fn find_free_cluster(&self, start_cluster: Cluster) -> FileSystemResult<Option<Cluster>> {
+        if let Some(free_bitmap) = &self.free_bitmap {
+            return Ok(free_bitmap.find_free(start_cluster));
+        }
+
+        let cluster_count = self.boot_record.cluster_count;
+        let first_cluster = (start_cluster.0 + 1).min(cluster_count);
+
+        for cluster in (first_cluster..cluster_count).chain(2..first_cluster) {
+            if FatValue::get(self, Cluster(cluster))? == FatValue::Free {
+                return Ok(Some(Cluster(cluster)));
+            }
+        }
+
+        Ok(None)
+    }
+
     /// Free a cluster and if specified remove of a cluster chain.
     pub(crate) fn free_cluster(
         &self,
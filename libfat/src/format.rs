@@ -0,0 +1,529 @@
+//! FAT volume formatting.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use libfs::block::{Block, BlockCount, BlockDevice, BlockIndex};
+use libfs::FileSystemError;
+use libfs::FileSystemResult;
+
+use crate::attribute::Attributes;
+use crate::FatFsType;
+
+/// Represent the parameters of a new FAT volume.
+#[derive(Debug, Clone, Copy)]
+pub struct FormatOptions<'a> {
+    /// The type of FAT to create.
+    pub fat_type: FatFsType,
+
+    /// The size of a cluster in bytes. Zero to select it from the size of the volume.
+    pub cluster_size: u32,
+
+    /// The label of the volume. Empty if the volume doesn't have a label.
+    pub volume_label: &'a str,
+
+    /// The serial number of the volume.
+    pub volume_id: u32,
+}
+
+/// The media type written in the BPB and in the first FAT entry (fixed disk).
+const MEDIA_TYPE: u8 = 0xF8;
+
+/// The count of FATs written on the volume.
+const FATS_COUNT: u32 = 2;
+
+/// The count of entries of the root directory on FAT12/FAT16 volumes.
+const ROOT_DIR_CHILDS_COUNT: u32 = 512;
+
+/// The size of a directory entry.
+const DIR_ENTRY_LEN: usize = 32;
+
+/// The label used when the volume doesn't have one.
+pub(crate) const NO_NAME_LABEL: &[u8; 11] = b"NO NAME    ";
+
+/// The logical sector holding the FSInfo structure on FAT32 volumes.
+const FS_INFO_SECTOR: u32 = 1;
+
+/// The logical sector holding the copy of the boot sector on FAT32 volumes.
+const BACKUP_BOOT_SECTOR: u32 = 6;
+
+/// The cluster of the root directory on FAT32 volumes.
+const FAT32_ROOT_CLUSTER: u32 = 2;
+
+/// Represent the position of the structures of a new volume.
+struct Layout {
+    /// The amount of bytes per logical sector.
+    bytes_per_sector: u32,
+
+    /// The amount of logical sectors per cluster.
+    sectors_per_cluster: u32,
+
+    /// The count of reserved logical sectors.
+    reserved_sectors: u32,
+
+    /// The count of entries of the root directory (FAT12/FAT16).
+    root_dir_childs_count: u32,
+
+    /// The count of logical sectors used by the root directory (FAT12/FAT16).
+    root_dir_sectors: u32,
+
+    /// The size of a FAT in logical sectors.
+    fat_size: u32,
+
+    /// The total count of logical sectors of the volume.
+    total_sectors: u32,
+
+    /// The count of clusters in the data region.
+    cluster_count: u32,
+}
+
+impl Layout {
+    /// Compute the layout of a volume of ``total_sectors`` logical sectors.
+    fn new(
+        fat_type: FatFsType,
+        bytes_per_sector: u32,
+        total_sectors: u32,
+        cluster_size: u32,
+    ) -> FileSystemResult<Layout> {
+        let cluster_size = if cluster_size == 0 {
+            let volume_size = u64::from(total_sectors) * u64::from(bytes_per_sector);
+            core::cmp::max(
+                default_cluster_size(fat_type, volume_size),
+                bytes_per_sector,
+            )
+        } else {
+            cluster_size
+        };
+
+        if !cluster_size.is_power_of_two()
+            || cluster_size < bytes_per_sector
+            || cluster_size / bytes_per_sector > 128
+        {
+            return Err(FileSystemError::Custom {
+                name: "Invalid cluster size",
+            });
+        }
+
+        let (reserved_sectors, root_dir_childs_count) = if fat_type == FatFsType::Fat32 {
+            (32, 0)
+        } else {
+            (1, ROOT_DIR_CHILDS_COUNT)
+        };
+
+        let root_dir_sectors = (root_dir_childs_count * DIR_ENTRY_LEN as u32
+            + (bytes_per_sector - 1))
+            / bytes_per_sector;
+
+        let mut res = Layout {
+            bytes_per_sector,
+            sectors_per_cluster: cluster_size / bytes_per_sector,
+            reserved_sectors,
+            root_dir_childs_count,
+            root_dir_sectors,
+            fat_size: 1,
+            total_sectors,
+            cluster_count: 0,
+        };
+
+        // The FAT size depends on the cluster count that depends on the FAT size, iterate until it's stable.
+        loop {
+            let metadata_sectors =
+                res.reserved_sectors + res.root_dir_sectors + FATS_COUNT * res.fat_size;
+            if metadata_sectors >= total_sectors {
+                return Err(FileSystemError::NoSpaceLeft);
+            }
+
+            res.cluster_count = (total_sectors - metadata_sectors) / res.sectors_per_cluster;
+
+            let fat_bytes = match fat_type {
+                FatFsType::Fat12 => ((res.cluster_count + 2) * 3 + 1) / 2,
+                FatFsType::Fat16 => (res.cluster_count + 2) * 2,
+                FatFsType::Fat32 | FatFsType::ExFat => (res.cluster_count + 2) * 4,
+            };
+            let fat_size = (fat_bytes + (bytes_per_sector - 1)) / bytes_per_sector;
+
+            if fat_size <= res.fat_size {
+                break;
+            }
+
+            res.fat_size = fat_size;
+        }
+
+        // The FAT type is determined by the cluster count, make sure other implementations will agree with it.
+        let is_valid = match fat_type {
+            FatFsType::Fat12 => res.cluster_count > 0 && res.cluster_count < 4085,
+            FatFsType::Fat16 => res.cluster_count >= 4085 && res.cluster_count < 65525,
+            FatFsType::Fat32 => res.cluster_count >= 65525 && res.cluster_count < 0x0FFF_FFF5,
+            FatFsType::ExFat => false,
+        };
+
+        if !is_valid {
+            return Err(FileSystemError::Custom {
+                name: "Volume size doesn't match the FAT type",
+            });
+        }
+
+        Ok(res)
+    }
+
+    /// The amount of blocks per logical sector.
+    fn blocks_per_sector(&self) -> u32 {
+        self.bytes_per_sector / Block::LEN_U32
+    }
+
+    /// The block index of the first FAT.
+    fn fat_block_index(&self) -> u32 {
+        self.reserved_sectors * self.blocks_per_sector()
+    }
+
+    /// The block index of the root directory region (FAT12/FAT16) or of the data region (FAT32).
+    fn root_dir_block_index(&self) -> u32 {
+        (self.reserved_sectors + FATS_COUNT * self.fat_size) * self.blocks_per_sector()
+    }
+}
+
+/// Select the cluster size of a volume of the given size, following the defaults of other implementations.
+fn default_cluster_size(fat_type: FatFsType, volume_size: u64) -> u32 {
+    /// One MiB.
+    const MB: u64 = 1024 * 1024;
+
+    match fat_type {
+        FatFsType::Fat12 => {
+            // the smallest cluster keeping the cluster count in the FAT12 range.
+            let mut cluster_size = 512;
+            while cluster_size < 32 * 1024 && volume_size / u64::from(cluster_size) >= 4085 {
+                cluster_size *= 2;
+            }
+            cluster_size
+        }
+        FatFsType::Fat16 => {
+            if volume_size <= 16 * MB {
+                1024
+            } else if volume_size <= 128 * MB {
+                2048
+            } else if volume_size <= 256 * MB {
+                4096
+            } else if volume_size <= 512 * MB {
+                8192
+            } else if volume_size <= 1024 * MB {
+                16 * 1024
+            } else {
+                32 * 1024
+            }
+        }
+        FatFsType::Fat32 | FatFsType::ExFat => {
+            if volume_size <= 260 * MB {
+                512
+            } else if volume_size <= 8 * 1024 * MB {
+                4096
+            } else if volume_size <= 16 * 1024 * MB {
+                8192
+            } else if volume_size <= 32 * 1024 * MB {
+                16 * 1024
+            } else {
+                32 * 1024
+            }
+        }
+    }
+}
+
+/// Convert a volume label to its on disk representation.
+pub(crate) fn encode_volume_label(volume_label: &str) -> FileSystemResult<Option<[u8; 11]>> {
+    /// Characters that cannot be used in a short name.
+    const INVALID_CHARS: &[u8] = b"\"*+,./:;<=>?[\\]|";
+
+    if volume_label.is_empty() {
+        return Ok(None);
+    }
+
+    if volume_label.len() > 11 {
+        return Err(FileSystemError::PathTooLong);
+    }
+
+    let mut res = [b' '; 11];
+    for (index, c) in volume_label.bytes().enumerate() {
+        if c < 0x20 || c > 0x7E || INVALID_CHARS.contains(&c) {
+            return Err(FileSystemError::Custom {
+                name: "Invalid character in volume label",
+            });
+        }
+
+        res[index] = c.to_ascii_uppercase();
+    }
+
+    Ok(Some(res))
+}
+
+/// Write the first block of a logical sector. The rest of the sector is expected to be cleared already.
+fn write_sector<T>(
+    block_device: &T,
+    partition_start: BlockIndex,
+    layout: &Layout,
+    sector: u32,
+    data: &Block,
+) -> FileSystemResult<()>
+where
+    T: BlockDevice,
+{
+    let first_block = sector * layout.blocks_per_sector();
+
+    block_device
+        .write(
+            core::slice::from_ref(data),
+            partition_start,
+            BlockIndex(first_block),
+        )
+        .or(Err(FileSystemError::WriteFailed))
+}
+
+/// Zero a range of blocks.
+fn clear_blocks<T>(
+    block_device: &T,
+    partition_start: BlockIndex,
+    start: u32,
+    count: u32,
+) -> FileSystemResult<()>
+where
+    T: BlockDevice,
+{
+    let blocks = [Block::new()];
+
+    for block_index in start..start + count {
+        block_device
+            .write(&blocks, partition_start, BlockIndex(block_index))
+            .or(Err(FileSystemError::WriteFailed))?;
+    }
+
+    Ok(())
+}
+
+/// Create the boot sector of a new volume.
+fn create_boot_sector(
+    options: &FormatOptions<'_>,
+    layout: &Layout,
+    partition_start: BlockIndex,
+    volume_label: &[u8; 11],
+) -> Block {
+    let mut block = Block::new();
+    let is_fat32 = options.fat_type == FatFsType::Fat32;
+
+    // Offset of the boot code, after the BPB.
+    let boot_code_offset = if is_fat32 { 0x5A } else { 0x3E };
+
+    block[0] = 0xEB;
+    block[1] = (boot_code_offset - 2) as u8;
+    block[2] = 0x90;
+    block[3..11].copy_from_slice(b"MSWIN4.1");
+
+    LittleEndian::write_u16(&mut block[11..13], layout.bytes_per_sector as u16);
+    block[13] = layout.sectors_per_cluster as u8;
+    LittleEndian::write_u16(&mut block[14..16], layout.reserved_sectors as u16);
+    block[16] = FATS_COUNT as u8;
+    LittleEndian::write_u16(&mut block[17..19], layout.root_dir_childs_count as u16);
+
+    if layout.total_sectors < 0x10000 && !is_fat32 {
+        LittleEndian::write_u16(&mut block[19..21], layout.total_sectors as u16);
+    } else {
+        LittleEndian::write_u32(&mut block[32..36], layout.total_sectors);
+    }
+
+    block[21] = MEDIA_TYPE;
+
+    // CHS geometry, only used by old BIOS
+    LittleEndian::write_u16(&mut block[24..26], 63);
+    LittleEndian::write_u16(&mut block[26..28], 255);
+    LittleEndian::write_u32(
+        &mut block[28..32],
+        partition_start.0 / layout.blocks_per_sector(),
+    );
+
+    let extended_bpb_offset = if is_fat32 {
+        LittleEndian::write_u32(&mut block[36..40], layout.fat_size);
+        LittleEndian::write_u32(&mut block[44..48], FAT32_ROOT_CLUSTER);
+        LittleEndian::write_u16(&mut block[48..50], FS_INFO_SECTOR as u16);
+        LittleEndian::write_u16(&mut block[50..52], BACKUP_BOOT_SECTOR as u16);
+        64
+    } else {
+        LittleEndian::write_u16(&mut block[22..24], layout.fat_size as u16);
+        36
+    };
+
+    let file_system_type: &[u8; 8] = match options.fat_type {
+        FatFsType::Fat12 => b"FAT12   ",
+        FatFsType::Fat16 => b"FAT16   ",
+        _ => b"FAT32   ",
+    };
+
+    // drive number and extended boot signature
+    block[extended_bpb_offset] = 0x80;
+    block[extended_bpb_offset + 2] = 0x29;
+    LittleEndian::write_u32(
+        &mut block[extended_bpb_offset + 3..extended_bpb_offset + 7],
+        options.volume_id,
+    );
+    block[extended_bpb_offset + 7..extended_bpb_offset + 18].copy_from_slice(volume_label);
+    block[extended_bpb_offset + 18..extended_bpb_offset + 26].copy_from_slice(file_system_type);
+
+    // The volume isn't bootable: ask the BIOS to try the next device (int 0x18).
+    block[boot_code_offset] = 0xCD;
+    block[boot_code_offset + 1] = 0x18;
+
+    block[510] = 0x55;
+    block[511] = 0xAA;
+
+    block
+}
+
+/// Create the FSInfo sector of a new FAT32 volume.
+fn create_fs_info_sector(layout: &Layout) -> Block {
+    let mut block = Block::new();
+
+    LittleEndian::write_u32(&mut block[0..4], 0x4161_5252);
+    LittleEndian::write_u32(&mut block[484..488], 0x6141_7272);
+
+    // every cluster except the root directory one is free
+    LittleEndian::write_u32(&mut block[488..492], layout.cluster_count - 1);
+    LittleEndian::write_u32(&mut block[492..496], FAT32_ROOT_CLUSTER + 1);
+    LittleEndian::write_u32(&mut block[508..512], 0xAA55_0000);
+
+    block
+}
+
+/// Format a partition as a FAT volume.
+pub fn format_partition<T>(
+    block_device: &T,
+    partition_start: BlockIndex,
+    partition_block_count: BlockCount,
+    options: &FormatOptions<'_>,
+) -> FileSystemResult<()>
+where
+    T: BlockDevice,
+{
+    if options.fat_type == FatFsType::ExFat {
+        return Err(FileSystemError::Custom {
+            name: "exFAT formatting is not supported",
+        });
+    }
+
+    let label = encode_volume_label(options.volume_label)?;
+
+    // Logical sectors must be at least as big as the device ones.
+    let bytes_per_sector = core::cmp::max(block_device.sector_size(), Block::LEN_U32);
+    if bytes_per_sector > 4096 || !bytes_per_sector.is_power_of_two() {
+        return Err(FileSystemError::InvalidPartition);
+    }
+
+    let blocks_per_sector = bytes_per_sector / Block::LEN_U32;
+    let layout = Layout::new(
+        options.fat_type,
+        bytes_per_sector,
+        partition_block_count.0 / blocks_per_sector,
+        options.cluster_size,
+    )?;
+
+    let is_fat32 = options.fat_type == FatFsType::Fat32;
+
+    // Clear everything up to the root directory included.
+    let root_dir_blocks = if is_fat32 {
+        layout.sectors_per_cluster * blocks_per_sector
+    } else {
+        layout.root_dir_sectors * blocks_per_sector
+    };
+    clear_blocks(
+        block_device,
+        partition_start,
+        0,
+        layout.root_dir_block_index() + root_dir_blocks,
+    )?;
+
+    // Reserved entries of the FATs: the media type, the end of chain marker (clean volume) and the FAT32 root directory.
+    let mut fat_block = Block::new();
+    match options.fat_type {
+        FatFsType::Fat12 => {
+            fat_block[0..3].copy_from_slice(&[MEDIA_TYPE, 0xFF, 0xFF]);
+        }
+        FatFsType::Fat16 => {
+            fat_block[0..4].copy_from_slice(&[MEDIA_TYPE, 0xFF, 0xFF, 0xFF]);
+        }
+        _ => {
+            LittleEndian::write_u32(&mut fat_block[0..4], 0x0FFF_FF00 | u32::from(MEDIA_TYPE));
+            LittleEndian::write_u32(&mut fat_block[4..8], 0x0FFF_FFFF);
+            LittleEndian::write_u32(&mut fat_block[8..12], 0x0FFF_FFFF);
+        }
+    }
+
+    for fat_index in 0..FATS_COUNT {
+        let fat_block_index =
+            layout.fat_block_index() + fat_index * layout.fat_size * blocks_per_sector;
+        block_device
+            .write(
+                core::slice::from_ref(&fat_block),
+                partition_start,
+                BlockIndex(fat_block_index),
+            )
+            .or(Err(FileSystemError::WriteFailed))?;
+    }
+
+    // The volume label also lives in the root directory.
+    if let Some(label) = label {
+        let mut root_block = Block::new();
+        root_block[0..11].copy_from_slice(&label);
+        root_block[11] = Attributes::VOLUME;
+
+        block_device
+            .write(
+                core::slice::from_ref(&root_block),
+                partition_start,
+                BlockIndex(layout.root_dir_block_index()),
+            )
+            .or(Err(FileSystemError::WriteFailed))?;
+    }
+
+    let boot_sector = create_boot_sector(
+        options,
+        &layout,
+        partition_start,
+        &label.unwrap_or(*NO_NAME_LABEL),
+    );
+
+    if is_fat32 {
+        let fs_info_sector = create_fs_info_sector(&layout);
+
+        write_sector(
+            block_device,
+            partition_start,
+            &layout,
+            FS_INFO_SECTOR,
+            &fs_info_sector,
+        )?;
+        write_sector(
+            block_device,
+            partition_start,
+            &layout,
+            BACKUP_BOOT_SECTOR + FS_INFO_SECTOR,
+            &fs_info_sector,
+        )?;
+        write_sector(
+            block_device,
+            partition_start,
+            &layout,
+            BACKUP_BOOT_SECTOR,
+            &boot_sector,
+        )?;
+    }
+
+    // The boot sector is written last so an interrupted format doesn't leave a mountable volume.
+    write_sector(block_device, partition_start, &layout, 0, &boot_sector)
+}
+
+/// Format the whole block device as a FAT volume.
+pub fn format_raw_partition<T>(
+    block_device: &T,
+    options: &FormatOptions<'_>,
+) -> FileSystemResult<()>
+where
+    T: BlockDevice,
+{
+    let block_count = block_device.count().or(Err(FileSystemError::ReadFailed))?;
+
+    format_partition(block_device, BlockIndex(0), block_count, options)
+}
@@ -0,0 +1,119 @@
+//! In-memory map of the free clusters of a FAT filesystem.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::cluster::Cluster;
+use crate::filesystem::FatFileSystem;
+use crate::table::{self, FatValue};
+
+use libfs::block::BlockDevice;
+use libfs::FileSystemResult;
+
+/// The count of clusters tracked by a word of the bitmap.
+const CLUSTERS_PER_WORD: u32 = 32;
+
+/// A bitmap of the free clusters of the volume, mirroring the first FAT.
+///
+/// A set bit marks a free cluster. The bits of the reserved clusters and of the ones past the end of the volume are
+/// never set.
+pub(crate) struct FreeClusterBitmap {
+    /// The words of the bitmap.
+    words: Vec<AtomicU32>,
+
+    /// The count of clusters on the volume, including the two reserved ones.
+    cluster_count: u32,
+}
+
+impl FreeClusterBitmap {
+    /// Build the bitmap by scanning the FAT of the filesystem.
+    pub(crate) fn from_fs<T>(fs: &FatFileSystem<T>) -> FileSystemResult<Self>
+    where
+        T: BlockDevice,
+    {
+        let cluster_count = fs.boot_record.cluster_count;
+        let word_count = (cluster_count + CLUSTERS_PER_WORD - 1) / CLUSTERS_PER_WORD;
+        let mut words: Vec<u32> = (0..word_count).map(|_| 0).collect();
+
+        table::scan_fat(fs, |cluster, value| {
+            if value == FatValue::Free {
+                words[(cluster.0 / CLUSTERS_PER_WORD) as usize] |=
+                    1 << (cluster.0 % CLUSTERS_PER_WORD);
+            }
+
+            false
+        })?;
+
+        Ok(FreeClusterBitmap {
+            words: words.into_iter().map(AtomicU32::new).collect(),
+            cluster_count,
+        })
+    }
+
+    /// Get the count of free clusters.
+    pub(crate) fn free_count(&self) -> u32 {
+        self.words
+            .iter()
+            .map(|word| word.load(Ordering::SeqCst).count_ones())
+            .sum()
+    }
+
+    /// Check if the given cluster is free.
+    pub(crate) fn is_free(&self, cluster: Cluster) -> bool {
+        if cluster.0 < 2 || cluster.0 >= self.cluster_count {
+            return false;
+        }
+
+        let word = self.words[(cluster.0 / CLUSTERS_PER_WORD) as usize].load(Ordering::SeqCst);
+        word & (1 << (cluster.0 % CLUSTERS_PER_WORD)) != 0
+    }
+
+    /// Mark the given cluster as free or used.
+    pub(crate) fn set_free(&self, cluster: Cluster, is_free: bool) {
+        if cluster.0 < 2 || cluster.0 >= self.cluster_count {
+            return;
+        }
+
+        let word = &self.words[(cluster.0 / CLUSTERS_PER_WORD) as usize];
+        let mask = 1 << (cluster.0 % CLUSTERS_PER_WORD);
+
+        if is_free {
+            word.fetch_or(mask, Ordering::SeqCst);
+        } else {
+            word.fetch_and(!mask, Ordering::SeqCst);
+        }
+    }
+
+    /// Find the first free cluster after ``start_cluster``, wrapping around to the start of the volume and ending
+    /// with ``start_cluster`` itself.
+    pub(crate) fn find_free(&self, start_cluster: Cluster) -> Option<Cluster> {
+        let first_cluster = (start_cluster.0 + 1).min(self.cluster_count);
+
+        self.find_free_in(first_cluster, self.cluster_count)
+            .or_else(|| self.find_free_in(2, first_cluster))
+    }
+
+    /// Find the first free cluster between ``start`` included and ``end`` excluded.
+    fn find_free_in(&self, start: u32, end: u32) -> Option<Cluster> {
+        let mut cluster = start;
+
+        while cluster < end {
+            let word_index = cluster / CLUSTERS_PER_WORD;
+            let word = self.words[word_index as usize].load(Ordering::SeqCst)
+                >> (cluster % CLUSTERS_PER_WORD);
+
+            if word != 0 {
+                let free_cluster = cluster + word.trailing_zeros();
+                if free_cluster < end {
+                    return Some(Cluster(free_cluster));
+                }
+
+                return None;
+            }
+
+            cluster = (word_index + 1) * CLUSTERS_PER_WORD;
+        }
+
+        None
+    }
+}
@@ -0,0 +1,1247 @@
+//! FAT filesystem consistency checker.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use core::iter;
+use core::sync::atomic::Ordering;
+
+use libfs::block::{Block, BlockDevice, BlockIndex};
+use libfs::FileSystemError;
+use libfs::FileSystemResult;
+
+use crate::attribute::Attributes;
+use crate::cluster::Cluster;
+use crate::directory::raw_dir_entry::FatDirEntry;
+use crate::directory::raw_dir_entry_iterator::FatDirEntryIterator;
+use crate::directory::Directory;
+use crate::filesystem::{FatFileSystem, FatFileSystemInfo};
+use crate::name::ShortFileName;
+use crate::table;
+use crate::table::FatValue;
+use crate::utils;
+use crate::FatFsType;
+
+/// Represent an inconsistency found on a FAT filesystem.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsckIssue {
+    /// A cluster chain isn't referenced by any directory entry.
+    LostChain {
+        /// The first cluster of the chain.
+        first_cluster: u32,
+
+        /// The count of clusters in the chain.
+        cluster_count: u32,
+    },
+
+    /// A cluster is used by the chains of two different entries.
+    CrossLinkedCluster {
+        /// The cluster shared by the two chains.
+        cluster: u32,
+
+        /// The path of the entry that was found to reuse the cluster.
+        path: String,
+
+        /// The path of the entry that first used the cluster.
+        other_path: String,
+    },
+
+    /// The chain of an entry starts out of the data area, reaches a free or bad cluster, points out of the data area or loops on itself.
+    InvalidChain {
+        /// The path of the entry.
+        path: String,
+
+        /// The cluster holding the invalid FAT value, or the start cluster if it's out of the data area.
+        cluster: u32,
+    },
+
+    /// The cluster count of a file chain doesn't match its size.
+    FileSizeMismatch {
+        /// The path of the file.
+        path: String,
+
+        /// The size of the file recorded in its directory entry.
+        file_size: u32,
+
+        /// The count of clusters in the chain of the file.
+        cluster_count: u32,
+    },
+
+    /// The "." entry of a directory is missing or doesn't point to the directory.
+    BadDotEntry {
+        /// The path of the directory.
+        path: String,
+
+        /// The cluster the entry should point to.
+        expected_cluster: u32,
+
+        /// The cluster the entry points to, None if the entry is missing.
+        found_cluster: Option<u32>,
+    },
+
+    /// The ".." entry of a directory is missing or doesn't point to the parent directory.
+    BadDotDotEntry {
+        /// The path of the directory.
+        path: String,
+
+        /// The cluster the entry should point to.
+        expected_cluster: u32,
+
+        /// The cluster the entry points to, None if the entry is missing.
+        found_cluster: Option<u32>,
+    },
+
+    /// VFAT long name entries don't belong to any 8.3 entry.
+    OrphanedLongFileName {
+        /// The path of the directory holding the entries.
+        path: String,
+
+        /// The cluster holding the first entry.
+        cluster: u32,
+
+        /// The block index of the first entry in its cluster.
+        block_index: u32,
+
+        /// The offset of the first entry in its block.
+        offset: u32,
+
+        /// The count of consecutive orphaned entries.
+        entry_count: u32,
+    },
+
+    /// A secondary FAT differs from the first FAT.
+    FatCopyMismatch {
+        /// The index of the secondary FAT.
+        fat_index: u32,
+
+        /// The count of blocks that differ from the first FAT.
+        mismatched_block_count: u32,
+    },
+
+    /// The free cluster count stored in the FS Info structure of a FAT32 filesystem is stale.
+    FsInfoFreeCountMismatch {
+        /// The free cluster count stored in the FS Info structure.
+        stored: u32,
+
+        /// The actual free cluster count.
+        actual: u32,
+    },
+}
+
+/// Represent the result of a consistency check.
+#[derive(Debug, Clone)]
+pub struct FsckReport {
+    /// The inconsistencies found on the filesystem.
+    pub issues: Vec<FsckIssue>,
+
+    /// The count of directories found, the root directory excluded.
+    pub directory_count: u32,
+
+    /// The count of files found.
+    pub file_count: u32,
+
+    /// The count of free clusters in the FAT.
+    pub free_cluster_count: u32,
+}
+
+impl FsckReport {
+    /// Return true if no inconsistencies were found.
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Represent what the repair mode does with lost cluster chains.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LostChainAction {
+    /// Free the clusters of the chains.
+    Free,
+
+    /// Save each chain as a ``FILEnnnn.CHK`` file in the ``FOUND.000`` directory of the root directory.
+    SaveAsFiles,
+}
+
+/// Represent the options of the repair mode.
+#[derive(Debug, Clone, Copy)]
+pub struct RepairOptions {
+    /// What to do with lost cluster chains.
+    pub lost_chains: LostChainAction,
+}
+
+/// The name of the directory receiving the lost chains saved as files.
+const FOUND_DIR_NAME: &str = "FOUND.000";
+
+/// The max count of files in the ``FOUND.000`` directory.
+const MAX_FOUND_FILES: u32 = 10000;
+
+/// Owner marker of a cluster that isn't reachable from any entry.
+const LOST_CLUSTER: u32 = 0xFFFF_FFFF;
+
+/// Owner marker of a lost cluster that is pointed to by another lost cluster.
+const LOST_LINKED_CLUSTER: u32 = 0xFFFF_FFFE;
+
+/// Owner marker of a lost cluster that was already reported in a lost chain.
+const LOST_REPORTED_CLUSTER: u32 = 0xFFFF_FFFD;
+
+/// The raw name of the "." entry.
+const DOT_NAME: &[u8; ShortFileName::MAX_LEN] = b".          ";
+
+/// The raw name of the ".." entry.
+const DOT_DOT_NAME: &[u8; ShortFileName::MAX_LEN] = b"..         ";
+
+/// Represent a directory waiting to be checked.
+struct PendingDirectory {
+    /// The path of the directory.
+    path: String,
+
+    /// The clusters of the directory.
+    clusters: Vec<Cluster>,
+
+    /// The cluster the ".." entry should point to. None for the root directory.
+    parent_cluster: Option<u32>,
+}
+
+/// Represent a sequence of VFAT long name entries waiting for their 8.3 entry.
+struct PendingLongFileName {
+    /// The first entry of the sequence.
+    first_entry: FatDirEntry,
+
+    /// The count of entries in the sequence.
+    entry_count: u32,
+
+    /// The order of the next entry expected in the sequence.
+    next_order: u8,
+
+    /// The checksum of the 8.3 name shared by the sequence.
+    checksum: u8,
+
+    /// The long name assembled so far.
+    name: String,
+}
+
+/// The state of a consistency check.
+struct Checker<'a, T> {
+    /// The filesystem being checked.
+    fs: &'a FatFileSystem<T>,
+
+    /// The owner of each cluster, as an index in ``paths`` plus one. Zero if unused.
+    ///
+    /// This takes 4 bytes per cluster of the volume, up to 1 GiB for the biggest FAT32 volumes.
+    owners: Vec<u32>,
+
+    /// The paths of the entries owning clusters.
+    paths: Vec<String>,
+
+    /// The report being built.
+    report: FsckReport,
+
+    /// The repair options, None if the filesystem must not be modified.
+    repair: Option<RepairOptions>,
+
+    /// The index of the next file to try in the ``FOUND.000`` directory.
+    next_found_file_index: u32,
+}
+
+impl<'a, T> Checker<'a, T>
+where
+    T: BlockDevice,
+{
+    /// Create a new checker for a filesystem.
+    ///
+    /// Fail if the owner table of the clusters can't be allocated.
+    fn new(fs: &'a FatFileSystem<T>, repair: Option<RepairOptions>) -> FileSystemResult<Self> {
+        let cluster_count = fs.boot_record.cluster_count as usize;
+
+        let mut owners = Vec::new();
+        owners
+            .try_reserve_exact(cluster_count)
+            .or(Err(FileSystemError::Custom {
+                name: "Not enough memory to check the volume",
+            }))?;
+        owners.extend(iter::repeat(0).take(cluster_count));
+
+        Ok(Checker {
+            fs,
+            owners,
+            paths: Vec::new(),
+            report: FsckReport {
+                issues: Vec::new(),
+                directory_count: 0,
+                file_count: 0,
+                free_cluster_count: 0,
+            },
+            repair,
+            next_found_file_index: 0,
+        })
+    }
+
+    /// Check if inconsistencies must be repaired.
+    fn is_repairing(&self) -> bool {
+        self.repair.is_some()
+    }
+
+    /// Check if a cluster is part of the data area.
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.fs.boot_record.cluster_count
+    }
+
+    /// Register an entry owning clusters and return its owner id.
+    fn add_owner(&mut self, path: &str) -> u32 {
+        self.paths.push(String::from(path));
+        self.paths.len() as u32
+    }
+
+    /// Walk the cluster chain of an entry and claim its clusters.
+    ///
+    /// When repairing, the chain is terminated at its first invalid FAT value. If ``copy_shared`` is set, the part of the chain shared with another entry is replaced by a copy, otherwise the chain is terminated before it.
+    ///
+    /// Return the count of clusters in the chain and its first cluster, that is zero if the chain is empty. If ``clusters`` is given, the clusters of the chain are pushed to it.
+    fn walk_chain(
+        &mut self,
+        owner: u32,
+        start_cluster: u32,
+        copy_shared: bool,
+        mut clusters: Option<&mut Vec<Cluster>>,
+    ) -> FileSystemResult<(u32, u32)> {
+        let path = self.paths[owner as usize - 1].clone();
+
+        if !self.is_data_cluster(start_cluster) {
+            self.report.issues.push(FsckIssue::InvalidChain {
+                path,
+                cluster: start_cluster,
+            });
+            return Ok((0, 0));
+        }
+
+        let mut first_cluster = start_cluster;
+        let mut cluster_count = 0;
+        let mut current_cluster = start_cluster;
+        let mut previous_cluster = None;
+        let mut is_cross_linked = false;
+
+        loop {
+            let current_owner = self.owners[current_cluster as usize];
+
+            if current_owner == owner {
+                // The chain loops on itself
+                let previous_cluster = previous_cluster.unwrap_or(current_cluster);
+                self.report.issues.push(FsckIssue::InvalidChain {
+                    path,
+                    cluster: previous_cluster,
+                });
+
+                if self.is_repairing() {
+                    FatValue::put(self.fs, Cluster(previous_cluster), FatValue::EndOfChain)?;
+                }
+                break;
+            } else if current_owner != 0 {
+                if !is_cross_linked {
+                    self.report.issues.push(FsckIssue::CrossLinkedCluster {
+                        cluster: current_cluster,
+                        path: path.clone(),
+                        other_path: self.paths[current_owner as usize - 1].clone(),
+                    });
+                    is_cross_linked = true;
+
+                    if self.is_repairing() {
+                        if let Some(previous_cluster) = previous_cluster {
+                            FatValue::put(
+                                self.fs,
+                                Cluster(previous_cluster),
+                                FatValue::EndOfChain,
+                            )?;
+                        }
+
+                        if copy_shared {
+                            let (copy_cluster_count, copy_first_cluster) = self.copy_chain(
+                                owner,
+                                previous_cluster,
+                                current_cluster,
+                                clusters,
+                            )?;
+
+                            cluster_count += copy_cluster_count;
+                            if previous_cluster.is_none() {
+                                first_cluster = copy_first_cluster;
+                            }
+                        } else if previous_cluster.is_none() {
+                            first_cluster = 0;
+                        }
+                        break;
+                    }
+                }
+            } else {
+                self.owners[current_cluster as usize] = owner;
+            }
+
+            cluster_count += 1;
+            if let Some(clusters) = clusters.as_mut() {
+                clusters.push(Cluster(current_cluster));
+            }
+
+            // The shared part of a cross-linked chain isn't claimed, avoid looping on it forever
+            if cluster_count >= self.fs.boot_record.cluster_count {
+                break;
+            }
+
+            match FatValue::get(self.fs, Cluster(current_cluster))? {
+                FatValue::EndOfChain => break,
+                FatValue::Data(next_cluster) if self.is_data_cluster(next_cluster) => {
+                    previous_cluster = Some(current_cluster);
+                    current_cluster = next_cluster;
+                }
+                _ => {
+                    self.report.issues.push(FsckIssue::InvalidChain {
+                        path,
+                        cluster: current_cluster,
+                    });
+
+                    if self.is_repairing() {
+                        FatValue::put(self.fs, Cluster(current_cluster), FatValue::EndOfChain)?;
+                    }
+                    break;
+                }
+            }
+        }
+
+        Ok((cluster_count, first_cluster))
+    }
+
+    /// Copy the clusters of a chain shared with another entry to new clusters and append them to ``previous_cluster``.
+    ///
+    /// The copy stops early if the filesystem is full. Return the count of clusters copied and the first new cluster, zero if none.
+    fn copy_chain(
+        &mut self,
+        owner: u32,
+        previous_cluster: Option<u32>,
+        shared_cluster: u32,
+        mut clusters: Option<&mut Vec<Cluster>>,
+    ) -> FileSystemResult<(u32, u32)> {
+        let fs = self.fs;
+        let mut blocks = [Block::new()];
+
+        let mut cluster_count = 0;
+        let mut first_cluster = 0;
+        let mut last_cluster = previous_cluster.map(Cluster);
+        let mut source_cluster = Cluster(shared_cluster);
+
+        loop {
+            let new_cluster = match fs.alloc_cluster(last_cluster) {
+                Ok(cluster) => cluster,
+                Err(FileSystemError::NoSpaceLeft) => break,
+                Err(error) => return Err(error),
+            };
+
+            for block_index in 0..fs.boot_record.blocks_per_cluster() {
+                fs.block_device
+                    .read(
+                        &mut blocks,
+                        fs.partition_start,
+                        BlockIndex(source_cluster.to_data_block_index(fs).0 + block_index),
+                    )
+                    .or(Err(FileSystemError::ReadFailed))?;
+                fs.block_device
+                    .write(
+                        &blocks,
+                        fs.partition_start,
+                        BlockIndex(new_cluster.to_data_block_index(fs).0 + block_index),
+                    )
+                    .or(Err(FileSystemError::WriteFailed))?;
+            }
+
+            self.owners[new_cluster.0 as usize] = owner;
+            if let Some(clusters) = clusters.as_mut() {
+                clusters.push(new_cluster);
+            }
+
+            cluster_count += 1;
+            if first_cluster == 0 {
+                first_cluster = new_cluster.0;
+            }
+            last_cluster = Some(new_cluster);
+
+            if cluster_count >= fs.boot_record.cluster_count {
+                break;
+            }
+
+            match FatValue::get(fs, source_cluster)? {
+                FatValue::Data(next_cluster) if self.is_data_cluster(next_cluster) => {
+                    source_cluster = Cluster(next_cluster)
+                }
+                _ => break,
+            }
+        }
+
+        Ok((cluster_count, first_cluster))
+    }
+
+    /// Check the whole directory tree, starting at the root directory.
+    fn check_tree(&mut self) -> FileSystemResult<()> {
+        let root_owner = self.add_owner("/");
+        let root_cluster = self.fs.boot_record.root_dir_childs_cluster();
+
+        let mut root_clusters = Vec::new();
+        if root_cluster.is_fixed_root_dir(self.fs) {
+            root_clusters.push(root_cluster);
+        } else {
+            self.walk_chain(root_owner, root_cluster.0, false, Some(&mut root_clusters))?;
+        }
+
+        let mut pending_directories = Vec::new();
+        pending_directories.push(PendingDirectory {
+            path: String::new(),
+            clusters: root_clusters,
+            parent_cluster: None,
+        });
+
+        while let Some(directory) = pending_directories.pop() {
+            self.check_directory(&directory, &mut pending_directories)?;
+        }
+
+        Ok(())
+    }
+
+    /// Report a sequence of VFAT long name entries that doesn't belong to any 8.3 entry.
+    ///
+    /// When repairing, the entries are deleted.
+    fn report_orphaned_lfn(
+        &mut self,
+        path: &str,
+        first_entry: &FatDirEntry,
+        entry_count: u32,
+    ) -> FileSystemResult<()> {
+        self.report.issues.push(FsckIssue::OrphanedLongFileName {
+            path: directory_path(path),
+            cluster: first_entry.entry_cluster.0,
+            block_index: first_entry.entry_index,
+            offset: first_entry.entry_offset,
+            entry_count,
+        });
+
+        if self.is_repairing() {
+            self.delete_entries(first_entry, entry_count)?;
+        }
+
+        Ok(())
+    }
+
+    /// Mark consecutive raw directory entries as deleted.
+    fn delete_entries(&self, first_entry: &FatDirEntry, entry_count: u32) -> FileSystemResult<()> {
+        let entries = FatDirEntryIterator::new(
+            self.fs,
+            first_entry.entry_cluster,
+            BlockIndex(first_entry.entry_index),
+            first_entry.entry_offset,
+        );
+
+        for entry in entries.take(entry_count as usize) {
+            let mut entry = entry?;
+
+            entry.set_deleted();
+            entry.flush(self.fs)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the "." or ".." entry at the start of a directory.
+    ///
+    /// When repairing, the cluster of the entry is fixed. Missing entries are recreated in their slot if it's deleted or
+    /// past the end of the directory, an entry still in use is never overwritten.
+    ///
+    /// Return true if the entry is, or now is, the expected special entry.
+    fn check_dot_entry(
+        &mut self,
+        directory: &PendingDirectory,
+        entry: Option<&FatDirEntry>,
+        is_dot_dot: bool,
+    ) -> FileSystemResult<bool> {
+        let (expected_name, expected_cluster) = if is_dot_dot {
+            (DOT_DOT_NAME, directory.parent_cluster.unwrap_or(0))
+        } else {
+            (
+                DOT_NAME,
+                directory.clusters.first().map_or(0, |cluster| cluster.0),
+            )
+        };
+
+        let found_cluster = match entry {
+            Some(entry)
+                if !entry.is_deleted()
+                    && !entry.is_long_file_name()
+                    && entry.data[0..ShortFileName::MAX_LEN] == expected_name[..] =>
+            {
+                Some(entry.get_cluster().0)
+            }
+            _ => None,
+        };
+
+        if found_cluster != Some(expected_cluster) {
+            let path = directory_path(&directory.path);
+            self.report.issues.push(if is_dot_dot {
+                FsckIssue::BadDotDotEntry {
+                    path,
+                    expected_cluster,
+                    found_cluster,
+                }
+            } else {
+                FsckIssue::BadDotEntry {
+                    path,
+                    expected_cluster,
+                    found_cluster,
+                }
+            });
+
+            if !self.is_repairing() {
+                return Ok(found_cluster.is_some());
+            }
+
+            match (entry, found_cluster) {
+                (Some(entry), Some(_)) => {
+                    let mut entry = *entry;
+                    entry.set_cluster(Cluster(expected_cluster));
+                    entry.flush(self.fs)?;
+                }
+                (Some(entry), None) if entry.is_deleted() => {
+                    self.write_dot_entry(*entry, expected_name, expected_cluster)?;
+                    return Ok(true);
+                }
+                (None, _) => {
+                    if let Some(first_cluster) = directory.clusters.first() {
+                        let slot = if is_dot_dot { 1 } else { 0 };
+                        let entry = FatDirEntry::from_raw(
+                            &[],
+                            *first_cluster,
+                            0,
+                            (slot * FatDirEntry::LEN) as u32,
+                        );
+                        self.write_dot_entry(entry, expected_name, expected_cluster)?;
+
+                        // The entry replaced the end of directory marker, move it to the next slot
+                        let end_entry = FatDirEntry::from_raw(
+                            &[],
+                            *first_cluster,
+                            0,
+                            ((slot + 1) * FatDirEntry::LEN) as u32,
+                        );
+                        end_entry.flush(self.fs)?;
+                        return Ok(true);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(found_cluster.is_some())
+    }
+
+    /// Write a "." or ".." entry with the given raw name and cluster in the slot of ``entry``.
+    fn write_dot_entry(
+        &self,
+        mut entry: FatDirEntry,
+        name: &[u8; ShortFileName::MAX_LEN],
+        cluster: u32,
+    ) -> FileSystemResult<()> {
+        entry.clear();
+        entry.data[0..ShortFileName::MAX_LEN].copy_from_slice(name);
+        entry.set_attribute(Attributes::new(Attributes::DIRECTORY));
+        entry.set_cluster(Cluster(cluster));
+
+        if let Some(now) = self.fs.now() {
+            entry.set_creation_datetime(&now);
+            entry.set_modification_datetime(&now);
+            entry.set_last_access_date(&now);
+        }
+
+        entry.flush(self.fs)
+    }
+
+    /// Check the entries of a directory and queue its subdirectories.
+    fn check_directory(
+        &mut self,
+        directory: &PendingDirectory,
+        pending_directories: &mut Vec<PendingDirectory>,
+    ) -> FileSystemResult<()> {
+        let fs = self.fs;
+        let is_root = directory.parent_cluster.is_none();
+
+        let mut blocks = [Block::new()];
+        let mut entry_position = 0;
+        let mut pending_lfn: Option<PendingLongFileName> = None;
+
+        'clusters: for cluster in &directory.clusters {
+            for block_index in 0..cluster.block_count(fs) {
+                fs.block_device
+                    .read(
+                        &mut blocks,
+                        fs.partition_start,
+                        BlockIndex(cluster.to_data_block_index(fs).0 + block_index),
+                    )
+                    .or(Err(FileSystemError::ReadFailed))?;
+
+                for entry_offset in (0..Block::LEN).step_by(FatDirEntry::LEN) {
+                    let entry = FatDirEntry::from_raw(
+                        &blocks[0][entry_offset..entry_offset + FatDirEntry::LEN],
+                        *cluster,
+                        block_index,
+                        entry_offset as u32,
+                    );
+
+                    // End of directory
+                    if entry.is_free() {
+                        break 'clusters;
+                    }
+
+                    entry_position += 1;
+
+                    if !is_root
+                        && entry_position <= 2
+                        && self.check_dot_entry(directory, Some(&entry), entry_position == 2)?
+                    {
+                        continue;
+                    }
+
+                    if entry.is_deleted() {
+                        if let Some(lfn) = pending_lfn.take() {
+                            self.report_orphaned_lfn(
+                                &directory.path,
+                                &lfn.first_entry,
+                                lfn.entry_count,
+                            )?;
+                        }
+                        continue;
+                    }
+
+                    if entry.is_long_file_name() {
+                        pending_lfn = self.check_lfn_entry(&directory.path, entry, pending_lfn)?;
+                        continue;
+                    }
+
+                    let mut long_file_name = None;
+                    if let Some(lfn) = pending_lfn.take() {
+                        let checksum =
+                            ShortFileName::checksum_lfn(&entry.data[0..ShortFileName::MAX_LEN]);
+
+                        if lfn.next_order == 0
+                            && lfn.checksum == checksum
+                            && !entry.attribute().is_volume()
+                        {
+                            long_file_name = Some(lfn);
+                        } else {
+                            self.report_orphaned_lfn(
+                                &directory.path,
+                                &lfn.first_entry,
+                                lfn.entry_count,
+                            )?;
+                        }
+                    }
+
+                    if entry.attribute().is_volume() {
+                        continue;
+                    }
+
+                    // Special entries are only expected at the start of subdirectories
+                    let raw_name = &entry.data[0..ShortFileName::MAX_LEN];
+                    if raw_name == &DOT_NAME[..] || raw_name == &DOT_DOT_NAME[..] {
+                        continue;
+                    }
+
+                    let mut path = directory.path.clone();
+                    path.push('/');
+
+                    // The first raw entry and the raw entry count of the whole entry
+                    let entry_set = match long_file_name {
+                        Some(lfn) => {
+                            path.push_str(&lfn.name);
+                            (lfn.first_entry, lfn.entry_count + 1)
+                        }
+                        None => {
+                            push_short_name(&mut path, &entry);
+                            (entry, 1)
+                        }
+                    };
+
+                    self.check_entry(directory, entry, entry_set, path, pending_directories)?;
+                }
+            }
+        }
+
+        if let Some(lfn) = pending_lfn.take() {
+            self.report_orphaned_lfn(&directory.path, &lfn.first_entry, lfn.entry_count)?;
+        }
+
+        // Report missing special entries
+        if !is_root {
+            if entry_position < 1 {
+                self.check_dot_entry(directory, None, false)?;
+            }
+
+            if entry_position < 2 {
+                self.check_dot_entry(directory, None, true)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check a VFAT long name entry against the sequence it should continue.
+    ///
+    /// Return the sequence updated with the entry.
+    fn check_lfn_entry(
+        &mut self,
+        path: &str,
+        entry: FatDirEntry,
+        pending_lfn: Option<PendingLongFileName>,
+    ) -> FileSystemResult<Option<PendingLongFileName>> {
+        /// The flag marking the last entry of a sequence, stored first on disk.
+        const LAST_LFN_ENTRY: u8 = 0x40;
+
+        /// The max count of entries in a sequence.
+        const MAX_LFN_ENTRIES: u8 = 20;
+
+        let order = entry.data[0];
+        let checksum = entry.data[13];
+
+        let mut part = String::new();
+        if let Some(chars) = entry.long_file_name_raw().and_then(|lfn| lfn.chars()) {
+            for c in chars.iter().take_while(|c| **c != '\0') {
+                part.push(*c);
+            }
+        }
+
+        if (order & LAST_LFN_ENTRY) != 0 {
+            if let Some(lfn) = pending_lfn {
+                self.report_orphaned_lfn(path, &lfn.first_entry, lfn.entry_count)?;
+            }
+
+            let order = order & !LAST_LFN_ENTRY;
+            if order == 0 || order > MAX_LFN_ENTRIES {
+                self.report_orphaned_lfn(path, &entry, 1)?;
+                return Ok(None);
+            }
+
+            return Ok(Some(PendingLongFileName {
+                first_entry: entry,
+                entry_count: 1,
+                next_order: order - 1,
+                checksum,
+                name: part,
+            }));
+        }
+
+        match pending_lfn {
+            Some(mut lfn) if order != 0 && lfn.next_order == order && lfn.checksum == checksum => {
+                part.push_str(&lfn.name);
+                lfn.name = part;
+                lfn.entry_count += 1;
+                lfn.next_order -= 1;
+                Ok(Some(lfn))
+            }
+            pending_lfn => {
+                if let Some(lfn) = pending_lfn {
+                    self.report_orphaned_lfn(path, &lfn.first_entry, lfn.entry_count)?;
+                }
+                self.report_orphaned_lfn(path, &entry, 1)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Check the chain of a file or directory entry and queue the directory for checking.
+    ///
+    /// When repairing, directories pointing to invalid or already used clusters are deleted, shared clusters of files are copied and file sizes are adjusted to their chains.
+    fn check_entry(
+        &mut self,
+        directory: &PendingDirectory,
+        mut entry: FatDirEntry,
+        entry_set: (FatDirEntry, u32),
+        path: String,
+        pending_directories: &mut Vec<PendingDirectory>,
+    ) -> FileSystemResult<()> {
+        let start_cluster = entry.get_cluster().0;
+        let owner = self.add_owner(&path);
+
+        if entry.attribute().is_directory() {
+            self.report.directory_count += 1;
+
+            if self.is_data_cluster(start_cluster) && self.owners[start_cluster as usize] != 0 {
+                let other_owner = self.owners[start_cluster as usize];
+                self.report.issues.push(FsckIssue::CrossLinkedCluster {
+                    cluster: start_cluster,
+                    path,
+                    other_path: self.paths[other_owner as usize - 1].clone(),
+                });
+
+                // Don't walk a directory twice, this could loop forever
+                if self.is_repairing() {
+                    self.delete_entries(&entry_set.0, entry_set.1)?;
+                }
+                return Ok(());
+            }
+
+            let mut clusters = Vec::new();
+            self.walk_chain(owner, start_cluster, false, Some(&mut clusters))?;
+
+            if clusters.is_empty() {
+                if self.is_repairing() {
+                    self.delete_entries(&entry_set.0, entry_set.1)?;
+                }
+                return Ok(());
+            }
+
+            let parent_cluster = if directory.parent_cluster.is_none() {
+                0
+            } else {
+                directory.clusters[0].0
+            };
+
+            pending_directories.push(PendingDirectory {
+                path,
+                clusters,
+                parent_cluster: Some(parent_cluster),
+            });
+        } else {
+            self.report.file_count += 1;
+
+            let file_size = entry.get_file_size();
+            let (cluster_count, first_cluster) = if start_cluster == 0 {
+                (0, 0)
+            } else {
+                self.walk_chain(owner, start_cluster, true, None)?
+            };
+
+            let cluster_size = u64::from(self.fs.boot_record.cluster_size());
+            let expected_cluster_count =
+                utils::align_up(u64::from(file_size), cluster_size) / cluster_size;
+
+            if u64::from(cluster_count) != expected_cluster_count {
+                self.report.issues.push(FsckIssue::FileSizeMismatch {
+                    path,
+                    file_size,
+                    cluster_count,
+                });
+            }
+
+            if self.is_repairing()
+                && (first_cluster != start_cluster
+                    || u64::from(cluster_count) != expected_cluster_count)
+            {
+                let max_file_size = u64::from(u32::max_value());
+                let new_file_size = if u64::from(cluster_count) == expected_cluster_count {
+                    file_size
+                } else {
+                    core::cmp::min(u64::from(cluster_count) * cluster_size, max_file_size) as u32
+                };
+
+                entry.set_cluster(Cluster(first_cluster));
+                entry.set_file_size(new_file_size);
+                entry.flush(self.fs)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Scan the FAT for clusters that aren't reachable from any entry.
+    fn check_lost_clusters(&mut self) -> FileSystemResult<()> {
+        let cluster_count = self.fs.boot_record.cluster_count;
+
+        for cluster in 2..cluster_count {
+            match FatValue::get(self.fs, Cluster(cluster))? {
+                FatValue::Free | FatValue::Bad => {}
+                _ => {
+                    if self.owners[cluster as usize] == 0 {
+                        self.owners[cluster as usize] = LOST_CLUSTER;
+                    }
+                }
+            }
+        }
+
+        // Find the lost clusters that aren't the start of a chain
+        for cluster in 2..cluster_count {
+            if !self.is_lost_cluster(cluster) {
+                continue;
+            }
+
+            if let Some(next_cluster) = self.next_lost_cluster(cluster)? {
+                self.owners[next_cluster as usize] = LOST_LINKED_CLUSTER;
+            }
+        }
+
+        // Chains are reported from their start, what remains after it only contains loops
+        for marker in &[LOST_CLUSTER, LOST_LINKED_CLUSTER] {
+            for cluster in 2..cluster_count {
+                if self.owners[cluster as usize] == *marker {
+                    self.report_lost_chain(cluster)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check if a cluster is lost and not reported yet.
+    fn is_lost_cluster(&self, cluster: u32) -> bool {
+        let owner = self.owners[cluster as usize];
+        owner == LOST_CLUSTER || owner == LOST_LINKED_CLUSTER
+    }
+
+    /// Return the next cluster of a lost chain if it's also lost and not reported yet.
+    fn next_lost_cluster(&self, cluster: u32) -> FileSystemResult<Option<u32>> {
+        match FatValue::get(self.fs, Cluster(cluster))? {
+            FatValue::Data(next_cluster)
+                if self.is_data_cluster(next_cluster) && self.is_lost_cluster(next_cluster) =>
+            {
+                Ok(Some(next_cluster))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Report the lost chain starting at the given cluster.
+    ///
+    /// When repairing, the chain is freed or saved as a file.
+    fn report_lost_chain(&mut self, first_cluster: u32) -> FileSystemResult<()> {
+        let lost_chain_action = self.repair.map(|options| options.lost_chains);
+
+        let mut cluster_count = 0;
+        let mut current_cluster = Some(first_cluster);
+
+        while let Some(cluster) = current_cluster {
+            self.owners[cluster as usize] = LOST_REPORTED_CLUSTER;
+            cluster_count += 1;
+            current_cluster = self.next_lost_cluster(cluster)?;
+
+            match lost_chain_action {
+                Some(LostChainAction::Free) => {
+                    FatValue::put(self.fs, Cluster(cluster), FatValue::Free)?
+                }
+                // The chain may end on a cluster of another chain or on itself
+                Some(LostChainAction::SaveAsFiles) if current_cluster.is_none() => {
+                    FatValue::put(self.fs, Cluster(cluster), FatValue::EndOfChain)?
+                }
+                _ => {}
+            }
+        }
+
+        self.report.issues.push(FsckIssue::LostChain {
+            first_cluster,
+            cluster_count,
+        });
+
+        if lost_chain_action == Some(LostChainAction::SaveAsFiles) {
+            self.save_lost_chain(first_cluster, cluster_count)?;
+        }
+
+        Ok(())
+    }
+
+    /// Create a file in the ``FOUND.000`` directory holding a lost chain.
+    fn save_lost_chain(&mut self, first_cluster: u32, cluster_count: u32) -> FileSystemResult<()> {
+        let fs = self.fs;
+
+        let found_dir_entry = match fs.get_root_directory().find_entry(FOUND_DIR_NAME) {
+            Err(FileSystemError::NotFound) => {
+                fs.mkdir(FOUND_DIR_NAME)?;
+                fs.get_root_directory().find_entry(FOUND_DIR_NAME)?
+            }
+            entry => entry?,
+        };
+
+        if !found_dir_entry.attribute.is_directory() {
+            return Err(FileSystemError::NotADirectory);
+        }
+
+        let found_dir = Directory::from_entry(fs, found_dir_entry);
+
+        // Find an unused file name
+        let file_name = loop {
+            if self.next_found_file_index >= MAX_FOUND_FILES {
+                return Err(FileSystemError::NoSpaceLeft);
+            }
+
+            let file_name = found_file_name(self.next_found_file_index);
+            self.next_found_file_index += 1;
+
+            match found_dir.clone().find_entry(&file_name) {
+                Err(FileSystemError::NotFound) => break file_name,
+                Err(error) => return Err(error),
+                Ok(_) => {}
+            }
+        };
+
+        let file_size = core::cmp::min(
+            u64::from(cluster_count) * u64::from(fs.boot_record.cluster_size()),
+            u64::from(u32::max_value()),
+        ) as u32;
+
+        Directory::create_dir_entry(
+            fs,
+            &found_dir_entry,
+            Attributes::new(0),
+            &file_name,
+            Cluster(first_cluster),
+            file_size,
+        )?;
+
+        Ok(())
+    }
+
+    /// Compare the secondary FATs with the first FAT.
+    fn check_fat_copies(&mut self) -> FileSystemResult<()> {
+        let fs = self.fs;
+        let fat_size = fs.boot_record.fat_size();
+        let first_fat_block = fs.boot_record.reserved_block_count();
+
+        let mut first_blocks = [Block::new()];
+        let mut blocks = [Block::new()];
+
+        for fat_index in 1..u32::from(fs.boot_record.fats_count()) {
+            let mut mismatched_block_count = 0;
+
+            for block_index in 0..fat_size {
+                fs.block_device
+                    .read(
+                        &mut first_blocks,
+                        fs.partition_start,
+                        BlockIndex(first_fat_block + block_index),
+                    )
+                    .or(Err(FileSystemError::ReadFailed))?;
+                fs.block_device
+                    .read(
+                        &mut blocks,
+                        fs.partition_start,
+                        BlockIndex(first_fat_block + fat_index * fat_size + block_index),
+                    )
+                    .or(Err(FileSystemError::ReadFailed))?;
+
+                if first_blocks[0][..] != blocks[0][..] {
+                    mismatched_block_count += 1;
+
+                    // The first FAT is the reference
+                    if self.is_repairing() {
+                        fs.block_device
+                            .write(
+                                &first_blocks,
+                                fs.partition_start,
+                                BlockIndex(first_fat_block + fat_index * fat_size + block_index),
+                            )
+                            .or(Err(FileSystemError::WriteFailed))?;
+                    }
+                }
+            }
+
+            if mismatched_block_count != 0 {
+                self.report.issues.push(FsckIssue::FatCopyMismatch {
+                    fat_index,
+                    mismatched_block_count,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Count the free clusters and compare the count with the one stored in the FS Info structure.
+    fn check_fs_info(&mut self) -> FileSystemResult<()> {
+        let actual = table::get_free_cluster_count(self.fs)?;
+        self.report.free_cluster_count = actual;
+
+        if self.fs.boot_record.fat_type != FatFsType::Fat32 {
+            return Ok(());
+        }
+
+        let stored = FatFileSystemInfo::from_fs(self.fs)?
+            .free_cluster
+            .load(Ordering::SeqCst);
+
+        // 0xFFFFFFFF means that the count is unknown
+        if stored != 0xFFFF_FFFF && stored != actual {
+            self.report
+                .issues
+                .push(FsckIssue::FsInfoFreeCountMismatch { stored, actual });
+        }
+
+        Ok(())
+    }
+}
+
+/// Return the path of a directory, "/" for the root directory.
+fn directory_path(path: &str) -> String {
+    if path.is_empty() {
+        String::from("/")
+    } else {
+        String::from(path)
+    }
+}
+
+/// Return the name of the file at the given index in the ``FOUND.000`` directory.
+fn found_file_name(index: u32) -> String {
+    let mut file_name = String::from("FILE");
+
+    for divisor in &[1000, 100, 10, 1] {
+        file_name.push((b'0' + ((index / divisor) % 10) as u8) as char);
+    }
+
+    file_name.push_str(".CHK");
+    file_name
+}
+
+/// Append the 8.3 name of an entry to a path.
+fn push_short_name(path: &mut String, entry: &FatDirEntry) {
+    let raw_name = ShortFileName::from_data(&entry.data[0..ShortFileName::MAX_LEN]).chars();
+
+    for c in raw_name.iter().take(8).take_while(|c| **c != ' ') {
+        path.push(*c);
+    }
+
+    // Short filename with extension
+    if raw_name[8] != ' ' {
+        path.push('.');
+        for c in raw_name.iter().skip(8).take_while(|c| **c != ' ') {
+            path.push(*c);
+        }
+    }
+}
+
+/// Check the consistency of a FAT filesystem.
+///
+/// The directory tree is walked from the root directory and cross-referenced with the cluster chains of the FAT. Nothing is written to the filesystem.
+///
+/// The check allocates 4 bytes per cluster of the volume and fails if this memory isn't available.
+pub fn check<T>(fs: &FatFileSystem<T>) -> FileSystemResult<FsckReport>
+where
+    T: BlockDevice,
+{
+    let mut checker = Checker::new(fs, None)?;
+
+    checker.check_fs_info()?;
+    checker.check_tree()?;
+    checker.check_lost_clusters()?;
+    checker.check_fat_copies()?;
+
+    Ok(checker.report)
+}
+
+/// Check the consistency of a FAT filesystem and repair the inconsistencies found.
+///
+/// Return the inconsistencies found before the repair.
+pub fn repair<T>(fs: &FatFileSystem<T>, options: &RepairOptions) -> FileSystemResult<FsckReport>
+where
+    T: BlockDevice,
+{
+    let mut checker = Checker::new(fs, Some(*options))?;
+
+    checker.check_fs_info()?;
+    checker.check_tree()?;
+    checker.check_lost_clusters()?;
+    checker.check_fat_copies()?;
+
+    // Clusters were freed and allocated behind the back of the filesystem
+    checker.report.free_cluster_count = fs.rebuild_fs_info()?;
+
+    // The volume is now consistent and can be marked clean on the next flush
+    fs.allow_mark_clean();
+
+    Ok(checker.report)
+}
@@ -0,0 +1,416 @@
+//! GUID Partition Table support.
+
+use alloc::vec::Vec;
+use byteorder::{ByteOrder, LittleEndian};
+
+use libfs::block::{Block, BlockCount, BlockDevice, BlockIndex};
+use libfs::FileSystemError;
+
+use crate::utils;
+
+/// Represent a GUID as stored on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// The GUID of unused partition entries.
+    pub const UNUSED: Guid = Guid([0; 16]);
+
+    /// The partition type GUID of EFI System Partitions.
+    pub const EFI_SYSTEM_PARTITION: Guid = Guid::from_fields(
+        0xC12A_7328,
+        0xF81F,
+        0x11D2,
+        [0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B],
+    );
+
+    /// The partition type GUID of Microsoft Basic Data partitions.
+    pub const MICROSOFT_BASIC_DATA: Guid = Guid::from_fields(
+        0xEBD0_A0A2,
+        0xB9E5,
+        0x4433,
+        [0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7],
+    );
+
+    /// Create a GUID from its textual representation fields (``data1-data2-data3-data4``).
+    ///
+    /// The first three fields are stored little endian on disk.
+    pub const fn from_fields(data1: u32, data2: u16, data3: u16, data4: [u8; 8]) -> Guid {
+        Guid([
+            data1 as u8,
+            (data1 >> 8) as u8,
+            (data1 >> 16) as u8,
+            (data1 >> 24) as u8,
+            data2 as u8,
+            (data2 >> 8) as u8,
+            data3 as u8,
+            (data3 >> 8) as u8,
+            data4[0],
+            data4[1],
+            data4[2],
+            data4[3],
+            data4[4],
+            data4[5],
+            data4[6],
+            data4[7],
+        ])
+    }
+
+    /// Create a GUID from raw data.
+    fn from_raw(data: &[u8]) -> Guid {
+        let mut res = [0x0; 16];
+        res.copy_from_slice(&data[..16]);
+        Guid(res)
+    }
+}
+
+/// Represent a partition entry of the GUID Partition Table.
+#[derive(Debug, Clone, Copy)]
+pub struct GptPartitionEntry {
+    /// The position of the entry in the partition entry array.
+    pub index: u32,
+
+    /// The type of the partition.
+    pub type_guid: Guid,
+
+    /// The unique GUID of the partition.
+    pub partition_guid: Guid,
+
+    /// The first LBA of the partition.
+    pub first_lba: u64,
+
+    /// The last LBA of the partition (inclusive).
+    pub last_lba: u64,
+
+    /// The attribute flags of the partition.
+    pub attributes: u64,
+
+    /// The UTF-16 name of the partition. Unused characters are zero.
+    pub name: [u16; GptPartitionEntry::NAME_LEN],
+}
+
+impl GptPartitionEntry {
+    /// The maximum length of a partition name.
+    pub const NAME_LEN: usize = 36;
+
+    /// The minimum size of a partition entry.
+    const MIN_LEN: usize = 128;
+
+    /// Parse a partition entry from raw data.
+    fn from_raw(index: u32, data: &[u8]) -> GptPartitionEntry {
+        let mut name = [0x0; Self::NAME_LEN];
+        LittleEndian::read_u16_into(&data[56..56 + Self::NAME_LEN * 2], &mut name);
+
+        GptPartitionEntry {
+            index,
+            type_guid: Guid::from_raw(&data[0..16]),
+            partition_guid: Guid::from_raw(&data[16..32]),
+            first_lba: LittleEndian::read_u64(&data[32..40]),
+            last_lba: LittleEndian::read_u64(&data[40..48]),
+            attributes: LittleEndian::read_u64(&data[48..56]),
+            name,
+        }
+    }
+
+    /// Return true if the entry describes a partition.
+    pub fn is_used(&self) -> bool {
+        self.type_guid != Guid::UNUSED
+    }
+
+    /// The amount of sectors in the partition, or None if the entry ends before it starts.
+    pub fn sector_count(&self) -> Option<u64> {
+        self.last_lba.checked_sub(self.first_lba)?.checked_add(1)
+    }
+}
+
+/// Represent a GUID Partition Table header.
+struct GptHeader {
+    /// The actual data of the header.
+    data: Block,
+}
+
+#[allow(dead_code)]
+impl GptHeader {
+    /// The signature of a GPT header ("EFI PART").
+    const SIGNATURE: &'static [u8] = b"EFI PART";
+
+    /// The minimum size of a GPT header.
+    const MIN_LEN: usize = 92;
+
+    /// The maximum size of the partition entry array we agree to load.
+    const MAX_PARTITION_ARRAY_SIZE: usize = 1024 * 1024;
+
+    /// Checks the validity of the header, ``lba`` being the sector the header was read from.
+    pub fn is_valid(&self, lba: u64) -> bool {
+        if &self.data[0..8] != Self::SIGNATURE {
+            return false;
+        }
+
+        let header_size = self.header_size() as usize;
+        if header_size < Self::MIN_LEN || header_size > Block::LEN {
+            return false;
+        }
+
+        // The CRC is computed with the CRC field zeroed.
+        let mut header = [0x0; Block::LEN];
+        header[..header_size].copy_from_slice(&self.data[..header_size]);
+        header[16..20].copy_from_slice(&[0x0; 4]);
+
+        if utils::crc32(&header[..header_size]) != self.header_crc32() {
+            return false;
+        }
+
+        if self.my_lba() != lba || self.first_usable_lba() > self.last_usable_lba() {
+            return false;
+        }
+
+        let entry_size = self.partition_entry_size() as usize;
+        if entry_size < GptPartitionEntry::MIN_LEN || !entry_size.is_power_of_two() {
+            return false;
+        }
+
+        (self.partition_entry_count() as usize)
+            .checked_mul(entry_size)
+            .map(|size| size <= Self::MAX_PARTITION_ARRAY_SIZE)
+            .unwrap_or(false)
+    }
+
+    /// The size of the header in bytes.
+    pub fn header_size(&self) -> u32 {
+        LittleEndian::read_u32(&self.data[12..16])
+    }
+
+    /// The CRC32 of the header.
+    pub fn header_crc32(&self) -> u32 {
+        LittleEndian::read_u32(&self.data[16..20])
+    }
+
+    /// The LBA of this header.
+    pub fn my_lba(&self) -> u64 {
+        LittleEndian::read_u64(&self.data[24..32])
+    }
+
+    /// The LBA of the other header.
+    pub fn alternate_lba(&self) -> u64 {
+        LittleEndian::read_u64(&self.data[32..40])
+    }
+
+    /// The first LBA usable by partitions.
+    pub fn first_usable_lba(&self) -> u64 {
+        LittleEndian::read_u64(&self.data[40..48])
+    }
+
+    /// The last LBA usable by partitions.
+    pub fn last_usable_lba(&self) -> u64 {
+        LittleEndian::read_u64(&self.data[48..56])
+    }
+
+    /// The GUID of the disk.
+    pub fn disk_guid(&self) -> Guid {
+        Guid::from_raw(&self.data[56..72])
+    }
+
+    /// The first LBA of the partition entry array.
+    pub fn partition_entry_lba(&self) -> u64 {
+        LittleEndian::read_u64(&self.data[72..80])
+    }
+
+    /// The count of entries in the partition entry array.
+    pub fn partition_entry_count(&self) -> u32 {
+        LittleEndian::read_u32(&self.data[80..84])
+    }
+
+    /// The size of a partition entry.
+    pub fn partition_entry_size(&self) -> u32 {
+        LittleEndian::read_u32(&self.data[84..88])
+    }
+
+    /// The CRC32 of the partition entry array.
+    pub fn partition_entry_array_crc32(&self) -> u32 {
+        LittleEndian::read_u32(&self.data[88..92])
+    }
+}
+
+/// Represent a GUID Partition Table.
+pub struct GptPartitionTable {
+    /// The GUID of the disk.
+    pub disk_guid: Guid,
+
+    /// The used partition entries.
+    entries: Vec<GptPartitionEntry>,
+
+    /// The amount of blocks per device sector.
+    blocks_per_sector: u32,
+}
+
+impl GptPartitionTable {
+    /// Read the GUID Partition Table of a block device.
+    ///
+    /// If the primary header or its partition entry array is corrupted, the backup header is used.
+    pub fn read<T>(block_device: &T) -> Result<GptPartitionTable, FileSystemError>
+    where
+        T: BlockDevice,
+    {
+        let blocks_per_sector = block_device.sector_size() / Block::LEN_U32;
+        if blocks_per_sector == 0 {
+            return Err(FileSystemError::InvalidPartition);
+        }
+
+        let primary = Self::read_header(block_device, blocks_per_sector, 1)?;
+
+        let backup_lba = match primary {
+            Some(ref header) => {
+                if let Some(entries) = Self::read_entries(block_device, blocks_per_sector, header)?
+                {
+                    return Ok(GptPartitionTable {
+                        disk_guid: header.disk_guid(),
+                        entries,
+                        blocks_per_sector,
+                    });
+                }
+
+                header.alternate_lba()
+            }
+            None => {
+                let block_count = block_device.count().or(Err(FileSystemError::ReadFailed))?;
+
+                u64::from(block_count.0 / blocks_per_sector)
+                    .checked_sub(1)
+                    .ok_or(FileSystemError::InvalidPartition)?
+            }
+        };
+
+        if let Some(header) = Self::read_header(block_device, blocks_per_sector, backup_lba)? {
+            if let Some(entries) = Self::read_entries(block_device, blocks_per_sector, &header)? {
+                return Ok(GptPartitionTable {
+                    disk_guid: header.disk_guid(),
+                    entries,
+                    blocks_per_sector,
+                });
+            }
+        }
+
+        Err(FileSystemError::InvalidPartition)
+    }
+
+    /// Read a header at the given LBA and return it if it's valid.
+    fn read_header<T>(
+        block_device: &T,
+        blocks_per_sector: u32,
+        lba: u64,
+    ) -> Result<Option<GptHeader>, FileSystemError>
+    where
+        T: BlockDevice,
+    {
+        let block_index = match Self::lba_to_block_index(blocks_per_sector, lba) {
+            Some(block_index) => block_index,
+            None => return Ok(None),
+        };
+
+        let mut blocks = [Block::new()];
+        block_device
+            .raw_read(&mut blocks, block_index)
+            .or(Err(FileSystemError::ReadFailed))?;
+
+        let header = GptHeader {
+            data: blocks[0].clone(),
+        };
+
+        if header.is_valid(lba) {
+            Ok(Some(header))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Read the partition entry array described by a header and return its used entries if its CRC32 is valid.
+    fn read_entries<T>(
+        block_device: &T,
+        blocks_per_sector: u32,
+        header: &GptHeader,
+    ) -> Result<Option<Vec<GptPartitionEntry>>, FileSystemError>
+    where
+        T: BlockDevice,
+    {
+        let entry_size = header.partition_entry_size() as usize;
+        let array_size = header.partition_entry_count() as usize * entry_size;
+
+        let block_index =
+            match Self::lba_to_block_index(blocks_per_sector, header.partition_entry_lba()) {
+                Some(block_index) => block_index,
+                None => return Ok(None),
+            };
+
+        let mut data = Vec::with_capacity(array_size);
+        let mut blocks = [Block::new()];
+        let mut index = 0;
+
+        while data.len() < array_size {
+            block_device
+                .raw_read(&mut blocks, BlockIndex(block_index.0 + index))
+                .or(Err(FileSystemError::ReadFailed))?;
+
+            let len = core::cmp::min(Block::LEN, array_size - data.len());
+            data.extend_from_slice(&blocks[0][..len]);
+            index += 1;
+        }
+
+        if utils::crc32(&data) != header.partition_entry_array_crc32() {
+            return Ok(None);
+        }
+
+        let entries = data
+            .chunks(entry_size)
+            .enumerate()
+            .map(|(index, raw_entry)| GptPartitionEntry::from_raw(index as u32, raw_entry))
+            .filter(GptPartitionEntry::is_used)
+            .collect();
+
+        Ok(Some(entries))
+    }
+
+    /// Convert a LBA to a block index, returning None if it cannot be addressed.
+    fn lba_to_block_index(blocks_per_sector: u32, lba: u64) -> Option<BlockIndex> {
+        let block_index = lba.checked_mul(u64::from(blocks_per_sector))?;
+
+        if block_index > u64::from(u32::max_value()) {
+            return None;
+        }
+
+        Some(BlockIndex(block_index as u32))
+    }
+
+    /// Iterate over the used partition entries.
+    pub fn iter(&self) -> core::slice::Iter<'_, GptPartitionEntry> {
+        self.entries.iter()
+    }
+
+    /// Get the partition entry at the given position of the partition entry array.
+    pub fn get(&self, index: u32) -> Option<&GptPartitionEntry> {
+        self.entries.iter().find(|entry| entry.index == index)
+    }
+
+    /// Get the partition entry with the given unique partition GUID.
+    pub fn find_by_guid(&self, partition_guid: &Guid) -> Option<&GptPartitionEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.partition_guid == *partition_guid)
+    }
+
+    /// Compute the first block and the block count of a partition.
+    pub fn partition_bounds(
+        &self,
+        entry: &GptPartitionEntry,
+    ) -> Result<(BlockIndex, BlockCount), FileSystemError> {
+        let sector_count = entry
+            .sector_count()
+            .ok_or(FileSystemError::InvalidPartition)?;
+
+        let start = Self::lba_to_block_index(self.blocks_per_sector, entry.first_lba)
+            .ok_or(FileSystemError::InvalidPartition)?;
+        let count = Self::lba_to_block_index(self.blocks_per_sector, sector_count)
+            .ok_or(FileSystemError::InvalidPartition)?;
+
+        Ok((start, BlockCount(count.0)))
+    }
+}
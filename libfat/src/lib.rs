@@ -2,6 +2,7 @@
 //! The FAT library
 
 #![feature(alloc)]
+#![feature(try_reserve)]
 #![no_std]
 #![warn(
     clippy::cast_possible_wrap,
@@ -17,13 +18,23 @@
     clippy::wrong_pub_self_convention
 )]
 
+extern crate alloc;
+
 pub mod attribute;
 pub(crate) mod block_iter;
 pub(crate) mod cluster;
 pub mod datetime;
 pub mod directory;
+pub mod exfat;
+pub mod extent;
 pub mod filesystem;
+pub mod format;
+pub(crate) mod free_bitmap;
+pub mod fsck;
+pub mod gpt;
+pub mod mbr;
 pub mod name;
+pub mod partition;
 pub(crate) mod table;
 mod utils;
 
@@ -33,11 +44,14 @@ use libfs::block::{Block, BlockCount, BlockDevice, BlockIndex};
 use cluster::Cluster;
 
 use filesystem::FatFileSystem;
+use gpt::{GptPartitionEntry, GptPartitionTable, Guid};
+use mbr::MbrPartitionTable;
+use partition::PartitionInfo;
 
 use libfs::FileSystemError;
 
 /// Represent FAT filesystem types.
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FatFsType {
     /// FAT12 volume.
     Fat12,
@@ -52,6 +66,55 @@ pub enum FatFsType {
     ExFat,
 }
 
+/// Represent the statistics of a FAT volume.
+#[derive(Debug, Clone, Copy)]
+pub struct VolumeStatistics {
+    /// The type of the volume.
+    pub fat_type: FatFsType,
+
+    /// The serial number of the volume. Zero if the volume doesn't have one.
+    pub serial_number: u32,
+
+    /// The size of a cluster in bytes.
+    pub cluster_size: u64,
+
+    /// The count of data clusters of the volume.
+    pub cluster_count: u32,
+
+    /// The count of free data clusters of the volume.
+    pub free_cluster_count: u32,
+
+    /// The size of the data area in bytes.
+    pub total_bytes: u64,
+
+    /// The free space of the data area in bytes.
+    pub free_bytes: u64,
+}
+
+impl VolumeStatistics {
+    /// Create the statistics of a volume and compute the sizes in bytes.
+    pub(crate) fn new(
+        fat_type: FatFsType,
+        serial_number: u32,
+        cluster_size: u64,
+        cluster_count: u32,
+        free_cluster_count: u32,
+    ) -> VolumeStatistics {
+        // A stale FS Info structure may report more free clusters than the volume has
+        let free_cluster_count = free_cluster_count.min(cluster_count);
+
+        VolumeStatistics {
+            fat_type,
+            serial_number,
+            cluster_size,
+            cluster_count,
+            free_cluster_count,
+            total_bytes: u64::from(cluster_count) * cluster_size,
+            free_bytes: u64::from(free_cluster_count) * cluster_size,
+        }
+    }
+}
+
 /// Represent the FAT Volume BootRecord.
 struct FatVolumeBootRecord {
     /// The actual data of the boot record.
@@ -74,14 +137,24 @@ impl FatVolumeBootRecord {
             cluster_count: 0,
         };
 
-        let root_dir_blocks = ((u32::from(res.root_dir_childs_count()) * 32)
-            + (u32::from(res.bytes_per_block()) - 1))
-            / u32::from(res.bytes_per_block());
-        let data_blocks = res.total_blocks()
-            - (u32::from(res.reserved_block_count())
-                + (u32::from(res.fats_count()) * res.fat_size())
-                + root_dir_blocks);
-        let cluster_count = data_blocks / u32::from(res.blocks_per_cluster());
+        if &res.data[3..11] == exfat::ExFatBootRecord::FILE_SYSTEM_NAME {
+            res.fat_type = FatFsType::ExFat;
+            return res;
+        }
+
+        // Invalid boot record, is_valid will reject it.
+        if res.sectors_per_cluster() == 0 || res.blocks_per_sector() == 0 {
+            return res;
+        }
+
+        // Invalid layout, is_valid will reject it.
+        let first_data_offset = match res.checked_first_data_offset() {
+            Some(first_data_offset) => first_data_offset,
+            None => return res,
+        };
+
+        let data_blocks = res.total_blocks().saturating_sub(first_data_offset);
+        let cluster_count = data_blocks / res.blocks_per_cluster();
         if cluster_count < 4085 {
             res.fat_type = FatFsType::Fat12;
         } else if cluster_count < 65525 {
@@ -89,7 +162,14 @@ impl FatVolumeBootRecord {
         } else {
             res.fat_type = FatFsType::Fat32;
         }
-        res.cluster_count = cluster_count + 2;
+
+        // Clusters whose number is a reserved FAT value can't be linked in a chain, leave them unused
+        let max_cluster_count = match res.fat_type {
+            FatFsType::Fat12 => 0x0FF0,
+            FatFsType::Fat16 => 0xFFF0,
+            _ => 0x0FFF_FFF0,
+        };
+        res.cluster_count = (cluster_count + 2).min(max_cluster_count);
 
         res
     }
@@ -99,8 +179,8 @@ impl FatVolumeBootRecord {
         /// Offset of the boot signature.
         const BOOTABLE_SIGNATURE: usize = 510;
 
-        /// Offset of the FAT system identifier.
-        const SYSTEM_IDENTIFIER_FAT: usize = 36;
+        /// Offset of the FAT12/FAT16 system identifier.
+        const SYSTEM_IDENTIFIER_FAT: usize = 54;
 
         /// Offset of the FAT32 system identifier.
         const SYSTEM_IDENTIFIER_FAT32: usize = 82;
@@ -124,29 +204,61 @@ impl FatVolumeBootRecord {
             return false;
         }
 
-        // TODO: bytes per block that are > to 512 bytes.
-        if self.bytes_per_block() != Block::LEN as u16 {
+        // check media descriptor, 0xF1 to 0xF7 are reserved
+        match self.media_type() {
+            0xF0 | 0xF8..=0xFF => {}
+            _ => return false,
+        }
+
+        // logical sectors must be made of whole blocks
+        match self.bytes_per_sector() {
+            512 | 1024 | 2048 | 4096 => {}
+            _ => return false,
+        }
+
+        // the layout must be addressable with 32 bits block indexes
+        if self.checked_total_blocks().is_none() || self.checked_first_data_offset().is_none() {
             return false;
         }
 
         true
     }
 
-    /// The amount of bytes per block.
-    pub fn bytes_per_block(&self) -> u16 {
+    /// The amount of bytes per logical sector.
+    pub fn bytes_per_sector(&self) -> u16 {
         LittleEndian::read_u16(&self.data[11..13])
     }
 
-    /// The amount of blocks per cluster.
-    pub fn blocks_per_cluster(&self) -> u8 {
+    /// The amount of blocks per logical sector.
+    pub fn blocks_per_sector(&self) -> u32 {
+        u32::from(self.bytes_per_sector()) / Block::LEN_U32
+    }
+
+    /// The amount of logical sectors per cluster.
+    pub fn sectors_per_cluster(&self) -> u8 {
         self.data[13]
     }
 
-    /// The count of reserved block.
-    pub fn reserved_block_count(&self) -> u16 {
+    /// The amount of blocks per cluster.
+    pub fn blocks_per_cluster(&self) -> u32 {
+        u32::from(self.sectors_per_cluster()) * self.blocks_per_sector()
+    }
+
+    /// The size of a cluster in bytes.
+    pub fn cluster_size(&self) -> u32 {
+        self.blocks_per_cluster() * Block::LEN_U32
+    }
+
+    /// The count of reserved logical sectors.
+    pub fn reserved_sector_count(&self) -> u16 {
         LittleEndian::read_u16(&self.data[14..16])
     }
 
+    /// The count of reserved blocks.
+    pub fn reserved_block_count(&self) -> u32 {
+        u32::from(self.reserved_sector_count()) * self.blocks_per_sector()
+    }
+
     /// The number of FAT present in the filesystem.
     pub fn fats_count(&self) -> u8 {
         self.data[16]
@@ -157,7 +269,7 @@ impl FatVolumeBootRecord {
         LittleEndian::read_u16(&self.data[17..19])
     }
 
-    /// The total of blocks of the filesystem. If zero, uses ``total_blocks32``.
+    /// The total of logical sectors of the filesystem. If zero, uses ``total_blocks32``.
     pub fn total_blocks16(&self) -> u16 {
         LittleEndian::read_u16(&self.data[19..21])
     }
@@ -167,7 +279,7 @@ impl FatVolumeBootRecord {
         self.data[21]
     }
 
-    /// Return the size in cluster of the FAT for FAT12/FAT16 filesystems.
+    /// Return the size in logical sectors of the FAT for FAT12/FAT16 filesystems.
     pub fn fat_size16(&self) -> u16 {
         LittleEndian::read_u16(&self.data[22..24])
     }
@@ -187,44 +299,159 @@ impl FatVolumeBootRecord {
         LittleEndian::read_u32(&self.data[28..32])
     }
 
-    /// The total block count on a FAT32 filesystem.
+    /// The total logical sector count on a FAT32 filesystem.
     pub fn total_blocks32(&self) -> u32 {
         LittleEndian::read_u32(&self.data[32..36])
     }
 
-    /// Return the size in cluster of the FAT for FAT32 filesystems.
+    /// Return the size in logical sectors of the FAT for FAT32 filesystems.
     pub fn fat_size32(&self) -> u32 {
         LittleEndian::read_u32(&self.data[36..40])
     }
 
-    /// The block index of the FAT32's filesystem informations.
-    pub fn fs_info_block(&self) -> u16 {
+    /// The logical sector index of the FAT32's filesystem informations.
+    pub fn fs_info_sector(&self) -> u16 {
         LittleEndian::read_u16(&self.data[48..50])
     }
 
-    /// The root directory cluster for FAT12/FAT16 filesystems.
+    /// The block index of the FAT32's filesystem informations.
+    pub fn fs_info_block(&self) -> u32 {
+        u32::from(self.fs_info_sector()) * self.blocks_per_sector()
+    }
+
+    /// The logical sector index of the FAT32's backup boot record. Zero if there isn't any.
+    pub fn backup_boot_sector(&self) -> u16 {
+        if self.fat_type == FatFsType::Fat32 {
+            LittleEndian::read_u16(&self.data[50..52])
+        } else {
+            0
+        }
+    }
+
+    /// The offset of the extended BIOS parameter block.
+    fn extended_bpb_offset(&self) -> usize {
+        if self.fat_type == FatFsType::Fat32 {
+            64
+        } else {
+            36
+        }
+    }
+
+    /// Check if the extended boot signature is present, meaning that the volume label field is valid.
+    pub fn has_extended_boot_signature(&self) -> bool {
+        self.data[self.extended_bpb_offset() + 2] == 0x29
+    }
+
+    /// The volume serial number stored in the extended BIOS parameter block, if any.
+    pub fn volume_id(&self) -> Option<u32> {
+        let offset = self.extended_bpb_offset();
+
+        match self.data[offset + 2] {
+            0x28 | 0x29 => Some(LittleEndian::read_u32(&self.data[offset + 3..offset + 7])),
+            _ => None,
+        }
+    }
+
+    /// The volume label stored in the extended BIOS parameter block, if any.
+    pub fn volume_label(&self) -> Option<[u8; 11]> {
+        if !self.has_extended_boot_signature() {
+            return None;
+        }
+
+        let offset = self.extended_bpb_offset() + 7;
+        let mut res = [0x0u8; 11];
+        res.copy_from_slice(&self.data[offset..offset + 11]);
+
+        Some(res)
+    }
+
+    /// Set the volume label stored in the extended BIOS parameter block.
+    /// Return false if the boot record doesn't have a volume label field.
+    pub fn set_volume_label(&mut self, volume_label: &[u8; 11]) -> bool {
+        if !self.has_extended_boot_signature() {
+            return false;
+        }
+
+        let offset = self.extended_bpb_offset() + 7;
+        self.data[offset..offset + 11].copy_from_slice(volume_label);
+
+        true
+    }
+
+    /// The root directory cluster.
+    ///
+    /// On FAT12/FAT16 filesystems, the root directory lives in a fixed region outside of the data area and ``Cluster(0)`` is returned.
     pub fn root_dir_childs_cluster(&self) -> Cluster {
-        Cluster(LittleEndian::read_u32(&self.data[44..48]))
+        if self.fat_type == FatFsType::Fat32 {
+            Cluster(LittleEndian::read_u32(&self.data[44..48]))
+        } else {
+            Cluster(0)
+        }
+    }
+
+    /// The count of blocks used by the root directory region of FAT12/FAT16 filesystems. Always zero on FAT32.
+    pub fn root_dir_blocks(&self) -> u32 {
+        let bytes_per_sector = u32::from(self.bytes_per_sector());
+        let root_dir_sectors = ((u32::from(self.root_dir_childs_count()) * 32)
+            + (bytes_per_sector - 1))
+            / bytes_per_sector;
+
+        root_dir_sectors * self.blocks_per_sector()
     }
 
-    /// Return the size in cluster of the FAT.
+    /// The block index of the root directory region of FAT12/FAT16 filesystems.
+    ///
+    /// Saturates on overflow, such boot records are rejected by ``is_valid``.
+    pub fn root_dir_offset(&self) -> u32 {
+        u32::from(self.fats_count())
+            .saturating_mul(self.fat_size())
+            .saturating_add(self.reserved_block_count())
+    }
+
+    /// The block index of the data region, None if it overflows.
+    fn checked_first_data_offset(&self) -> Option<u32> {
+        u32::from(self.fats_count())
+            .checked_mul(self.checked_fat_size()?)?
+            .checked_add(self.reserved_block_count())?
+            .checked_add(self.root_dir_blocks())
+    }
+
+    /// Return the size in blocks of the FAT.
+    ///
+    /// Saturates on overflow, such boot records are rejected by ``is_valid``.
     pub fn fat_size(&self) -> u32 {
+        self.checked_fat_size().unwrap_or_else(u32::max_value)
+    }
+
+    /// The size in blocks of the FAT, None if it overflows.
+    fn checked_fat_size(&self) -> Option<u32> {
         let result = u32::from(self.fat_size16());
-        if result != 0 {
+        let result = if result != 0 {
             result
         } else {
             self.fat_size32()
-        }
+        };
+
+        result.checked_mul(self.blocks_per_sector())
     }
 
     /// The total block count on a FAT filesystem.
+    ///
+    /// Saturates on overflow, such boot records are rejected by ``is_valid``.
     pub fn total_blocks(&self) -> u32 {
+        self.checked_total_blocks().unwrap_or_else(u32::max_value)
+    }
+
+    /// The total block count, None if it overflows.
+    fn checked_total_blocks(&self) -> Option<u32> {
         let result = u32::from(self.total_blocks16());
-        if result != 0 {
+        let result = if result != 0 {
             result
         } else {
             self.total_blocks32()
-        }
+        };
+
+        result.checked_mul(self.blocks_per_sector())
     }
 }
 
@@ -252,10 +479,12 @@ where
     }
 
     match boot_record.fat_type {
-        FatFsType::Fat12 | FatFsType::Fat16 | FatFsType::ExFat => unimplemented!(),
-        FatFsType::Fat32 => {
-            let first_data_offset = u32::from(boot_record.reserved_block_count())
-                + (u32::from(boot_record.fats_count()) * boot_record.fat_size());
+        // exFAT volumes are handled by the exfat module.
+        FatFsType::ExFat => Err(FileSystemError::InvalidPartition),
+        FatFsType::Fat12 | FatFsType::Fat16 | FatFsType::Fat32 => {
+            let first_data_offset = boot_record
+                .checked_first_data_offset()
+                .ok_or(FileSystemError::InvalidPartition)?;
             let mut file_system = FatFileSystem::new(
                 block_device,
                 partition_start,
@@ -278,57 +507,85 @@ where
 }
 
 /// Parse the MBR and return an instance to a filesystem at the given partition index.
-pub fn get_partition<T>(
-    block_device: T,
-    index: BlockIndex,
-) -> Result<FatFileSystem<T>, FileSystemError>
+pub fn get_partition<T>(block_device: T, index: u32) -> Result<FatFileSystem<T>, FileSystemError>
 where
     T: BlockDevice,
 {
-    let mut blocks = [Block::new()];
+    let partition_table = MbrPartitionTable::read(&block_device)?;
 
-    /// The Partition Table offset.
-    const PARITION_TABLE_OFFSET: usize = 446;
+    let partition = partition_table
+        .get(index)
+        .ok_or(FileSystemError::PartitionNotFound)?;
 
-    /// The MBR signature offset.
-    const MBR_SIGNATURE: usize = 510;
+    if !partition.is_fat() {
+        return Err(FileSystemError::Custom {
+            name: "Unknown Partition Type",
+        });
+    }
 
-    /// The size of a partition table entry.
-    const PARITION_TABLE_ENTRY_SIZE: usize = 16;
+    let (partition_start, partition_block_count) = partition_table.partition_bounds(partition)?;
 
-    block_device
-        .raw_read(&mut blocks, index)
-        .or(Err(FileSystemError::ReadFailed))?;
+    parse_fat_boot_record(block_device, partition_start, partition_block_count)
+}
 
-    let block = &blocks[0];
+/// Open the FAT filesystem of a GPT partition.
+fn open_gpt_partition<T>(
+    block_device: T,
+    partition_table: &GptPartitionTable,
+    entry: &GptPartitionEntry,
+) -> Result<FatFileSystem<T>, FileSystemError>
+where
+    T: BlockDevice,
+{
+    let (partition_start, partition_block_count) = partition_table.partition_bounds(entry)?;
 
-    if LittleEndian::read_u16(&block[MBR_SIGNATURE..MBR_SIGNATURE + 2]) != 0xAA55 {
-        return Err(FileSystemError::InvalidPartition);
-    }
+    parse_fat_boot_record(block_device, partition_start, partition_block_count)
+}
 
-    let partition = if index.0 < 4 {
-        let offset = PARITION_TABLE_OFFSET + (PARITION_TABLE_ENTRY_SIZE * (index.0 as usize));
-        &block[offset..offset + PARITION_TABLE_ENTRY_SIZE]
-    } else {
-        return Err(FileSystemError::PartitionNotFound);
-    };
+/// Parse the GPT and return an instance to a filesystem at the given index of the partition entry array.
+pub fn get_gpt_partition<T>(
+    block_device: T,
+    index: u32,
+) -> Result<FatFileSystem<T>, FileSystemError>
+where
+    T: BlockDevice,
+{
+    let partition_table = GptPartitionTable::read(&block_device)?;
+    let entry = *partition_table
+        .get(index)
+        .ok_or(FileSystemError::PartitionNotFound)?;
 
-    if (partition[0] & 0x7F) != 0 {
-        return Err(FileSystemError::InvalidPartition);
-    }
+    open_gpt_partition(block_device, &partition_table, &entry)
+}
 
-    let partition_start = LittleEndian::read_u32(&partition[0x8..0xC]);
-    let partition_block_count = LittleEndian::read_u32(&partition[0xC..0x10]);
-    let partition_type: u32 = partition[0x4].into();
+/// Parse the GPT and return an instance to a filesystem on the partition with the given unique GUID.
+pub fn get_gpt_partition_by_guid<T>(
+    block_device: T,
+    partition_guid: &Guid,
+) -> Result<FatFileSystem<T>, FileSystemError>
+where
+    T: BlockDevice,
+{
+    let partition_table = GptPartitionTable::read(&block_device)?;
+    let entry = *partition_table
+        .find_by_guid(partition_guid)
+        .ok_or(FileSystemError::PartitionNotFound)?;
 
-    match partition_type {
-        0xC => parse_fat_boot_record(
-            block_device,
-            BlockIndex(partition_start),
-            BlockCount(partition_block_count),
-        ),
-        _ => Err(FileSystemError::Custom {
-            name: "Unknown Partition Type",
-        }),
+    open_gpt_partition(block_device, &partition_table, &entry)
+}
+
+/// Return an instance to the filesystem of a partition returned by ``partition::list_partitions``.
+pub fn open_partition<T>(
+    block_device: T,
+    partition: &PartitionInfo,
+) -> Result<FatFileSystem<T>, FileSystemError>
+where
+    T: BlockDevice,
+{
+    match partition.filesystem {
+        Some(FatFsType::Fat12) | Some(FatFsType::Fat16) | Some(FatFsType::Fat32) => {
+            parse_fat_boot_record(block_device, partition.start, partition.block_count)
+        }
+        _ => Err(FileSystemError::InvalidPartition),
     }
 }
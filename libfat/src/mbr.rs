@@ -0,0 +1,237 @@
+//! Master Boot Record partition table support.
+
+use alloc::vec::Vec;
+use byteorder::{ByteOrder, LittleEndian};
+
+use libfs::block::{Block, BlockCount, BlockDevice, BlockIndex};
+use libfs::FileSystemError;
+
+/// Represent a partition entry of the MBR or of an EBR.
+#[derive(Debug, Clone, Copy)]
+pub struct MbrPartitionEntry {
+    /// The index of the partition. Primary partitions use 0 to 3, logical partitions start at 4.
+    pub index: u32,
+
+    /// Set if the partition is marked as bootable.
+    pub bootable: bool,
+
+    /// The partition type ID.
+    pub partition_type: u8,
+
+    /// The first sector of the partition, relative to the start of the device.
+    pub first_lba: u32,
+
+    /// The amount of sectors in the partition.
+    pub sector_count: u32,
+}
+
+impl MbrPartitionEntry {
+    /// The size of a partition table entry.
+    const LEN: usize = 16;
+
+    /// The partition type IDs of the FAT family.
+    const FAT_TYPES: &'static [u8] = &[0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E, 0xEF];
+
+    /// The partition type IDs of extended partitions.
+    const EXTENDED_TYPES: &'static [u8] = &[0x05, 0x0F, 0x85];
+
+    /// Parse a raw partition entry. ``base_lba`` is the sector its start is relative to.
+    ///
+    /// Return None if the entry is empty or invalid.
+    fn from_raw(index: u32, data: &[u8], base_lba: u32) -> Option<MbrPartitionEntry> {
+        // status must be 0x00 or 0x80
+        if (data[0] & 0x7F) != 0 {
+            return None;
+        }
+
+        let partition_type = data[0x4];
+        let sector_count = LittleEndian::read_u32(&data[0xC..0x10]);
+
+        if partition_type == 0 || sector_count == 0 {
+            return None;
+        }
+
+        Some(MbrPartitionEntry {
+            index,
+            bootable: data[0] == 0x80,
+            partition_type,
+            first_lba: base_lba.checked_add(LittleEndian::read_u32(&data[0x8..0xC]))?,
+            sector_count,
+        })
+    }
+
+    /// Return true if the partition type is a FAT one.
+    pub fn is_fat(&self) -> bool {
+        Self::FAT_TYPES.contains(&self.partition_type)
+    }
+
+    /// Return true if the partition is an extended partition holding logical partitions.
+    pub fn is_extended(&self) -> bool {
+        Self::EXTENDED_TYPES.contains(&self.partition_type)
+    }
+}
+
+/// Represent the partition table of a MBR and its extended partitions.
+pub struct MbrPartitionTable {
+    /// The partitions of the table, including the extended ones.
+    entries: Vec<MbrPartitionEntry>,
+
+    /// The amount of blocks per device sector.
+    blocks_per_sector: u32,
+}
+
+impl MbrPartitionTable {
+    /// The Partition Table offset.
+    const PARITION_TABLE_OFFSET: usize = 446;
+
+    /// The MBR signature offset.
+    const MBR_SIGNATURE: usize = 510;
+
+    /// The maximum amount of logical partitions. Used to avoid looping on corrupted EBR chains.
+    const MAX_LOGICAL_PARTITIONS: u32 = 128;
+
+    /// Read the MBR of a block device and follow the EBR chains of its extended partitions.
+    pub fn read<T>(block_device: &T) -> Result<MbrPartitionTable, FileSystemError>
+    where
+        T: BlockDevice,
+    {
+        // The partition table is expressed in device sectors.
+        let blocks_per_sector = block_device.sector_size() / Block::LEN_U32;
+        if blocks_per_sector == 0 {
+            return Err(FileSystemError::InvalidPartition);
+        }
+
+        let mut res = MbrPartitionTable {
+            entries: Vec::new(),
+            blocks_per_sector,
+        };
+
+        let block = res
+            .read_boot_record(block_device, 0)?
+            .ok_or(FileSystemError::InvalidPartition)?;
+
+        for index in 0..4 {
+            let offset = Self::PARITION_TABLE_OFFSET + MbrPartitionEntry::LEN * index;
+            let raw_entry = &block[offset..offset + MbrPartitionEntry::LEN];
+
+            if let Some(entry) = MbrPartitionEntry::from_raw(index as u32, raw_entry, 0) {
+                res.entries.push(entry);
+            }
+        }
+
+        let extended_partitions: Vec<MbrPartitionEntry> = res
+            .entries
+            .iter()
+            .filter(|entry| entry.is_extended())
+            .cloned()
+            .collect();
+
+        for extended_partition in extended_partitions {
+            res.read_logical_partitions(block_device, extended_partition.first_lba)?;
+        }
+
+        Ok(res)
+    }
+
+    /// Follow an EBR chain starting at ``extended_lba`` and add its logical partitions.
+    fn read_logical_partitions<T>(
+        &mut self,
+        block_device: &T,
+        extended_lba: u32,
+    ) -> Result<(), FileSystemError>
+    where
+        T: BlockDevice,
+    {
+        let mut ebr_lba = extended_lba;
+
+        for _ in 0..Self::MAX_LOGICAL_PARTITIONS {
+            let block = match self.read_boot_record(block_device, ebr_lba)? {
+                Some(block) => block,
+                // A broken chain only hides the partitions after it
+                None => return Ok(()),
+            };
+
+            let offset = Self::PARITION_TABLE_OFFSET;
+            let logical_index = self.entries.iter().filter(|entry| entry.index >= 4).count() as u32;
+
+            // The first entry describes the logical partition, relative to its EBR
+            if let Some(entry) = MbrPartitionEntry::from_raw(
+                4 + logical_index,
+                &block[offset..offset + MbrPartitionEntry::LEN],
+                ebr_lba,
+            ) {
+                if !entry.is_extended() {
+                    self.entries.push(entry);
+                }
+            }
+
+            // The second entry points to the next EBR, relative to the extended partition
+            let offset = offset + MbrPartitionEntry::LEN;
+            match MbrPartitionEntry::from_raw(
+                0,
+                &block[offset..offset + MbrPartitionEntry::LEN],
+                extended_lba,
+            ) {
+                Some(ref next) if next.is_extended() && next.first_lba != ebr_lba => {
+                    ebr_lba = next.first_lba
+                }
+                _ => return Ok(()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a MBR or EBR at the given LBA, returning None if its signature is invalid.
+    fn read_boot_record<T>(
+        &self,
+        block_device: &T,
+        lba: u32,
+    ) -> Result<Option<Block>, FileSystemError>
+    where
+        T: BlockDevice,
+    {
+        let block_index = lba
+            .checked_mul(self.blocks_per_sector)
+            .ok_or(FileSystemError::InvalidPartition)?;
+
+        let mut blocks = [Block::new()];
+        block_device
+            .raw_read(&mut blocks, BlockIndex(block_index))
+            .or(Err(FileSystemError::ReadFailed))?;
+
+        let block = &blocks[0];
+        if LittleEndian::read_u16(&block[Self::MBR_SIGNATURE..Self::MBR_SIGNATURE + 2]) != 0xAA55 {
+            return Ok(None);
+        }
+
+        Ok(Some(blocks[0].clone()))
+    }
+
+    /// Iterate over the partitions, extended ones included.
+    pub fn iter(&self) -> core::slice::Iter<'_, MbrPartitionEntry> {
+        self.entries.iter()
+    }
+
+    /// Get the partition at the given index.
+    pub fn get(&self, index: u32) -> Option<&MbrPartitionEntry> {
+        self.entries.iter().find(|entry| entry.index == index)
+    }
+
+    /// Compute the first block and the block count of a partition.
+    pub fn partition_bounds(
+        &self,
+        entry: &MbrPartitionEntry,
+    ) -> Result<(BlockIndex, BlockCount), FileSystemError> {
+        let start = entry
+            .first_lba
+            .checked_mul(self.blocks_per_sector)
+            .ok_or(FileSystemError::InvalidPartition)?;
+        let count = entry
+            .sector_count
+            .checked_mul(self.blocks_per_sector)
+            .ok_or(FileSystemError::InvalidPartition)?;
+
+        Ok((BlockIndex(start), BlockCount(count)))
+    }
+}
@@ -0,0 +1,125 @@
+//! Partition enumeration.
+
+use alloc::vec::Vec;
+
+use libfs::block::{Block, BlockCount, BlockDevice, BlockIndex};
+use libfs::FileSystemError;
+
+use crate::exfat::ExFatBootRecord;
+use crate::gpt::{GptPartitionTable, Guid};
+use crate::mbr::MbrPartitionTable;
+use crate::{FatFsType, FatVolumeBootRecord};
+
+/// Represent the type of a partition, as described by its partition table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PartitionType {
+    /// A MBR partition type ID.
+    Mbr(u8),
+
+    /// A GPT partition type GUID.
+    Gpt(Guid),
+}
+
+/// Represent a partition of a block device.
+#[derive(Debug, Clone, Copy)]
+pub struct PartitionInfo {
+    /// The index of the partition, as expected by ``get_partition`` or ``get_gpt_partition``.
+    pub index: u32,
+
+    /// The first block of the partition.
+    pub start: BlockIndex,
+
+    /// The count of blocks in the partition.
+    pub block_count: BlockCount,
+
+    /// The type of the partition.
+    pub partition_type: PartitionType,
+
+    /// Set if the partition is marked as bootable.
+    pub bootable: bool,
+
+    /// The filesystem found on the partition, if any.
+    pub filesystem: Option<FatFsType>,
+}
+
+/// The MBR partition type of a GPT protective partition.
+const GPT_PROTECTIVE_TYPE: u8 = 0xEE;
+
+/// The GPT attribute marking a partition as bootable by legacy BIOS.
+const GPT_LEGACY_BIOS_BOOTABLE: u64 = 1 << 2;
+
+/// Detect the filesystem stored at the start of a partition.
+fn detect_filesystem<T>(
+    block_device: &T,
+    partition_start: BlockIndex,
+) -> Result<Option<FatFsType>, FileSystemError>
+where
+    T: BlockDevice,
+{
+    let mut blocks = [Block::new()];
+
+    block_device
+        .read(&mut blocks, partition_start, BlockIndex(0))
+        .or(Err(FileSystemError::ReadFailed))?;
+
+    if ExFatBootRecord::new(blocks[0].clone()).is_valid() {
+        return Ok(Some(FatFsType::ExFat));
+    }
+
+    let boot_record = FatVolumeBootRecord::new(blocks[0].clone());
+    if boot_record.fat_type != FatFsType::ExFat && boot_record.is_valid() {
+        return Ok(Some(boot_record.fat_type));
+    }
+
+    Ok(None)
+}
+
+/// Read the partition table of a block device and list its partitions.
+///
+/// GPT is used when the MBR holds a protective partition. Extended MBR partitions are not listed, only the logical partitions they contain.
+pub fn list_partitions<T>(block_device: &T) -> Result<Vec<PartitionInfo>, FileSystemError>
+where
+    T: BlockDevice,
+{
+    let mbr_partition_table = MbrPartitionTable::read(block_device)?;
+    let mut res = Vec::new();
+
+    let is_gpt = mbr_partition_table
+        .iter()
+        .any(|entry| entry.partition_type == GPT_PROTECTIVE_TYPE);
+
+    if is_gpt {
+        let gpt_partition_table = GptPartitionTable::read(block_device)?;
+
+        for entry in gpt_partition_table.iter() {
+            let (start, block_count) = gpt_partition_table.partition_bounds(entry)?;
+
+            res.push(PartitionInfo {
+                index: entry.index,
+                start,
+                block_count,
+                partition_type: PartitionType::Gpt(entry.type_guid),
+                bootable: (entry.attributes & GPT_LEGACY_BIOS_BOOTABLE) != 0,
+                filesystem: detect_filesystem(block_device, start)?,
+            });
+        }
+    } else {
+        for entry in mbr_partition_table
+            .iter()
+            .filter(|entry| !entry.is_extended())
+        {
+            let (start, block_count) = mbr_partition_table.partition_bounds(entry)?;
+
+            res.push(PartitionInfo {
+                index: entry.index,
+                start,
+                block_count,
+                partition_type: PartitionType::Mbr(entry.partition_type),
+                bootable: entry.bootable,
+                filesystem: detect_filesystem(block_device, start)?,
+            });
+        }
+    }
+
+    Ok(res)
+}
@@ -2,6 +2,8 @@
 
 use super::filesystem::FatFileSystem;
 use super::Cluster;
+use super::FatFsType;
+use alloc::vec::Vec;
 use byteorder::{ByteOrder, LittleEndian};
 use libfs::block::{Block, BlockDevice, BlockIndex};
 
@@ -19,6 +21,10 @@ pub enum FatValue {
     /// Represent a corrupted cluster (bad sectors)
     Bad,
 
+    /// Represent a reserved value, never pointing to a cluster.
+    /// NOTE: The media descriptor stored in the first entry of the FAT decodes to this on FAT12/16.
+    Reserved,
+
     /// Represent the end of a cluster chain.
     EndOfChain,
 }
@@ -41,7 +47,13 @@ where
 {
     /// Create a new Cluster iteractor starting at ``cluster``.
     pub fn new(fs: &'a FatFileSystem<T>, cluster: Cluster) -> FatClusterIter<'a, T> {
-        let fat_value = FatValue::get(fs, cluster).ok();
+        // The fixed root directory isn't part of the FAT, its entry holds the media descriptor
+        let fat_value = if cluster.is_fixed_root_dir(fs) {
+            None
+        } else {
+            FatValue::get(fs, cluster).ok()
+        };
+
         FatClusterIter {
             fs,
             current_cluster: Some(cluster),
@@ -71,48 +83,161 @@ where
 }
 
 impl FatValue {
+    /// Create a ``FatValue`` from a raw FAT12 value.
+    pub fn from_u12(val: u16) -> Self {
+        match val & 0x0FFF {
+            0 => FatValue::Free,
+            0x0FF0..=0x0FF6 => FatValue::Reserved,
+            0x0FF7 => FatValue::Bad,
+            0x0FF8..=0x0FFF => FatValue::EndOfChain,
+            n => FatValue::Data(u32::from(n)),
+        }
+    }
+
+    /// Create a ``FatValue`` from a raw FAT16 value.
+    pub fn from_u16(val: u16) -> Self {
+        match val {
+            0 => FatValue::Free,
+            0xFFF0..=0xFFF6 => FatValue::Reserved,
+            0xFFF7 => FatValue::Bad,
+            0xFFF8..=0xFFFF => FatValue::EndOfChain,
+            n => FatValue::Data(u32::from(n)),
+        }
+    }
+
     /// Create a ``FatValue`` from a raw FAT32 value.
     pub fn from_u32(val: u32) -> Self {
         match val {
             0 => FatValue::Free,
+            0x0FFF_FFF0..=0x0FFF_FFF6 => FatValue::Reserved,
             0x0FFF_FFF7 => FatValue::Bad,
             0x0FFF_FFF8..=0x0FFF_FFFF => FatValue::EndOfChain,
             n => FatValue::Data(n as u32),
         }
     }
 
+    /// Convert a ```FatValue``` to a raw FAT12 value.
+    pub fn to_u12(self) -> u16 {
+        match self {
+            FatValue::Free => 0,
+            FatValue::Reserved => 0x0FF0,
+            FatValue::Bad => 0x0FF7,
+            FatValue::EndOfChain => 0x0FFF,
+            FatValue::Data(n) => (n & 0x0FFF) as u16,
+        }
+    }
+
+    /// Convert a ```FatValue``` to a raw FAT16 value.
+    pub fn to_u16(self) -> u16 {
+        match self {
+            FatValue::Free => 0,
+            FatValue::Reserved => 0xFFF0,
+            FatValue::Bad => 0xFFF7,
+            FatValue::EndOfChain => 0xFFFF,
+            FatValue::Data(n) => n as u16,
+        }
+    }
+
     /// Convert a ```FatValue``` to a raw FAT32 value.
     pub fn to_u32(self) -> u32 {
         match self {
             FatValue::Free => 0,
+            FatValue::Reserved => 0x0FFF_FFF0,
             FatValue::Bad => 0x0FFF_FFF7,
             FatValue::EndOfChain => 0x0FFF_FFFF,
             FatValue::Data(n) => n,
         }
     }
 
-    /// Create a ```FatValue``` from a raw block and offset.
+    /// Create a ```FatValue``` from a raw FAT32 block and offset.
     pub fn from_block(block: &Block, cluster_offset: usize) -> Self {
         let val = LittleEndian::read_u32(&block[cluster_offset..cluster_offset + 4]) & 0x0FFF_FFFF;
         FatValue::from_u32(val)
     }
 
+    /// Create a ```FatValue``` from raw blocks and an offset, using the encoding of the given filesystem.
+    ///
+    /// On FAT12, an entry may span two blocks, in this case the second block must be present.
+    fn from_blocks<T>(
+        fs: &FatFileSystem<T>,
+        blocks: &[Block],
+        cluster: Cluster,
+        cluster_offset: usize,
+    ) -> Self
+    where
+        T: BlockDevice,
+    {
+        match fs.boot_record.fat_type {
+            FatFsType::Fat12 => {
+                let raw_value = Self::read_fat12_pair(blocks, cluster_offset);
+                if cluster.0 & 1 == 1 {
+                    FatValue::from_u12(raw_value >> 4)
+                } else {
+                    FatValue::from_u12(raw_value & 0x0FFF)
+                }
+            }
+            FatFsType::Fat16 => FatValue::from_u16(LittleEndian::read_u16(
+                &blocks[0][cluster_offset..cluster_offset + 2],
+            )),
+            FatFsType::Fat32 | FatFsType::ExFat => FatValue::from_block(&blocks[0], cluster_offset),
+        }
+    }
+
+    /// Read the two bytes holding a FAT12 entry, that may be split between two blocks.
+    fn read_fat12_pair(blocks: &[Block], cluster_offset: usize) -> u16 {
+        let low = blocks[0][cluster_offset];
+        let high = if cluster_offset + 1 < Block::LEN {
+            blocks[0][cluster_offset + 1]
+        } else {
+            blocks[1][0]
+        };
+
+        u16::from(low) | (u16::from(high) << 8)
+    }
+
+    /// Write the two bytes holding a FAT12 entry, that may be split between two blocks.
+    fn write_fat12_pair(blocks: &mut [Block], cluster_offset: usize, value: u16) {
+        blocks[0][cluster_offset] = value as u8;
+        if cluster_offset + 1 < Block::LEN {
+            blocks[0][cluster_offset + 1] = (value >> 8) as u8;
+        } else {
+            blocks[1][0] = (value >> 8) as u8;
+        }
+    }
+
+    /// Compute the count of blocks that needs to be accessed to read a FAT entry at the given offset.
+    fn entry_block_count<T>(fs: &FatFileSystem<T>, cluster_offset: usize) -> usize
+    where
+        T: BlockDevice,
+    {
+        if fs.boot_record.fat_type == FatFsType::Fat12 && cluster_offset == Block::LEN - 1 {
+            2
+        } else {
+            1
+        }
+    }
+
     /// Get the ```FatValue``` of a given cluster.
     pub fn get<T>(fs: &FatFileSystem<T>, cluster: Cluster) -> Result<FatValue, FileSystemError>
     where
         T: BlockDevice,
     {
-        let mut blocks = [Block::new()];
+        let mut blocks = [Block::new(), Block::new()];
 
-        let fat_offset = cluster.to_fat_offset();
+        let fat_offset = cluster.to_fat_offset(fs);
         let cluster_block_index = cluster.to_fat_block_index(fs);
         let cluster_offset = (fat_offset % Block::LEN_U32) as usize;
+        let block_count = Self::entry_block_count(fs, cluster_offset);
 
         fs.block_device
-            .read(&mut blocks, fs.partition_start, cluster_block_index)
+            .read(
+                &mut blocks[..block_count],
+                fs.partition_start,
+                cluster_block_index,
+            )
             .or(Err(FileSystemError::ReadFailed))?;
 
-        let res = FatValue::from_block(&blocks[0], cluster_offset);
+        let res = FatValue::from_blocks(fs, &blocks, cluster, cluster_offset);
 
         Ok(res)
     }
@@ -127,29 +252,57 @@ impl FatValue {
     where
         T: BlockDevice,
     {
-        let mut blocks = [Block::new()];
+        let mut blocks = [Block::new(), Block::new()];
 
-        let fat_offset = cluster.to_fat_offset();
+        let fat_offset = cluster.to_fat_offset(fs);
         let cluster_block_index =
             BlockIndex(cluster.to_fat_block_index(fs).0 + (fat_index * fs.boot_record.fat_size()));
         let cluster_offset = (fat_offset % Block::LEN_U32) as usize;
+        let block_count = Self::entry_block_count(fs, cluster_offset);
 
         fs.block_device
-            .read(&mut blocks, fs.partition_start, cluster_block_index)
+            .read(
+                &mut blocks[..block_count],
+                fs.partition_start,
+                cluster_block_index,
+            )
             .or(Err(FileSystemError::ReadFailed))?;
 
-        let res = FatValue::from_block(&blocks[0], cluster_offset);
+        let res = FatValue::from_blocks(fs, &blocks, cluster, cluster_offset);
 
         // no write needed
         if res == value {
             return Ok(());
         }
 
-        let value = value.to_u32() & 0x0FFF_FFFF;
-        LittleEndian::write_u32(&mut blocks[0][cluster_offset..cluster_offset + 4], value);
+        match fs.boot_record.fat_type {
+            FatFsType::Fat12 => {
+                let raw_value = Self::read_fat12_pair(&blocks, cluster_offset);
+                let raw_value = if cluster.0 & 1 == 1 {
+                    (raw_value & 0x000F) | (value.to_u12() << 4)
+                } else {
+                    (raw_value & 0xF000) | value.to_u12()
+                };
+                Self::write_fat12_pair(&mut blocks, cluster_offset, raw_value);
+            }
+            FatFsType::Fat16 => {
+                LittleEndian::write_u16(
+                    &mut blocks[0][cluster_offset..cluster_offset + 2],
+                    value.to_u16(),
+                );
+            }
+            FatFsType::Fat32 | FatFsType::ExFat => {
+                let value = value.to_u32() & 0x0FFF_FFFF;
+                LittleEndian::write_u32(&mut blocks[0][cluster_offset..cluster_offset + 4], value);
+            }
+        }
 
         fs.block_device
-            .write(&blocks, fs.partition_start, cluster_block_index)
+            .write(
+                &blocks[..block_count],
+                fs.partition_start,
+                cluster_block_index,
+            )
             .or(Err(FileSystemError::WriteFailed))?;
 
         Ok(())
@@ -164,13 +317,86 @@ impl FatValue {
     where
         T: BlockDevice,
     {
+        fs.mark_dirty()?;
+
         for fat_index in 0..u32::from(fs.boot_record.fats_count()) {
             Self::raw_put(fs, cluster, value, fat_index)?;
         }
+
+        if let Some(free_bitmap) = &fs.free_bitmap {
+            free_bitmap.set_free(cluster, value == FatValue::Free);
+        }
         Ok(())
     }
 }
 
+/// Get the raw value of the second FAT entry, that holds the volume state bits on FAT16 and FAT32 filesystems.
+pub fn get_volume_state_entry<T>(fs: &FatFileSystem<T>) -> Result<u32, FileSystemError>
+where
+    T: BlockDevice,
+{
+    let mut blocks = [Block::new()];
+    let offset = Cluster(1).to_fat_offset(fs) as usize;
+
+    fs.block_device
+        .read(
+            &mut blocks,
+            fs.partition_start,
+            Cluster(1).to_fat_block_index(fs),
+        )
+        .or(Err(FileSystemError::ReadFailed))?;
+
+    let res = match fs.boot_record.fat_type {
+        FatFsType::Fat12 => u32::from(FatValue::read_fat12_pair(&blocks, offset) >> 4),
+        FatFsType::Fat16 => u32::from(LittleEndian::read_u16(&blocks[0][offset..offset + 2])),
+        FatFsType::Fat32 | FatFsType::ExFat => {
+            LittleEndian::read_u32(&blocks[0][offset..offset + 4]) & 0x0FFF_FFFF
+        }
+    };
+
+    Ok(res)
+}
+
+/// Write the raw value of the second FAT entry in all FATs. Only supported on FAT16 and FAT32 filesystems.
+pub fn put_volume_state_entry<T>(fs: &FatFileSystem<T>, value: u32) -> Result<(), FileSystemError>
+where
+    T: BlockDevice,
+{
+    let mut blocks = [Block::new()];
+    let offset = Cluster(1).to_fat_offset(fs) as usize;
+
+    for fat_index in 0..u32::from(fs.boot_record.fats_count()) {
+        let block_index = BlockIndex(
+            Cluster(1).to_fat_block_index(fs).0 + (fat_index * fs.boot_record.fat_size()),
+        );
+
+        fs.block_device
+            .read(&mut blocks, fs.partition_start, block_index)
+            .or(Err(FileSystemError::ReadFailed))?;
+
+        match fs.boot_record.fat_type {
+            FatFsType::Fat16 => {
+                LittleEndian::write_u16(&mut blocks[0][offset..offset + 2], value as u16)
+            }
+            FatFsType::Fat32 => {
+                // The high 4 bits are reserved
+                let reserved = LittleEndian::read_u32(&blocks[0][offset..offset + 4]) & 0xF000_0000;
+                LittleEndian::write_u32(
+                    &mut blocks[0][offset..offset + 4],
+                    reserved | (value & 0x0FFF_FFFF),
+                );
+            }
+            FatFsType::Fat12 | FatFsType::ExFat => return Err(FileSystemError::AccessDenied),
+        }
+
+        fs.block_device
+            .write(&blocks, fs.partition_start, block_index)
+            .or(Err(FileSystemError::WriteFailed))?;
+    }
+
+    Ok(())
+}
+
 /// Get the last cluster of a cluster chain.
 pub fn get_last_cluster<T>(
     fs: &FatFileSystem<T>,
@@ -201,22 +427,74 @@ where
     Ok((current_cluster, previous_cluster))
 }
 
+/// The count of FAT blocks read at once when scanning a whole FAT.
+const FAT_SCAN_BLOCK_COUNT: usize = 32;
+
+/// Call ``f`` with the ```FatValue``` of every data cluster until it returns true, reading the first FAT in chunks of
+/// blocks.
+pub fn scan_fat<T, F>(fs: &FatFileSystem<T>, mut f: F) -> Result<(), FileSystemError>
+where
+    T: BlockDevice,
+    F: FnMut(Cluster, FatValue) -> bool,
+{
+    let fat_size = fs.boot_record.fat_size();
+
+    // One extra block is kept so that FAT12 entries spanning the end of a chunk can be decoded
+    let mut blocks: Vec<Block> = (0..=FAT_SCAN_BLOCK_COUNT).map(|_| Block::new()).collect();
+    let mut chunk_start = 0;
+    let mut chunk_len = 0;
+
+    for cluster in 2..fs.boot_record.cluster_count {
+        let cluster = Cluster(cluster);
+        let fat_offset = cluster.to_fat_offset(fs);
+        let block_offset = fat_offset / Block::LEN_U32;
+        let cluster_offset = (fat_offset % Block::LEN_U32) as usize;
+        let block_count = FatValue::entry_block_count(fs, cluster_offset) as u32;
+
+        if block_offset + block_count > chunk_start + chunk_len {
+            // The cluster count of a corrupted boot record can exceed what the FAT holds
+            if block_offset + block_count > fat_size {
+                return Err(FileSystemError::InvalidPartition);
+            }
+
+            chunk_start = block_offset;
+            chunk_len = (fat_size - block_offset).min(blocks.len() as u32);
+
+            fs.block_device
+                .read(
+                    &mut blocks[..chunk_len as usize],
+                    fs.partition_start,
+                    BlockIndex(fs.boot_record.reserved_block_count() + chunk_start),
+                )
+                .or(Err(FileSystemError::ReadFailed))?;
+        }
+
+        let blocks = &blocks[(block_offset - chunk_start) as usize..];
+        if f(
+            cluster,
+            FatValue::from_blocks(fs, blocks, cluster, cluster_offset),
+        ) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
 /// Compute the whole cluster count of a given FileSystem.
 pub fn get_free_cluster_count<T>(fs: &FatFileSystem<T>) -> Result<u32, FileSystemError>
 where
     T: BlockDevice,
 {
-    let mut current_cluster = Cluster(2);
-
     let mut res = 0;
 
-    while current_cluster.0 < fs.boot_record.cluster_count {
-        if let FatValue::Free = FatValue::get(fs, current_cluster)? {
+    scan_fat(fs, |_, value| {
+        if value == FatValue::Free {
             res += 1;
         }
 
-        current_cluster = Cluster(current_cluster.0 + 1);
-    }
+        false
+    })?;
 
     Ok(res)
 }
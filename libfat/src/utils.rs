@@ -48,3 +48,18 @@ pub fn split_path(path: &str) -> (&str, Option<&str>) {
 
     (comp, rest_opt)
 }
+
+/// Compute the CRC32 (IEEE 802.3) of the given data.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for value in data {
+        crc ^= u32::from(*value);
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
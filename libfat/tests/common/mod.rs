@@ -0,0 +1,123 @@
+//! Helpers shared by the integration tests.
+
+// Every test only uses some of the helpers
+#![allow(dead_code)]
+
+use byteorder::{ByteOrder, LittleEndian};
+use libfat::filesystem::FatFileSystem;
+use libfat::format::{format_raw_partition, FormatOptions};
+use libfat::fsck;
+use libfat::FatFsType;
+use libfs::block::{BlockDevice, RamBlockDevice};
+use libfs::FileSystemResult;
+
+/// The volumes to test, with their size and cluster size. They are kept small as some checks read the whole FAT.
+pub const VOLUMES: [(FatFsType, usize, u32); 3] = [
+    (FatFsType::Fat12, 1 << 20, 512),
+    (FatFsType::Fat16, 4 << 20, 512),
+    (FatFsType::Fat32, 34 << 20, 512),
+];
+
+/// Represent an operation on a volume.
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    /// Create a directory.
+    Mkdir(&'static str),
+
+    /// Create an empty file.
+    Touch(&'static str),
+
+    /// Write the given count of bytes at the given offset of a file.
+    Write(&'static str, u64, usize),
+
+    /// Set the length of a file.
+    SetLen(&'static str, u64),
+
+    /// Rename a file.
+    Rename(&'static str, &'static str),
+
+    /// Delete a file.
+    Unlink(&'static str),
+
+    /// Create a file of the given length made of a single run of clusters.
+    CreateContiguous(&'static str, u64),
+}
+
+/// Format an in-memory volume.
+pub fn create_volume(fat_type: FatFsType, size: usize, cluster_size: u32) -> RamBlockDevice {
+    let device = RamBlockDevice::new(size);
+    let options = FormatOptions {
+        fat_type,
+        cluster_size,
+        volume_label: "TEST",
+        volume_id: 0x1234_5678,
+    };
+
+    format_raw_partition(&device, &options).unwrap();
+
+    device
+}
+
+/// Run an operation on the volume.
+pub fn run_operation<T>(fs: &FatFileSystem<T>, operation: Operation) -> FileSystemResult<()>
+where
+    T: BlockDevice,
+{
+    match operation {
+        Operation::Mkdir(path) => fs.mkdir(path),
+        Operation::Touch(path) => fs.touch(path),
+        Operation::Write(path, offset, size) => fs
+            .get_root_directory()
+            .open_file(path)
+            .and_then(|mut file| file.write(fs, offset, &vec![0x42; size], true)),
+        Operation::SetLen(path, size) => fs
+            .get_root_directory()
+            .open_file(path)
+            .and_then(|mut file| file.set_len(fs, size)),
+        Operation::Rename(old_path, new_path) => fs.rename(old_path, new_path, false),
+        Operation::Unlink(path) => fs.unlink(path, false),
+        Operation::CreateContiguous(path, size) => fs.create_contiguous_file(path, size),
+    }
+}
+
+/// Check that a volume is consistent and that its free cluster count is up to date.
+pub fn assert_clean<T>(fs: &FatFileSystem<T>)
+where
+    T: BlockDevice,
+{
+    let statistics = fs.statistics();
+    let report = fsck::check(fs).unwrap();
+    assert!(report.is_clean(), "{:?}: {:?}", statistics, report.issues);
+    assert_eq!(report.free_cluster_count, statistics.free_cluster_count);
+}
+
+/// Append ``data`` to the file at ``path``.
+pub fn append<T>(fs: &FatFileSystem<T>, path: &str, data: &[u8]) -> FileSystemResult<()>
+where
+    T: BlockDevice,
+{
+    let mut file = fs.get_root_directory().open_file(path)?;
+    let offset = u64::from(file.file_size);
+
+    file.write(fs, offset, data, true)
+}
+
+/// Write an entry of the partition table of the MBR or EBR at ``lba`` of a disk image, and the boot record signature.
+pub fn write_partition_entry(
+    image: &mut [u8],
+    lba: u32,
+    slot: usize,
+    partition_type: u8,
+    first_lba: u32,
+    sector_count: u32,
+) {
+    let boot_record = &mut image[lba as usize * 512..(lba as usize + 1) * 512];
+    let entry = &mut boot_record[446 + slot * 16..446 + (slot + 1) * 16];
+
+    entry[4] = partition_type;
+    LittleEndian::write_u32(&mut entry[8..12], first_lba);
+    LittleEndian::write_u32(&mut entry[12..16], sector_count);
+
+    boot_record[510] = 0x55;
+    boot_record[511] = 0xAA;
+}
@@ -0,0 +1,281 @@
+//! Check that contiguous preallocation produces single runs of clusters or fails without touching the volume.
+
+mod common;
+
+use common::VOLUMES;
+use libfat::extent::{Extent, ExtentMap};
+use libfat::filesystem::FatFileSystem;
+use libfat::FatFsType;
+use libfs::block::{BlockDevice, RamBlockDevice};
+use libfs::fault::FaultyBlockDevice;
+use libfs::FileSystemError;
+
+/// The files whose clusters get interleaved, the first one being deleted to leave holes.
+const FILES: [&str; 2] = ["/holes.bin", "/kept.bin"];
+
+/// The count of clusters of every hole left in the free space.
+const HOLE_CLUSTERS: u32 = 2;
+
+/// The count of holes left in the free space.
+const HOLE_COUNT: u32 = 8;
+
+/// Format an in-memory volume and leave holes of ``HOLE_CLUSTERS`` free clusters at its start.
+///
+/// If ``fill`` is set, the free space past the holes is used up.
+fn create_volume_with_holes(
+    fat_type: FatFsType,
+    size: usize,
+    cluster_size: u32,
+    fill: bool,
+) -> RamBlockDevice {
+    let device = common::create_volume(fat_type, size, cluster_size);
+
+    {
+        let fs = libfat::get_raw_partition(&device).unwrap();
+        let cluster_size = u64::from(cluster_size);
+
+        for path in &FILES {
+            fs.touch(path).unwrap();
+        }
+
+        // Grow the files in turn so that their clusters interleave
+        for hole in 1..=u64::from(HOLE_COUNT) {
+            for path in &FILES {
+                let mut file = fs.get_root_directory().open_file(path).unwrap();
+                file.set_len(&fs, hole * u64::from(HOLE_CLUSTERS) * cluster_size)
+                    .unwrap();
+            }
+        }
+
+        if fill {
+            let free_size = u64::from(fs.statistics().free_cluster_count) * cluster_size;
+            create_file(&fs, "/fill.bin", free_size);
+        }
+
+        fs.unlink(FILES[0], false).unwrap();
+        fs.flush().unwrap();
+    }
+
+    device
+}
+
+/// Create a file of ``size`` bytes with the default allocation policy.
+fn create_file<T>(fs: &FatFileSystem<T>, path: &str, size: u64)
+where
+    T: BlockDevice,
+{
+    fs.touch(path).unwrap();
+
+    let mut file = fs.get_root_directory().open_file(path).unwrap();
+    file.set_len(fs, size).unwrap();
+}
+
+/// Get the runs of clusters of the file at ``path``.
+fn extents<T>(fs: &FatFileSystem<T>, path: &str) -> Vec<Extent>
+where
+    T: BlockDevice,
+{
+    let mut file = fs.get_root_directory().open_file(path).unwrap();
+    let mut extent_map = ExtentMap::new();
+
+    file.read_with_extents(fs, &mut extent_map, 0, &mut [0; 1])
+        .unwrap();
+    extent_map.extents().to_vec()
+}
+
+/// Mount the volume, optionally with the free cluster map.
+fn mount(device: &RamBlockDevice, use_bitmap: bool) -> FatFileSystem<&RamBlockDevice> {
+    let mut fs = libfat::get_raw_partition(device).unwrap();
+
+    if use_bitmap {
+        fs.enable_free_cluster_bitmap().unwrap();
+    }
+
+    fs
+}
+
+#[test]
+fn contiguous_files_skip_holes() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let image = create_volume_with_holes(*fat_type, *size, *cluster_size, false).into_vec();
+
+        for use_bitmap in &[false, true] {
+            let device = RamBlockDevice::from_vec(image.clone());
+            let fs = mount(&device, *use_bitmap);
+            let hole_size = u64::from(HOLE_CLUSTERS * *cluster_size);
+
+            // Files fitting in a hole use the first one
+            fs.create_contiguous_file("/small.bin", hole_size).unwrap();
+            let small_extents = extents(&fs, "/small.bin");
+            assert_eq!(small_extents.len(), 1);
+
+            // Larger files go past the holes
+            let file_size = hole_size * 3 - 10;
+            fs.create_contiguous_file("/large.bin", file_size).unwrap();
+            let large_extents = extents(&fs, "/large.bin");
+
+            assert_eq!(
+                large_extents.len(),
+                1,
+                "{:?}: {:?}",
+                fat_type,
+                large_extents
+            );
+            assert_eq!(large_extents[0].cluster_count, HOLE_CLUSTERS * 3);
+            assert!(large_extents[0].start_cluster > extents(&fs, FILES[1])[0].start_cluster);
+
+            let file = fs.get_root_directory().open_file("/large.bin").unwrap();
+            assert_eq!(u64::from(file.file_size), file_size);
+
+            // The holes are left for the next files
+            fs.create_contiguous_file("/other.bin", hole_size).unwrap();
+            let other_extents = extents(&fs, "/other.bin");
+            assert_eq!(other_extents[0].cluster_count, HOLE_CLUSTERS);
+            assert!(other_extents[0].start_cluster < large_extents[0].start_cluster);
+
+            common::assert_clean(&fs);
+        }
+    }
+}
+
+#[test]
+fn contiguous_extensions_follow_the_file() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let image = create_volume_with_holes(*fat_type, *size, *cluster_size, false).into_vec();
+
+        for use_bitmap in &[false, true] {
+            let device = RamBlockDevice::from_vec(image.clone());
+            let fs = mount(&device, *use_bitmap);
+            let cluster_size = u64::from(*cluster_size);
+
+            // Extensions land right after the file when the clusters there are free
+            fs.create_contiguous_file("/file.bin", cluster_size * 4)
+                .unwrap();
+            let mut file = fs.get_root_directory().open_file("/file.bin").unwrap();
+            file.set_len_contiguous(&fs, cluster_size * 5).unwrap();
+            file.set_len_contiguous(&fs, cluster_size * 5 + 1).unwrap();
+            assert_eq!(extents(&fs, "/file.bin").len(), 1);
+
+            // Otherwise the file isn't fragmented and is left untouched
+            create_file(&fs, "/blocker.bin", cluster_size);
+            match file.set_len_contiguous(&fs, cluster_size * 10) {
+                Err(FileSystemError::NoContiguousSpaceLeft) => {}
+                result => panic!("{:?}: unexpected result {:?}", fat_type, result),
+            }
+            let file_extents = extents(&fs, "/file.bin");
+            assert_eq!(file_extents.len(), 1, "{:?}: {:?}", fat_type, file_extents);
+            assert_eq!(file_extents[0].cluster_count, 6);
+            assert_eq!(u64::from(file.file_size), cluster_size * 5 + 1);
+
+            // Growing inside the last cluster or shrinking works as usual
+            file.set_len_contiguous(&fs, cluster_size * 6).unwrap();
+            file.set_len_contiguous(&fs, cluster_size * 2).unwrap();
+            assert_eq!(extents(&fs, "/file.bin").len(), 1);
+
+            // Empty files get a new run
+            file.set_len_contiguous(&fs, 0).unwrap();
+            file.set_len_contiguous(&fs, cluster_size * 3).unwrap();
+            assert_eq!(extents(&fs, "/file.bin").len(), 1);
+
+            common::assert_clean(&fs);
+        }
+    }
+}
+
+#[test]
+fn fragmented_free_space_is_refused() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let image = create_volume_with_holes(*fat_type, *size, *cluster_size, true).into_vec();
+
+        for use_bitmap in &[false, true] {
+            let device = RamBlockDevice::from_vec(image.clone());
+            let fs = mount(&device, *use_bitmap);
+            let free_cluster_count = fs.statistics().free_cluster_count;
+            let run_size = u64::from((HOLE_CLUSTERS + 1) * *cluster_size);
+
+            assert_eq!(free_cluster_count, HOLE_CLUSTERS * HOLE_COUNT);
+
+            match fs.create_contiguous_file("/file.bin", run_size) {
+                Err(FileSystemError::NoContiguousSpaceLeft) => {}
+                result => panic!("unexpected result {:?}", result),
+            }
+
+            match fs.get_root_directory().open_file("/file.bin") {
+                Err(FileSystemError::NotFound) => {}
+                result => panic!("unexpected result {:?}", result.map(|_| ())),
+            }
+
+            let mut file = fs.get_root_directory().open_file(FILES[1]).unwrap();
+            let file_size = u64::from(file.file_size);
+            match file.set_len_contiguous(&fs, file_size + run_size) {
+                Err(FileSystemError::NoContiguousSpaceLeft) => {}
+                result => panic!("unexpected result {:?}", result),
+            }
+            assert_eq!(u64::from(file.file_size), file_size);
+
+            // Requests larger than the free space keep their usual error
+            let free_size = u64::from(free_cluster_count * *cluster_size);
+            match fs.create_contiguous_file("/file.bin", free_size + 1) {
+                Err(FileSystemError::NoSpaceLeft) => {}
+                result => panic!("unexpected result {:?}", result),
+            }
+
+            // Runs fitting in a hole are still allocated
+            fs.create_contiguous_file("/file.bin", run_size - u64::from(*cluster_size))
+                .unwrap();
+            assert_eq!(extents(&fs, "/file.bin").len(), 1);
+            assert_eq!(
+                fs.statistics().free_cluster_count,
+                free_cluster_count - HOLE_CLUSTERS
+            );
+
+            common::assert_clean(&fs);
+            fs.unlink("/file.bin", false).unwrap();
+            fs.flush().unwrap();
+            drop(fs);
+
+            // Every cluster went back to the free space
+            let fs = libfat::get_raw_partition(&device).unwrap();
+            assert_eq!(fs.statistics().free_cluster_count, free_cluster_count);
+            common::assert_clean(&fs);
+        }
+    }
+}
+
+#[test]
+fn fat_scans_stop_at_the_first_fit() {
+    let (fat_type, size, cluster_size) = VOLUMES[2];
+    let image = create_volume_with_holes(fat_type, size, cluster_size, false).into_vec();
+
+    // Count the device reads done to create a file fitting in the first hole, and to scan the whole FAT
+    let count_reads = |use_bitmap: bool| {
+        let device = FaultyBlockDevice::new(RamBlockDevice::from_vec(image.clone()));
+        let mut fs = libfat::get_raw_partition(&device).unwrap();
+
+        device.reset_counters();
+        if use_bitmap {
+            fs.enable_free_cluster_bitmap().unwrap();
+        }
+        let scan_reads = device.read_count();
+
+        device.reset_counters();
+        fs.create_contiguous_file("/file.bin", u64::from(HOLE_CLUSTERS * cluster_size))
+            .unwrap();
+        let allocation_reads = device.read_count();
+
+        common::assert_clean(&fs);
+        (allocation_reads, scan_reads)
+    };
+
+    let (scan_allocation_reads, _) = count_reads(false);
+    let (bitmap_allocation_reads, scan_reads) = count_reads(true);
+
+    // Without the bitmap, only the start of the FAT is read
+    assert!(
+        scan_allocation_reads < bitmap_allocation_reads + scan_reads / 4,
+        "{} reads to allocate with a bitmap, {} without, {} to scan the FAT",
+        bitmap_allocation_reads,
+        scan_allocation_reads,
+        scan_reads
+    );
+}
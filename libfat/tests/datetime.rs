@@ -0,0 +1,175 @@
+//! Check the conversions of FAT datetimes from and to UNIX timestamps and the on-disk fields.
+
+use libfat::datetime::FatDateTime;
+
+/// The UNIX timestamp of 1980-01-01 00:00:00 UTC, the first representable datetime.
+const MIN_TIMESTAMP: u64 = 315_532_800;
+
+/// The UNIX timestamp of 2107-12-31 23:59:58 UTC, the last datetime representable with a 2 seconds resolution.
+const MAX_TIMESTAMP: u64 = 4_354_819_198;
+
+/// The UNIX timestamp of 2000-02-29 12:34:56 UTC.
+const LEAP_DAY_TIMESTAMP: u64 = 951_827_696;
+
+/// Create a datetime with a UTC offset.
+fn with_utc_offset(mut datetime: FatDateTime, utc_offset: Option<i8>) -> FatDateTime {
+    datetime.set_utc_offset(utc_offset);
+
+    datetime
+}
+
+#[test]
+fn unix_time_round_trips_at_range_edges() {
+    let first = FatDateTime::new(1980, 1, 1, 0, 0, 0, 0);
+    let last = FatDateTime::new(2107, 12, 31, 23, 59, 58, 0);
+    let leap_day = FatDateTime::new(2000, 2, 29, 12, 34, 56, 0);
+
+    for (datetime, timestamp) in &[
+        (first, MIN_TIMESTAMP),
+        (last, MAX_TIMESTAMP),
+        (leap_day, LEAP_DAY_TIMESTAMP),
+    ] {
+        assert_eq!(FatDateTime::from_unix_time(*timestamp, None), *datetime);
+        assert_eq!(datetime.to_unix_time(), *timestamp);
+    }
+
+    // Timestamps outside of the range are clamped
+    assert_eq!(FatDateTime::from_unix_time(0, None), first);
+    assert_eq!(FatDateTime::from_unix_time(MIN_TIMESTAMP - 1, None), first);
+    assert_eq!(
+        FatDateTime::from_unix_time(u64::max_value(), None),
+        FatDateTime::new(2107, 12, 31, 23, 59, 59, 99)
+    );
+
+    // Invalid dates are converted to the UNIX epoch
+    assert_eq!(FatDateTime::new(1980, 0, 1, 0, 0, 0, 0).to_unix_time(), 0);
+    assert_eq!(FatDateTime::new(2108, 1, 1, 0, 0, 0, 0).to_unix_time(), 0);
+}
+
+#[test]
+fn fat_fields_round_trip_at_range_edges() {
+    let first = FatDateTime::new(1980, 1, 1, 0, 0, 0, 0);
+    assert_eq!(first.to_fat_date(), 0x0021);
+    assert_eq!(first.to_fat_time(), 0x0000);
+    assert_eq!(FatDateTime::from_fat_date_time(0x0021, 0x0000, 0), first);
+
+    let last = FatDateTime::new(2107, 12, 31, 23, 59, 58, 0);
+    assert_eq!(last.to_fat_date(), 0xFF9F);
+    assert_eq!(last.to_fat_time(), 0xBF7D);
+    assert_eq!(FatDateTime::from_fat_date_time(0xFF9F, 0xBF7D, 0), last);
+
+    // Years outside of the range are clamped
+    assert_eq!(
+        FatDateTime::new(1970, 1, 1, 0, 0, 0, 0).to_fat_date(),
+        0x0021
+    );
+    assert_eq!(
+        FatDateTime::new(2200, 12, 31, 0, 0, 0, 0).to_fat_date(),
+        0xFF9F
+    );
+}
+
+#[test]
+fn fine_resolution_keeps_odd_seconds_and_tenths() {
+    // FAT creation times store up to 1.99s in the fine resolution field
+    let datetime = FatDateTime::new(2000, 2, 29, 12, 34, 56, 150);
+    assert_eq!(datetime, FatDateTime::new(2000, 2, 29, 12, 34, 57, 50));
+    assert_eq!(
+        datetime.to_unix_time_millis(),
+        LEAP_DAY_TIMESTAMP * 1000 + 1500
+    );
+
+    let date = datetime.to_fat_date();
+    let time = datetime.to_fat_time();
+    assert_eq!(time & 0x1f, 28);
+    assert_eq!(datetime.to_fat_time_fine_resolution(), 150);
+    assert_eq!(
+        FatDateTime::from_fat_date_time(date, time, datetime.to_fat_time_fine_resolution()),
+        datetime
+    );
+
+    // Without the fine resolution field, the time is rounded down to an even second
+    assert_eq!(
+        FatDateTime::from_fat_date_time(date, time, 0),
+        FatDateTime::new(2000, 2, 29, 12, 34, 56, 0)
+    );
+
+    let last = FatDateTime::new(2107, 12, 31, 23, 59, 59, 99);
+    assert_eq!(last.to_fat_time_fine_resolution(), 199);
+    assert_eq!(
+        FatDateTime::from_fat_date_time(last.to_fat_date(), last.to_fat_time(), 199),
+        last
+    );
+    assert_eq!(
+        FatDateTime::from_unix_time_millis(last.to_unix_time_millis(), None),
+        last
+    );
+}
+
+#[test]
+fn utc_offsets_shift_local_time() {
+    // UTC+02:30, the local time is 2000-02-29 12:34:56
+    let timestamp = LEAP_DAY_TIMESTAMP - 9000;
+    let datetime = FatDateTime::from_unix_time(timestamp, Some(10));
+    assert_eq!(
+        datetime,
+        with_utc_offset(FatDateTime::new(2000, 2, 29, 12, 34, 56, 0), Some(10))
+    );
+    assert_eq!(datetime.to_unix_time(), timestamp);
+
+    // UTC-05:00, the local time is the previous day
+    let datetime = FatDateTime::from_unix_time(MIN_TIMESTAMP + 86_400, Some(-20));
+    assert_eq!(
+        datetime,
+        with_utc_offset(FatDateTime::new(1980, 1, 1, 19, 0, 0, 0), Some(-20))
+    );
+    assert_eq!(datetime.to_unix_time(), MIN_TIMESTAMP + 86_400);
+
+    // Local times outside of the range are clamped
+    assert_eq!(
+        FatDateTime::from_unix_time(MIN_TIMESTAMP, Some(-4)),
+        with_utc_offset(FatDateTime::new(1980, 1, 1, 0, 0, 0, 0), Some(-4))
+    );
+    assert_eq!(
+        FatDateTime::from_unix_time(MAX_TIMESTAMP, Some(4)),
+        with_utc_offset(FatDateTime::new(2107, 12, 31, 23, 59, 59, 99), Some(4))
+    );
+}
+
+#[test]
+fn exfat_utc_offsets_round_trip() {
+    for utc_offset in &[None, Some(0), Some(10), Some(-20), Some(63), Some(-64)] {
+        let raw = FatDateTime::utc_offset_to_exfat(*utc_offset);
+        assert_eq!(FatDateTime::utc_offset_from_exfat(raw), *utc_offset);
+    }
+
+    assert_eq!(FatDateTime::utc_offset_to_exfat(None), 0x00);
+    assert_eq!(FatDateTime::utc_offset_to_exfat(Some(0)), 0x80);
+    assert_eq!(FatDateTime::utc_offset_to_exfat(Some(63)), 0xBF);
+    assert_eq!(FatDateTime::utc_offset_to_exfat(Some(-64)), 0xC0);
+    assert_eq!(FatDateTime::utc_offset_to_exfat(Some(-1)), 0xFF);
+
+    // Offsets without the valid flag are unknown
+    assert_eq!(FatDateTime::utc_offset_from_exfat(0x3F), None);
+}
+
+#[test]
+fn out_of_range_seconds_are_clamped() {
+    let last_second = FatDateTime::new(2000, 2, 29, 12, 34, 59, 99);
+
+    assert_eq!(FatDateTime::new(2000, 2, 29, 12, 34, 59, 150), last_second);
+    assert_eq!(FatDateTime::new(2000, 2, 29, 12, 34, 58, 255), last_second);
+    assert_eq!(FatDateTime::new(2000, 2, 29, 12, 34, 255, 255), last_second);
+    assert_eq!(
+        FatDateTime::new(2000, 2, 29, 12, 34, 60, 0),
+        FatDateTime::new(2000, 2, 29, 12, 34, 59, 0)
+    );
+
+    // Corrupted FAT times may hold up to 62 seconds and any fine resolution
+    let datetime = FatDateTime::new(2000, 2, 29, 12, 34, 56, 0);
+    let time = datetime.to_fat_time() | 0x1f;
+    assert_eq!(
+        FatDateTime::from_fat_date_time(datetime.to_fat_date(), time, 0xFF),
+        last_second
+    );
+}
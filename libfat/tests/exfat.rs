@@ -0,0 +1,506 @@
+//! Check that a known exFAT image is mounted, that a contiguous file bigger than 4 GiB is read and written, and that
+//! renames follow the up-case table.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use byteorder::{ByteOrder, LittleEndian};
+use libfat::exfat;
+use libfat::FatFsType;
+use libfs::block::{Block, BlockCount, BlockDevice, BlockError, BlockIndex, BlockResult};
+use libfs::FileSystemError;
+
+/// The size of a cluster as a power of two of sectors (1 MiB).
+const SECTORS_PER_CLUSTER_SHIFT: u8 = 11;
+
+/// The size of a cluster in bytes.
+const CLUSTER_SIZE: u64 = 512 << SECTORS_PER_CLUSTER_SHIFT;
+
+/// The sector offset of the FAT.
+const FAT_OFFSET: u32 = 128;
+
+/// The size of the FAT in sectors.
+const FAT_LENGTH: u32 = 64;
+
+/// The sector offset of the cluster heap.
+const CLUSTER_HEAP_OFFSET: u32 = 2048;
+
+/// The count of clusters of the volume (6 GiB).
+const CLUSTER_COUNT: u32 = 6144;
+
+/// The cluster holding the allocation bitmap.
+const BITMAP_CLUSTER: u32 = 2;
+
+/// The cluster holding the up-case table.
+const UPCASE_CLUSTER: u32 = 3;
+
+/// The cluster holding the root directory.
+const ROOT_CLUSTER: u32 = 4;
+
+/// The first cluster of the file.
+const FILE_CLUSTER: u32 = 5;
+
+/// The name of the file.
+const FILE_NAME: &str = "BIG.BIN";
+
+/// The size of the file.
+const FILE_SIZE: u64 = 5 << 30;
+
+/// How far the data of the file was written.
+const VALID_DATA_LENGTH: u64 = (4 << 30) + CLUSTER_SIZE;
+
+/// The offset of the data written in the image, past the 4 GiB limit of FAT32.
+const DATA_OFFSET: u64 = (4 << 30) + 100;
+
+/// The data written in the image.
+const DATA: &[u8] = b"Hello from beyond 4 GiB";
+
+/// The serial number of the volume.
+const VOLUME_SERIAL_NUMBER: u32 = 0xEF01_2345;
+
+/// A block device only storing the non-zero blocks that were written, reading zeros everywhere else. Clones share their
+/// blocks.
+#[derive(Clone)]
+struct SparseBlockDevice {
+    /// The blocks written so far.
+    blocks: Rc<RefCell<BTreeMap<u32, Block>>>,
+
+    /// The count of blocks of the device.
+    count: u32,
+}
+
+impl SparseBlockDevice {
+    /// Create a zero filled device of ``count`` blocks.
+    fn new(count: u32) -> Self {
+        SparseBlockDevice {
+            blocks: Rc::new(RefCell::new(BTreeMap::new())),
+            count,
+        }
+    }
+
+    /// Write ``data`` at the given byte offset.
+    fn write_bytes(&self, offset: u64, data: &[u8]) {
+        for (index, value) in data.iter().enumerate() {
+            let position = offset + index as u64;
+            let mut blocks = self.blocks.borrow_mut();
+            let block = blocks
+                .entry((position / Block::LEN as u64) as u32)
+                .or_default();
+
+            block[(position % Block::LEN as u64) as usize] = *value;
+        }
+    }
+
+    /// Read ``len`` bytes at the given byte offset.
+    fn read_bytes(&self, offset: u64, len: usize) -> Vec<u8> {
+        let blocks = self.blocks.borrow();
+
+        (offset..offset + len as u64)
+            .map(|position| {
+                blocks
+                    .get(&((position / Block::LEN as u64) as u32))
+                    .map(|block| block[(position % Block::LEN as u64) as usize])
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+}
+
+impl BlockDevice for SparseBlockDevice {
+    fn raw_read(&self, blocks: &mut [Block], index: BlockIndex) -> BlockResult<()> {
+        for (offset, block) in blocks.iter_mut().enumerate() {
+            let block_index = index.0 + offset as u32;
+            if block_index >= self.count {
+                return Err(BlockError::ReadError);
+            }
+
+            *block = self
+                .blocks
+                .borrow()
+                .get(&block_index)
+                .cloned()
+                .unwrap_or_else(Block::new);
+        }
+
+        Ok(())
+    }
+
+    fn raw_write(&self, blocks: &[Block], index: BlockIndex) -> BlockResult<()> {
+        for (offset, block) in blocks.iter().enumerate() {
+            let block_index = index.0 + offset as u32;
+            if block_index >= self.count {
+                return Err(BlockError::WriteError);
+            }
+
+            // Zeroed blocks aren't kept, so that large zero fills don't use memory
+            let mut stored_blocks = self.blocks.borrow_mut();
+            if block[..] == [0; Block::LEN][..] {
+                stored_blocks.remove(&block_index);
+            } else {
+                stored_blocks.insert(block_index, block.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn count(&self) -> BlockResult<BlockCount> {
+        Ok(BlockCount(self.count))
+    }
+}
+
+/// Compute the byte offset of a cluster.
+fn cluster_offset(cluster: u32) -> u64 {
+    (u64::from(CLUSTER_HEAP_OFFSET) + u64::from(cluster - 2) * (CLUSTER_SIZE / 512)) * 512
+}
+
+/// Compute the byte offset of an entry of the root directory.
+fn root_entry_offset(index: u64) -> u64 {
+    cluster_offset(ROOT_CLUSTER) + index * 32
+}
+
+/// Compute the byte offset of the FAT entry of a cluster.
+fn fat_entry_offset(cluster: u32) -> u64 {
+    u64::from(FAT_OFFSET) * 512 + u64::from(cluster) * 4
+}
+
+/// Compute a 32 bits exFAT checksum, skipping the bytes at the ``skipped`` offsets.
+fn checksum_u32(data: &[u8], skipped: &[usize]) -> u32 {
+    let mut checksum = 0u32;
+    for (index, value) in data.iter().enumerate() {
+        if !skipped.contains(&index) {
+            checksum = (checksum << 31)
+                .wrapping_add(checksum >> 1)
+                .wrapping_add(u32::from(*value));
+        }
+    }
+    checksum
+}
+
+/// Compute a 16 bits exFAT checksum, skipping the bytes at the ``skipped`` offsets.
+fn checksum_u16(data: &[u8], skipped: &[usize]) -> u16 {
+    let mut checksum = 0u16;
+    for (index, value) in data.iter().enumerate() {
+        if !skipped.contains(&index) {
+            checksum = (checksum << 15)
+                .wrapping_add(checksum >> 1)
+                .wrapping_add(u16::from(*value));
+        }
+    }
+    checksum
+}
+
+/// Create an exFAT volume holding ``FILE_NAME``, a contiguous file not described by the FAT, written up to
+/// ``valid_data_length``.
+fn create_volume(valid_data_length: u64) -> SparseBlockDevice {
+    let volume_length = CLUSTER_HEAP_OFFSET + CLUSTER_COUNT * (CLUSTER_SIZE / 512) as u32;
+    let device = SparseBlockDevice::new(volume_length);
+
+    // Main boot sector
+    let mut boot_sector = [0; 512];
+    boot_sector[0..3].copy_from_slice(&[0xEB, 0x76, 0x90]);
+    boot_sector[3..11].copy_from_slice(b"EXFAT   ");
+    LittleEndian::write_u64(&mut boot_sector[72..80], u64::from(volume_length));
+    LittleEndian::write_u32(&mut boot_sector[80..84], FAT_OFFSET);
+    LittleEndian::write_u32(&mut boot_sector[84..88], FAT_LENGTH);
+    LittleEndian::write_u32(&mut boot_sector[88..92], CLUSTER_HEAP_OFFSET);
+    LittleEndian::write_u32(&mut boot_sector[92..96], CLUSTER_COUNT);
+    LittleEndian::write_u32(&mut boot_sector[96..100], ROOT_CLUSTER);
+    LittleEndian::write_u32(&mut boot_sector[100..104], VOLUME_SERIAL_NUMBER);
+    LittleEndian::write_u16(&mut boot_sector[104..106], 0x0100);
+    boot_sector[108] = 9;
+    boot_sector[109] = SECTORS_PER_CLUSTER_SHIFT;
+    boot_sector[110] = 1;
+    boot_sector[111] = 0x80;
+    boot_sector[510..512].copy_from_slice(&[0x55, 0xAA]);
+    device.write_bytes(0, &boot_sector);
+
+    // Boot checksum sector, covering the 11 first sectors
+    let mut boot_region = boot_sector.to_vec();
+    boot_region.resize(11 * 512, 0);
+    let boot_checksum = checksum_u32(&boot_region, &[106, 107, 112]);
+    for index in 0..128 {
+        device.write_bytes(11 * 512 + index * 4, &boot_checksum.to_le_bytes());
+    }
+
+    // FAT: the media type, then the single cluster chains of the metadata. The file doesn't use the FAT.
+    device.write_bytes(fat_entry_offset(0), &0xFFFF_FFF8u32.to_le_bytes());
+    for cluster in &[1, BITMAP_CLUSTER, UPCASE_CLUSTER, ROOT_CLUSTER] {
+        device.write_bytes(fat_entry_offset(*cluster), &0xFFFF_FFFFu32.to_le_bytes());
+    }
+
+    // Allocation bitmap: the metadata clusters and the clusters of the file
+    let file_cluster_count = (FILE_SIZE / CLUSTER_SIZE) as u32;
+    let used_cluster_count = FILE_CLUSTER - 2 + file_cluster_count;
+    let mut bitmap = vec![0; (CLUSTER_COUNT / 8) as usize];
+    for index in 0..used_cluster_count as usize {
+        bitmap[index / 8] |= 1 << (index % 8);
+    }
+    device.write_bytes(cluster_offset(BITMAP_CLUSTER), &bitmap);
+
+    // Up-case table: ASCII letters only
+    let mut upcase_table = vec![0; 128 * 2];
+    for character in 0..128u16 {
+        let upper = if character >= u16::from(b'a') && character <= u16::from(b'z') {
+            character - 0x20
+        } else {
+            character
+        };
+        LittleEndian::write_u16(
+            &mut upcase_table[usize::from(character) * 2..usize::from(character) * 2 + 2],
+            upper,
+        );
+    }
+    device.write_bytes(cluster_offset(UPCASE_CLUSTER), &upcase_table);
+
+    let mut bitmap_entry = [0; 32];
+    bitmap_entry[0] = 0x81;
+    LittleEndian::write_u32(&mut bitmap_entry[20..24], BITMAP_CLUSTER);
+    LittleEndian::write_u64(&mut bitmap_entry[24..32], bitmap.len() as u64);
+
+    let mut upcase_entry = [0; 32];
+    upcase_entry[0] = 0x82;
+    LittleEndian::write_u32(&mut upcase_entry[4..8], checksum_u32(&upcase_table, &[]));
+    LittleEndian::write_u32(&mut upcase_entry[20..24], UPCASE_CLUSTER);
+    LittleEndian::write_u64(&mut upcase_entry[24..32], upcase_table.len() as u64);
+
+    // File entry set: File, Stream Extension and File Name entries
+    let name: Vec<u16> = FILE_NAME.encode_utf16().collect();
+    let mut name_hash_data = Vec::new();
+    for character in &name {
+        name_hash_data.extend_from_slice(&character.to_le_bytes());
+    }
+
+    let mut entry_set = [0; 96];
+    entry_set[0] = 0x85;
+    entry_set[1] = 2;
+    entry_set[4] = 0x20;
+    entry_set[32] = 0xC0;
+    entry_set[33] = 0x03;
+    entry_set[35] = name.len() as u8;
+    LittleEndian::write_u16(&mut entry_set[36..38], checksum_u16(&name_hash_data, &[]));
+    LittleEndian::write_u64(&mut entry_set[40..48], valid_data_length);
+    LittleEndian::write_u32(&mut entry_set[52..56], FILE_CLUSTER);
+    LittleEndian::write_u64(&mut entry_set[56..64], FILE_SIZE);
+    entry_set[64] = 0xC1;
+    entry_set[66..66 + name_hash_data.len()].copy_from_slice(&name_hash_data);
+    let entry_set_checksum = checksum_u16(&entry_set, &[2, 3]);
+    LittleEndian::write_u16(&mut entry_set[2..4], entry_set_checksum);
+
+    device.write_bytes(root_entry_offset(0), &bitmap_entry);
+    device.write_bytes(root_entry_offset(1), &upcase_entry);
+    device.write_bytes(root_entry_offset(2), &entry_set);
+
+    device.write_bytes(cluster_offset(FILE_CLUSTER) + DATA_OFFSET, DATA);
+
+    device
+}
+
+/// Check that the clusters of the file are still contiguous and not described by the FAT.
+fn assert_no_fat_chain(device: &SparseBlockDevice, cluster_count: u32) {
+    let stream_entry = device.read_bytes(root_entry_offset(3), 32);
+    assert_eq!(stream_entry[1] & 0x02, 0x02);
+    assert_eq!(LittleEndian::read_u32(&stream_entry[20..24]), FILE_CLUSTER);
+
+    for cluster in FILE_CLUSTER..FILE_CLUSTER + cluster_count {
+        assert_eq!(device.read_bytes(fat_entry_offset(cluster), 4), [0; 4]);
+    }
+}
+
+#[test]
+fn known_image_is_mounted() {
+    let device = create_volume(VALID_DATA_LENGTH);
+    let fs = exfat::get_raw_partition(device.clone()).unwrap();
+
+    let statistics = fs.statistics();
+    assert_eq!(statistics.fat_type, FatFsType::ExFat);
+    assert_eq!(statistics.serial_number, VOLUME_SERIAL_NUMBER);
+    assert_eq!(statistics.cluster_size, CLUSTER_SIZE);
+    assert_eq!(statistics.cluster_count, CLUSTER_COUNT);
+    assert_eq!(
+        statistics.free_cluster_count,
+        CLUSTER_COUNT - 3 - (FILE_SIZE / CLUSTER_SIZE) as u32
+    );
+
+    let entries: Vec<_> = fs
+        .get_root_directory()
+        .iter()
+        .map(|entry| entry.unwrap())
+        .collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].file_name.as_str(), FILE_NAME);
+    assert_eq!(entries[0].file_size, FILE_SIZE);
+    assert!(!entries[0].attribute.is_directory());
+
+    // Names are compared through the up-case table
+    let file = fs.get_root_directory().open_file("big.bin").unwrap();
+    assert_eq!(file.file_size, FILE_SIZE);
+
+    match fs.get_root_directory().open_file("missing.bin") {
+        Err(FileSystemError::NotFound) => {}
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+
+    // A corrupted boot region isn't mounted
+    device.write_bytes(200, &[0x42]);
+    match exfat::get_raw_partition(device.clone()) {
+        Err(FileSystemError::InvalidPartition) => {}
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn large_no_fat_chain_file_is_read() {
+    let device = create_volume(VALID_DATA_LENGTH);
+    let fs = exfat::get_raw_partition(device.clone()).unwrap();
+    let mut file = fs.get_root_directory().open_file(FILE_NAME).unwrap();
+
+    let mut buf = vec![0xFF; DATA.len() + 10];
+    assert_eq!(
+        file.read(&fs, DATA_OFFSET - 10, &mut buf).unwrap(),
+        buf.len() as u64
+    );
+    assert_eq!(&buf[..10], &[0; 10]);
+    assert_eq!(&buf[10..], DATA);
+
+    // The data past the valid data length reads as zeros, even if the clusters hold something else
+    device.write_bytes(cluster_offset(FILE_CLUSTER) + VALID_DATA_LENGTH, b"garbage");
+    let mut buf = [0xFF; 32];
+    assert_eq!(
+        file.read(&fs, VALID_DATA_LENGTH - 16, &mut buf).unwrap(),
+        32
+    );
+    assert_eq!(buf, [0; 32]);
+
+    // Reads are bounded by the size of the file
+    assert_eq!(file.read(&fs, FILE_SIZE - 10, &mut buf).unwrap(), 10);
+    assert_eq!(file.read(&fs, FILE_SIZE, &mut buf).unwrap(), 0);
+}
+
+#[test]
+fn large_no_fat_chain_file_is_written() {
+    let device = create_volume(VALID_DATA_LENGTH);
+    let fs = exfat::get_raw_partition(device.clone()).unwrap();
+    let mut file = fs.get_root_directory().open_file(FILE_NAME).unwrap();
+
+    // Inside the valid data length, the data lands at the offset past 4 GiB
+    let offset = DATA_OFFSET + 1000;
+    file.write(&fs, offset, b"written", false).unwrap();
+    assert_eq!(
+        device.read_bytes(cluster_offset(FILE_CLUSTER) + offset, 7),
+        b"written"
+    );
+
+    // Past the valid data length, the gap is zeroed and the valid data length grows
+    device.write_bytes(cluster_offset(FILE_CLUSTER) + VALID_DATA_LENGTH, b"garbage");
+    let offset = VALID_DATA_LENGTH + 1000;
+    file.write(&fs, offset, b"appended", false).unwrap();
+
+    let stream_entry = device.read_bytes(root_entry_offset(3), 32);
+    assert_eq!(LittleEndian::read_u64(&stream_entry[8..16]), offset + 8);
+    assert_eq!(LittleEndian::read_u64(&stream_entry[24..32]), FILE_SIZE);
+
+    let mut file = fs.get_root_directory().open_file(FILE_NAME).unwrap();
+    let mut buf = [0xFF; 1008];
+    assert_eq!(file.read(&fs, VALID_DATA_LENGTH, &mut buf).unwrap(), 1008);
+    assert_eq!(&buf[..1000], &[0; 1000][..]);
+    assert_eq!(&buf[1000..], b"appended");
+
+    let mut buf = [0; 7];
+    file.read(&fs, DATA_OFFSET + 1000, &mut buf).unwrap();
+    assert_eq!(&buf, b"written");
+    assert_no_fat_chain(&device, (FILE_SIZE / CLUSTER_SIZE) as u32);
+
+    // Growing the file keeps it contiguous as the following cluster is free
+    let cluster_count = (FILE_SIZE / CLUSTER_SIZE) as u32 + 1;
+    file.set_len(&fs, FILE_SIZE + CLUSTER_SIZE).unwrap();
+    assert_no_fat_chain(&device, cluster_count);
+
+    let mut file = fs.get_root_directory().open_file(FILE_NAME).unwrap();
+    assert_eq!(file.file_size, FILE_SIZE + CLUSTER_SIZE);
+    let mut buf = [0xFF; 16];
+    assert_eq!(file.read(&fs, FILE_SIZE + 10, &mut buf).unwrap(), 16);
+    assert_eq!(buf, [0; 16]);
+    assert_eq!(
+        fs.statistics().free_cluster_count,
+        CLUSTER_COUNT - 3 - cluster_count
+    );
+}
+
+#[test]
+fn zero_fills_larger_than_4_gib_are_written() {
+    // Only the first cluster is valid, the data written in the image past it is stale
+    let device = create_volume(CLUSTER_SIZE);
+    let fs = exfat::get_raw_partition(device.clone()).unwrap();
+    let mut file = fs.get_root_directory().open_file(FILE_NAME).unwrap();
+
+    let mut buf = [0xFF; DATA.len()];
+    assert_eq!(
+        file.read(&fs, DATA_OFFSET, &mut buf).unwrap(),
+        DATA.len() as u64
+    );
+    assert_eq!(buf, [0; DATA.len()]);
+
+    // Writing at the end of the file zeroes a gap wider than a u32
+    let offset = FILE_SIZE - 8;
+    assert!(offset - CLUSTER_SIZE > u64::from(u32::max_value()));
+    file.write(&fs, offset, b"the end!", false).unwrap();
+
+    let stream_entry = device.read_bytes(root_entry_offset(3), 32);
+    assert_eq!(LittleEndian::read_u64(&stream_entry[8..16]), FILE_SIZE);
+    assert_eq!(
+        device.read_bytes(cluster_offset(FILE_CLUSTER) + DATA_OFFSET, DATA.len()),
+        vec![0; DATA.len()]
+    );
+    assert_eq!(
+        device.read_bytes(cluster_offset(FILE_CLUSTER) + offset, 8),
+        b"the end!"
+    );
+
+    // Only the blocks of the metadata and the end of the file are left
+    assert!(device.blocks.borrow().len() < 64);
+
+    let mut file = fs.get_root_directory().open_file(FILE_NAME).unwrap();
+    let mut buf = [0xFF; 16];
+    assert_eq!(file.read(&fs, FILE_SIZE - 16, &mut buf).unwrap(), 16);
+    assert_eq!(&buf[..8], &[0; 8]);
+    assert_eq!(&buf[8..], b"the end!");
+}
+
+#[test]
+fn renames_compare_names_through_the_up_case_table() {
+    let device = create_volume(VALID_DATA_LENGTH);
+    let fs = exfat::get_raw_partition(device.clone()).unwrap();
+
+    // Only the case of the name changes, the entry is kept
+    fs.rename("/big.bin", "/Big.Bin", false).unwrap();
+    let entries: Vec<_> = fs
+        .get_root_directory()
+        .iter()
+        .map(|entry| entry.unwrap())
+        .collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].file_name.as_str(), "Big.Bin");
+    assert_eq!(entries[0].file_size, FILE_SIZE);
+
+    // Other entries with the same name are still reported
+    fs.touch("/other.bin").unwrap();
+    match fs.rename("/other.bin", "/BIG.BIN", false) {
+        Err(FileSystemError::FileExists) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    fs.mkdir("/dir").unwrap();
+    fs.rename("/dir", "/Dir", true).unwrap();
+    fs.get_root_directory().open_dir("/DIR").unwrap();
+
+    // A directory can't be moved inside itself, whatever the case of the path
+    match fs.rename("/dir", "/DIR/sub", true) {
+        Err(FileSystemError::AccessDenied) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+    fs.mkdir("/dir2").unwrap();
+    fs.rename("/dir2", "/dir/dir2", true).unwrap();
+    fs.get_root_directory().open_dir("/Dir/Dir2").unwrap();
+}
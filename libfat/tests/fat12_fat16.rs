@@ -0,0 +1,226 @@
+//! Check that FAT12/16 volumes can be mounted, read and written, with any valid media descriptor.
+
+mod common;
+
+use byteorder::{ByteOrder, LittleEndian};
+use libfat::fsck;
+use libfat::FatFsType;
+use libfs::block::RamBlockDevice;
+use libfs::FileSystemError;
+
+/// The path of the file used by the tests.
+const FILE: &str = "/file.bin";
+
+/// The media descriptor of 1.44 MB floppies.
+const FLOPPY_MEDIA_DESCRIPTOR: u8 = 0xF0;
+
+/// The volumes to test, with their size and cluster size. The FAT12 one is a 1.44 MB floppy.
+const VOLUMES: [(FatFsType, usize, u32); 2] = [
+    (FatFsType::Fat12, 1_474_560, 512),
+    (FatFsType::Fat16, 4 << 20, 512),
+];
+
+/// Get ``len`` bytes of the content of the file used by the tests, starting at ``offset``.
+fn file_content(offset: usize, len: usize) -> Vec<u8> {
+    (offset..offset + len)
+        .map(|offset| (offset % 251) as u8)
+        .collect()
+}
+
+/// Decode the FAT12 entry of ``cluster`` from the first FAT of a volume image.
+fn read_fat12_entry(image: &[u8], cluster: usize) -> u16 {
+    let bytes_per_sector = usize::from(LittleEndian::read_u16(&image[11..13]));
+    let reserved_sectors = usize::from(LittleEndian::read_u16(&image[14..16]));
+    let offset = reserved_sectors * bytes_per_sector + cluster * 3 / 2;
+    let raw_value = LittleEndian::read_u16(&image[offset..offset + 2]);
+
+    if cluster & 1 == 1 {
+        raw_value >> 4
+    } else {
+        raw_value & 0x0FFF
+    }
+}
+
+/// Format an in-memory volume and set its media descriptor to ``media_descriptor``, both in the BPB and the FATs.
+fn create_volume_with_media_descriptor(
+    fat_type: FatFsType,
+    size: usize,
+    cluster_size: u32,
+    media_descriptor: u8,
+) -> RamBlockDevice {
+    let mut image = common::create_volume(fat_type, size, cluster_size).into_vec();
+    let bytes_per_sector = usize::from(LittleEndian::read_u16(&image[11..13]));
+    let reserved_sectors = usize::from(LittleEndian::read_u16(&image[14..16]));
+    let fats_count = usize::from(image[16]);
+    let fat_size = usize::from(LittleEndian::read_u16(&image[22..24]));
+
+    image[21] = media_descriptor;
+    for fat_index in 0..fats_count {
+        image[(reserved_sectors + fat_index * fat_size) * bytes_per_sector] = media_descriptor;
+    }
+
+    RamBlockDevice::from_vec(image)
+}
+
+#[test]
+fn fixed_root_directory_ignores_media_descriptor() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let device = create_volume_with_media_descriptor(
+            *fat_type,
+            *size,
+            *cluster_size,
+            FLOPPY_MEDIA_DESCRIPTOR,
+        );
+
+        let fs = libfat::get_raw_partition(&device).unwrap();
+        assert_eq!(fs.statistics().fat_type, *fat_type);
+
+        // Fill the root directory so that walking it reaches the end of its fixed region.
+        // With the label and the directory, the 512 entries are used up by files taking two entries each.
+        fs.mkdir("/NESTED_DIRECTORY").unwrap();
+        fs.touch("/NESTED_DIRECTORY/NESTED.TXT").unwrap();
+
+        let mut file_count = 0;
+        loop {
+            match fs.touch(&format!("/FILE{}.TXT", file_count)) {
+                Ok(()) => file_count += 1,
+                Err(FileSystemError::NoSpaceLeft) => break,
+                Err(error) => panic!("{:?}: unexpected error {:?}", fat_type, error),
+            }
+        }
+        assert_eq!(file_count, 254, "{:?}", fat_type);
+
+        match fs.get_root_directory().open_file("/MISSING.TXT") {
+            Err(FileSystemError::NotFound) => {}
+            result => panic!("{:?}: unexpected result {:?}", fat_type, result.map(|_| ())),
+        }
+        fs.get_root_directory()
+            .open_file("/NESTED_DIRECTORY/NESTED.TXT")
+            .unwrap();
+        fs.flush().unwrap();
+
+        let report = fsck::check(&fs).unwrap();
+        assert!(report.is_clean(), "{:?}: {:?}", fat_type, report.issues);
+    }
+}
+
+#[test]
+fn files_can_be_written_extended_and_read_back() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let device = common::create_volume(*fat_type, *size, *cluster_size);
+
+        {
+            let fs = libfat::get_raw_partition(&device).unwrap();
+            fs.mkdir("/dir").unwrap();
+            fs.touch("/dir/file.bin").unwrap();
+
+            let mut file = fs.get_root_directory().open_file("/dir/file.bin").unwrap();
+            file.write(&fs, 0, &file_content(0, 3000), true).unwrap();
+
+            // Overwrite the middle of the file, then append past its end
+            file.write(&fs, 1000, &file_content(1000, 1500), true)
+                .unwrap();
+            file.write(&fs, 3000, &file_content(3000, 5000), true)
+                .unwrap();
+            assert_eq!(file.file_size, 8000);
+
+            // Extending the file zero-fills the new space
+            file.set_len(&fs, 10000).unwrap();
+            assert_eq!(file.file_size, 10000);
+            fs.flush().unwrap();
+            common::assert_clean(&fs);
+        }
+
+        let fs = libfat::get_raw_partition(&device).unwrap();
+        let mut file = fs.get_root_directory().open_file("/dir/file.bin").unwrap();
+        assert_eq!(file.file_size, 10000);
+
+        let mut buf = vec![0xFF; 10000];
+        assert_eq!(file.read(&fs, 0, &mut buf).unwrap(), 10000);
+        assert_eq!(&buf[..8000], &file_content(0, 8000)[..], "{:?}", fat_type);
+        assert!(
+            buf[8000..].iter().all(|value| *value == 0),
+            "{:?}",
+            fat_type
+        );
+
+        // Reads stop at the end of the file
+        assert_eq!(file.read(&fs, 9000, &mut buf).unwrap(), 1000);
+    }
+}
+
+#[test]
+fn fat12_entries_can_span_blocks() {
+    /// The first odd cluster whose FAT12 entry starts at the last byte of a FAT block.
+    const SPANNING_CLUSTER: usize = 341;
+
+    let (fat_type, size, cluster_size) = VOLUMES[0];
+    let device = common::create_volume(fat_type, size, cluster_size);
+
+    // The file starts at the first data cluster, 2, and ends past the spanning cluster
+    let file_size = 400 * cluster_size as usize;
+    {
+        let fs = libfat::get_raw_partition(&device).unwrap();
+        fs.touch(FILE).unwrap();
+        common::append(&fs, FILE, &file_content(0, file_size)).unwrap();
+        fs.flush().unwrap();
+        common::assert_clean(&fs);
+    }
+
+    let image = device.to_vec();
+    let bytes_per_sector = usize::from(LittleEndian::read_u16(&image[11..13]));
+    assert_eq!(
+        (SPANNING_CLUSTER * 3 / 2) % bytes_per_sector,
+        bytes_per_sector - 1
+    );
+    assert_eq!(
+        read_fat12_entry(&image, SPANNING_CLUSTER),
+        SPANNING_CLUSTER as u16 + 1
+    );
+
+    // The chain is followed across the boundary
+    let fs = libfat::get_raw_partition(&device).unwrap();
+    let mut file = fs.get_root_directory().open_file(FILE).unwrap();
+    let mut buf = vec![0; file_size];
+    assert_eq!(file.read(&fs, 0, &mut buf).unwrap(), file_size as u64);
+    assert_eq!(buf, file_content(0, file_size));
+
+    // Truncate the file so that the spanning cluster ends the chain
+    file.set_len(&fs, (SPANNING_CLUSTER as u64 - 1) * u64::from(cluster_size))
+        .unwrap();
+    fs.flush().unwrap();
+    common::assert_clean(&fs);
+    drop(fs);
+
+    let image = device.to_vec();
+    assert!(read_fat12_entry(&image, SPANNING_CLUSTER) >= 0xFF8);
+    assert_eq!(
+        read_fat12_entry(&image, SPANNING_CLUSTER - 1),
+        SPANNING_CLUSTER as u16
+    );
+    assert_eq!(read_fat12_entry(&image, SPANNING_CLUSTER + 1), 0);
+}
+
+#[test]
+fn invalid_media_descriptors_are_rejected() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        for media_descriptor in &[0x00, 0xF1, 0xF7] {
+            let device = create_volume_with_media_descriptor(
+                *fat_type,
+                *size,
+                *cluster_size,
+                *media_descriptor,
+            );
+
+            match libfat::get_raw_partition(&device) {
+                Err(FileSystemError::InvalidPartition) => {}
+                result => panic!(
+                    "{:?} with media descriptor {:#x}: unexpected result {:?}",
+                    fat_type,
+                    media_descriptor,
+                    result.map(|_| ())
+                ),
+            }
+        }
+    }
+}
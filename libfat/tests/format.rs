@@ -0,0 +1,178 @@
+//! Check that formatted volumes are mounted with the requested parameters and are consistent.
+
+mod common;
+
+use common::{Operation, VOLUMES};
+use libfat::filesystem::FatFileSystem;
+use libfat::format::{format_partition, format_raw_partition, FormatOptions};
+use libfat::partition::{self, PartitionType};
+use libfat::FatFsType;
+use libfs::block::{BlockCount, BlockDevice, BlockIndex, RamBlockDevice};
+use libfs::FileSystemError;
+
+/// The operations run on the formatted volumes.
+const OPERATIONS: [Operation; 5] = [
+    Operation::Mkdir("/dir"),
+    Operation::Touch("/dir/file.txt"),
+    Operation::Write("/dir/file.txt", 0, 5000),
+    Operation::Touch("/a file with a long name.txt"),
+    Operation::Write("/a file with a long name.txt", 0, 100),
+];
+
+/// The volumes to test with the cluster size selected from the size of the volume.
+const DEFAULT_CLUSTER_SIZE_VOLUMES: [(FatFsType, usize, u32); 3] = [
+    (FatFsType::Fat12, 1 << 20, 0),
+    (FatFsType::Fat16, 16 << 20, 0),
+    (FatFsType::Fat32, 64 << 20, 0),
+];
+
+/// Create some files and directories on a volume.
+fn run_operations<T>(fs: &FatFileSystem<T>)
+where
+    T: BlockDevice,
+{
+    for operation in &OPERATIONS {
+        common::run_operation(fs, *operation).unwrap();
+    }
+    fs.flush().unwrap();
+
+    let file = fs.get_root_directory().open_file("/dir/file.txt").unwrap();
+    assert_eq!(file.file_size, 5000);
+}
+
+#[test]
+fn formatted_volumes_pass_fsck() {
+    for (fat_type, size, cluster_size) in VOLUMES.iter().chain(&DEFAULT_CLUSTER_SIZE_VOLUMES) {
+        let device = common::create_volume(*fat_type, *size, *cluster_size);
+        let fs = libfat::get_raw_partition(&device).unwrap();
+
+        let statistics = fs.statistics();
+        assert_eq!(statistics.fat_type, *fat_type);
+        assert_eq!(statistics.serial_number, 0x1234_5678);
+        if *cluster_size != 0 {
+            assert_eq!(statistics.cluster_size, u64::from(*cluster_size));
+        }
+
+        // Only the root directory of FAT32 volumes uses a cluster
+        let used_cluster_count = if *fat_type == FatFsType::Fat32 { 1 } else { 0 };
+        assert_eq!(
+            statistics.free_cluster_count,
+            statistics.cluster_count - used_cluster_count
+        );
+
+        assert_eq!(fs.volume_label().unwrap(), Some(String::from("TEST")));
+        assert!(fs.mount_state().clean_shutdown);
+        common::assert_clean(&fs);
+
+        run_operations(&fs);
+        common::assert_clean(&fs);
+    }
+}
+
+#[test]
+fn unlabeled_volumes_have_no_label() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let device = RamBlockDevice::new(*size);
+        let options = FormatOptions {
+            fat_type: *fat_type,
+            cluster_size: *cluster_size,
+            volume_label: "",
+            volume_id: 0,
+        };
+        format_raw_partition(&device, &options).unwrap();
+
+        let fs = libfat::get_raw_partition(&device).unwrap();
+        assert_eq!(fs.volume_label().unwrap(), None, "{:?}", fat_type);
+        common::assert_clean(&fs);
+    }
+}
+
+#[test]
+fn invalid_options_are_rejected() {
+    let format = |fat_type, size, cluster_size, volume_label| {
+        let options = FormatOptions {
+            fat_type,
+            cluster_size,
+            volume_label,
+            volume_id: 0,
+        };
+        format_raw_partition(&RamBlockDevice::new(size), &options)
+    };
+
+    match format(FatFsType::ExFat, 4 << 20, 0, "") {
+        Err(FileSystemError::Custom { .. }) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // The cluster count of a 1 MiB volume is in the FAT12 range
+    match format(FatFsType::Fat16, 1 << 20, 512, "") {
+        Err(FileSystemError::Custom { .. }) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    match format(FatFsType::Fat12, 1 << 20, 768, "") {
+        Err(FileSystemError::Custom { .. }) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    match format(FatFsType::Fat12, 1 << 20, 512, "LABEL/SLASH") {
+        Err(FileSystemError::Custom { .. }) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    match format(FatFsType::Fat12, 1 << 20, 512, "TOO LONG LABEL") {
+        Err(FileSystemError::PathTooLong) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn partitions_are_formatted_in_place() {
+    const PARTITION_START: u32 = 2048;
+    const PARTITION_SECTORS: u32 = 12 * 2048;
+
+    let mut image = vec![0xE5; (PARTITION_START + PARTITION_SECTORS + 2048) as usize * 512];
+    image[..512].iter_mut().for_each(|byte| *byte = 0);
+    common::write_partition_entry(&mut image, 0, 0, 0x06, PARTITION_START, PARTITION_SECTORS);
+    let device = RamBlockDevice::from_vec(image);
+
+    let options = FormatOptions {
+        fat_type: FatFsType::Fat16,
+        cluster_size: 0,
+        volume_label: "PARTITION",
+        volume_id: 0xCAFE_BABE,
+    };
+    format_partition(
+        &device,
+        BlockIndex(PARTITION_START),
+        BlockCount(PARTITION_SECTORS),
+        &options,
+    )
+    .unwrap();
+
+    // Nothing is written outside of the partition
+    let image = device.to_vec();
+    let partition_range =
+        PARTITION_START as usize * 512..(PARTITION_START + PARTITION_SECTORS) as usize * 512;
+    assert_eq!(image[510..512], [0x55, 0xAA]);
+    assert!(image[512..partition_range.start]
+        .iter()
+        .all(|byte| *byte == 0xE5));
+    assert!(image[partition_range.end..]
+        .iter()
+        .all(|byte| *byte == 0xE5));
+
+    let partitions = partition::list_partitions(&device).unwrap();
+    assert_eq!(partitions.len(), 1);
+    assert_eq!(partitions[0].start, BlockIndex(PARTITION_START));
+    assert_eq!(partitions[0].block_count.0, PARTITION_SECTORS);
+    assert_eq!(partitions[0].partition_type, PartitionType::Mbr(0x06));
+    assert_eq!(partitions[0].filesystem, Some(FatFsType::Fat16));
+
+    let fs = libfat::get_partition(&device, 0).unwrap();
+    assert_eq!(fs.statistics().serial_number, 0xCAFE_BABE);
+    assert_eq!(fs.volume_label().unwrap(), Some(String::from("PARTITION")));
+
+    run_operations(&fs);
+    common::assert_clean(&fs);
+}
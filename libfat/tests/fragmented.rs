@@ -0,0 +1,342 @@
+//! Check that reads and writes at any offset of a fragmented file reach the right clusters.
+
+mod common;
+
+use libfat::extent::ExtentMap;
+use libfat::filesystem::FatFileSystem;
+use libfat::fsck;
+use libfat::FatFsType;
+use libfs::block::{BlockDevice, RamBlockDevice};
+use libfs::fault::FaultyBlockDevice;
+
+/// The volumes to test, with their size and cluster size. Unlike the shared ones, clusters span several blocks.
+const VOLUMES: [(FatFsType, usize, u32); 3] = [
+    (FatFsType::Fat12, 1 << 20, 1024),
+    (FatFsType::Fat16, 16 << 20, 2048),
+    (FatFsType::Fat32, 34 << 20, 512),
+];
+
+/// The files whose clusters get interleaved.
+const FILES: [&str; 3] = ["/first.bin", "/second.bin", "/a file with a long name.bin"];
+
+/// A file deleted once the others are populated, leaving holes between their clusters.
+const HOLE_FILE: &str = "/hole.bin";
+
+/// The count of clusters appended to every file.
+const CLUSTERS_PER_FILE: usize = 6;
+
+/// The size of the buffers used to read and write, spanning several blocks and clusters.
+const ACCESS_SIZE: usize = 1500;
+
+/// Get the byte of a file at a given offset, distinct between files so that misdirected accesses are caught.
+fn pattern(file_index: usize, offset: usize) -> u8 {
+    (offset.wrapping_mul(7) ^ (offset >> 8) ^ (file_index * 0x55)) as u8
+}
+
+/// Append ``size`` bytes to the file at ``path`` and to its expected content.
+fn append<T>(
+    fs: &FatFileSystem<T>,
+    path: &str,
+    file_index: usize,
+    content: &mut Vec<u8>,
+    size: usize,
+) where
+    T: BlockDevice,
+{
+    let offset = content.len();
+    let data: Vec<u8> = (offset..offset + size)
+        .map(|offset| pattern(file_index, offset))
+        .collect();
+
+    common::append(fs, path, &data).unwrap();
+    content.extend_from_slice(&data);
+}
+
+/// Create a volume holding files made of scattered clusters and return it with the expected content of the files.
+fn create_fragmented_volume(
+    fat_type: FatFsType,
+    size: usize,
+    cluster_size: u32,
+) -> (RamBlockDevice, Vec<Vec<u8>>) {
+    let device = common::create_volume(fat_type, size, cluster_size);
+    let mut contents = vec![Vec::new(); FILES.len()];
+    let mut hole_content = Vec::new();
+    let cluster_size = cluster_size as usize;
+
+    {
+        let fs = libfat::get_raw_partition(&device).unwrap();
+
+        for path in FILES.iter().chain(Some(&HOLE_FILE)) {
+            fs.touch(path).unwrap();
+        }
+
+        // Grow the files one cluster at a time so that their clusters interleave
+        for _ in 0..CLUSTERS_PER_FILE {
+            for (file_index, path) in FILES.iter().enumerate() {
+                append(
+                    &fs,
+                    path,
+                    file_index,
+                    &mut contents[file_index],
+                    cluster_size,
+                );
+            }
+            append(&fs, HOLE_FILE, FILES.len(), &mut hole_content, cluster_size);
+        }
+
+        // Free clusters between the others, then let the first file grow into them or past them
+        fs.unlink(HOLE_FILE, false).unwrap();
+        append(&fs, FILES[0], 0, &mut contents[0], cluster_size * 3 + 100);
+
+        // Leave partial clusters at the end of the files
+        for (file_index, path) in FILES.iter().enumerate().skip(1) {
+            append(
+                &fs,
+                path,
+                file_index,
+                &mut contents[file_index],
+                cluster_size / 3,
+            );
+        }
+
+        fs.flush().unwrap();
+    }
+
+    (device, contents)
+}
+
+/// Check that every file holds its expected content and that the volume is consistent.
+fn assert_contents<T>(fs: &FatFileSystem<T>, contents: &[Vec<u8>])
+where
+    T: BlockDevice,
+{
+    for (path, content) in FILES.iter().zip(contents.iter()) {
+        let mut file = fs.get_root_directory().open_file(path).unwrap();
+        let mut data = vec![0; content.len()];
+
+        assert_eq!(file.file_size as usize, content.len());
+        assert_eq!(file.read(fs, 0, &mut data).unwrap() as usize, content.len());
+        assert!(data == *content, "{}: content mismatch", path);
+    }
+
+    let report = fsck::check(fs).unwrap();
+    assert!(report.is_clean(), "{:?}", report.issues);
+}
+
+#[test]
+fn reads_at_every_offset_are_correct() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let (device, contents) = create_fragmented_volume(*fat_type, *size, *cluster_size);
+        let fs = libfat::get_raw_partition(&device).unwrap();
+
+        for (path, content) in FILES.iter().zip(contents.iter()) {
+            let mut file = fs.get_root_directory().open_file(path).unwrap();
+            let mut buffer = [0; ACCESS_SIZE];
+
+            for offset in 0..content.len() {
+                let expected = &content[offset..content.len().min(offset + ACCESS_SIZE)];
+                let read_size = file.read(&fs, offset as u64, &mut buffer).unwrap() as usize;
+
+                assert_eq!(read_size, expected.len());
+                assert!(
+                    buffer[..read_size] == *expected,
+                    "{:?} {}: bad read at offset {}",
+                    fat_type,
+                    path,
+                    offset
+                );
+            }
+        }
+
+        assert_contents(&fs, &contents);
+    }
+}
+
+#[test]
+fn writes_at_every_offset_are_correct() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let (device, mut contents) = create_fragmented_volume(*fat_type, *size, *cluster_size);
+
+        {
+            let fs = libfat::get_raw_partition(&device).unwrap();
+
+            for (file_index, (path, content)) in FILES.iter().zip(contents.iter_mut()).enumerate() {
+                let mut file = fs.get_root_directory().open_file(path).unwrap();
+                let mut buffer = [0; ACCESS_SIZE];
+
+                for offset in 0..content.len() {
+                    let end = content.len().min(offset + ACCESS_SIZE);
+                    let data: Vec<u8> = (offset..end)
+                        .map(|offset| !pattern(file_index, offset))
+                        .collect();
+
+                    file.write(&fs, offset as u64, &data, false).unwrap();
+                    content[offset..end].copy_from_slice(&data);
+
+                    let read_size = file.read(&fs, offset as u64, &mut buffer).unwrap() as usize;
+                    assert!(
+                        buffer[..read_size] == content[offset..end],
+                        "{:?} {}: bad write at offset {}",
+                        fat_type,
+                        path,
+                        offset
+                    );
+                }
+            }
+
+            // The other files must not have been touched
+            assert_contents(&fs, &contents);
+            fs.flush().unwrap();
+        }
+
+        let fs = libfat::get_raw_partition(&device).unwrap();
+        assert_contents(&fs, &contents);
+    }
+}
+
+#[test]
+fn extent_map_accesses_at_every_offset_are_correct() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let (device, mut contents) = create_fragmented_volume(*fat_type, *size, *cluster_size);
+        let fs = libfat::get_raw_partition(&device).unwrap();
+
+        for (file_index, (path, content)) in FILES.iter().zip(contents.iter_mut()).enumerate() {
+            let mut file = fs.get_root_directory().open_file(path).unwrap();
+            let mut extent_map = ExtentMap::new();
+            let mut buffer = [0; ACCESS_SIZE];
+
+            for offset in 0..content.len() {
+                let expected = &content[offset..content.len().min(offset + ACCESS_SIZE)];
+                let read_size = file
+                    .read_with_extents(&fs, &mut extent_map, offset as u64, &mut buffer)
+                    .unwrap() as usize;
+
+                assert!(
+                    buffer[..read_size] == *expected,
+                    "{:?} {}: bad read at offset {}",
+                    fat_type,
+                    path,
+                    offset
+                );
+            }
+
+            let cluster_size = *cluster_size as usize;
+            let cluster_count = (content.len() + cluster_size - 1) / cluster_size;
+            assert_eq!(extent_map.cluster_count() as usize, cluster_count);
+            assert!(extent_map.extents().len() > 1, "{}: not fragmented", path);
+
+            for offset in 0..content.len() {
+                let end = content.len().min(offset + ACCESS_SIZE);
+                let data: Vec<u8> = (offset..end)
+                    .map(|offset| !pattern(file_index, offset))
+                    .collect();
+
+                file.write_with_extents(&fs, &mut extent_map, offset as u64, &data, false)
+                    .unwrap();
+                content[offset..end].copy_from_slice(&data);
+            }
+        }
+
+        assert_contents(&fs, &contents);
+    }
+}
+
+#[test]
+fn extent_map_follows_set_len() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let (device, mut contents) = create_fragmented_volume(*fat_type, *size, *cluster_size);
+        let fs = libfat::get_raw_partition(&device).unwrap();
+        let cluster_size = *cluster_size as usize;
+
+        let mut file = fs.get_root_directory().open_file(FILES[1]).unwrap();
+        let mut extent_map = ExtentMap::new();
+        let content = &mut contents[1];
+        let mut buffer = vec![0; content.len()];
+        file.read_with_extents(&fs, &mut extent_map, 0, &mut buffer)
+            .unwrap();
+
+        // Shrink the file then let other files reuse the freed clusters
+        let new_len = cluster_size * 2 + 10;
+        file.set_len_with_extents(&fs, &mut extent_map, new_len as u64)
+            .unwrap();
+        content.truncate(new_len);
+        assert_eq!(extent_map.cluster_count(), 3);
+
+        append(&fs, FILES[2], 2, &mut contents[2], cluster_size * 2);
+
+        // Grow the file through writes and set_len, then check it against a fresh map
+        let content = &mut contents[1];
+        let data: Vec<u8> = (0..cluster_size * 3).map(|offset| offset as u8).collect();
+        file.write_with_extents(&fs, &mut extent_map, content.len() as u64, &data, true)
+            .unwrap();
+        content.extend_from_slice(&data);
+
+        file.set_len_with_extents(&fs, &mut extent_map, (content.len() + cluster_size) as u64)
+            .unwrap();
+        content.resize(content.len() + cluster_size, 0);
+        file.write_with_extents(
+            &fs,
+            &mut extent_map,
+            (content.len() - 5) as u64,
+            &[7; 5],
+            false,
+        )
+        .unwrap();
+        content.truncate(content.len() - 5);
+        content.extend_from_slice(&[7; 5]);
+
+        let mut fresh_map = ExtentMap::new();
+        let mut buffer = vec![0; content.len()];
+        file.read_with_extents(&fs, &mut fresh_map, 0, &mut buffer)
+            .unwrap();
+        assert_eq!(extent_map.extents(), fresh_map.extents());
+
+        // Emptying the file drops every run
+        file.set_len_with_extents(&fs, &mut extent_map, 0).unwrap();
+        assert!(extent_map.extents().is_empty());
+        file.write_with_extents(&fs, &mut extent_map, 0, &content, true)
+            .unwrap();
+
+        assert_contents(&fs, &contents);
+    }
+}
+
+#[test]
+fn extent_map_seeks_without_walking_the_chain() {
+    let (fat_type, size, cluster_size) = VOLUMES[2];
+    let (device, _) = create_fragmented_volume(fat_type, size, cluster_size);
+    let device = FaultyBlockDevice::new(device);
+    let fs = libfat::get_raw_partition(&device).unwrap();
+
+    let mut file = fs.get_root_directory().open_file(FILES[0]).unwrap();
+    let mut extent_map = ExtentMap::new();
+    let last_offset = u64::from(file.file_size) - 1;
+    let mut buffer = [0; 1];
+
+    // Count the device reads done to read a byte of the file
+    let mut count_reads = |offset: u64, use_extents: bool| {
+        device.reset_counters();
+        if use_extents {
+            file.read_with_extents(&fs, &mut extent_map, offset, &mut buffer)
+                .unwrap();
+        } else {
+            file.read(&fs, offset, &mut buffer).unwrap();
+        }
+        device.read_count()
+    };
+
+    let chain_reads = count_reads(last_offset, false);
+
+    // Once the map is filled, the chain isn't read anymore
+    count_reads(last_offset, true);
+    let first_byte_reads = count_reads(0, true);
+    let last_byte_reads = count_reads(last_offset, true);
+
+    assert_eq!(first_byte_reads, last_byte_reads);
+    assert!(
+        last_byte_reads < chain_reads,
+        "{} reads with a map, {} without",
+        last_byte_reads,
+        chain_reads
+    );
+}
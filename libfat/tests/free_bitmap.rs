@@ -0,0 +1,251 @@
+//! Check that the in-memory free cluster map allocates like a FAT scan while avoiding FAT reads.
+
+mod common;
+
+use byteorder::{ByteOrder, LittleEndian};
+use common::VOLUMES;
+use libfat::filesystem::FatFileSystem;
+use libfat::fsck::{self, FsckIssue, LostChainAction, RepairOptions};
+use libfs::block::{BlockCount, BlockDevice, RamBlockDevice};
+use libfs::fault::{Fault, FaultOperation, FaultyBlockDevice, RecordingBlockDevice};
+use libfs::FileSystemError;
+
+/// The size of the writes used to fill the volumes.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Append ``size`` bytes to the file at ``path``.
+fn append<T>(fs: &FatFileSystem<T>, path: &str, size: usize) -> Result<(), FileSystemError>
+where
+    T: BlockDevice,
+{
+    common::append(fs, path, &vec![0x42; size])
+}
+
+/// Fill the free space of the volume with the file at ``path``.
+fn fill<T>(fs: &FatFileSystem<T>, path: &str)
+where
+    T: BlockDevice,
+{
+    let statistics = fs.statistics();
+    let mut free_size = statistics.free_cluster_count as usize * statistics.cluster_size as usize;
+
+    fs.touch(path).unwrap();
+
+    while free_size > 0 {
+        let size = free_size.min(CHUNK_SIZE);
+        append(fs, path, size).unwrap();
+        free_size -= size;
+    }
+}
+
+/// Fragment the free space of the volume, fill it, then free and reuse clusters behind the allocation hint.
+fn run_operations<T>(fs: &FatFileSystem<T>)
+where
+    T: BlockDevice,
+{
+    let paths = ["/first.bin", "/second.bin", "/third.bin"];
+
+    for path in &paths {
+        fs.touch(path).unwrap();
+    }
+
+    for _ in 0..8 {
+        for path in &paths {
+            append(fs, path, 700).unwrap();
+        }
+    }
+
+    fs.unlink(paths[1], false).unwrap();
+    fs.mkdir("/dir").unwrap();
+    append(fs, paths[0], 5000).unwrap();
+
+    let mut file = fs.get_root_directory().open_file(paths[2]).unwrap();
+    file.set_len(fs, 1000).unwrap();
+
+    fill(fs, "/fill.bin");
+    assert_eq!(fs.statistics().free_cluster_count, 0);
+
+    match fs.mkdir("/full") {
+        Err(FileSystemError::NoSpaceLeft) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // Free clusters before the last allocated one and allocate them again
+    fs.unlink(paths[0], false).unwrap();
+    append(fs, paths[2], 3000).unwrap();
+    fs.touch("/last.bin").unwrap();
+    append(fs, "/last.bin", 2000).unwrap();
+
+    fs.flush().unwrap();
+}
+
+#[test]
+fn bitmap_allocations_match_fat_scans() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let image = common::create_volume(*fat_type, *size, *cluster_size).into_vec();
+
+        let scan_device = RamBlockDevice::from_vec(image.clone());
+        let scan_fs = libfat::get_raw_partition(&scan_device).unwrap();
+        run_operations(&scan_fs);
+
+        let bitmap_device = RamBlockDevice::from_vec(image);
+        let mut bitmap_fs = libfat::get_raw_partition(&bitmap_device).unwrap();
+        bitmap_fs.enable_free_cluster_bitmap().unwrap();
+        assert!(bitmap_fs.has_free_cluster_bitmap());
+        run_operations(&bitmap_fs);
+
+        assert_eq!(
+            scan_fs.statistics().free_cluster_count,
+            bitmap_fs.statistics().free_cluster_count
+        );
+        assert!(
+            scan_device.to_vec() == bitmap_device.to_vec(),
+            "{:?}: images differ",
+            fat_type
+        );
+
+        let report = fsck::check(&bitmap_fs).unwrap();
+        assert!(report.is_clean(), "{:?}", report.issues);
+        assert_eq!(
+            report.free_cluster_count,
+            bitmap_fs.statistics().free_cluster_count
+        );
+    }
+}
+
+/// Lose the chain of a file by failing the update of its entry, repair the volume, then fill it.
+///
+/// Return the resulting image.
+fn run_repair(image: &[u8], use_bitmap: bool) -> Vec<u8> {
+    let recording_device = RecordingBlockDevice::new(RamBlockDevice::from_vec(image.to_vec()));
+    let device = FaultyBlockDevice::new(&recording_device);
+
+    {
+        let mut fs = libfat::get_raw_partition(&device).unwrap();
+        if use_bitmap {
+            fs.enable_free_cluster_bitmap().unwrap();
+        }
+
+        fs.touch("/first.bin").unwrap();
+        recording_device.take_writes();
+
+        // Find the block holding the entry of the file and forbid writing it
+        fs.touch("/file.bin").unwrap();
+        let entry_write = recording_device.take_writes().pop().unwrap();
+
+        device.add_fault(Fault::FailRange {
+            start: entry_write.index,
+            count: BlockCount(1),
+            operation: FaultOperation::Write,
+        });
+        assert!(append(&fs, "/file.bin", 10_000).is_err());
+        device.clear_faults();
+
+        let options = RepairOptions {
+            lost_chains: LostChainAction::Free,
+        };
+        let report = fsck::repair(&fs, &options).unwrap();
+        assert!(
+            report.issues.iter().any(|issue| match issue {
+                FsckIssue::LostChain { .. } => true,
+                _ => false,
+            }),
+            "{:?}",
+            report.issues
+        );
+
+        fill(&fs, "/fill.bin");
+        fs.flush().unwrap();
+    }
+
+    recording_device.into_inner().into_vec()
+}
+
+#[test]
+fn bitmap_follows_fsck_repairs() {
+    let (fat_type, size, cluster_size) = VOLUMES[1];
+    let image = common::create_volume(fat_type, size, cluster_size).into_vec();
+
+    assert!(run_repair(&image, false) == run_repair(&image, true));
+}
+
+#[test]
+fn bitmap_allocations_skip_fat_reads() {
+    let (fat_type, size, cluster_size) = VOLUMES[1];
+    let device = common::create_volume(fat_type, size, cluster_size);
+
+    // Leave a single free cluster at the end of the volume, far from where the scan starts after mounting
+    {
+        let fs = libfat::get_raw_partition(&device).unwrap();
+        fill(&fs, "/fill.bin");
+
+        let mut file = fs.get_root_directory().open_file("/fill.bin").unwrap();
+        let file_size = u64::from(file.file_size);
+        file.set_len(&fs, file_size - u64::from(cluster_size))
+            .unwrap();
+        fs.flush().unwrap();
+    }
+
+    let image = device.into_vec();
+
+    // Count the device reads done to allocate the cluster
+    let count_reads = |use_bitmap: bool| {
+        let device = FaultyBlockDevice::new(RamBlockDevice::from_vec(image.clone()));
+        let mut fs = libfat::get_raw_partition(&device).unwrap();
+        if use_bitmap {
+            fs.enable_free_cluster_bitmap().unwrap();
+        }
+
+        device.reset_counters();
+        fs.mkdir("/dir").unwrap();
+        device.read_count()
+    };
+
+    let scan_reads = count_reads(false);
+    let bitmap_reads = count_reads(true);
+
+    assert!(
+        bitmap_reads * 10 < scan_reads,
+        "{} reads with a bitmap, {} without",
+        bitmap_reads,
+        scan_reads
+    );
+}
+
+#[test]
+fn mount_counts_free_clusters_with_bulk_reads() {
+    let (fat_type, size, cluster_size) = VOLUMES[1];
+    let device = FaultyBlockDevice::new(common::create_volume(fat_type, size, cluster_size));
+
+    let mut fs = libfat::get_raw_partition(&device).unwrap();
+    let mount_reads = device.read_count();
+    let cluster_count = fs.statistics().cluster_count;
+
+    device.reset_counters();
+    fs.enable_free_cluster_bitmap().unwrap();
+    let bitmap_reads = device.read_count();
+
+    // FAT16 has no FS Info, the free clusters are counted at mount
+    assert!(mount_reads < 32, "{} reads at mount", mount_reads);
+    assert!(
+        bitmap_reads < 32,
+        "{} reads to build the bitmap",
+        bitmap_reads
+    );
+    assert_eq!(fs.statistics().free_cluster_count, cluster_count);
+}
+
+#[test]
+fn oversized_cluster_count_is_rejected() {
+    let (fat_type, size, cluster_size) = VOLUMES[1];
+    let mut image = common::create_volume(fat_type, size, cluster_size).into_vec();
+
+    // Shrink the FATs so that they can't hold an entry for every cluster
+    LittleEndian::write_u16(&mut image[22..24], 4);
+
+    let device = RamBlockDevice::from_vec(image);
+    match libfat::get_raw_partition(&device) {
+        Err(FileSystemError::InvalidPartition) => {}
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+}
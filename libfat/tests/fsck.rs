@@ -0,0 +1,516 @@
+//! Check that fsck reports every kind of inconsistency built on a volume image, and that repairs leave a clean volume.
+
+mod common;
+
+use byteorder::{ByteOrder, LittleEndian};
+use libfat::fsck::{self, FsckIssue, FsckReport, LostChainAction, RepairOptions};
+use libfat::FatFsType;
+use libfs::block::RamBlockDevice;
+
+/// The raw name of the "." entry.
+const DOT_NAME: &[u8; 11] = b".          ";
+
+/// The raw name of the ".." entry.
+const DOT_DOT_NAME: &[u8; 11] = b"..         ";
+
+/// The raw name of the file in the subdirectory, made of 10 clusters.
+const FILE_NAME: &[u8; 11] = b"FILE    TXT";
+
+/// The raw name of the file in the root directory, made of 2 clusters.
+const OTHER_NAME: &[u8; 11] = b"OTHER   TXT";
+
+/// The path of the empty file with a long name in the root directory.
+const LONG_FILE_NAME: &str = "/a file with a long name.txt";
+
+/// A cluster left free on the volume.
+const FREE_CLUSTER: u32 = 1000;
+
+/// Format an in-memory FAT32 volume holding a directory, files and a long file name, and return its image.
+fn create_image() -> Vec<u8> {
+    let device = common::create_volume(FatFsType::Fat32, 34 << 20, 512);
+
+    {
+        let fs = libfat::get_raw_partition(&device).unwrap();
+        fs.mkdir("/DIR").unwrap();
+        fs.touch("/DIR/FILE.TXT").unwrap();
+        common::append(&fs, "/DIR/FILE.TXT", &[0x42; 5000]).unwrap();
+        fs.touch("/OTHER.TXT").unwrap();
+        common::append(&fs, "/OTHER.TXT", &[0x42; 1000]).unwrap();
+        fs.touch(LONG_FILE_NAME).unwrap();
+        fs.flush().unwrap();
+        common::assert_clean(&fs);
+    }
+
+    device.into_vec()
+}
+
+/// Mount a volume image and check it.
+fn check_image(image: Vec<u8>) -> FsckReport {
+    let device = RamBlockDevice::from_vec(image);
+    let fs = libfat::get_raw_partition(&device).unwrap();
+
+    fsck::check(&fs).unwrap()
+}
+
+/// Return the offset of the data area of a volume image.
+fn data_offset(image: &[u8]) -> usize {
+    let bytes_per_sector = usize::from(LittleEndian::read_u16(&image[11..13]));
+    let reserved_sectors = usize::from(LittleEndian::read_u16(&image[14..16]));
+    let fat_size = LittleEndian::read_u32(&image[36..40]) as usize;
+
+    (reserved_sectors + usize::from(image[16]) * fat_size) * bytes_per_sector
+}
+
+/// Return the offset of the first directory entry with the given raw name in a volume image.
+fn find_entry(image: &[u8], name: &[u8; 11]) -> usize {
+    (data_offset(image)..image.len())
+        .step_by(32)
+        .find(|offset| image[*offset..*offset + 11] == name[..])
+        .unwrap()
+}
+
+/// Return the offset of the first entry of the VFAT long name sequence of ``LONG_FILE_NAME`` in a volume image.
+///
+/// The other names fit in one entry, this is the only sequence made of 3 entries.
+fn find_long_file_name(image: &[u8]) -> usize {
+    (data_offset(image)..image.len())
+        .step_by(32)
+        .find(|offset| image[offset + 11] == 0x0F && image[*offset] == 0x43)
+        .unwrap()
+}
+
+/// Get the first cluster of the directory entry at ``offset``.
+fn entry_cluster(image: &[u8], offset: usize) -> u32 {
+    u32::from(LittleEndian::read_u16(&image[offset + 20..offset + 22])) << 16
+        | u32::from(LittleEndian::read_u16(&image[offset + 26..offset + 28]))
+}
+
+/// Set the first cluster of the directory entry at ``offset``.
+fn set_entry_cluster(image: &mut [u8], offset: usize, cluster: u32) {
+    LittleEndian::write_u16(&mut image[offset + 20..offset + 22], (cluster >> 16) as u16);
+    LittleEndian::write_u16(&mut image[offset + 26..offset + 28], cluster as u16);
+}
+
+/// Return the offset of the entry of ``cluster`` in the FAT at ``fat_index``.
+fn fat_entry_offset(image: &[u8], fat_index: usize, cluster: u32) -> usize {
+    let bytes_per_sector = usize::from(LittleEndian::read_u16(&image[11..13]));
+    let reserved_sectors = usize::from(LittleEndian::read_u16(&image[14..16]));
+    let fat_size = LittleEndian::read_u32(&image[36..40]) as usize;
+
+    (reserved_sectors + fat_index * fat_size) * bytes_per_sector + cluster as usize * 4
+}
+
+/// Set the entry of ``cluster`` in every FAT.
+fn set_fat_entry(image: &mut [u8], cluster: u32, value: u32) {
+    for fat_index in 0..usize::from(image[16]) {
+        let offset = fat_entry_offset(image, fat_index, cluster);
+        LittleEndian::write_u32(&mut image[offset..offset + 4], value);
+    }
+}
+
+/// Return the offset of the free cluster count in the FS Info structure.
+fn fs_info_free_count_offset(image: &[u8]) -> usize {
+    let bytes_per_sector = usize::from(LittleEndian::read_u16(&image[11..13]));
+    let fs_info_sector = usize::from(LittleEndian::read_u16(&image[48..50]));
+
+    fs_info_sector * bytes_per_sector + 488
+}
+
+/// Mount a volume image, repair it with ``lost_chains`` and check that the repaired volume is clean.
+///
+/// Return the issues found by the repair and the repaired device.
+fn repair_image(image: Vec<u8>, lost_chains: LostChainAction) -> (Vec<FsckIssue>, RamBlockDevice) {
+    let device = RamBlockDevice::from_vec(image);
+
+    let issues = {
+        let fs = libfat::get_raw_partition(&device).unwrap();
+        let report = fsck::repair(&fs, &RepairOptions { lost_chains }).unwrap();
+        fs.flush().unwrap();
+        report.issues
+    };
+
+    let fs = libfat::get_raw_partition(&device).unwrap();
+    let report = fsck::check(&fs).unwrap();
+    assert!(
+        report.is_clean(),
+        "found {:?}, repair left {:?}",
+        issues,
+        report.issues
+    );
+    drop(fs);
+
+    (issues, device)
+}
+
+/// Add a lost chain of 2 clusters starting at ``FREE_CLUSTER``, keeping the FS Info structure in sync.
+fn add_lost_chain(image: &mut [u8]) {
+    set_fat_entry(image, FREE_CLUSTER, FREE_CLUSTER + 1);
+    set_fat_entry(image, FREE_CLUSTER + 1, 0x0FFF_FFFF);
+
+    let offset = fs_info_free_count_offset(image);
+    let free_cluster_count = LittleEndian::read_u32(&image[offset..offset + 4]);
+    LittleEndian::write_u32(&mut image[offset..offset + 4], free_cluster_count - 2);
+}
+
+/// Point the entry of the file in the root directory to the chain of the file in the subdirectory.
+///
+/// Return the first cluster of the shared chain.
+fn cross_link_files(image: &mut [u8]) -> u32 {
+    let file_cluster = entry_cluster(image, find_entry(image, FILE_NAME));
+    let other_offset = find_entry(image, OTHER_NAME);
+    set_entry_cluster(image, other_offset, file_cluster);
+
+    file_cluster
+}
+
+/// Record a size of 100000 bytes for the file in the subdirectory.
+fn grow_file_size(image: &mut [u8]) {
+    let file_offset = find_entry(image, FILE_NAME);
+    LittleEndian::write_u32(&mut image[file_offset + 28..file_offset + 32], 100_000);
+}
+
+/// Break the checksum of the whole long name sequence of ``LONG_FILE_NAME``, the 8.3 entry stays valid on its own.
+fn break_long_file_name(image: &mut [u8]) {
+    let mut lfn_offset = find_long_file_name(image);
+    while image[lfn_offset + 11] == 0x0F {
+        image[lfn_offset + 13] ^= 0xFF;
+        lfn_offset += 32;
+    }
+}
+
+/// Change a byte of the second FAT.
+fn corrupt_fat_copy(image: &mut [u8]) {
+    let offset = fat_entry_offset(image, 1, FREE_CLUSTER);
+    image[offset] = 0x42;
+}
+
+/// Lower the free cluster count of the FS Info structure by 10.
+///
+/// Return the actual free cluster count.
+fn make_fs_info_stale(image: &mut [u8]) -> u32 {
+    let offset = fs_info_free_count_offset(image);
+    let actual = LittleEndian::read_u32(&image[offset..offset + 4]);
+    LittleEndian::write_u32(&mut image[offset..offset + 4], actual - 10);
+
+    actual
+}
+
+#[test]
+fn unmodified_images_are_clean() {
+    let report = check_image(create_image());
+    assert!(report.is_clean(), "{:?}", report.issues);
+    assert_eq!(report.directory_count, 1);
+    assert_eq!(report.file_count, 3);
+}
+
+#[test]
+fn lost_chains_are_reported() {
+    let mut image = create_image();
+    add_lost_chain(&mut image);
+
+    let report = check_image(image);
+    assert_eq!(
+        report.issues,
+        vec![FsckIssue::LostChain {
+            first_cluster: FREE_CLUSTER,
+            cluster_count: 2,
+        }]
+    );
+}
+
+#[test]
+fn cross_linked_clusters_are_reported() {
+    let mut image = create_image();
+    let file_cluster = cross_link_files(&mut image);
+
+    let report = check_image(image);
+    assert!(
+        report.issues.iter().any(|issue| match issue {
+            FsckIssue::CrossLinkedCluster { cluster, .. } => *cluster == file_cluster,
+            _ => false,
+        }),
+        "{:?}",
+        report.issues
+    );
+}
+
+#[test]
+fn invalid_chains_are_reported() {
+    let mut image = create_image();
+    let file_cluster = entry_cluster(&image, find_entry(&image, FILE_NAME));
+    set_fat_entry(&mut image, file_cluster, 0);
+
+    let report = check_image(image);
+    assert!(
+        report.issues.iter().any(|issue| match issue {
+            FsckIssue::InvalidChain { cluster, .. } => *cluster == file_cluster,
+            _ => false,
+        }),
+        "{:?}",
+        report.issues
+    );
+}
+
+#[test]
+fn file_size_mismatches_are_reported() {
+    let mut image = create_image();
+    grow_file_size(&mut image);
+
+    let report = check_image(image);
+    assert_eq!(
+        report.issues,
+        vec![FsckIssue::FileSizeMismatch {
+            path: String::from("/DIR/FILE.TXT"),
+            file_size: 100_000,
+            cluster_count: 10,
+        }]
+    );
+}
+
+#[test]
+fn bad_dot_entries_are_reported() {
+    let image = create_image();
+    let dot_offset = find_entry(&image, DOT_NAME);
+    let dir_cluster = entry_cluster(&image, dot_offset);
+
+    // Pointing to the wrong cluster
+    let mut wrong_image = image.clone();
+    set_entry_cluster(&mut wrong_image, dot_offset, FREE_CLUSTER);
+    assert_eq!(
+        check_image(wrong_image).issues,
+        vec![FsckIssue::BadDotEntry {
+            path: String::from("/DIR"),
+            expected_cluster: dir_cluster,
+            found_cluster: Some(FREE_CLUSTER),
+        }]
+    );
+
+    // Missing
+    let mut missing_image = image;
+    missing_image[dot_offset] = 0xE5;
+    assert_eq!(
+        check_image(missing_image).issues,
+        vec![FsckIssue::BadDotEntry {
+            path: String::from("/DIR"),
+            expected_cluster: dir_cluster,
+            found_cluster: None,
+        }]
+    );
+}
+
+#[test]
+fn bad_dot_dot_entries_are_reported() {
+    let mut image = create_image();
+    let dot_dot_offset = find_entry(&image, DOT_DOT_NAME);
+    set_entry_cluster(&mut image, dot_dot_offset, FREE_CLUSTER);
+
+    // The parent of a directory of the root directory is recorded as cluster 0
+    assert_eq!(
+        check_image(image).issues,
+        vec![FsckIssue::BadDotDotEntry {
+            path: String::from("/DIR"),
+            expected_cluster: 0,
+            found_cluster: Some(FREE_CLUSTER),
+        }]
+    );
+}
+
+#[test]
+fn orphaned_long_file_names_are_reported() {
+    let mut image = create_image();
+    break_long_file_name(&mut image);
+
+    let report = check_image(image);
+    assert_eq!(report.issues.len(), 1, "{:?}", report.issues);
+    match &report.issues[0] {
+        FsckIssue::OrphanedLongFileName {
+            path, entry_count, ..
+        } => {
+            assert_eq!(path, "/");
+            assert_eq!(*entry_count, 3);
+        }
+        issue => panic!("unexpected issue {:?}", issue),
+    }
+}
+
+#[test]
+fn fat_copy_mismatches_are_reported() {
+    let mut image = create_image();
+    corrupt_fat_copy(&mut image);
+
+    assert_eq!(
+        check_image(image).issues,
+        vec![FsckIssue::FatCopyMismatch {
+            fat_index: 1,
+            mismatched_block_count: 1,
+        }]
+    );
+}
+
+#[test]
+fn stale_fs_info_free_counts_are_reported() {
+    let mut image = create_image();
+    let actual = make_fs_info_stale(&mut image);
+
+    assert_eq!(
+        check_image(image).issues,
+        vec![FsckIssue::FsInfoFreeCountMismatch {
+            stored: actual - 10,
+            actual,
+        }]
+    );
+}
+
+#[test]
+fn lost_chains_are_freed() {
+    let mut image = create_image();
+    add_lost_chain(&mut image);
+
+    let (issues, device) = repair_image(image, LostChainAction::Free);
+    assert_eq!(issues.len(), 1, "{:?}", issues);
+
+    let image = device.into_vec();
+    for cluster in &[FREE_CLUSTER, FREE_CLUSTER + 1] {
+        let offset = fat_entry_offset(&image, 0, *cluster);
+        assert_eq!(LittleEndian::read_u32(&image[offset..offset + 4]), 0);
+    }
+}
+
+#[test]
+fn lost_chains_are_saved_as_files() {
+    let mut image = create_image();
+    add_lost_chain(&mut image);
+
+    let (issues, device) = repair_image(image, LostChainAction::SaveAsFiles);
+    assert_eq!(issues.len(), 1, "{:?}", issues);
+
+    let fs = libfat::get_raw_partition(&device).unwrap();
+    let file = fs
+        .get_root_directory()
+        .open_file("/FOUND.000/FILE0000.CHK")
+        .unwrap();
+    assert_eq!(file.file_size, 1024);
+    drop(fs);
+
+    // The chain is kept as is
+    let image = device.into_vec();
+    let offset = fat_entry_offset(&image, 0, FREE_CLUSTER);
+    assert_eq!(
+        LittleEndian::read_u32(&image[offset..offset + 4]),
+        FREE_CLUSTER + 1
+    );
+}
+
+#[test]
+fn cross_linked_clusters_are_copied() {
+    let mut image = create_image();
+    cross_link_files(&mut image);
+
+    let (_, device) = repair_image(image, LostChainAction::Free);
+
+    // Both files start with the data of the shared chain, that got copied for the second one
+    let fs = libfat::get_raw_partition(&device).unwrap();
+    for path in &["/DIR/FILE.TXT", "/OTHER.TXT"] {
+        let mut file = fs.get_root_directory().open_file(path).unwrap();
+        let mut buf = [0; 5000];
+        assert_eq!(file.read(&fs, 0, &mut buf).unwrap(), 5000);
+        assert!(buf.iter().all(|value| *value == 0x42), "{}", path);
+    }
+}
+
+#[test]
+fn file_sizes_are_adjusted_to_their_chains() {
+    let mut image = create_image();
+    grow_file_size(&mut image);
+
+    let (issues, device) = repair_image(image, LostChainAction::Free);
+    assert_eq!(issues.len(), 1, "{:?}", issues);
+
+    let fs = libfat::get_raw_partition(&device).unwrap();
+    let file = fs.get_root_directory().open_file("/DIR/FILE.TXT").unwrap();
+    assert_eq!(file.file_size, 10 * 512);
+}
+
+#[test]
+fn orphaned_long_file_names_are_deleted() {
+    let mut image = create_image();
+    break_long_file_name(&mut image);
+
+    let (issues, device) = repair_image(image, LostChainAction::Free);
+    assert_eq!(issues.len(), 1, "{:?}", issues);
+
+    // The file is still reachable through its 8.3 entry
+    let fs = libfat::get_raw_partition(&device).unwrap();
+    assert_eq!(fsck::check(&fs).unwrap().file_count, 3);
+}
+
+#[test]
+fn fat_copies_are_synchronized() {
+    let mut image = create_image();
+    corrupt_fat_copy(&mut image);
+
+    let (issues, device) = repair_image(image, LostChainAction::Free);
+    assert_eq!(issues.len(), 1, "{:?}", issues);
+
+    let image = device.into_vec();
+    assert_eq!(
+        image[fat_entry_offset(&image, 1, FREE_CLUSTER)],
+        image[fat_entry_offset(&image, 0, FREE_CLUSTER)]
+    );
+}
+
+#[test]
+fn stale_fs_info_free_counts_are_updated() {
+    let mut image = create_image();
+    let actual = make_fs_info_stale(&mut image);
+
+    let (issues, device) = repair_image(image, LostChainAction::Free);
+    assert_eq!(issues.len(), 1, "{:?}", issues);
+
+    let image = device.into_vec();
+    let offset = fs_info_free_count_offset(&image);
+    assert_eq!(LittleEndian::read_u32(&image[offset..offset + 4]), actual);
+}
+
+#[test]
+fn missing_dot_entries_are_recreated() {
+    let image = create_image();
+    let dot_offset = find_entry(&image, DOT_NAME);
+    let dir_cluster = entry_cluster(&image, dot_offset);
+
+    // A deleted "." entry
+    let mut deleted_image = image.clone();
+    deleted_image[dot_offset] = 0xE5;
+    let (issues, device) = repair_image(deleted_image, LostChainAction::Free);
+    assert_eq!(issues.len(), 1, "{:?}", issues);
+    let repaired_image = device.into_vec();
+    assert_eq!(&repaired_image[dot_offset..dot_offset + 11], &DOT_NAME[..]);
+    assert_eq!(entry_cluster(&repaired_image, dot_offset), dir_cluster);
+
+    // A directory whose cluster never got written, the file it held is lost
+    let mut empty_image = image;
+    for value in &mut empty_image[dot_offset..dot_offset + 64] {
+        *value = 0;
+    }
+    let (issues, device) = repair_image(empty_image, LostChainAction::Free);
+    assert!(
+        issues.iter().any(|issue| match issue {
+            FsckIssue::BadDotDotEntry {
+                found_cluster: None,
+                ..
+            } => true,
+            _ => false,
+        }),
+        "{:?}",
+        issues
+    );
+    let repaired_image = device.into_vec();
+    assert_eq!(&repaired_image[dot_offset..dot_offset + 11], &DOT_NAME[..]);
+    assert_eq!(entry_cluster(&repaired_image, dot_offset), dir_cluster);
+    assert_eq!(
+        &repaired_image[dot_offset + 32..dot_offset + 43],
+        &DOT_DOT_NAME[..]
+    );
+    assert_eq!(entry_cluster(&repaired_image, dot_offset + 32), 0);
+    assert_eq!(repaired_image[dot_offset + 64], 0);
+}
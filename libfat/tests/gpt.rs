@@ -0,0 +1,302 @@
+//! Check that GPT disks are read from their primary header, or from the backup one when the primary is corrupted.
+
+mod common;
+
+use byteorder::{ByteOrder, LittleEndian};
+use libfat::format::{format_partition, FormatOptions};
+use libfat::fsck;
+use libfat::gpt::{GptPartitionTable, Guid};
+use libfat::partition::{self, PartitionType};
+use libfat::FatFsType;
+use libfs::block::{BlockCount, BlockIndex, RamBlockDevice};
+use libfs::FileSystemError;
+
+/// The count of sectors of the disk.
+const DISK_SECTORS: u64 = 8192;
+
+/// The count of entries of the partition entry arrays.
+const ENTRY_COUNT: usize = 128;
+
+/// The size of a partition entry.
+const ENTRY_LEN: usize = 128;
+
+/// The count of sectors of a partition entry array.
+const ENTRY_ARRAY_SECTORS: u64 = (ENTRY_COUNT * ENTRY_LEN / 512) as u64;
+
+/// The LBA of the backup header.
+const BACKUP_HEADER_LBA: u64 = DISK_SECTORS - 1;
+
+/// The GUID of the disk.
+const DISK_GUID: Guid = Guid::from_fields(
+    0x0123_4567,
+    0x89AB,
+    0xCDEF,
+    [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF],
+);
+
+/// The partitions of the disk with their index in the partition entry array, type GUID, unique GUID, first and last LBA.
+const PARTITIONS: [(u32, Guid, Guid, u64, u64); 2] = [
+    (
+        0,
+        Guid::MICROSOFT_BASIC_DATA,
+        Guid::from_fields(0x1111_1111, 0x2222, 0x3333, [0x44; 8]),
+        2048,
+        4095,
+    ),
+    (
+        2,
+        Guid::EFI_SYSTEM_PARTITION,
+        Guid::from_fields(0x5555_5555, 0x6666, 0x7777, [0x88; 8]),
+        4096,
+        6143,
+    ),
+];
+
+/// Compute the CRC32 (IEEE 802.3) of the given data.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for value in data {
+        crc ^= u32::from(*value);
+        for _ in 0..8 {
+            crc = if (crc & 1) != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+/// Get the bytes of a sector of a disk image.
+fn sector(image: &mut [u8], lba: u64) -> &mut [u8] {
+    let offset = lba as usize * 512;
+
+    &mut image[offset..offset + 512]
+}
+
+/// Write a GPT header at ``my_lba`` describing the partition entry array at ``entry_lba``.
+fn write_header(image: &mut [u8], my_lba: u64, alternate_lba: u64, entry_lba: u64, array_crc: u32) {
+    let header = sector(image, my_lba);
+
+    header[0..8].copy_from_slice(b"EFI PART");
+    LittleEndian::write_u32(&mut header[8..12], 0x0001_0000);
+    LittleEndian::write_u32(&mut header[12..16], 92);
+    LittleEndian::write_u64(&mut header[24..32], my_lba);
+    LittleEndian::write_u64(&mut header[32..40], alternate_lba);
+    LittleEndian::write_u64(&mut header[40..48], 2 + ENTRY_ARRAY_SECTORS);
+    LittleEndian::write_u64(
+        &mut header[48..56],
+        BACKUP_HEADER_LBA - ENTRY_ARRAY_SECTORS - 1,
+    );
+    header[56..72].copy_from_slice(&DISK_GUID.0);
+    LittleEndian::write_u64(&mut header[72..80], entry_lba);
+    LittleEndian::write_u32(&mut header[80..84], ENTRY_COUNT as u32);
+    LittleEndian::write_u32(&mut header[84..88], ENTRY_LEN as u32);
+    LittleEndian::write_u32(&mut header[88..92], array_crc);
+
+    let header_crc = crc32(&header[..92]);
+    LittleEndian::write_u32(&mut header[16..20], header_crc);
+}
+
+/// Create a GPT disk image holding two FAT12 partitions, named after their index.
+fn create_disk() -> Vec<u8> {
+    let mut image = vec![0; DISK_SECTORS as usize * 512];
+
+    common::write_partition_entry(&mut image, 0, 0, 0xEE, 1, (DISK_SECTORS - 1) as u32);
+
+    let mut entries = vec![0; ENTRY_COUNT * ENTRY_LEN];
+    for (index, type_guid, partition_guid, first_lba, last_lba) in &PARTITIONS {
+        let entry = &mut entries[*index as usize * ENTRY_LEN..(*index as usize + 1) * ENTRY_LEN];
+
+        entry[0..16].copy_from_slice(&type_guid.0);
+        entry[16..32].copy_from_slice(&partition_guid.0);
+        LittleEndian::write_u64(&mut entry[32..40], *first_lba);
+        LittleEndian::write_u64(&mut entry[40..48], *last_lba);
+        LittleEndian::write_u16(&mut entry[56..58], u16::from(b'0') + *index as u16);
+    }
+
+    let array_crc = crc32(&entries);
+    let backup_entry_lba = BACKUP_HEADER_LBA - ENTRY_ARRAY_SECTORS;
+    for entry_lba in &[2, backup_entry_lba] {
+        let offset = *entry_lba as usize * 512;
+        image[offset..offset + entries.len()].copy_from_slice(&entries);
+    }
+
+    write_header(&mut image, 1, BACKUP_HEADER_LBA, 2, array_crc);
+    write_header(
+        &mut image,
+        BACKUP_HEADER_LBA,
+        1,
+        backup_entry_lba,
+        array_crc,
+    );
+
+    let device = RamBlockDevice::from_vec(image);
+    for (index, _, _, first_lba, last_lba) in &PARTITIONS {
+        let options = FormatOptions {
+            fat_type: FatFsType::Fat12,
+            cluster_size: 512,
+            volume_label: "",
+            volume_id: *index,
+        };
+        format_partition(
+            &device,
+            BlockIndex(*first_lba as u32),
+            BlockCount((last_lba - first_lba + 1) as u32),
+            &options,
+        )
+        .unwrap();
+    }
+
+    device.into_vec()
+}
+
+/// Check that the partitions of a disk image are found and can be mounted.
+fn assert_partitions(image: Vec<u8>) {
+    let device = RamBlockDevice::from_vec(image);
+
+    let partition_table = GptPartitionTable::read(&device).unwrap();
+    assert_eq!(partition_table.disk_guid, DISK_GUID);
+    assert_eq!(partition_table.iter().count(), PARTITIONS.len());
+    assert!(partition_table.get(1).is_none());
+
+    for (index, type_guid, partition_guid, first_lba, last_lba) in &PARTITIONS {
+        let entry = partition_table.get(*index).unwrap();
+        assert_eq!(entry.type_guid, *type_guid);
+        assert_eq!(entry.partition_guid, *partition_guid);
+        assert_eq!(entry.first_lba, *first_lba);
+        assert_eq!(entry.last_lba, *last_lba);
+        assert_eq!(entry.name[0], u16::from(b'0') + *index as u16);
+        assert_eq!(entry.name[1], 0);
+
+        let by_guid = partition_table.find_by_guid(partition_guid).unwrap();
+        assert_eq!(by_guid.index, *index);
+    }
+
+    let partitions = partition::list_partitions(&device).unwrap();
+    assert_eq!(partitions.len(), PARTITIONS.len());
+    for (info, (index, type_guid, _, first_lba, last_lba)) in partitions.iter().zip(&PARTITIONS) {
+        assert_eq!(info.index, *index);
+        assert_eq!(info.partition_type, PartitionType::Gpt(*type_guid));
+        assert_eq!(info.start, BlockIndex(*first_lba as u32));
+        assert_eq!(u64::from(info.block_count.0), last_lba - first_lba + 1);
+        assert_eq!(info.filesystem, Some(FatFsType::Fat12));
+    }
+
+    for (index, _, partition_guid, _, _) in &PARTITIONS {
+        let fs = libfat::get_gpt_partition(&device, *index).unwrap();
+        assert_eq!(fs.statistics().serial_number, *index);
+
+        fs.touch("/file.txt").unwrap();
+        fs.flush().unwrap();
+
+        let report = fsck::check(&fs).unwrap();
+        assert!(report.is_clean(), "{}: {:?}", index, report.issues);
+
+        let fs = libfat::get_gpt_partition_by_guid(&device, partition_guid).unwrap();
+        assert_eq!(fs.statistics().serial_number, *index);
+        fs.get_root_directory().open_file("/file.txt").unwrap();
+    }
+}
+
+#[test]
+fn primary_header_is_used() {
+    assert_partitions(create_disk());
+
+    // The backup header isn't needed when the primary one is valid
+    let mut image = create_disk();
+    sector(&mut image, BACKUP_HEADER_LBA)
+        .iter_mut()
+        .for_each(|byte| *byte = 0);
+    assert_partitions(image);
+}
+
+#[test]
+fn backup_header_is_used_when_primary_is_corrupted() {
+    // Corrupted CRC of the primary header, the backup is found at the end of the disk
+    let mut image = create_disk();
+    sector(&mut image, 1)[16] ^= 0xFF;
+    assert_partitions(image);
+
+    // Corrupted primary header and partition entry array
+    let mut image = create_disk();
+    sector(&mut image, 1)[0] = 0;
+    for lba in 2..2 + ENTRY_ARRAY_SECTORS {
+        sector(&mut image, lba)
+            .iter_mut()
+            .for_each(|byte| *byte = 0);
+    }
+    assert_partitions(image);
+
+    // Corrupted primary partition entry array, the backup is found from the primary header
+    let mut image = create_disk();
+    sector(&mut image, 2)[0] ^= 0xFF;
+    assert_partitions(image);
+}
+
+#[test]
+fn corrupted_tables_are_rejected() {
+    let mut image = create_disk();
+    sector(&mut image, 1)[16] ^= 0xFF;
+    sector(&mut image, BACKUP_HEADER_LBA)[16] ^= 0xFF;
+    let device = RamBlockDevice::from_vec(image);
+
+    match GptPartitionTable::read(&device) {
+        Err(FileSystemError::InvalidPartition) => {}
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+    match libfat::get_gpt_partition(&device, 0) {
+        Err(FileSystemError::InvalidPartition) => {}
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn reversed_partitions_are_rejected() {
+    let mut image = create_disk();
+
+    // The second partition ends before it starts, in both partition entry arrays
+    let (index, _, _, first_lba, _) = PARTITIONS[1];
+    let backup_entry_lba = BACKUP_HEADER_LBA - ENTRY_ARRAY_SECTORS;
+    for entry_lba in &[2, backup_entry_lba] {
+        let offset = *entry_lba as usize * 512 + index as usize * ENTRY_LEN;
+        LittleEndian::write_u64(&mut image[offset + 40..offset + 48], first_lba - 1);
+    }
+
+    let array_offset = 2 * 512;
+    let array_crc = crc32(&image[array_offset..array_offset + ENTRY_COUNT * ENTRY_LEN]);
+    for lba in &[1, BACKUP_HEADER_LBA] {
+        sector(&mut image, *lba)
+            .iter_mut()
+            .for_each(|byte| *byte = 0);
+    }
+    write_header(&mut image, 1, BACKUP_HEADER_LBA, 2, array_crc);
+    write_header(
+        &mut image,
+        BACKUP_HEADER_LBA,
+        1,
+        backup_entry_lba,
+        array_crc,
+    );
+
+    let device = RamBlockDevice::from_vec(image);
+    let partition_table = GptPartitionTable::read(&device).unwrap();
+    let entry = partition_table.get(index).unwrap();
+    assert_eq!(entry.sector_count(), None);
+    assert_eq!(partition_table.get(0).unwrap().sector_count(), Some(2048));
+
+    match partition::list_partitions(&device) {
+        Err(FileSystemError::InvalidPartition) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+    match libfat::get_gpt_partition(&device, index) {
+        Err(FileSystemError::InvalidPartition) => {}
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+
+    // The other partition is still usable
+    libfat::get_gpt_partition(&device, 0).unwrap();
+}
@@ -0,0 +1,194 @@
+//! Check that the logical partitions of extended MBR partitions are found by walking their EBR chain.
+
+mod common;
+
+use libfat::format::{format_partition, FormatOptions};
+use libfat::fsck;
+use libfat::mbr::MbrPartitionTable;
+use libfat::partition::{self, PartitionType};
+use libfat::FatFsType;
+use libfs::block::{BlockCount, BlockIndex, RamBlockDevice};
+use libfs::FileSystemError;
+
+/// The first sector of the extended partition.
+const EXTENDED_START: u32 = 4096;
+
+/// The count of sectors of the extended partition.
+const EXTENDED_SECTORS: u32 = 8192;
+
+/// The EBRs of the chain, relative to the start of the extended partition.
+const EBRS: [u32; 3] = [0, 2112, 3200];
+
+/// The partitions of the disk with their index, type, first sector and sector count. Only the non-Linux ones are formatted.
+const PARTITIONS: [(u32, u8, u32, u32); 4] = [
+    (0, 0x01, 64, 2048),
+    (4, 0x06, EXTENDED_START + EBRS[0] + 64, 2048),
+    (5, 0x83, EXTENDED_START + EBRS[1] + 64, 1024),
+    (6, 0x0E, EXTENDED_START + EBRS[2] + 64, 2048),
+];
+
+/// The MBR partition type of Linux partitions.
+const LINUX_TYPE: u8 = 0x83;
+
+/// Create a disk image with a primary partition and an extended partition holding three logical partitions.
+fn create_disk() -> Vec<u8> {
+    let mut image = vec![0; (EXTENDED_START + EXTENDED_SECTORS) as usize * 512];
+
+    let (_, partition_type, first_lba, sector_count) = PARTITIONS[0];
+    common::write_partition_entry(&mut image, 0, 0, partition_type, first_lba, sector_count);
+    common::write_partition_entry(&mut image, 0, 1, 0x0F, EXTENDED_START, EXTENDED_SECTORS);
+
+    for (index, ebr) in EBRS.iter().enumerate() {
+        let ebr_lba = EXTENDED_START + ebr;
+        let (_, partition_type, first_lba, sector_count) = PARTITIONS[index + 1];
+
+        // The logical partition is relative to its EBR, the next EBR to the extended partition
+        common::write_partition_entry(
+            &mut image,
+            ebr_lba,
+            0,
+            partition_type,
+            first_lba - ebr_lba,
+            sector_count,
+        );
+        if let Some(next_ebr) = EBRS.get(index + 1) {
+            common::write_partition_entry(&mut image, ebr_lba, 1, 0x05, *next_ebr, 1);
+        }
+    }
+
+    let device = RamBlockDevice::from_vec(image);
+    for (index, partition_type, first_lba, sector_count) in &PARTITIONS {
+        if *partition_type == LINUX_TYPE {
+            continue;
+        }
+
+        let options = FormatOptions {
+            fat_type: FatFsType::Fat12,
+            cluster_size: 512,
+            volume_label: "",
+            volume_id: *index,
+        };
+        format_partition(
+            &device,
+            BlockIndex(*first_lba),
+            BlockCount(*sector_count),
+            &options,
+        )
+        .unwrap();
+    }
+
+    device.into_vec()
+}
+
+/// Get the indexes of the partitions of a disk image, extended ones included.
+fn partition_indexes(image: Vec<u8>) -> Vec<u32> {
+    let device = RamBlockDevice::from_vec(image);
+
+    MbrPartitionTable::read(&device)
+        .unwrap()
+        .iter()
+        .map(|entry| entry.index)
+        .collect()
+}
+
+#[test]
+fn logical_partitions_are_listed() {
+    let device = RamBlockDevice::from_vec(create_disk());
+
+    let partition_table = MbrPartitionTable::read(&device).unwrap();
+    let extended = partition_table.get(1).unwrap();
+    assert!(extended.is_extended());
+    assert_eq!(extended.first_lba, EXTENDED_START);
+
+    for (index, partition_type, first_lba, sector_count) in &PARTITIONS {
+        let entry = partition_table.get(*index).unwrap();
+        assert_eq!(entry.partition_type, *partition_type);
+        assert_eq!(entry.first_lba, *first_lba);
+        assert_eq!(entry.sector_count, *sector_count);
+        assert_eq!(entry.is_fat(), *partition_type != LINUX_TYPE);
+    }
+    assert!(partition_table.get(7).is_none());
+
+    // Extended partitions only hold logical partitions
+    let partitions = partition::list_partitions(&device).unwrap();
+    assert_eq!(partitions.len(), PARTITIONS.len());
+    for (info, (index, partition_type, first_lba, sector_count)) in
+        partitions.iter().zip(&PARTITIONS)
+    {
+        assert_eq!(info.index, *index);
+        assert_eq!(info.partition_type, PartitionType::Mbr(*partition_type));
+        assert_eq!(info.start, BlockIndex(*first_lba));
+        assert_eq!(info.block_count.0, *sector_count);
+
+        let filesystem = if *partition_type == LINUX_TYPE {
+            None
+        } else {
+            Some(FatFsType::Fat12)
+        };
+        assert_eq!(info.filesystem, filesystem);
+    }
+}
+
+#[test]
+fn logical_partitions_are_mounted() {
+    let device = RamBlockDevice::from_vec(create_disk());
+
+    for (index, partition_type, _, _) in &PARTITIONS {
+        if *partition_type == LINUX_TYPE {
+            continue;
+        }
+
+        let fs = libfat::get_partition(&device, *index).unwrap();
+        assert_eq!(fs.statistics().serial_number, *index);
+
+        fs.touch("/file.txt").unwrap();
+        let mut file = fs.get_root_directory().open_file("/file.txt").unwrap();
+        file.write(&fs, 0, &[0x42; 3000], true).unwrap();
+        fs.flush().unwrap();
+
+        let report = fsck::check(&fs).unwrap();
+        assert!(report.is_clean(), "{}: {:?}", index, report.issues);
+    }
+
+    // The writes stayed in their partitions
+    for (index, partition_type, _, _) in &PARTITIONS {
+        if *partition_type != LINUX_TYPE {
+            let fs = libfat::get_partition(&device, *index).unwrap();
+            let file = fs.get_root_directory().open_file("/file.txt").unwrap();
+            assert_eq!(file.file_size, 3000);
+        }
+    }
+
+    for index in &[1, 5] {
+        match libfat::get_partition(&device, *index) {
+            Err(FileSystemError::Custom { .. }) => {}
+            result => panic!("{}: unexpected result {:?}", index, result.map(|_| ())),
+        }
+    }
+
+    match libfat::get_partition(&device, 7) {
+        Err(FileSystemError::PartitionNotFound) => {}
+        result => panic!("unexpected result {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn broken_chains_stop_the_walk() {
+    // A missing signature hides the following logical partitions
+    let mut image = create_disk();
+    let ebr_offset = (EXTENDED_START + EBRS[1]) as usize * 512;
+    image[ebr_offset + 510] = 0;
+    assert_eq!(partition_indexes(image), vec![0, 1, 4]);
+
+    // An EBR pointing to itself ends the chain
+    let mut image = create_disk();
+    common::write_partition_entry(&mut image, EXTENDED_START + EBRS[2], 1, 0x05, EBRS[2], 1);
+    assert_eq!(partition_indexes(image), vec![0, 1, 4, 5, 6]);
+
+    // An EBR pointing back to the first one is bounded
+    let mut image = create_disk();
+    common::write_partition_entry(&mut image, EXTENDED_START + EBRS[2], 1, 0x05, EBRS[0], 1);
+    let indexes = partition_indexes(image);
+    assert_eq!(indexes[..5], [0, 1, 4, 5, 6]);
+    assert!(indexes.len() <= 2 + 128);
+}
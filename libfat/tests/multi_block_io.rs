@@ -0,0 +1,135 @@
+//! Check that file data contiguous on disk is transferred with as few device accesses as possible.
+
+mod common;
+
+use libfat::extent::ExtentMap;
+use libfat::FatFsType;
+use libfs::block::{Block, BlockCount, RamBlockDevice};
+use libfs::fault::{Fault, FaultOperation, FaultyBlockDevice, RecordingBlockDevice};
+use libfs::FileSystemError;
+
+/// The path of the file used by the tests.
+const FILE: &str = "/data.bin";
+
+/// The size of the file used by the tests.
+const FILE_SIZE: usize = 256 * 1024;
+
+/// Format an in-memory FAT16 volume with multi-block clusters, holding an empty file.
+fn create_volume_with_file() -> RamBlockDevice {
+    let device = common::create_volume(FatFsType::Fat16, 16 << 20, 2048);
+
+    let fs = libfat::get_raw_partition(&device).unwrap();
+    fs.touch(FILE).unwrap();
+    fs.flush().unwrap();
+    drop(fs);
+
+    device
+}
+
+/// Get the content of the file used by the tests.
+fn file_content() -> Vec<u8> {
+    (0..FILE_SIZE).map(|offset| (offset % 251) as u8).collect()
+}
+
+#[test]
+fn contiguous_data_uses_one_device_access() {
+    let device = FaultyBlockDevice::new(create_volume_with_file());
+    let fs = libfat::get_raw_partition(&device).unwrap();
+    let content = file_content();
+
+    let mut file = fs.get_root_directory().open_file(FILE).unwrap();
+    let mut extent_map = ExtentMap::new();
+    file.set_len_with_extents(&fs, &mut extent_map, FILE_SIZE as u64)
+        .unwrap();
+    assert_eq!(extent_map.extents().len(), 1);
+
+    // Transferring the whole file costs as many device accesses as transferring a single block
+    device.reset_counters();
+    file.write_with_extents(&fs, &mut extent_map, 0, &content[..Block::LEN], false)
+        .unwrap();
+    let block_write_count = device.write_count();
+
+    device.reset_counters();
+    file.write_with_extents(&fs, &mut extent_map, 0, &content, false)
+        .unwrap();
+    assert_eq!(device.write_count(), block_write_count);
+
+    let mut data = vec![0; FILE_SIZE];
+    device.reset_counters();
+    file.read_with_extents(&fs, &mut extent_map, 0, &mut data[..Block::LEN])
+        .unwrap();
+    let block_read_count = device.read_count();
+
+    device.reset_counters();
+    file.read_with_extents(&fs, &mut extent_map, 0, &mut data)
+        .unwrap();
+    assert_eq!(device.read_count(), block_read_count);
+    assert!(data == content);
+
+    // Unaligned accesses only add the partial blocks around the run
+    let mut data = vec![0; FILE_SIZE - 1000];
+    device.reset_counters();
+    file.read_with_extents(&fs, &mut extent_map, 100, &mut data)
+        .unwrap();
+    assert_eq!(device.read_count(), block_read_count + 2);
+    assert!(data[..] == content[100..FILE_SIZE - 900]);
+}
+
+#[test]
+fn full_block_writes_skip_reads() {
+    let recording_device = RecordingBlockDevice::new(create_volume_with_file());
+    let device = FaultyBlockDevice::new(&recording_device);
+    let fs = libfat::get_raw_partition(&device).unwrap();
+    let content = file_content();
+
+    let mut file = fs.get_root_directory().open_file(FILE).unwrap();
+    file.write(&fs, 0, &content, true).unwrap();
+
+    // Find where the data landed and forbid reading it
+    let data_write = recording_device
+        .take_writes()
+        .into_iter()
+        .find(|write| write.blocks.len() > 1)
+        .unwrap();
+    assert_eq!(data_write.blocks.len(), FILE_SIZE / Block::LEN);
+
+    device.add_fault(Fault::FailRange {
+        start: data_write.index,
+        count: BlockCount(data_write.blocks.len() as u32),
+        operation: FaultOperation::Read,
+    });
+
+    let new_content: Vec<u8> = content.iter().map(|byte| !byte).collect();
+    file.write(&fs, 0, &new_content, false).unwrap();
+    file.write(&fs, 4 * Block::LEN as u64, &content[..Block::LEN], false)
+        .unwrap();
+
+    // Partial blocks must still be merged with their old content
+    match file.write(&fs, 10, &content[..Block::LEN], false) {
+        Err(FileSystemError::ReadFailed) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    device.clear_faults();
+
+    let mut data = vec![0; FILE_SIZE];
+    file.read(&fs, 0, &mut data).unwrap();
+    assert!(data[..4 * Block::LEN] == new_content[..4 * Block::LEN]);
+    assert!(data[4 * Block::LEN..5 * Block::LEN] == content[..Block::LEN]);
+    assert!(data[5 * Block::LEN..] == new_content[5 * Block::LEN..]);
+}
+
+#[test]
+fn block_slices_from_bytes() {
+    let mut bytes = vec![0; 3 * Block::LEN];
+    bytes[Block::LEN + 1] = 0x42;
+
+    assert!(Block::slice_from_bytes(&bytes[1..]).is_none());
+    assert_eq!(Block::slice_from_bytes(&bytes).unwrap()[1][1], 0x42);
+
+    // Views can start anywhere in the buffer
+    let blocks = Block::slice_from_bytes_mut(&mut bytes[1..=2 * Block::LEN]).unwrap();
+    assert_eq!(blocks.len(), 2);
+    blocks[1][0] = 0x24;
+    assert_eq!(bytes[Block::LEN + 1], 0x24);
+}
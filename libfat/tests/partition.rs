@@ -0,0 +1,91 @@
+//! Check the listing of the partitions of disks that hold no usable partition table.
+
+mod common;
+
+use libfat::format::{format_partition, FormatOptions};
+use libfat::partition::{self, PartitionType};
+use libfat::FatFsType;
+use libfs::block::{BlockCount, BlockIndex, RamBlockDevice};
+use libfs::FileSystemError;
+
+/// The count of sectors of the disks.
+const DISK_SECTORS: u32 = 4096;
+
+/// Check that listing the partitions of a disk image fails with ``InvalidPartition``.
+fn assert_invalid(image: Vec<u8>) {
+    let device = RamBlockDevice::from_vec(image);
+
+    match partition::list_partitions(&device) {
+        Err(FileSystemError::InvalidPartition) => {}
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn empty_tables_have_no_partitions() {
+    let mut image = vec![0; DISK_SECTORS as usize * 512];
+    image[510] = 0x55;
+    image[511] = 0xAA;
+    let device = RamBlockDevice::from_vec(image);
+
+    assert!(partition::list_partitions(&device).unwrap().is_empty());
+}
+
+#[test]
+fn disks_without_a_signature_are_rejected() {
+    assert_invalid(vec![0; DISK_SECTORS as usize * 512]);
+
+    // The entries aren't read without the signature
+    let mut image = vec![0; DISK_SECTORS as usize * 512];
+    common::write_partition_entry(&mut image, 0, 0, 0x06, 64, 2048);
+    image[510] = 0;
+    assert_invalid(image);
+}
+
+#[test]
+fn protective_mbrs_need_a_valid_gpt() {
+    // No GPT header at all
+    let mut image = vec![0; DISK_SECTORS as usize * 512];
+    common::write_partition_entry(&mut image, 0, 0, 0xEE, 1, DISK_SECTORS - 1);
+    assert_invalid(image.clone());
+
+    // A header whose CRC doesn't match
+    image[512..520].copy_from_slice(b"EFI PART");
+    image[520..524].copy_from_slice(&[0x00, 0x00, 0x01, 0x00]);
+    image[524] = 92;
+    assert_invalid(image);
+}
+
+#[test]
+fn unformatted_partitions_have_no_filesystem() {
+    let mut image = vec![0; DISK_SECTORS as usize * 512];
+    common::write_partition_entry(&mut image, 0, 0, 0x06, 64, 2048);
+    common::write_partition_entry(&mut image, 0, 1, 0x0B, 2112, 1024);
+    // Mark the first partition as bootable
+    image[446] = 0x80;
+
+    let device = RamBlockDevice::from_vec(image);
+    let options = FormatOptions {
+        fat_type: FatFsType::Fat12,
+        cluster_size: 512,
+        volume_label: "",
+        volume_id: 0,
+    };
+    format_partition(&device, BlockIndex(64), BlockCount(2048), &options).unwrap();
+
+    // The second partition is typed as FAT32, but only its boot sector tells the filesystem
+    let partitions = partition::list_partitions(&device).unwrap();
+    assert_eq!(partitions.len(), 2);
+
+    assert_eq!(partitions[0].index, 0);
+    assert_eq!(partitions[0].partition_type, PartitionType::Mbr(0x06));
+    assert!(partitions[0].bootable);
+    assert_eq!(partitions[0].filesystem, Some(FatFsType::Fat12));
+
+    assert_eq!(partitions[1].index, 1);
+    assert_eq!(partitions[1].partition_type, PartitionType::Mbr(0x0B));
+    assert_eq!(partitions[1].start, BlockIndex(2112));
+    assert_eq!(partitions[1].block_count.0, 1024);
+    assert!(!partitions[1].bootable);
+    assert_eq!(partitions[1].filesystem, None);
+}
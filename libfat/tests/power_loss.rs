@@ -0,0 +1,266 @@
+//! Check that a power loss during an operation sequence leaves a volume that fsck can repair.
+//!
+//! Every block write of the sequence is recorded, then replayed on a copy of the image up to every in-order crash
+//! point. The reorderings of a write-back cache are only covered for the cases where it wrote back all but one, or only
+//! one, of the blocks dirtied by an operation.
+
+mod common;
+
+use std::fmt;
+
+use common::{Operation, VOLUMES};
+use libfat::fsck::{self, FsckIssue, LostChainAction, RepairOptions};
+use libfat::FatFsType;
+use libfs::block::{Block, BlockDevice, BlockIndex, RamBlockDevice};
+use libfs::fault::{RecordedWrite, RecordingBlockDevice};
+
+/// The operation sequence to interrupt.
+const OPERATIONS: [Operation; 8] = [
+    Operation::Mkdir("/dir"),
+    Operation::Mkdir("/dir/a directory with a long name"),
+    Operation::Touch("/dir/file.txt"),
+    Operation::Write("/dir/file.txt", 0, 5000),
+    Operation::Write("/dir/file.txt", 1000, 3000),
+    Operation::Rename("/dir/file.txt", "/a file with a long name.txt"),
+    Operation::SetLen("/a file with a long name.txt", 600),
+    Operation::SetLen("/a file with a long name.txt", 4000),
+];
+
+/// Represent the state of the device at a power loss.
+#[derive(Clone, Copy)]
+enum CrashPoint {
+    /// The writes were done in order, up to and including the given write of the operation.
+    AfterWrite {
+        /// The index of the last write done in the operation.
+        write: usize,
+
+        /// The first block of the last write done.
+        index: BlockIndex,
+    },
+
+    /// The cache wrote back every block dirtied by the operation but the given one.
+    AllBut(BlockIndex),
+
+    /// The cache only wrote back the given block dirtied by the operation.
+    Only(BlockIndex),
+}
+
+impl fmt::Display for CrashPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CrashPoint::AfterWrite { write, index } => {
+                write!(f, "after write #{} to block {}", write, index.0)
+            }
+            CrashPoint::AllBut(index) => write!(f, "with every block but {} written", index.0),
+            CrashPoint::Only(index) => write!(f, "with only block {} written", index.0),
+        }
+    }
+}
+
+/// Represent a crash state that left the volume corrupted.
+struct Corruption {
+    /// The volume type.
+    fat_type: FatFsType,
+
+    /// The operation interrupted.
+    operation: Operation,
+
+    /// Where the operation was interrupted.
+    crash_point: CrashPoint,
+
+    /// What is wrong with the volume.
+    error: String,
+}
+
+impl fmt::Debug for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} {:?} interrupted {}: {}",
+            self.fat_type, self.operation, self.crash_point, self.error
+        )
+    }
+}
+
+/// Run the operation sequence on ``image`` and return the writes done by every operation.
+fn record_writes(image: &[u8]) -> Vec<Vec<RecordedWrite>> {
+    let device = RecordingBlockDevice::new(RamBlockDevice::from_vec(image.to_vec()));
+    let fs = libfat::get_raw_partition(&device).unwrap();
+    let mut writes = Vec::new();
+
+    for operation in &OPERATIONS {
+        common::run_operation(&fs, *operation).unwrap();
+        writes.push(device.take_writes());
+    }
+
+    writes
+}
+
+/// Merge the writes of an operation the way a write-back cache would, keeping the last data of every block.
+fn dirty_blocks(writes: &[RecordedWrite]) -> Vec<(BlockIndex, Block)> {
+    let mut blocks: Vec<(BlockIndex, Block)> = Vec::new();
+
+    for write in writes {
+        for (i, block) in write.blocks.iter().enumerate() {
+            let index = BlockIndex(write.index.0 + i as u32);
+
+            match blocks
+                .iter_mut()
+                .find(|(dirty_index, _)| *dirty_index == index)
+            {
+                Some(dirty) => dirty.1 = block.clone(),
+                None => blocks.push((index, block.clone())),
+            }
+        }
+    }
+
+    blocks
+}
+
+/// Check if an issue found on a crash image only leaks space or leaves unused metadata behind.
+///
+/// Such issues are expected when an operation is interrupted, as long as every file still has at least its data.
+fn is_benign(issue: &FsckIssue, operation: Operation, cluster_size: u32) -> bool {
+    match issue {
+        FsckIssue::LostChain { .. }
+        | FsckIssue::OrphanedLongFileName { .. }
+        | FsckIssue::FatCopyMismatch { .. }
+        | FsckIssue::FsInfoFreeCountMismatch { .. } => true,
+
+        // Clusters allocated to a file before its size is updated
+        FsckIssue::FileSizeMismatch {
+            file_size,
+            cluster_count,
+            ..
+        } => u64::from(*cluster_count) * u64::from(cluster_size) >= u64::from(*file_size),
+
+        // A rename writes the new entry before deleting the old one so that the file is never lost. The old entry may
+        // have lost its long name already.
+        FsckIssue::CrossLinkedCluster {
+            path, other_path, ..
+        } => match operation {
+            Operation::Rename(_, new_path) => path == new_path || other_path == new_path,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Mount the volume and check that it can be repaired to a consistent state.
+///
+/// Writes reaching the device in order must only leave benign issues, checked before any repair so that the write
+/// causing a corruption is the one reported. Reordered writes can leave any issue, only the repaired volume is
+/// checked.
+fn check_volume(image: Vec<u8>, operation: Operation, reordered: bool) -> Result<(), String> {
+    let device = RamBlockDevice::from_vec(image);
+    let fs = libfat::get_raw_partition(&device)
+        .map_err(|error| format!("mount failed with {:?}", error))?;
+    let cluster_size = fs.statistics().cluster_size as u32;
+
+    let found = fsck::check(&fs).map_err(|error| format!("check failed with {:?}", error))?;
+    if !reordered
+        && !found
+            .issues
+            .iter()
+            .all(|issue| is_benign(issue, operation, cluster_size))
+    {
+        return Err(format!("found {:?}", found.issues));
+    }
+
+    let options = RepairOptions {
+        lost_chains: LostChainAction::Free,
+    };
+    fsck::repair(&fs, &options).map_err(|error| format!("repair failed with {:?}", error))?;
+
+    let report = fsck::check(&fs).map_err(|error| format!("check failed with {:?}", error))?;
+    if !report.is_clean() {
+        return Err(format!(
+            "found {:?}, repair left {:?}",
+            found.issues, report.issues
+        ));
+    }
+
+    Ok(())
+}
+
+/// Replay every crash point of every operation and return the corruptions found.
+///
+/// Only the first in-order crash point corrupting the volume is reported for an operation, as the following ones share the culprit write.
+fn find_corruptions(fat_type: FatFsType, size: usize, cluster_size: u32) -> Vec<Corruption> {
+    let image = common::create_volume(fat_type, size, cluster_size).into_vec();
+    let writes = record_writes(&image);
+    let mut corruptions = Vec::new();
+
+    // The state of the volume before the current operation
+    let base = RamBlockDevice::from_vec(image);
+
+    for (operation, operation_writes) in OPERATIONS.iter().zip(writes.iter()) {
+        let mut report = |crash_point, error| {
+            corruptions.push(Corruption {
+                fat_type,
+                operation: *operation,
+                crash_point,
+                error,
+            })
+        };
+
+        // Writes reaching the device in order
+        let device = RamBlockDevice::from_vec(base.to_vec());
+        for (write_index, write) in operation_writes.iter().enumerate() {
+            write.replay(&device).unwrap();
+
+            if let Err(error) = check_volume(device.to_vec(), *operation, false) {
+                let crash_point = CrashPoint::AfterWrite {
+                    write: write_index,
+                    index: write.index,
+                };
+                report(crash_point, error);
+                break;
+            }
+        }
+
+        // Writes reordered by a write-back cache. As no write barrier is used, the entry of a new directory can reach
+        // the device before its "." and ".." entries.
+        let blocks = dirty_blocks(operation_writes);
+        if blocks.len() > 1 {
+            for (index, _) in &blocks {
+                let device = RamBlockDevice::from_vec(base.to_vec());
+                for (dirty_index, block) in &blocks {
+                    if dirty_index != index {
+                        device
+                            .raw_write(core::slice::from_ref(block), *dirty_index)
+                            .unwrap();
+                    }
+                }
+                if let Err(error) = check_volume(device.to_vec(), *operation, true) {
+                    report(CrashPoint::AllBut(*index), error);
+                }
+            }
+
+            for (index, block) in &blocks {
+                let device = RamBlockDevice::from_vec(base.to_vec());
+                device
+                    .raw_write(core::slice::from_ref(block), *index)
+                    .unwrap();
+                if let Err(error) = check_volume(device.to_vec(), *operation, true) {
+                    report(CrashPoint::Only(*index), error);
+                }
+            }
+        }
+
+        for write in operation_writes {
+            write.replay(&base).unwrap();
+        }
+    }
+
+    corruptions
+}
+
+#[test]
+fn power_loss_never_corrupts_volume() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let corruptions = find_corruptions(*fat_type, *size, *cluster_size);
+
+        assert!(corruptions.is_empty(), "{:#?}", corruptions);
+    }
+}
@@ -0,0 +1,168 @@
+//! Check the geometry of volumes using logical sectors bigger than a block.
+
+mod common;
+
+use byteorder::{ByteOrder, LittleEndian};
+use common::Operation;
+use libfat::format::{format_partition, format_raw_partition, FormatOptions};
+use libfat::partition;
+use libfat::FatFsType;
+use libfs::block::{BlockIndex, RamBlockDevice};
+use libfs::FileSystemError;
+
+/// The sector sizes to test, bigger than a block.
+const SECTOR_SIZES: [u32; 3] = [1024, 2048, 4096];
+
+/// The volumes to test, with their count of clusters of a sector. FAT32 needs so many clusters that it's only tested
+/// with the smallest sectors, to keep the images small.
+const VOLUMES: [(FatFsType, u32); 3] = [
+    (FatFsType::Fat12, 1000),
+    (FatFsType::Fat16, 5000),
+    (FatFsType::Fat32, 66000),
+];
+
+/// The operations run on the volumes.
+const OPERATIONS: [Operation; 5] = [
+    Operation::Mkdir("/dir"),
+    Operation::Touch("/dir/file.txt"),
+    Operation::Write("/dir/file.txt", 0, 10000),
+    Operation::Touch("/a file with a long name.txt"),
+    Operation::Write("/a file with a long name.txt", 1000, 100),
+];
+
+#[test]
+fn overflowing_layouts_are_rejected() {
+    let image = common::create_volume(FatFsType::Fat32, 34 << 20, 512).into_vec();
+
+    // The sector size, and the offset and value of the field patched in the boot sector
+    let patches: [(u16, usize, u32); 3] = [
+        // 0x2000_0000 sectors of 4096 bytes don't fit in 32 bits block indexes
+        (4096, 32, 0x2000_0000),
+        // Neither does the FAT
+        (4096, 36, 0x2000_0000),
+        // The FATs together overflow, even with 512 bytes sectors
+        (512, 36, 0x8000_0000),
+    ];
+
+    for (bytes_per_sector, offset, value) in &patches {
+        let mut image = image.clone();
+        LittleEndian::write_u16(&mut image[11..13], *bytes_per_sector);
+        LittleEndian::write_u32(&mut image[*offset..*offset + 4], *value);
+
+        let device = RamBlockDevice::from_vec(image);
+        match libfat::get_raw_partition(&device) {
+            Err(FileSystemError::InvalidPartition) => {}
+            result => panic!("{}: unexpected result {:?}", offset, result.map(|_| ())),
+        }
+    }
+}
+
+#[test]
+fn volumes_with_big_sectors_pass_fsck() {
+    for sector_size in &SECTOR_SIZES {
+        for (fat_type, cluster_count) in &VOLUMES {
+            if *fat_type == FatFsType::Fat32 && *sector_size > 1024 {
+                continue;
+            }
+
+            let size = *cluster_count as usize * *sector_size as usize + (1 << 20);
+            let device = RamBlockDevice::new(size).with_sector_size(*sector_size);
+            let options = FormatOptions {
+                fat_type: *fat_type,
+                cluster_size: *sector_size,
+                volume_label: "SECTORS",
+                volume_id: *sector_size,
+            };
+            format_raw_partition(&device, &options).unwrap();
+
+            // The logical sectors of the volume are the sectors of the device
+            let boot_sector = device.to_vec();
+            assert_eq!(
+                u32::from(LittleEndian::read_u16(&boot_sector[11..13])),
+                *sector_size
+            );
+
+            let fs = libfat::get_raw_partition(&device).unwrap();
+            let statistics = fs.statistics();
+            assert_eq!(statistics.fat_type, *fat_type);
+            assert_eq!(statistics.cluster_size, u64::from(*sector_size));
+            assert_eq!(statistics.serial_number, *sector_size);
+            assert!(statistics.cluster_count > *cluster_count);
+            common::assert_clean(&fs);
+
+            for operation in &OPERATIONS {
+                common::run_operation(&fs, *operation).unwrap();
+            }
+            fs.flush().unwrap();
+            common::assert_clean(&fs);
+
+            let fs = libfat::get_raw_partition(&device).unwrap();
+            let mut file = fs
+                .get_root_directory()
+                .open_file("/a file with a long name.txt")
+                .unwrap();
+            let mut buf = [0xFF; 1100];
+            assert_eq!(file.read(&fs, 0, &mut buf).unwrap(), 1100);
+            assert_eq!(&buf[..1000], &[0; 1000][..]);
+            assert!(buf[1000..].iter().all(|value| *value == 0x42));
+        }
+    }
+}
+
+#[test]
+fn partitions_are_addressed_in_device_sectors() {
+    const PARTITION_START_SECTOR: u32 = 16;
+    const PARTITION_SECTORS: u32 = 2048;
+
+    for sector_size in &SECTOR_SIZES {
+        let blocks_per_sector = *sector_size / 512;
+        let mut image =
+            vec![0; ((PARTITION_START_SECTOR + PARTITION_SECTORS) * *sector_size) as usize];
+        common::write_partition_entry(
+            &mut image,
+            0,
+            0,
+            0x06,
+            PARTITION_START_SECTOR,
+            PARTITION_SECTORS,
+        );
+        let device = RamBlockDevice::from_vec(image).with_sector_size(*sector_size);
+
+        let partitions = partition::list_partitions(&device).unwrap();
+        assert_eq!(partitions.len(), 1);
+        let partition_start = BlockIndex(PARTITION_START_SECTOR * blocks_per_sector);
+        assert_eq!(partitions[0].start, partition_start);
+        assert_eq!(
+            partitions[0].block_count.0,
+            PARTITION_SECTORS * blocks_per_sector
+        );
+
+        let options = FormatOptions {
+            fat_type: FatFsType::Fat12,
+            cluster_size: *sector_size,
+            volume_label: "PARTITION",
+            volume_id: *sector_size,
+        };
+        format_partition(
+            &device,
+            partition_start,
+            partitions[0].block_count,
+            &options,
+        )
+        .unwrap();
+
+        // The first sectors of the disk are left untouched
+        let image = device.to_vec();
+        assert!(image[512..(PARTITION_START_SECTOR * *sector_size) as usize]
+            .iter()
+            .all(|value| *value == 0));
+
+        let fs = libfat::get_partition(&device, 0).unwrap();
+        assert_eq!(fs.statistics().serial_number, *sector_size);
+        for operation in &OPERATIONS {
+            common::run_operation(&fs, *operation).unwrap();
+        }
+        fs.flush().unwrap();
+        common::assert_clean(&fs);
+    }
+}
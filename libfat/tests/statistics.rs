@@ -0,0 +1,151 @@
+//! Check the volume statistics against the geometry written in the boot sector.
+
+mod common;
+
+use byteorder::{ByteOrder, LittleEndian};
+use common::{Operation, VOLUMES};
+use libfat::fsck::{self, LostChainAction, RepairOptions};
+use libfat::{FatFsType, VolumeStatistics};
+use libfs::block::RamBlockDevice;
+
+/// Volumes with clusters of several sectors, checked on top of ``VOLUMES``.
+const MULTI_SECTOR_CLUSTER_VOLUMES: [(FatFsType, usize, u32); 2] = [
+    (FatFsType::Fat12, 4 << 20, 2048),
+    (FatFsType::Fat16, 32 << 20, 2048),
+];
+
+/// Compute the count of data clusters of a volume image from its boot sector.
+fn data_cluster_count(image: &[u8]) -> u32 {
+    let bytes_per_sector = u32::from(LittleEndian::read_u16(&image[11..13]));
+    let sectors_per_cluster = u32::from(image[13]);
+    let reserved_sectors = u32::from(LittleEndian::read_u16(&image[14..16]));
+    let fat_count = u32::from(image[16]);
+    let root_entry_count = u32::from(LittleEndian::read_u16(&image[17..19]));
+
+    let total_sectors = match LittleEndian::read_u16(&image[19..21]) {
+        0 => LittleEndian::read_u32(&image[32..36]),
+        total_sectors => u32::from(total_sectors),
+    };
+    let fat_size = match LittleEndian::read_u16(&image[22..24]) {
+        0 => LittleEndian::read_u32(&image[36..40]),
+        fat_size => u32::from(fat_size),
+    };
+    let root_dir_sectors = (root_entry_count * 32 + bytes_per_sector - 1) / bytes_per_sector;
+
+    let data_sectors = total_sectors - reserved_sectors - fat_count * fat_size - root_dir_sectors;
+    data_sectors / sectors_per_cluster
+}
+
+/// Get the offset of the free cluster count of the FS Info structure of a FAT32 volume image.
+fn fs_info_free_count_offset(image: &[u8]) -> usize {
+    let bytes_per_sector = usize::from(LittleEndian::read_u16(&image[11..13]));
+    let fs_info_sector = usize::from(LittleEndian::read_u16(&image[48..50]));
+
+    fs_info_sector * bytes_per_sector + 488
+}
+
+/// Check that ``statistics`` match the geometry of the volume image ``image`` formatted by ``common::create_volume``.
+fn assert_geometry(
+    statistics: &VolumeStatistics,
+    image: &[u8],
+    fat_type: FatFsType,
+    cluster_size: u32,
+) {
+    let cluster_count = data_cluster_count(image);
+
+    assert_eq!(statistics.fat_type, fat_type);
+    assert_eq!(statistics.serial_number, 0x1234_5678);
+    assert_eq!(statistics.cluster_size, u64::from(cluster_size));
+    assert_eq!(statistics.cluster_count, cluster_count, "{:?}", fat_type);
+    assert_eq!(
+        statistics.total_bytes,
+        u64::from(cluster_count) * u64::from(cluster_size)
+    );
+    assert_eq!(
+        statistics.free_bytes,
+        u64::from(statistics.free_cluster_count) * u64::from(cluster_size)
+    );
+}
+
+#[test]
+fn statistics_match_the_formatted_geometry() {
+    for (fat_type, size, cluster_size) in VOLUMES.iter().chain(&MULTI_SECTOR_CLUSTER_VOLUMES) {
+        let device = common::create_volume(*fat_type, *size, *cluster_size);
+        let image = device.to_vec();
+
+        let fs = libfat::get_raw_partition(&device).unwrap();
+        let statistics = fs.statistics();
+        assert_geometry(&statistics, &image, *fat_type, *cluster_size);
+
+        // Only the root directory of FAT32 volumes uses a cluster
+        let used_cluster_count = if *fat_type == FatFsType::Fat32 { 1 } else { 0 };
+        assert_eq!(
+            statistics.free_cluster_count,
+            statistics.cluster_count - used_cluster_count
+        );
+    }
+}
+
+#[test]
+fn statistics_follow_allocations() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let device = common::create_volume(*fat_type, *size, *cluster_size);
+        let fs = libfat::get_raw_partition(&device).unwrap();
+        let free_cluster_count = fs.statistics().free_cluster_count;
+
+        common::run_operation(&fs, Operation::Touch("/file.bin")).unwrap();
+        common::run_operation(
+            &fs,
+            Operation::Write("/file.bin", 0, 10 * *cluster_size as usize),
+        )
+        .unwrap();
+        assert_eq!(
+            fs.statistics().free_cluster_count,
+            free_cluster_count - 10,
+            "{:?}",
+            fat_type
+        );
+    }
+}
+
+#[test]
+fn out_of_range_fs_info_counts_are_recomputed() {
+    let device = common::create_volume(FatFsType::Fat32, 34 << 20, 512);
+    let mut image = device.into_vec();
+    let offset = fs_info_free_count_offset(&image);
+    let actual = LittleEndian::read_u32(&image[offset..offset + 4]);
+
+    // More free clusters than the volume has is ignored at mount time
+    LittleEndian::write_u32(&mut image[offset..offset + 4], 0x0FFF_FFF0);
+
+    let device = RamBlockDevice::from_vec(image.clone());
+    let fs = libfat::get_raw_partition(&device).unwrap();
+    let statistics = fs.statistics();
+    assert_geometry(&statistics, &image, FatFsType::Fat32, 512);
+    assert_eq!(statistics.free_cluster_count, actual);
+}
+
+#[test]
+fn stale_fs_info_counts_are_reported_until_repaired() {
+    let device = common::create_volume(FatFsType::Fat32, 34 << 20, 512);
+    let mut image = device.into_vec();
+    let offset = fs_info_free_count_offset(&image);
+    let actual = LittleEndian::read_u32(&image[offset..offset + 4]);
+
+    // A count in range is trusted, only the free space is affected
+    LittleEndian::write_u32(&mut image[offset..offset + 4], actual - 10);
+
+    let device = RamBlockDevice::from_vec(image.clone());
+    let fs = libfat::get_raw_partition(&device).unwrap();
+    let statistics = fs.statistics();
+    assert_geometry(&statistics, &image, FatFsType::Fat32, 512);
+    assert_eq!(statistics.free_cluster_count, actual - 10);
+
+    let options = RepairOptions {
+        lost_chains: LostChainAction::Free,
+    };
+    fsck::repair(&fs, &options).unwrap();
+    let statistics = fs.statistics();
+    assert_geometry(&statistics, &image, FatFsType::Fat32, 512);
+    assert_eq!(statistics.free_cluster_count, actual);
+}
@@ -0,0 +1,137 @@
+//! Check that directory entries are stamped once per open file, and never by reads alone.
+
+mod common;
+
+use common::VOLUMES;
+use libfat::datetime::{FatDateTime, TimeProvider};
+use libfs::block::{BlockDevice, BlockIndex, RamBlockDevice};
+use libfs::fault::{Fault, FaultOperation, FaultyBlockDevice};
+
+/// The path of the file used by the tests.
+const FILE: &str = "/file.txt";
+
+/// A ``TimeProvider`` always returning the same datetime.
+struct FixedTimeProvider(FatDateTime);
+
+impl TimeProvider for FixedTimeProvider {
+    fn now(&self) -> FatDateTime {
+        self.0
+    }
+}
+
+/// The datetime at which the file used by the tests is created.
+fn creation_time() -> FatDateTime {
+    FatDateTime::new(2001, 2, 3, 4, 5, 6, 0)
+}
+
+/// The datetime at which the file used by the tests is accessed.
+fn access_time() -> FatDateTime {
+    FatDateTime::new(2019, 7, 8, 9, 10, 12, 0)
+}
+
+/// Format an in-memory FAT16 volume holding a file created at ``creation_time``.
+fn create_volume_with_file() -> RamBlockDevice {
+    let (fat_type, size, cluster_size) = VOLUMES[1];
+    let device = common::create_volume(fat_type, size, cluster_size);
+
+    let mut fs = libfat::get_raw_partition(&device).unwrap();
+    fs.set_time_provider(Box::new(FixedTimeProvider(creation_time())));
+    fs.touch(FILE).unwrap();
+
+    common::append(&fs, FILE, b"Hello world").unwrap();
+    fs.flush().unwrap();
+    drop(fs);
+
+    device
+}
+
+#[test]
+fn reads_leave_the_volume_clean() {
+    let device = FaultyBlockDevice::new(create_volume_with_file());
+    let mut fs = libfat::get_raw_partition(&device).unwrap();
+    fs.set_time_provider(Box::new(FixedTimeProvider(access_time())));
+
+    // Reads don't write anything, even on write-protected media
+    device.add_fault(Fault::FailRange {
+        start: BlockIndex(0),
+        count: device.count().unwrap(),
+        operation: FaultOperation::Write,
+    });
+
+    let mut file = fs.get_root_directory().open_file(FILE).unwrap();
+    let mut buf = [0; 11];
+    assert_eq!(file.read(&fs, 0, &mut buf).unwrap(), 11);
+    assert_eq!(&buf, b"Hello world");
+    assert!(file.update_timestamps(&fs, false).is_err());
+    assert!(!fs.is_dirty());
+
+    // The last access date alone doesn't dirty the volume either
+    device.clear_faults();
+    device.reset_counters();
+    file.update_timestamps(&fs, false).unwrap();
+    assert_eq!(device.write_count(), 1);
+    assert!(!fs.is_dirty());
+
+    let file = fs.get_root_directory().open_file(FILE).unwrap();
+    assert_eq!(
+        file.last_access_timestamp,
+        FatDateTime::new(2019, 7, 8, 0, 0, 0, 0).to_unix_time()
+    );
+    assert_eq!(
+        file.last_modification_timestamp,
+        creation_time().to_unix_time()
+    );
+}
+
+#[test]
+fn writes_are_stamped_on_update() {
+    let device = FaultyBlockDevice::new(create_volume_with_file());
+    let mut fs = libfat::get_raw_partition(&device).unwrap();
+    fs.set_time_provider(Box::new(FixedTimeProvider(access_time())));
+
+    let mut file = fs.get_root_directory().open_file(FILE).unwrap();
+
+    // Overwriting data doesn't touch the entry once the volume is dirty
+    file.write(&fs, 0, b"J", false).unwrap();
+    device.reset_counters();
+    for offset in 1..5 {
+        file.write(&fs, offset, b"J", false).unwrap();
+    }
+    assert_eq!(device.write_count(), 4);
+
+    let reopened_file = fs.get_root_directory().open_file(FILE).unwrap();
+    assert_eq!(
+        reopened_file.last_modification_timestamp,
+        creation_time().to_unix_time()
+    );
+
+    file.update_timestamps(&fs, true).unwrap();
+    assert!(fs.is_dirty());
+
+    let reopened_file = fs.get_root_directory().open_file(FILE).unwrap();
+    assert_eq!(
+        reopened_file.last_modification_timestamp,
+        access_time().to_unix_time()
+    );
+}
+
+#[test]
+fn timestamps_are_kept_without_time_provider() {
+    let device = create_volume_with_file();
+    let fs = libfat::get_raw_partition(&device).unwrap();
+
+    let mut file = fs.get_root_directory().open_file(FILE).unwrap();
+    file.write(&fs, 0, b"Hello there", true).unwrap();
+    file.set_len(&fs, 5000).unwrap();
+    file.update_timestamps(&fs, true).unwrap();
+
+    fs.touch("/other.txt").unwrap();
+    fs.flush().unwrap();
+
+    let file = fs.get_root_directory().open_file(FILE).unwrap();
+    assert_eq!(file.file_size, 5000);
+    assert_eq!(
+        file.last_modification_timestamp,
+        creation_time().to_unix_time()
+    );
+}
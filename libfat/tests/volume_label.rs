@@ -0,0 +1,150 @@
+//! Check that volume labels are kept in sync between the boot records and the root directory.
+
+mod common;
+
+use byteorder::{ByteOrder, LittleEndian};
+use common::VOLUMES;
+use libfat::FatFsType;
+use libfs::block::RamBlockDevice;
+use libfs::FileSystemError;
+
+/// Get the offset of the volume label in the boot sector of a volume.
+fn label_offset(fat_type: FatFsType) -> usize {
+    if fat_type == FatFsType::Fat32 {
+        71
+    } else {
+        43
+    }
+}
+
+/// Get the raw volume labels of the boot sector and, on FAT32, of the backup boot sector of a volume image.
+fn boot_record_labels(image: &[u8], fat_type: FatFsType) -> Vec<Vec<u8>> {
+    let offset = label_offset(fat_type);
+    let mut labels = vec![image[offset..offset + 11].to_vec()];
+
+    if fat_type == FatFsType::Fat32 {
+        let bytes_per_sector = usize::from(LittleEndian::read_u16(&image[11..13]));
+        let backup_offset = usize::from(LittleEndian::read_u16(&image[50..52])) * bytes_per_sector;
+        labels.push(image[backup_offset + offset..backup_offset + offset + 11].to_vec());
+    }
+
+    labels
+}
+
+/// Get the raw name of the volume label entry at the start of the root directory of a volume image.
+fn root_label_entry(image: &[u8], fat_type: FatFsType) -> Option<Vec<u8>> {
+    let bytes_per_sector = usize::from(LittleEndian::read_u16(&image[11..13]));
+    let reserved_sectors = usize::from(LittleEndian::read_u16(&image[14..16]));
+    let fat_size = if fat_type == FatFsType::Fat32 {
+        LittleEndian::read_u32(&image[36..40]) as usize
+    } else {
+        usize::from(LittleEndian::read_u16(&image[22..24]))
+    };
+
+    // The FAT32 root directory starts at the first cluster of the data area on formatted volumes
+    let root_offset = (reserved_sectors + usize::from(image[16]) * fat_size) * bytes_per_sector;
+
+    image[root_offset..root_offset + bytes_per_sector]
+        .chunks(32)
+        .take_while(|entry| entry[0] != 0)
+        .find(|entry| entry[0] != 0xE5 && entry[11] == 0x08)
+        .map(|entry| entry[..11].to_vec())
+}
+
+/// Check that the label found in the root directory and in the boot records of a volume is ``expected``.
+///
+/// ``raw_label`` is the label expected in the boot records, "NO NAME" when the volume has no label.
+fn assert_label(
+    device: &RamBlockDevice,
+    fat_type: FatFsType,
+    expected: Option<&str>,
+    raw_label: &[u8; 11],
+) {
+    let fs = libfat::get_raw_partition(device).unwrap();
+    assert_eq!(
+        fs.volume_label().unwrap(),
+        expected.map(String::from),
+        "{:?}",
+        fat_type
+    );
+    common::assert_clean(&fs);
+    drop(fs);
+
+    let image = device.to_vec();
+    for label in boot_record_labels(&image, fat_type) {
+        assert_eq!(&label[..], &raw_label[..], "{:?}", fat_type);
+    }
+
+    let entry_label = root_label_entry(&image, fat_type);
+    match expected {
+        Some(_) => assert_eq!(
+            entry_label.as_ref().map(|label| &label[..]),
+            Some(&raw_label[..])
+        ),
+        None => assert_eq!(entry_label, None, "{:?}", fat_type),
+    }
+}
+
+#[test]
+fn labels_are_updated_everywhere() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let device = common::create_volume(*fat_type, *size, *cluster_size);
+        assert_label(&device, *fat_type, Some("TEST"), b"TEST       ");
+
+        {
+            let fs = libfat::get_raw_partition(&device).unwrap();
+            fs.set_volume_label("new label").unwrap();
+            fs.flush().unwrap();
+        }
+        assert_label(&device, *fat_type, Some("NEW LABEL"), b"NEW LABEL  ");
+    }
+}
+
+#[test]
+fn empty_labels_delete_the_entry() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let device = common::create_volume(*fat_type, *size, *cluster_size);
+
+        {
+            let fs = libfat::get_raw_partition(&device).unwrap();
+            fs.set_volume_label("").unwrap();
+            fs.flush().unwrap();
+        }
+        assert_label(&device, *fat_type, None, b"NO NAME    ");
+
+        // The entry is created again
+        {
+            let fs = libfat::get_raw_partition(&device).unwrap();
+            fs.set_volume_label("AGAIN").unwrap();
+            fs.flush().unwrap();
+        }
+        assert_label(&device, *fat_type, Some("AGAIN"), b"AGAIN      ");
+    }
+}
+
+#[test]
+fn invalid_labels_are_rejected() {
+    for (fat_type, size, cluster_size) in &VOLUMES {
+        let device = common::create_volume(*fat_type, *size, *cluster_size);
+        let image = device.to_vec();
+
+        {
+            let fs = libfat::get_raw_partition(&device).unwrap();
+            for label in &["BAD*LABEL", "A/B", "TAB\tLABEL", "ÉTÉ"] {
+                match fs.set_volume_label(label) {
+                    Err(FileSystemError::Custom { .. }) => {}
+                    result => panic!("{:?}: {:?} unexpectedly gave {:?}", fat_type, label, result),
+                }
+            }
+
+            match fs.set_volume_label("A LABEL TOO LONG") {
+                Err(FileSystemError::PathTooLong) => {}
+                result => panic!("{:?}: unexpected result {:?}", fat_type, result),
+            }
+        }
+
+        // Nothing was written
+        assert!(device.to_vec() == image, "{:?}", fat_type);
+        assert_label(&device, *fat_type, Some("TEST"), b"TEST       ");
+    }
+}
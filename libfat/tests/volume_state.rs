@@ -0,0 +1,44 @@
+//! Check that only the explicit filesystem flush marks a volume as cleanly unmounted.
+
+mod common;
+
+use common::VOLUMES;
+use libfs::block::RamBlockDevice;
+
+/// Check if a copy of the volume would be mounted as cleanly unmounted.
+fn is_clean(device: &RamBlockDevice) -> bool {
+    let device = RamBlockDevice::from_vec(device.to_vec());
+    let fs = libfat::get_raw_partition(&device).unwrap();
+
+    fs.mount_state().clean_shutdown
+}
+
+#[test]
+fn fs_info_flushes_keep_the_volume_dirty() {
+    // FAT12 doesn't have any volume state bits
+    for (fat_type, size, cluster_size) in &VOLUMES[1..] {
+        let device = common::create_volume(*fat_type, *size, *cluster_size);
+        let fs = libfat::get_raw_partition(&device).unwrap();
+        assert!(fs.mount_state().clean_shutdown);
+
+        fs.touch("/file.txt").unwrap();
+        common::append(&fs, "/file.txt", &[0x42; 3000]).unwrap();
+        assert!(!is_clean(&device), "{:?}", fat_type);
+
+        // The free cluster count is written, but the volume is still in use
+        fs.flush_fs_info().unwrap();
+        assert!(fs.is_dirty());
+        assert!(!is_clean(&device), "{:?}", fat_type);
+
+        let copy = RamBlockDevice::from_vec(device.to_vec());
+        let copy_fs = libfat::get_raw_partition(&copy).unwrap();
+        assert_eq!(
+            copy_fs.statistics().free_cluster_count,
+            fs.statistics().free_cluster_count
+        );
+
+        fs.flush().unwrap();
+        assert!(!fs.is_dirty());
+        assert!(is_clean(&device), "{:?}", fat_type);
+    }
+}
@@ -1,3 +1,5 @@
+use alloc::vec::Vec;
+use core::convert::TryFrom;
 use lru::LruCache;
 use spin::Mutex;
 
@@ -19,6 +21,7 @@ pub type BlockResult<T> = core::result::Result<T, BlockError>;
 
 /// Represent a certain amount of data from a block device.
 #[derive(Clone)]
+#[repr(transparent)]
 pub struct Block {
     /// The actual storage of the block.
     pub contents: [u8; Block::LEN],
@@ -48,6 +51,41 @@ impl Block {
     pub fn as_contents(&self) -> [u8; Block::LEN] {
         self.contents
     }
+
+    /// View a byte buffer as blocks, to transfer data without copies.
+    ///
+    /// Return None if the length of ``bytes`` isn't a multiple of ``Block::LEN``.
+    pub fn slice_from_bytes(bytes: &[u8]) -> Option<&[Block]> {
+        if bytes.len() % Self::LEN != 0 {
+            return None;
+        }
+
+        // Block is a transparent wrapper around a byte array, its alignment is the one of u8
+        let blocks = unsafe {
+            core::slice::from_raw_parts(bytes.as_ptr() as *const Block, bytes.len() / Self::LEN)
+        };
+
+        Some(blocks)
+    }
+
+    /// View a mutable byte buffer as blocks, to transfer data without copies.
+    ///
+    /// Return None if the length of ``bytes`` isn't a multiple of ``Block::LEN``.
+    pub fn slice_from_bytes_mut(bytes: &mut [u8]) -> Option<&mut [Block]> {
+        if bytes.len() % Self::LEN != 0 {
+            return None;
+        }
+
+        // Block is a transparent wrapper around a byte array, its alignment is the one of u8
+        let blocks = unsafe {
+            core::slice::from_raw_parts_mut(
+                bytes.as_mut_ptr() as *mut Block,
+                bytes.len() / Self::LEN,
+            )
+        };
+
+        Some(blocks)
+    }
 }
 
 impl Default for Block {
@@ -115,6 +153,33 @@ pub trait BlockDevice: Sized {
 
     /// Return the amount of blocks hold by the block device.
     fn count(&self) -> BlockResult<BlockCount>;
+
+    /// Return the size in bytes of a sector of the underlying device.
+    ///
+    /// Blocks are always ``Block::LEN`` bytes, devices with bigger sectors are expected to split them in blocks.
+    /// This is needed to interpret partition tables, as they express offsets in device sectors.
+    fn sector_size(&self) -> u32 {
+        Block::LEN_U32
+    }
+}
+
+/// Allow borrowing a block device, to keep access to it while a filesystem uses it.
+impl<'a, B: BlockDevice> BlockDevice for &'a B {
+    fn raw_read(&self, blocks: &mut [Block], index: BlockIndex) -> BlockResult<()> {
+        (**self).raw_read(blocks, index)
+    }
+
+    fn raw_write(&self, blocks: &[Block], index: BlockIndex) -> BlockResult<()> {
+        (**self).raw_write(blocks, index)
+    }
+
+    fn count(&self) -> BlockResult<BlockCount> {
+        (**self).count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        (**self).sector_size()
+    }
 }
 
 /// A BlockDevice that reduces device accesses by keeping the most recently used blocks in a cache.
@@ -288,4 +353,131 @@ impl<B: BlockDevice> BlockDevice for CachedBlockDevice<B> {
     fn count(&self) -> BlockResult<BlockCount> {
         self.block_device.count()
     }
+
+    fn sector_size(&self) -> u32 {
+        self.block_device.sector_size()
+    }
+}
+
+/// A BlockDevice storing its blocks in memory.
+///
+/// Useful for tests, ramdisks and building images in memory.
+pub struct RamBlockDevice {
+    /// The content of the device.
+    data: Mutex<Vec<u8>>,
+
+    /// The size in bytes of a sector of the device.
+    sector_size: u32,
+}
+
+impl RamBlockDevice {
+    /// Create a new zero filled device of at least ``size`` bytes.
+    ///
+    /// The size is rounded up to a multiple of ``Block::LEN``.
+    pub fn new(size: usize) -> RamBlockDevice {
+        RamBlockDevice::padded(Vec::new(), size)
+    }
+
+    /// Create a new device holding the given ``data``.
+    ///
+    /// The data is zero padded up to a multiple of ``Block::LEN``.
+    pub fn from_vec(data: Vec<u8>) -> RamBlockDevice {
+        let size = data.len();
+        RamBlockDevice::padded(data, size)
+    }
+
+    /// Create a new device of at least ``size`` bytes holding ``data``, zero padded up to a multiple of ``Block::LEN``.
+    fn padded(mut data: Vec<u8>, size: usize) -> RamBlockDevice {
+        let size = (size + Block::LEN - 1) / Block::LEN * Block::LEN;
+        data.resize(size, 0);
+
+        RamBlockDevice {
+            data: Mutex::new(data),
+            sector_size: Block::LEN_U32,
+        }
+    }
+
+    /// Make the device report sectors of ``sector_size`` bytes, emulating disks with bigger sectors.
+    ///
+    /// The content is zero padded up to a multiple of ``sector_size``, which must be a multiple of ``Block::LEN``.
+    pub fn with_sector_size(self, sector_size: u32) -> RamBlockDevice {
+        assert!(
+            sector_size != 0 && sector_size % Block::LEN_U32 == 0,
+            "sectors must be made of whole blocks"
+        );
+
+        let sector_size_bytes = sector_size as usize;
+        let mut data = self.data.into_inner();
+        let size = (data.len() + sector_size_bytes - 1) / sector_size_bytes * sector_size_bytes;
+        data.resize(size, 0);
+
+        RamBlockDevice {
+            data: Mutex::new(data),
+            sector_size,
+        }
+    }
+
+    /// Return a copy of the content of the device.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.data.lock().clone()
+    }
+
+    /// Unwrap the device, returning its content.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.data.into_inner()
+    }
+
+    /// Get the byte range covered by ``block_count`` blocks starting at ``index``, if the device holds them.
+    fn byte_range(
+        data_len: usize,
+        index: BlockIndex,
+        block_count: usize,
+    ) -> Option<core::ops::Range<usize>> {
+        let start = usize::try_from(index.into_offset()).ok()?;
+        let end = start.checked_add(block_count.checked_mul(Block::LEN)?)?;
+
+        if end > data_len {
+            return None;
+        }
+
+        Some(start..end)
+    }
+}
+
+impl BlockDevice for RamBlockDevice {
+    fn raw_read(&self, blocks: &mut [Block], index: BlockIndex) -> BlockResult<()> {
+        let data = self.data.lock();
+        let range =
+            Self::byte_range(data.len(), index, blocks.len()).ok_or(BlockError::ReadError)?;
+
+        for (block, contents) in blocks.iter_mut().zip(data[range].chunks_exact(Block::LEN)) {
+            block.contents.copy_from_slice(contents);
+        }
+
+        Ok(())
+    }
+
+    fn raw_write(&self, blocks: &[Block], index: BlockIndex) -> BlockResult<()> {
+        let mut data = self.data.lock();
+        let range =
+            Self::byte_range(data.len(), index, blocks.len()).ok_or(BlockError::WriteError)?;
+
+        for (block, contents) in blocks.iter().zip(data[range].chunks_exact_mut(Block::LEN)) {
+            contents.copy_from_slice(&block.contents);
+        }
+
+        Ok(())
+    }
+
+    fn count(&self) -> BlockResult<BlockCount> {
+        let block_count = self.data.lock().len() / Block::LEN;
+
+        u32::try_from(block_count)
+            .map(BlockCount)
+            .or(Err(BlockError::Unknown))
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.sector_size
+    }
 }
@@ -0,0 +1,369 @@
+//! Fault injection and write recording for block devices.
+
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::block::{Block, BlockCount, BlockDevice, BlockError, BlockIndex, BlockResult};
+
+/// Represent the block operations a fault applies to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultOperation {
+    /// Only reads are affected.
+    Read,
+
+    /// Only writes are affected.
+    Write,
+
+    /// Both reads and writes are affected.
+    Any,
+}
+
+impl FaultOperation {
+    /// Check if the fault applies to a read if ``is_write`` is false or to a write otherwise.
+    fn matches(self, is_write: bool) -> bool {
+        match self {
+            FaultOperation::Read => !is_write,
+            FaultOperation::Write => is_write,
+            FaultOperation::Any => true,
+        }
+    }
+}
+
+/// Represent a fault to inject in a ``FaultyBlockDevice``.
+///
+/// Operations are counted per call to ``raw_read`` or ``raw_write``, starting at 0.
+#[derive(Debug, Clone, Copy)]
+pub enum Fault {
+    /// Fail the read operation of the given index.
+    FailRead {
+        /// The index of the read operation to fail.
+        nth: usize,
+    },
+
+    /// Fail the write operation of the given index without writing anything.
+    FailWrite {
+        /// The index of the write operation to fail.
+        nth: usize,
+    },
+
+    /// Fail every operation touching a block of the given range.
+    FailRange {
+        /// The first block of the range.
+        start: BlockIndex,
+
+        /// The count of blocks in the range.
+        count: BlockCount,
+
+        /// The operations to fail.
+        operation: FaultOperation,
+    },
+
+    /// Only write the first bytes of the write operation of the given index, then fail it.
+    ///
+    /// This simulates a power loss in the middle of a write.
+    TornWrite {
+        /// The index of the write operation to tear.
+        nth: usize,
+
+        /// The count of bytes actually written.
+        written_bytes: usize,
+    },
+
+    /// Flip bits of a block every time it is read.
+    FlipBits {
+        /// The block to corrupt.
+        index: BlockIndex,
+
+        /// The offset of the byte to corrupt in the block.
+        offset: usize,
+
+        /// The bits to flip.
+        mask: u8,
+    },
+}
+
+/// The mutable state of a ``FaultyBlockDevice``.
+struct FaultState {
+    /// The faults to inject.
+    faults: Vec<Fault>,
+
+    /// The count of read operations done.
+    read_count: usize,
+
+    /// The count of write operations done.
+    write_count: usize,
+
+    /// The count of faults injected.
+    injected_count: usize,
+}
+
+/// A BlockDevice wrapper injecting faults on demand, used to test error paths.
+pub struct FaultyBlockDevice<B: BlockDevice> {
+    /// The inner block device.
+    block_device: B,
+
+    /// The faults and the operation counters.
+    state: Mutex<FaultState>,
+}
+
+impl<B: BlockDevice> FaultyBlockDevice<B> {
+    /// Create a new FaultyBlockDevice wrapping ``device``, without any fault.
+    pub fn new(device: B) -> FaultyBlockDevice<B> {
+        FaultyBlockDevice {
+            block_device: device,
+            state: Mutex::new(FaultState {
+                faults: Vec::new(),
+                read_count: 0,
+                write_count: 0,
+                injected_count: 0,
+            }),
+        }
+    }
+
+    /// Add a fault to inject.
+    pub fn add_fault(&self, fault: Fault) {
+        self.state.lock().faults.push(fault);
+    }
+
+    /// Remove every fault.
+    pub fn clear_faults(&self) {
+        self.state.lock().faults.clear();
+    }
+
+    /// Reset the operation and injected fault counters.
+    pub fn reset_counters(&self) {
+        let mut state = self.state.lock();
+
+        state.read_count = 0;
+        state.write_count = 0;
+        state.injected_count = 0;
+    }
+
+    /// Return the count of read operations done since the last counter reset.
+    pub fn read_count(&self) -> usize {
+        self.state.lock().read_count
+    }
+
+    /// Return the count of write operations done since the last counter reset.
+    pub fn write_count(&self) -> usize {
+        self.state.lock().write_count
+    }
+
+    /// Return the count of faults injected since the last counter reset.
+    pub fn injected_count(&self) -> usize {
+        self.state.lock().injected_count
+    }
+
+    /// Get a reference to the inner block device.
+    pub fn inner(&self) -> &B {
+        &self.block_device
+    }
+
+    /// Unwrap the device, returning the inner block device.
+    pub fn into_inner(self) -> B {
+        self.block_device
+    }
+
+    /// Check if a fault fails the operation of index ``nth`` on ``block_count`` blocks starting at ``index``.
+    fn should_fail(
+        faults: &[Fault],
+        is_write: bool,
+        nth: usize,
+        index: BlockIndex,
+        block_count: usize,
+    ) -> bool {
+        faults.iter().any(|fault| match *fault {
+            Fault::FailRead { nth: fault_nth } => !is_write && fault_nth == nth,
+            Fault::FailWrite { nth: fault_nth } => is_write && fault_nth == nth,
+            Fault::FailRange {
+                start,
+                count,
+                operation,
+            } => {
+                let end = u64::from(index.0) + block_count as u64;
+                let fault_end = u64::from(start.0) + u64::from(count.0);
+
+                operation.matches(is_write)
+                    && u64::from(index.0) < fault_end
+                    && u64::from(start.0) < end
+            }
+            _ => false,
+        })
+    }
+
+    /// Get the count of bytes to write if the write operation of index ``nth`` must be torn.
+    fn torn_write_size(faults: &[Fault], nth: usize) -> Option<usize> {
+        faults.iter().find_map(|fault| match *fault {
+            Fault::TornWrite {
+                nth: fault_nth,
+                written_bytes,
+            } if fault_nth == nth => Some(written_bytes),
+            _ => None,
+        })
+    }
+
+    /// Write the first ``written_bytes`` bytes of ``blocks`` at ``index``, keeping the rest of the last block untouched.
+    fn write_torn(
+        &self,
+        blocks: &[Block],
+        index: BlockIndex,
+        written_bytes: usize,
+    ) -> BlockResult<()> {
+        let full_blocks = (written_bytes / Block::LEN).min(blocks.len());
+        let partial_bytes = written_bytes % Block::LEN;
+
+        if full_blocks != 0 {
+            self.block_device.raw_write(&blocks[..full_blocks], index)?;
+        }
+
+        if full_blocks < blocks.len() && partial_bytes != 0 {
+            let partial_index = BlockIndex(index.0 + full_blocks as u32);
+            let mut block = [Block::new()];
+
+            self.block_device.raw_read(&mut block, partial_index)?;
+            block[0][..partial_bytes].copy_from_slice(&blocks[full_blocks][..partial_bytes]);
+            self.block_device.raw_write(&block, partial_index)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<B: BlockDevice> BlockDevice for FaultyBlockDevice<B> {
+    fn raw_read(&self, blocks: &mut [Block], index: BlockIndex) -> BlockResult<()> {
+        let mut state = self.state.lock();
+        let nth = state.read_count;
+        state.read_count += 1;
+
+        if Self::should_fail(&state.faults, false, nth, index, blocks.len()) {
+            state.injected_count += 1;
+            return Err(BlockError::ReadError);
+        }
+
+        self.block_device.raw_read(blocks, index)?;
+
+        let state = &mut *state;
+        for fault in &state.faults {
+            if let Fault::FlipBits {
+                index: fault_index,
+                offset,
+                mask,
+            } = *fault
+            {
+                if fault_index >= index && fault_index.0 - index.0 < blocks.len() as u32 {
+                    blocks[(fault_index.0 - index.0) as usize][offset] ^= mask;
+                    state.injected_count += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn raw_write(&self, blocks: &[Block], index: BlockIndex) -> BlockResult<()> {
+        let mut state = self.state.lock();
+        let nth = state.write_count;
+        state.write_count += 1;
+
+        if Self::should_fail(&state.faults, true, nth, index, blocks.len()) {
+            state.injected_count += 1;
+            return Err(BlockError::WriteError);
+        }
+
+        if let Some(written_bytes) = Self::torn_write_size(&state.faults, nth) {
+            state.injected_count += 1;
+            self.write_torn(blocks, index, written_bytes)?;
+            return Err(BlockError::WriteError);
+        }
+
+        self.block_device.raw_write(blocks, index)
+    }
+
+    fn count(&self) -> BlockResult<BlockCount> {
+        self.block_device.count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.block_device.sector_size()
+    }
+}
+
+/// Represent a write operation recorded by a ``RecordingBlockDevice``.
+#[derive(Clone)]
+pub struct RecordedWrite {
+    /// The first block written.
+    pub index: BlockIndex,
+
+    /// The data written.
+    pub blocks: Vec<Block>,
+}
+
+impl RecordedWrite {
+    /// Write the recorded data to ``device``.
+    pub fn replay<B: BlockDevice>(&self, device: &B) -> BlockResult<()> {
+        device.raw_write(&self.blocks, self.index)
+    }
+}
+
+/// A BlockDevice wrapper recording every successful write, used to replay them later.
+pub struct RecordingBlockDevice<B: BlockDevice> {
+    /// The inner block device.
+    block_device: B,
+
+    /// The writes recorded, in order.
+    writes: Mutex<Vec<RecordedWrite>>,
+}
+
+impl<B: BlockDevice> RecordingBlockDevice<B> {
+    /// Create a new RecordingBlockDevice wrapping ``device``.
+    pub fn new(device: B) -> RecordingBlockDevice<B> {
+        RecordingBlockDevice {
+            block_device: device,
+            writes: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Return the count of writes recorded.
+    pub fn write_count(&self) -> usize {
+        self.writes.lock().len()
+    }
+
+    /// Return the writes recorded and start a new record.
+    pub fn take_writes(&self) -> Vec<RecordedWrite> {
+        self.writes.lock().drain(..).collect()
+    }
+
+    /// Get a reference to the inner block device.
+    pub fn inner(&self) -> &B {
+        &self.block_device
+    }
+
+    /// Unwrap the device, returning the inner block device.
+    pub fn into_inner(self) -> B {
+        self.block_device
+    }
+}
+
+impl<B: BlockDevice> BlockDevice for RecordingBlockDevice<B> {
+    fn raw_read(&self, blocks: &mut [Block], index: BlockIndex) -> BlockResult<()> {
+        self.block_device.raw_read(blocks, index)
+    }
+
+    fn raw_write(&self, blocks: &[Block], index: BlockIndex) -> BlockResult<()> {
+        self.block_device.raw_write(blocks, index)?;
+        self.writes.lock().push(RecordedWrite {
+            index,
+            blocks: blocks.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    fn count(&self) -> BlockResult<BlockCount> {
+        self.block_device.count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.block_device.sector_size()
+    }
+}
@@ -4,12 +4,23 @@
 
 extern crate alloc;
 
+#[cfg(feature = "std")]
+extern crate std;
+
 #[macro_use]
 extern crate bitflags;
 
 /// I/O driver representation.
 pub mod block;
 
+/// Fault injection for block devices.
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+
+/// Adapters to the standard library I/O traits.
+#[cfg(feature = "std")]
+pub mod std_io;
+
 use alloc::boxed::Box;
 
 /// Represent a filesystem error.
@@ -21,6 +32,9 @@ pub enum FileSystemError {
     /// There isn't enough space for a resource to be stored.
     NoSpaceLeft,
 
+    /// There isn't enough contiguous space for a resource to be stored, even if the total free space is large enough.
+    NoContiguousSpaceLeft,
+
     /// The access to a given resource has been denied.
     AccessDenied,
 
@@ -109,6 +123,23 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Flags representing the attributes of a resource.
+    pub struct FileAttributes: u32 {
+        /// The resource cannot be modified, deleted or renamed.
+        const READ_ONLY = 0b0000_0001;
+
+        /// The resource is hidden from normal directory views.
+        const HIDDEN = 0b0000_0010;
+
+        /// The resource belongs to the system.
+        const SYSTEM = 0b0000_0100;
+
+        /// The resource was modified since its last backup.
+        const ARCHIVE = 0b0000_1000;
+    }
+}
+
 /// Represent the attached timestamps on a given resource.
 #[derive(Debug)]
 pub struct FileTimeStampRaw {
@@ -125,6 +156,44 @@ pub struct FileTimeStampRaw {
     pub is_valid: bool,
 }
 
+/// Represent the timestamps to set on a given resource. ``None`` timestamps are left untouched.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileTimeStampUpdate {
+    /// The new resource creation UNIX timestamp.
+    pub creation_timestamp: Option<u64>,
+
+    /// The new resource last modification UNIX timestamp.
+    pub modified_timestamp: Option<u64>,
+
+    /// The new resource last access UNIX timestamp.
+    pub accessed_timestamp: Option<u64>,
+}
+
+/// Represent the capacity and identity of a filesystem.
+#[derive(Debug, Clone, Copy)]
+pub struct FileSystemStatistics {
+    /// The name of the filesystem type (e.g. "FAT32").
+    pub filesystem_type: &'static str,
+
+    /// The serial number of the volume. Zero if the volume doesn't have one.
+    pub serial_number: u64,
+
+    /// The size of an allocation unit in bytes.
+    pub cluster_size: u64,
+
+    /// The count of allocation units available to store data.
+    pub total_cluster_count: u64,
+
+    /// The count of free allocation units.
+    pub free_cluster_count: u64,
+
+    /// The space available to store data in bytes.
+    pub total_bytes: u64,
+
+    /// The free space in bytes.
+    pub free_bytes: u64,
+}
+
 /// Represent a filesystem result.
 pub type FileSystemResult<T> = core::result::Result<T, FileSystemError>;
 
@@ -194,4 +263,21 @@ pub trait FileSystemOperations {
 
     /// Return the attached timestamps on a resource at the given ``path``.
     fn get_file_timestamp_raw(&self, path: &str) -> FileSystemResult<FileTimeStampRaw>;
+
+    /// Return the attributes of a resource at the given ``path``.
+    fn get_file_attributes(&self, path: &str) -> FileSystemResult<FileAttributes>;
+
+    /// Set the attributes of a resource at the given ``path``.
+    fn set_file_attributes(&self, path: &str, attributes: FileAttributes) -> FileSystemResult<()>;
+
+    /// Set the attached timestamps on a resource at the given ``path``.
+    /// NOTE: Timestamps are rounded down to the granularity of the filesystem.
+    fn set_file_timestamp_raw(
+        &self,
+        path: &str,
+        timestamps: &FileTimeStampUpdate,
+    ) -> FileSystemResult<()>;
+
+    /// Return the capacity and identity of the filesystem.
+    fn get_statistics(&self) -> FileSystemResult<FileSystemStatistics>;
 }
@@ -0,0 +1,190 @@
+//! Adapters between libfs and the standard library I/O traits.
+
+use alloc::boxed::Box;
+use core::convert::TryFrom;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use spin::Mutex;
+
+use super::block::{Block, BlockCount, BlockDevice, BlockError, BlockIndex, BlockResult};
+use super::{FileOperations, FileSystemError};
+
+impl From<FileSystemError> for io::Error {
+    fn from(error: FileSystemError) -> io::Error {
+        let (kind, description) = match error {
+            FileSystemError::NotFound => (io::ErrorKind::NotFound, "resource not found"),
+            FileSystemError::NoSpaceLeft => (io::ErrorKind::Other, "no space left"),
+            FileSystemError::NoContiguousSpaceLeft => {
+                (io::ErrorKind::Other, "no contiguous space left")
+            }
+            FileSystemError::AccessDenied => (io::ErrorKind::PermissionDenied, "access denied"),
+            FileSystemError::WriteFailed => (io::ErrorKind::Other, "device write failed"),
+            FileSystemError::ReadFailed => (io::ErrorKind::Other, "device read failed"),
+            FileSystemError::PartitionNotFound => (io::ErrorKind::NotFound, "partition not found"),
+            FileSystemError::NotAFile => (io::ErrorKind::InvalidInput, "not a file"),
+            FileSystemError::NotADirectory => (io::ErrorKind::InvalidInput, "not a directory"),
+            FileSystemError::FileExists => (io::ErrorKind::AlreadyExists, "file already exists"),
+            FileSystemError::PathTooLong => (io::ErrorKind::InvalidInput, "path too long"),
+            FileSystemError::InvalidPartition => (io::ErrorKind::InvalidData, "invalid partition"),
+            FileSystemError::Custom { name } => (io::ErrorKind::Other, name),
+        };
+
+        io::Error::new(kind, description)
+    }
+}
+
+/// A file handle keeping track of a position and implementing ``Read``, ``Write`` and ``Seek``.
+pub struct FileHandle<'a> {
+    /// The underlying file.
+    file: Box<dyn FileOperations + 'a>,
+
+    /// The current position in the file.
+    position: u64,
+}
+
+impl<'a> FileHandle<'a> {
+    /// Create a new handle positioned at the start of the given ``file``.
+    pub fn new(file: Box<dyn FileOperations + 'a>) -> FileHandle<'a> {
+        FileHandle { file, position: 0 }
+    }
+
+    /// Get the current position in the file.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Get a mutable reference to the underlying file.
+    pub fn get_mut(&mut self) -> &mut (dyn FileOperations + 'a) {
+        &mut *self.file
+    }
+
+    /// Unwrap the handle, returning the underlying file.
+    pub fn into_inner(self) -> Box<dyn FileOperations + 'a> {
+        self.file
+    }
+}
+
+impl<'a> Read for FileHandle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_size = self.file.read(self.position, buf)?;
+        self.position += read_size;
+
+        Ok(read_size as usize)
+    }
+}
+
+impl<'a> Write for FileHandle<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(self.position, buf)?;
+        self.position += buf.len() as u64;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        Ok(())
+    }
+}
+
+impl<'a> Seek for FileHandle<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.position = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.file.get_len()?, offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+
+        match u64::try_from(i128::from(base) + i128::from(offset)) {
+            Ok(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            )),
+        }
+    }
+}
+
+/// A BlockDevice backed by a file of the host, like a disk image or a device node.
+pub struct FileBlockDevice {
+    /// The underlying file.
+    file: Mutex<File>,
+}
+
+impl FileBlockDevice {
+    /// Create a new block device from an already opened ``file``.
+    pub fn new(file: File) -> FileBlockDevice {
+        FileBlockDevice {
+            file: Mutex::new(file),
+        }
+    }
+
+    /// Open the file at the given ``path`` for reading and writing.
+    pub fn open<P>(path: P) -> io::Result<FileBlockDevice>
+    where
+        P: AsRef<Path>,
+    {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        Ok(FileBlockDevice::new(file))
+    }
+
+    /// Unwrap the block device, returning the underlying file.
+    pub fn into_inner(self) -> File {
+        self.file.into_inner()
+    }
+}
+
+impl BlockDevice for FileBlockDevice {
+    fn raw_read(&self, blocks: &mut [Block], index: BlockIndex) -> BlockResult<()> {
+        let mut file = self.file.lock();
+
+        file.seek(SeekFrom::Start(index.into_offset()))
+            .or(Err(BlockError::ReadError))?;
+        for block in blocks.iter_mut() {
+            file.read_exact(&mut block.contents)
+                .or(Err(BlockError::ReadError))?;
+        }
+
+        Ok(())
+    }
+
+    fn raw_write(&self, blocks: &[Block], index: BlockIndex) -> BlockResult<()> {
+        let mut file = self.file.lock();
+
+        file.seek(SeekFrom::Start(index.into_offset()))
+            .or(Err(BlockError::WriteError))?;
+        for block in blocks.iter() {
+            file.write_all(&block.contents)
+                .or(Err(BlockError::WriteError))?;
+        }
+
+        Ok(())
+    }
+
+    fn count(&self) -> BlockResult<BlockCount> {
+        let mut file = self.file.lock();
+
+        // The metadata of a device node reports a length of 0, seek to the end instead
+        let position = file
+            .seek(SeekFrom::Current(0))
+            .or(Err(BlockError::Unknown))?;
+        let len = file.seek(SeekFrom::End(0)).or(Err(BlockError::Unknown))?;
+        file.seek(SeekFrom::Start(position))
+            .or(Err(BlockError::Unknown))?;
+
+        u32::try_from(len / Block::LEN as u64)
+            .map(BlockCount)
+            .or(Err(BlockError::Unknown))
+    }
+}
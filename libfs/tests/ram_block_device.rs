@@ -0,0 +1,88 @@
+//! Check the sizing and the bounds checks of the in-memory block device.
+
+use libfs::block::{Block, BlockDevice, BlockError, BlockIndex, RamBlockDevice};
+
+/// Create a block filled with ``value``.
+fn filled_block(value: u8) -> Block {
+    let mut block = Block::new();
+    block.contents.iter_mut().for_each(|byte| *byte = value);
+
+    block
+}
+
+#[test]
+fn sizes_are_padded_to_whole_blocks() {
+    let device = RamBlockDevice::new(1000);
+    assert_eq!(device.count().unwrap().0, 2);
+    assert_eq!(device.to_vec().len(), 2 * Block::LEN);
+
+    let device = RamBlockDevice::new(2 * Block::LEN);
+    assert_eq!(device.count().unwrap().0, 2);
+
+    // The data is kept and followed by zeros
+    let device = RamBlockDevice::from_vec(vec![0x42; Block::LEN + 1]);
+    assert_eq!(device.count().unwrap().0, 2);
+    let data = device.into_vec();
+    assert_eq!(data.len(), 2 * Block::LEN);
+    assert!(data[..=Block::LEN].iter().all(|byte| *byte == 0x42));
+    assert!(data[Block::LEN + 1..].iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn sizes_are_padded_to_whole_sectors() {
+    let device = RamBlockDevice::from_vec(vec![0x42; 3 * Block::LEN]).with_sector_size(2048);
+    assert_eq!(device.sector_size(), 2048);
+    assert_eq!(device.count().unwrap().0, 4);
+
+    let data = device.into_vec();
+    assert!(data[..3 * Block::LEN].iter().all(|byte| *byte == 0x42));
+    assert!(data[3 * Block::LEN..].iter().all(|byte| *byte == 0));
+
+    // Devices already made of whole sectors are left untouched
+    let device = RamBlockDevice::new(8 * Block::LEN).with_sector_size(4096);
+    assert_eq!(device.count().unwrap().0, 8);
+}
+
+#[test]
+#[should_panic]
+fn sector_sizes_must_be_whole_blocks() {
+    RamBlockDevice::new(4096).with_sector_size(1000);
+}
+
+#[test]
+fn blocks_are_read_and_written_in_place() {
+    let device = RamBlockDevice::new(4 * Block::LEN);
+
+    let blocks = [filled_block(1), filled_block(2)];
+    device.raw_write(&blocks, BlockIndex(2)).unwrap();
+
+    let mut blocks = [Block::new(), Block::new(), Block::new()];
+    device.raw_read(&mut blocks, BlockIndex(1)).unwrap();
+    assert!(blocks[0].contents.iter().all(|byte| *byte == 0));
+    assert!(blocks[1].contents.iter().all(|byte| *byte == 1));
+    assert!(blocks[2].contents.iter().all(|byte| *byte == 2));
+}
+
+#[test]
+fn out_of_range_accesses_fail() {
+    let device = RamBlockDevice::new(4 * Block::LEN);
+    let image = device.to_vec();
+
+    // Past the end, straddling the end, and far enough to overflow the byte offsets
+    for (index, block_count) in &[(4, 1), (3, 2), (u32::max_value(), 1)] {
+        let mut blocks = vec![Block::new(); *block_count];
+        match device.raw_read(&mut blocks, BlockIndex(*index)) {
+            Err(BlockError::ReadError) => {}
+            result => panic!("read at {}: unexpected result {:?}", index, result),
+        }
+
+        let blocks = vec![filled_block(0x42); *block_count];
+        match device.raw_write(&blocks, BlockIndex(*index)) {
+            Err(BlockError::WriteError) => {}
+            result => panic!("write at {}: unexpected result {:?}", index, result),
+        }
+    }
+
+    // Failed writes don't write the blocks that fit
+    assert!(device.to_vec() == image);
+}
@@ -0,0 +1,190 @@
+//! Check the adapters between libfs and the standard library I/O traits.
+#![cfg(feature = "std")]
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use libfs::block::{Block, BlockDevice, BlockIndex};
+use libfs::std_io::{FileBlockDevice, FileHandle};
+use libfs::{FileOperations, FileSystemResult};
+
+/// A file kept in memory.
+struct MemoryFile {
+    /// The content of the file.
+    data: Vec<u8>,
+
+    /// The count of calls to ``flush``.
+    flush_count: u32,
+}
+
+impl FileOperations for MemoryFile {
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> FileSystemResult<u64> {
+        let offset = std::cmp::min(offset as usize, self.data.len());
+        let read_size = std::cmp::min(buf.len(), self.data.len() - offset);
+        buf[..read_size].copy_from_slice(&self.data[offset..offset + read_size]);
+
+        Ok(read_size as u64)
+    }
+
+    fn write(&mut self, offset: u64, buf: &[u8]) -> FileSystemResult<()> {
+        let end = offset as usize + buf.len();
+        if self.data.len() < end {
+            self.data.resize(end, 0);
+        }
+        self.data[offset as usize..end].copy_from_slice(buf);
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> FileSystemResult<()> {
+        self.flush_count += 1;
+
+        Ok(())
+    }
+
+    fn set_len(&mut self, size: u64) -> FileSystemResult<()> {
+        self.data.resize(size as usize, 0);
+
+        Ok(())
+    }
+
+    fn get_len(&mut self) -> FileSystemResult<u64> {
+        Ok(self.data.len() as u64)
+    }
+}
+
+/// Create a handle on an in-memory file holding ``data``.
+fn create_handle(data: &[u8]) -> FileHandle<'static> {
+    FileHandle::new(Box::new(MemoryFile {
+        data: data.to_vec(),
+        flush_count: 0,
+    }))
+}
+
+/// A file of the host removed when dropped.
+struct TemporaryFile(PathBuf);
+
+impl TemporaryFile {
+    /// Create a file of the host named after ``name`` holding ``data``.
+    fn new(name: &str, data: &[u8]) -> TemporaryFile {
+        let path = std::env::temp_dir().join(format!("libfs-{}-{}", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+
+        TemporaryFile(path)
+    }
+}
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn file_handles_read_and_write_at_their_position() {
+    let mut handle = create_handle(b"Hello world");
+
+    let mut buf = [0; 5];
+    handle.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"Hello");
+    assert_eq!(handle.position(), 5);
+
+    handle.write_all(b", big world!").unwrap();
+    assert_eq!(handle.position(), 17);
+
+    // Reads stop at the end of the file
+    assert_eq!(handle.read(&mut buf).unwrap(), 0);
+
+    handle.flush().unwrap();
+    let mut data = Vec::new();
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    handle.read_to_end(&mut data).unwrap();
+    assert_eq!(&data[..], &b"Hello, big world!"[..]);
+}
+
+#[test]
+fn file_handles_seek_from_every_base() {
+    let mut handle = create_handle(b"0123456789");
+
+    assert_eq!(handle.seek(SeekFrom::Start(3)).unwrap(), 3);
+    assert_eq!(handle.seek(SeekFrom::Current(4)).unwrap(), 7);
+    assert_eq!(handle.seek(SeekFrom::Current(-2)).unwrap(), 5);
+    assert_eq!(handle.seek(SeekFrom::End(-1)).unwrap(), 9);
+
+    let mut buf = [0; 1];
+    handle.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"9");
+
+    // Seeking past the end is allowed, writing there extends the file
+    assert_eq!(handle.seek(SeekFrom::End(2)).unwrap(), 12);
+    handle.write_all(b"!").unwrap();
+    assert_eq!(handle.get_mut().get_len().unwrap(), 13);
+
+    // Seeking before the start fails and leaves the position untouched
+    let error = handle.seek(SeekFrom::Current(-14)).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    let error = handle.seek(SeekFrom::End(-14)).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(handle.position(), 13);
+}
+
+#[test]
+fn file_block_devices_access_the_file_blocks() {
+    let mut data = vec![0; 4 * Block::LEN];
+    for (index, block) in data.chunks_mut(Block::LEN).enumerate() {
+        block.iter_mut().for_each(|value| *value = index as u8);
+    }
+    let file = TemporaryFile::new("blocks.img", &data);
+
+    let device = FileBlockDevice::open(&file.0).unwrap();
+    assert_eq!(device.count().unwrap().0, 4);
+
+    let mut blocks = [Block::new(), Block::new()];
+    device.raw_read(&mut blocks, BlockIndex(1)).unwrap();
+    assert!(blocks[0].iter().all(|value| *value == 1));
+    assert!(blocks[1].iter().all(|value| *value == 2));
+
+    let mut block = Block::new();
+    block.iter_mut().for_each(|value| *value = 0x42);
+    device
+        .raw_write(core::slice::from_ref(&block), BlockIndex(3))
+        .unwrap();
+    drop(device);
+
+    let data = fs::read(&file.0).unwrap();
+    assert_eq!(data.len(), 4 * Block::LEN);
+    assert!(data[..3 * Block::LEN]
+        .chunks(Block::LEN)
+        .enumerate()
+        .all(|(index, block)| block.iter().all(|value| *value == index as u8)));
+    assert!(data[3 * Block::LEN..].iter().all(|value| *value == 0x42));
+}
+
+#[test]
+fn file_block_devices_count_blocks_without_moving() {
+    let file = TemporaryFile::new("count.img", &vec![0; 3 * Block::LEN + 100]);
+
+    let mut host_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&file.0)
+        .unwrap();
+    host_file.seek(SeekFrom::Start(42)).unwrap();
+
+    // The incomplete last block isn't counted
+    let device = FileBlockDevice::new(host_file);
+    assert_eq!(device.count().unwrap().0, 3);
+
+    let mut host_file: File = device.into_inner();
+    assert_eq!(host_file.seek(SeekFrom::Current(0)).unwrap(), 42);
+}
+
+#[test]
+fn file_block_devices_fail_reads_past_the_end() {
+    let file = TemporaryFile::new("short.img", &vec![0; 2 * Block::LEN]);
+    let device = FileBlockDevice::open(&file.0).unwrap();
+
+    let mut blocks = [Block::new(), Block::new()];
+    assert!(device.raw_read(&mut blocks, BlockIndex(1)).is_err());
+}
@@ -1,78 +1,11 @@
 use std::fs::File;
-use std::fs::OpenOptions;
-use std::io::prelude::*;
+use std::io;
 
-use std::cell::RefCell;
-use std::io::SeekFrom;
-use std::path::Path;
-
-use libfs::block::*;
+use libfs::std_io::{FileBlockDevice, FileHandle};
 use libfs::*;
 
-#[macro_use]
-extern crate log;
-
 extern crate env_logger;
 
-#[derive(Debug)]
-struct LinuxBlockDevice {
-    file: RefCell<File>,
-}
-
-impl LinuxBlockDevice {
-    fn new<P>(device_name: P) -> Result<LinuxBlockDevice>
-    where
-        P: AsRef<Path>,
-    {
-        Ok(LinuxBlockDevice {
-            file: RefCell::new(
-                OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .open(device_name)
-                    .unwrap(),
-            ),
-        })
-    }
-}
-
-impl BlockDevice for LinuxBlockDevice {
-    fn raw_read(&self, blocks: &mut [Block], index: BlockIndex) -> Result<()> {
-        /*trace!(
-            "Reading block index 0x{:x} (0x{:x})",
-            index.0,
-            index.into_offset()
-        );*/
-        self.file
-            .borrow_mut()
-            .seek(SeekFrom::Start(index.into_offset()))
-            .unwrap();
-        for block in blocks.iter_mut() {
-            self.file
-                .borrow_mut()
-                .read_exact(&mut block.contents)
-                .unwrap();
-        }
-        Ok(())
-    }
-
-    fn raw_write(&self, blocks: &[Block], index: BlockIndex) -> Result<()> {
-        self.file
-            .borrow_mut()
-            .seek(SeekFrom::Start(index.into_offset()))
-            .unwrap();
-        for block in blocks.iter() {
-            self.file.borrow_mut().write_all(&block.contents).unwrap();
-        }
-        Ok(())
-    }
-
-    fn count(&self) -> Result<BlockCount> {
-        let num_blocks = self.file.borrow().metadata().unwrap().len() / (Block::LEN as u64);
-        Ok(BlockCount(num_blocks as u32))
-    }
-}
-
 fn print_dir<T>(filesystem: &T, path: &str, level: u32, recursive: bool) -> FileSystemResult<()>
 where
     T: FileSystemOperations,
@@ -111,30 +44,32 @@ where
     Ok(())
 }
 
-fn dump_to_file<'a>(file: &mut Box<dyn FileOperations + 'a>, path: &str) -> FileSystemResult<()> {
-    let mut f = File::create(path).unwrap();
-
-    let mut buffer: [u8; 0x200] = [0x0; 0x200];
-    let mut offset = 0;
-
-    loop {
-        let read_size = file.read(offset as u64, &mut buffer)? as usize;
-        f.write_all(&buffer[0..read_size]).unwrap();
-        if read_size == 0 {
-            break;
-        }
-        offset += read_size;
-    }
+fn dump_to_file<'a>(file: Box<dyn FileOperations + 'a>, path: &str) -> io::Result<u64> {
+    let mut output = File::create(path)?;
 
-    Ok(())
+    io::copy(&mut FileHandle::new(file), &mut output)
 }
 
 fn main() -> FileSystemResult<()> {
     env_logger::init();
 
-    let system_device = LinuxBlockDevice::new(std::env::args().nth(1).unwrap()).unwrap();
+    let mut args = std::env::args().skip(1);
+
+    let system_device = FileBlockDevice::open(args.next().unwrap()).unwrap();
     let filesystem = libfs_fat::FatFileSystem::get_raw_partition(system_device)?;
 
     print_dir(&filesystem, "/", 0, true)?;
+
+    // Optionally extract a file of the filesystem to the host
+    if let (Some(source), Some(destination)) = (args.next(), args.next()) {
+        let file = filesystem.open_file(&source, FileModeFlags::READABLE)?;
+        let size = dump_to_file(file, &destination).unwrap();
+
+        println!(
+            "Dumped {} bytes from \"{}\" to \"{}\"",
+            size, source, destination
+        );
+    }
+
     Ok(())
 }
@@ -0,0 +1,385 @@
+//! libfs compatibility layer arround libfat's exFAT driver.
+
+use alloc::boxed::Box;
+use core::iter::Iterator;
+
+use libfs::block::BlockDevice;
+
+use libfs::FileSystemResult;
+use libfs::{
+    DirFilterFlags, DirectoryEntry, DirectoryEntryType, DirectoryOperations, FileAttributes,
+    FileModeFlags, FileOperations, FileSystemError, FileSystemOperations, FileSystemStatistics,
+    FileTimeStampRaw, FileTimeStampUpdate,
+};
+
+use libfat::datetime::FatDateTime;
+use libfat::exfat::directory::DirectoryEntry as ExFatDirectoryEntry;
+use libfat::exfat::directory::DirectoryEntryIterator as ExFatDirectoryEntryIterator;
+
+/// A libfat exFAT directory reader implementing ``DirectoryOperations``.
+struct DirectoryReader<'a, T> {
+    /// The opened directory path. Used to get the complete path of every entries.
+    base_path: [u8; DirectoryEntry::PATH_LEN],
+
+    /// The iterator used to iter over libfat's directory entries.
+    internal_iter: ExFatDirectoryEntryIterator<'a, T>,
+
+    /// The filter required by the user.
+    filter_fn: &'static dyn Fn(&FileSystemResult<ExFatDirectoryEntry>) -> bool,
+
+    /// The number of entries in the directory after ``filter_fn``.
+    entry_count: u64,
+}
+
+/// A libfat exFAT file interface implementing ``FileOperations``.
+struct FileInterface<'a, T> {
+    /// Internal interface to libfat's filesystem.
+    fs: &'a libfat::exfat::filesystem::ExFatFileSystem<T>,
+
+    /// The libfat's directory entry of this file.
+    file_info: ExFatDirectoryEntry,
+
+    /// The flags applied to the given file.
+    mode: FileModeFlags,
+}
+
+/// A wrapper arround libfat ``ExFatFileSystem`` implementing ``FileSystemOperations``.
+pub struct ExFatFileSystem<T> {
+    /// libfat filesystem interface.
+    inner: libfat::exfat::filesystem::ExFatFileSystem<T>,
+}
+
+/// Predicate helper used to filter directory entries.
+///
+/// exFAT directories don't contain "." & ".." entries so there is no need to skip them.
+struct DirectoryFilterPredicate;
+
+impl DirectoryFilterPredicate {
+    /// Accept all entries.
+    fn all(entry: &FileSystemResult<ExFatDirectoryEntry>) -> bool {
+        entry.is_ok()
+    }
+
+    /// Only accept directory entries.
+    fn dirs(entry: &FileSystemResult<ExFatDirectoryEntry>) -> bool {
+        if let Ok(entry_val) = entry {
+            entry_val.attribute.is_directory()
+        } else {
+            false
+        }
+    }
+
+    /// Only accept file entries.
+    fn files(entry: &FileSystemResult<ExFatDirectoryEntry>) -> bool {
+        if let Ok(entry_val) = entry {
+            !entry_val.attribute.is_directory()
+        } else {
+            false
+        }
+    }
+}
+
+impl<B> ExFatFileSystem<B>
+where
+    B: BlockDevice,
+{
+    /// Helper used to open a directory using the root directory.
+    fn get_dir_from_path(
+        &self,
+        path: &str,
+    ) -> FileSystemResult<libfat::exfat::directory::Directory<'_, B>> {
+        if path == "/" {
+            Ok(self.inner.get_root_directory())
+        } else {
+            self.inner.get_root_directory().open_dir(path)
+        }
+    }
+
+    /// Open the given block device as an exFAT filesystem.
+    pub fn get_raw_partition(block_device: B) -> FileSystemResult<Self> {
+        let inner_fs = libfat::exfat::get_raw_partition(block_device)?;
+
+        Ok(ExFatFileSystem { inner: inner_fs })
+    }
+}
+
+impl<B> FileSystemOperations for ExFatFileSystem<B>
+where
+    B: BlockDevice,
+{
+    fn create_file(&self, path: &str, size: u64) -> FileSystemResult<()> {
+        self.inner.touch(path)?;
+
+        let mut file = self.open_file(path, FileModeFlags::APPENDABLE)?;
+        file.set_len(size)
+    }
+
+    fn create_directory(&self, path: &str) -> FileSystemResult<()> {
+        self.inner.mkdir(path)
+    }
+
+    fn rename_file(&self, old_path: &str, new_path: &str) -> FileSystemResult<()> {
+        self.inner.rename(old_path, new_path, false)
+    }
+
+    fn rename_directory(&self, old_path: &str, new_path: &str) -> FileSystemResult<()> {
+        self.inner.rename(old_path, new_path, true)
+    }
+
+    fn delete_file(&self, path: &str) -> FileSystemResult<()> {
+        self.inner.unlink(path, false)
+    }
+
+    fn delete_directory(&self, path: &str) -> FileSystemResult<()> {
+        self.inner.unlink(path, true)
+    }
+
+    fn open_file<'a>(
+        &'a self,
+        path: &str,
+        mode: FileModeFlags,
+    ) -> FileSystemResult<Box<dyn FileOperations + 'a>> {
+        let file_entry = self.inner.get_root_directory().open_file(path)?;
+
+        if file_entry.attribute.is_directory() {
+            return Err(FileSystemError::NotAFile);
+        }
+
+        if file_entry.attribute.is_read_only() && crate::is_modification_mode(mode) {
+            return Err(FileSystemError::AccessDenied);
+        }
+
+        let res = Box::new(FileInterface {
+            fs: &self.inner,
+            file_info: file_entry,
+            mode,
+        });
+
+        Ok(res as Box<dyn FileOperations + 'a>)
+    }
+
+    fn open_directory<'a>(
+        &'a self,
+        path: &str,
+        filter: DirFilterFlags,
+    ) -> FileSystemResult<Box<dyn DirectoryOperations + 'a>> {
+        // reject path that are too big (shoudn't never happens but well we don't know)
+        if path.len() >= DirectoryEntry::PATH_LEN {
+            return Err(FileSystemError::NotFound);
+        }
+
+        let filter_fn: &'static dyn Fn(&FileSystemResult<ExFatDirectoryEntry>) -> bool =
+            if (filter & DirFilterFlags::ALL) == DirFilterFlags::ALL {
+                &DirectoryFilterPredicate::all
+            } else if (filter & DirFilterFlags::DIRECTORY) == DirFilterFlags::DIRECTORY {
+                &DirectoryFilterPredicate::dirs
+            } else {
+                &DirectoryFilterPredicate::files
+            };
+
+        let target_dir = self.get_dir_from_path(path)?;
+
+        let entry_count = target_dir.clone().iter().filter(filter_fn).count() as u64;
+
+        let mut data: [u8; DirectoryEntry::PATH_LEN] = [0x0; DirectoryEntry::PATH_LEN];
+        for (index, c) in path
+            .as_bytes()
+            .iter()
+            .enumerate()
+            .take(DirectoryEntry::PATH_LEN)
+        {
+            data[index] = *c;
+        }
+
+        // Add '/' if missing at the end
+        if let Some('/') = path.chars().last() {
+            // Already valid
+        } else {
+            data[path.as_bytes().len()] = 0x2F;
+        }
+
+        let res = Box::new(DirectoryReader {
+            base_path: data,
+            internal_iter: target_dir.iter(),
+            filter_fn,
+            entry_count,
+        });
+
+        Ok(res as Box<dyn DirectoryOperations + 'a>)
+    }
+
+    fn get_file_timestamp_raw(&self, name: &str) -> FileSystemResult<FileTimeStampRaw> {
+        let file_entry = self.inner.get_root_directory().open_file(name)?;
+
+        let result = FileTimeStampRaw {
+            creation_timestamp: file_entry.creation_timestamp,
+            modified_timestamp: file_entry.last_modification_timestamp,
+            accessed_timestamp: file_entry.last_access_timestamp,
+            is_valid: true,
+        };
+
+        Ok(result)
+    }
+
+    fn get_file_attributes(&self, path: &str) -> FileSystemResult<FileAttributes> {
+        let file_entry = self.inner.get_root_directory().open_file(path)?;
+
+        Ok(crate::to_file_attributes(file_entry.attribute))
+    }
+
+    fn set_file_attributes(&self, path: &str, attributes: FileAttributes) -> FileSystemResult<()> {
+        let mut file_entry = self.inner.get_root_directory().open_file(path)?;
+        let attribute = crate::apply_file_attributes(file_entry.attribute, attributes);
+
+        file_entry.set_attribute(&self.inner, attribute)
+    }
+
+    fn set_file_timestamp_raw(
+        &self,
+        path: &str,
+        timestamps: &FileTimeStampUpdate,
+    ) -> FileSystemResult<()> {
+        let mut file_entry = self.inner.get_root_directory().open_file(path)?;
+
+        // exFAT timestamps are stored as UTC times
+        let to_datetime = |timestamp| FatDateTime::from_unix_time(timestamp, Some(0));
+
+        // The access time is rounded down to 2 seconds by the encoding.
+        file_entry.set_timestamps(
+            &self.inner,
+            timestamps.creation_timestamp.map(to_datetime),
+            timestamps.modified_timestamp.map(to_datetime),
+            timestamps.accessed_timestamp.map(to_datetime),
+        )
+    }
+
+    fn get_statistics(&self) -> FileSystemResult<FileSystemStatistics> {
+        Ok(crate::to_filesystem_statistics(self.inner.statistics()))
+    }
+}
+
+impl<'a, T> DirectoryOperations for DirectoryReader<'a, T>
+where
+    T: BlockDevice,
+{
+    fn read(&mut self, buf: &mut [DirectoryEntry]) -> FileSystemResult<u64> {
+        for (index, entry) in buf.iter_mut().enumerate() {
+            let mut raw_dir_entry;
+            loop {
+                let entry_opt = self.internal_iter.next();
+
+                // Prematury ending
+                if entry_opt.is_none() {
+                    return Ok(index as u64);
+                }
+
+                raw_dir_entry = entry_opt.unwrap();
+                let filter_fn = self.filter_fn;
+
+                if filter_fn(&raw_dir_entry) {
+                    break;
+                }
+            }
+
+            *entry = Self::convert_entry(raw_dir_entry?, &self.base_path);
+        }
+
+        // everything was read correctly
+        Ok(buf.len() as u64)
+    }
+
+    fn entry_count(&self) -> FileSystemResult<u64> {
+        Ok(self.entry_count)
+    }
+}
+
+impl<'a, T> FileOperations for FileInterface<'a, T>
+where
+    T: BlockDevice,
+{
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> FileSystemResult<u64> {
+        if (self.mode & FileModeFlags::READABLE) != FileModeFlags::READABLE {
+            return Err(FileSystemError::AccessDenied);
+        }
+
+        self.file_info.read(self.fs, offset, buf)
+    }
+
+    fn write(&mut self, offset: u64, buf: &[u8]) -> FileSystemResult<()> {
+        if (self.mode & FileModeFlags::WRITABLE) != FileModeFlags::WRITABLE {
+            return Err(FileSystemError::AccessDenied);
+        }
+
+        self.file_info.write(
+            self.fs,
+            offset,
+            buf,
+            (self.mode & FileModeFlags::APPENDABLE) == FileModeFlags::APPENDABLE,
+        )
+    }
+
+    fn flush(&mut self) -> FileSystemResult<()> {
+        // NOP
+        Ok(())
+    }
+
+    fn set_len(&mut self, size: u64) -> FileSystemResult<()> {
+        if (self.mode & FileModeFlags::APPENDABLE) != FileModeFlags::APPENDABLE {
+            return Err(FileSystemError::AccessDenied);
+        }
+
+        self.file_info.set_len(self.fs, size)
+    }
+
+    fn get_len(&mut self) -> FileSystemResult<u64> {
+        Ok(self.file_info.file_size)
+    }
+}
+
+impl<'a, T> DirectoryReader<'a, T>
+where
+    T: BlockDevice,
+{
+    /// convert libfat's exFAT DirectoryEntry to libfs's DirectoryEntry.
+    fn convert_entry(
+        exfat_dir_entry: ExFatDirectoryEntry,
+        base_path: &[u8; DirectoryEntry::PATH_LEN],
+    ) -> DirectoryEntry {
+        let mut path: [u8; DirectoryEntry::PATH_LEN] = [0x0; DirectoryEntry::PATH_LEN];
+
+        // exFAT directories have a size, but libfs users expect it to be 0 like on FAT
+        let (entry_type, file_size) = if exfat_dir_entry.attribute.is_directory() {
+            (DirectoryEntryType::Directory, 0)
+        } else {
+            (DirectoryEntryType::File, exfat_dir_entry.file_size)
+        };
+
+        let mut base_index = 0;
+
+        loop {
+            let c = base_path[base_index];
+            if c == 0x0 {
+                break;
+            }
+
+            path[base_index] = c;
+            base_index += 1;
+        }
+
+        for (index, c) in exfat_dir_entry
+            .file_name
+            .as_bytes()
+            .iter()
+            .enumerate()
+            .take(DirectoryEntry::PATH_LEN - base_index)
+        {
+            path[base_index + index] = *c;
+        }
+
+        DirectoryEntry {
+            path,
+            entry_type,
+            file_size,
+        }
+    }
+}
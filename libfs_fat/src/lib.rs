@@ -5,18 +5,27 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
+use alloc::string::String;
 use core::iter::Iterator;
 
 use libfs::block::BlockDevice;
 
 use libfs::FileSystemResult;
 use libfs::{
-    DirFilterFlags, DirectoryEntry, DirectoryEntryType, DirectoryOperations, FileModeFlags,
-    FileOperations, FileSystemError, FileSystemOperations, FileTimeStampRaw,
+    DirFilterFlags, DirectoryEntry, DirectoryEntryType, DirectoryOperations, FileAttributes,
+    FileModeFlags, FileOperations, FileSystemError, FileSystemOperations, FileSystemStatistics,
+    FileTimeStampRaw, FileTimeStampUpdate,
 };
 
+use libfat::attribute::Attributes;
+use libfat::datetime::FatDateTime;
 use libfat::directory::dir_entry::DirectoryEntry as FatDirectoryEntry;
 use libfat::directory::dir_entry_iterator::DirectoryEntryIterator as FatDirectoryEntryIterator;
+use libfat::extent::ExtentMap;
+use libfat::{FatFsType, VolumeStatistics};
+
+mod exfat;
+pub use exfat::ExFatFileSystem;
 
 /// A libfat directory reader implementing ``DirectoryOperations``.
 struct DirectoryReader<'a, T> {
@@ -34,15 +43,82 @@ struct DirectoryReader<'a, T> {
 }
 
 /// A libfat file interface implementing ``FileOperations``.
-struct FileInterface<'a, T> {
+struct FileInterface<'a, T>
+where
+    T: BlockDevice,
+{
     /// Internal interface to libfat's filesystem.
     fs: &'a libfat::filesystem::FatFileSystem<T>,
 
     /// The libfat's directory entry of this file.
     file_info: FatDirectoryEntry,
 
+    /// The cached cluster chain of this file.
+    extent_map: ExtentMap,
+
     /// The flags applied to the given file.
     mode: FileModeFlags,
+
+    /// Set if the file was read since its timestamps were last updated.
+    accessed: bool,
+
+    /// Set if the file was written since its timestamps were last updated.
+    modified: bool,
+}
+
+/// The libfat attributes matching every ``FileAttributes`` flags.
+const ATTRIBUTES_MAPPING: [(FileAttributes, u8); 4] = [
+    (FileAttributes::READ_ONLY, Attributes::READ_ONLY),
+    (FileAttributes::HIDDEN, Attributes::HIDDEN),
+    (FileAttributes::SYSTEM, Attributes::SYSTEM),
+    (FileAttributes::ARCHIVE, Attributes::ARCHIVE),
+];
+
+/// Convert libfat attributes to ``FileAttributes``.
+fn to_file_attributes(attribute: Attributes) -> FileAttributes {
+    let mut res = FileAttributes::empty();
+
+    for (flag, mask) in ATTRIBUTES_MAPPING.iter() {
+        if (attribute.get_value() & mask) != 0 {
+            res |= *flag;
+        }
+    }
+
+    res
+}
+
+/// Apply ``FileAttributes`` to libfat attributes, leaving the other attributes untouched.
+fn apply_file_attributes(mut attribute: Attributes, attributes: FileAttributes) -> Attributes {
+    for (flag, mask) in ATTRIBUTES_MAPPING.iter() {
+        attribute.set(*mask, attributes.contains(*flag));
+    }
+
+    attribute
+}
+
+/// Convert libfat volume statistics to ``FileSystemStatistics``.
+fn to_filesystem_statistics(statistics: VolumeStatistics) -> FileSystemStatistics {
+    let filesystem_type = match statistics.fat_type {
+        FatFsType::Fat12 => "FAT12",
+        FatFsType::Fat16 => "FAT16",
+        FatFsType::Fat32 => "FAT32",
+        FatFsType::ExFat => "exFAT",
+    };
+
+    FileSystemStatistics {
+        filesystem_type,
+        serial_number: u64::from(statistics.serial_number),
+        cluster_size: statistics.cluster_size,
+        total_cluster_count: u64::from(statistics.cluster_count),
+        free_cluster_count: u64::from(statistics.free_cluster_count),
+        total_bytes: statistics.total_bytes,
+        free_bytes: statistics.free_bytes,
+    }
+}
+
+/// Check if opening a file with the given ``mode`` flags would allow to modify it.
+fn is_modification_mode(mode: FileModeFlags) -> bool {
+    mode.intersects(FileModeFlags::WRITABLE | FileModeFlags::APPENDABLE)
 }
 
 /// A wrapper arround libfat ``FatFileSystem`` implementing ``FileSystemOperations``.
@@ -118,6 +194,52 @@ where
 
         Ok(FatFileSystem { inner: inner_fs })
     }
+
+    /// Set the source of the time used to stamp files and directories.
+    pub fn set_time_provider(&mut self, time_provider: Box<dyn libfat::datetime::TimeProvider>) {
+        self.inner.set_time_provider(time_provider);
+    }
+
+    /// Keep an in-memory map of the free clusters to speed up allocations.
+    pub fn enable_free_cluster_bitmap(&mut self) -> FileSystemResult<()> {
+        self.inner.enable_free_cluster_bitmap()
+    }
+
+    /// Create a file holding ``size`` bytes stored in a single run of contiguous clusters.
+    pub fn create_contiguous_file(&self, path: &str, size: u64) -> FileSystemResult<()> {
+        self.inner.create_contiguous_file(path, size)
+    }
+
+    /// Set the length of a file, allocating the clusters added as a single contiguous run.
+    pub fn set_file_len_contiguous(&self, path: &str, size: u64) -> FileSystemResult<()> {
+        let mut file_entry = self.inner.get_root_directory().open_file(path)?;
+
+        if file_entry.attribute.is_read_only() {
+            return Err(FileSystemError::AccessDenied);
+        }
+
+        file_entry.set_len_contiguous(&self.inner, size)
+    }
+
+    /// Get the volume state as it was when the filesystem was mounted.
+    pub fn mount_state(&self) -> libfat::filesystem::VolumeState {
+        self.inner.mount_state()
+    }
+
+    /// Get the label of the volume, or None if it doesn't have any.
+    pub fn volume_label(&self) -> FileSystemResult<Option<String>> {
+        self.inner.volume_label()
+    }
+
+    /// Set the label of the volume. An empty label removes it.
+    pub fn set_volume_label(&self, volume_label: &str) -> FileSystemResult<()> {
+        self.inner.set_volume_label(volume_label)
+    }
+
+    /// Flush the filesystem and mark the volume as cleanly unmounted.
+    pub fn flush(&self) -> FileSystemResult<()> {
+        self.inner.flush()
+    }
 }
 
 impl<B> FileSystemOperations for FatFileSystem<B>
@@ -160,10 +282,17 @@ where
 
         let file_entry = self.inner.get_root_directory().open_file(path)?;
 
+        if file_entry.attribute.is_read_only() && is_modification_mode(mode) {
+            return Err(FileSystemError::AccessDenied);
+        }
+
         let res = Box::new(FileInterface {
             fs: &self.inner,
             file_info: file_entry,
+            extent_map: ExtentMap::new(),
             mode,
+            accessed: false,
+            modified: false,
         });
 
         Ok(res as Box<dyn FileOperations + 'a>)
@@ -233,6 +362,42 @@ where
 
         Ok(result)
     }
+
+    fn get_file_attributes(&self, path: &str) -> FileSystemResult<FileAttributes> {
+        let file_entry = self.inner.get_root_directory().open_file(path)?;
+
+        Ok(to_file_attributes(file_entry.attribute))
+    }
+
+    fn set_file_attributes(&self, path: &str, attributes: FileAttributes) -> FileSystemResult<()> {
+        let mut file_entry = self.inner.get_root_directory().open_file(path)?;
+        let attribute = apply_file_attributes(file_entry.attribute, attributes);
+
+        file_entry.set_attribute(&self.inner, attribute)
+    }
+
+    fn set_file_timestamp_raw(
+        &self,
+        path: &str,
+        timestamps: &FileTimeStampUpdate,
+    ) -> FileSystemResult<()> {
+        let mut file_entry = self.inner.get_root_directory().open_file(path)?;
+
+        // FAT timestamps are local times without any UTC offset
+        let to_datetime = |timestamp| FatDateTime::from_unix_time(timestamp, None);
+
+        // The modification time is rounded down to 2 seconds and the access time to the day by the encoding.
+        file_entry.set_timestamps(
+            &self.inner,
+            timestamps.creation_timestamp.map(to_datetime),
+            timestamps.modified_timestamp.map(to_datetime),
+            timestamps.accessed_timestamp.map(to_datetime),
+        )
+    }
+
+    fn get_statistics(&self) -> FileSystemResult<FileSystemStatistics> {
+        Ok(to_filesystem_statistics(self.inner.statistics()))
+    }
 }
 
 impl<'a, T> DirectoryOperations for DirectoryReader<'a, T>
@@ -270,6 +435,37 @@ where
     }
 }
 
+impl<'a, T> FileInterface<'a, T>
+where
+    T: BlockDevice,
+{
+    /// Stamp the entry of the file once for all the reads and writes done since the last call.
+    ///
+    /// Failing to update the last access date is ignored, reads must keep working on write-protected media.
+    fn update_timestamps(&mut self) -> FileSystemResult<()> {
+        if self.modified {
+            self.modified = false;
+            self.accessed = false;
+            self.file_info.update_timestamps(self.fs, true)
+        } else if self.accessed {
+            self.accessed = false;
+            self.file_info.update_timestamps(self.fs, false).or(Ok(()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a, T> Drop for FileInterface<'a, T>
+where
+    T: BlockDevice,
+{
+    fn drop(&mut self) {
+        // Nothing can be reported here, files are expected to be flushed before being closed
+        let _ = self.update_timestamps();
+    }
+}
+
 impl<'a, T> FileOperations for FileInterface<'a, T>
 where
     T: BlockDevice,
@@ -279,7 +475,12 @@ where
             return Err(FileSystemError::AccessDenied);
         }
 
-        self.file_info.read(self.fs, offset, buf)
+        let read_size =
+            self.file_info
+                .read_with_extents(self.fs, &mut self.extent_map, offset, buf)?;
+
+        self.accessed = true;
+        Ok(read_size)
     }
 
     fn write(&mut self, offset: u64, buf: &[u8]) -> FileSystemResult<()> {
@@ -287,17 +488,23 @@ where
             return Err(FileSystemError::AccessDenied);
         }
 
-        self.file_info.write(
+        self.file_info.write_with_extents(
             self.fs,
+            &mut self.extent_map,
             offset,
             buf,
             (self.mode & FileModeFlags::APPENDABLE) == FileModeFlags::APPENDABLE,
-        )
+        )?;
+
+        self.modified = true;
+        Ok(())
     }
 
     fn flush(&mut self) -> FileSystemResult<()> {
-        // NOP
-        Ok(())
+        self.update_timestamps()?;
+
+        // Other files may be in the middle of an update, only the explicit filesystem flush marks the volume clean
+        self.fs.flush_fs_info()
     }
 
     fn set_len(&mut self, size: u64) -> FileSystemResult<()> {
@@ -305,7 +512,8 @@ where
             return Err(FileSystemError::AccessDenied);
         }
 
-        self.file_info.set_len(self.fs, size)
+        self.file_info
+            .set_len_with_extents(self.fs, &mut self.extent_map, size)
     }
 
     fn get_len(&mut self) -> FileSystemResult<u64> {
@@ -0,0 +1,135 @@
+//! Helpers shared by the integration tests.
+
+// Every test only uses some of the helpers
+#![allow(dead_code)]
+
+use libfat::format::{format_raw_partition, FormatOptions};
+use libfat::FatFsType;
+use libfs::block::RamBlockDevice;
+
+/// The sector offset of the FAT of the exFAT volume.
+const EXFAT_FAT_OFFSET: u32 = 24;
+
+/// The sector offset of the cluster heap of the exFAT volume.
+const EXFAT_CLUSTER_HEAP_OFFSET: u32 = 32;
+
+/// The count of clusters of one sector of the exFAT volume.
+const EXFAT_CLUSTER_COUNT: u32 = 96;
+
+/// The cluster of the exFAT volume holding the allocation bitmap.
+const EXFAT_BITMAP_CLUSTER: u32 = 2;
+
+/// The cluster of the exFAT volume holding the up-case table.
+const EXFAT_UPCASE_CLUSTER: u32 = 3;
+
+/// The cluster of the exFAT volume holding the root directory.
+const EXFAT_ROOT_CLUSTER: u32 = 4;
+
+/// Write a little-endian 32 bits value at the given byte offset of ``image``.
+fn write_u32(image: &mut [u8], offset: usize, value: u32) {
+    image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Compute the byte offset of a cluster of the exFAT volume.
+fn exfat_cluster_offset(cluster: u32) -> usize {
+    (EXFAT_CLUSTER_HEAP_OFFSET + cluster - 2) as usize * 512
+}
+
+/// Compute a 32 bits exFAT checksum, skipping the bytes at the ``skipped`` offsets.
+fn exfat_checksum(data: &[u8], skipped: &[usize]) -> u32 {
+    let mut checksum = 0u32;
+    for (index, value) in data.iter().enumerate() {
+        if !skipped.contains(&index) {
+            checksum = (checksum << 31)
+                .wrapping_add(checksum >> 1)
+                .wrapping_add(u32::from(*value));
+        }
+    }
+    checksum
+}
+
+/// Format an in-memory FAT16 volume.
+pub fn create_fat_volume() -> RamBlockDevice {
+    let device = RamBlockDevice::new(4 << 20);
+    let options = FormatOptions {
+        fat_type: FatFsType::Fat16,
+        cluster_size: 512,
+        volume_label: "TEST",
+        volume_id: 0x1234_5678,
+    };
+    format_raw_partition(&device, &options).unwrap();
+
+    device
+}
+
+/// Create an empty in-memory exFAT volume with clusters of one sector.
+pub fn create_exfat_volume() -> RamBlockDevice {
+    let volume_length = EXFAT_CLUSTER_HEAP_OFFSET + EXFAT_CLUSTER_COUNT;
+    let mut image = vec![0; volume_length as usize * 512];
+
+    // Main boot sector, with one FAT of one sector
+    image[0..3].copy_from_slice(&[0xEB, 0x76, 0x90]);
+    image[3..11].copy_from_slice(b"EXFAT   ");
+    image[72..80].copy_from_slice(&u64::from(volume_length).to_le_bytes());
+    write_u32(&mut image, 80, EXFAT_FAT_OFFSET);
+    write_u32(&mut image, 84, 1);
+    write_u32(&mut image, 88, EXFAT_CLUSTER_HEAP_OFFSET);
+    write_u32(&mut image, 92, EXFAT_CLUSTER_COUNT);
+    write_u32(&mut image, 96, EXFAT_ROOT_CLUSTER);
+    write_u32(&mut image, 100, 0x1234_5678);
+    image[104..106].copy_from_slice(&0x0100u16.to_le_bytes());
+    image[108] = 9;
+    image[110] = 1;
+    image[111] = 0x80;
+    image[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    // Boot checksum sector, covering the 11 first sectors
+    let boot_checksum = exfat_checksum(&image[..11 * 512], &[106, 107, 112]);
+    for index in 0..128 {
+        write_u32(&mut image, 11 * 512 + index * 4, boot_checksum);
+    }
+
+    // FAT: the media type, then the single cluster chains of the metadata
+    let fat_offset = EXFAT_FAT_OFFSET as usize * 512;
+    write_u32(&mut image, fat_offset, 0xFFFF_FFF8);
+    write_u32(&mut image, fat_offset + 4, 0xFFFF_FFFF);
+    for cluster in &[
+        EXFAT_BITMAP_CLUSTER,
+        EXFAT_UPCASE_CLUSTER,
+        EXFAT_ROOT_CLUSTER,
+    ] {
+        write_u32(&mut image, fat_offset + *cluster as usize * 4, 0xFFFF_FFFF);
+    }
+
+    // Allocation bitmap: the metadata clusters
+    let bitmap_len = (EXFAT_CLUSTER_COUNT / 8) as usize;
+    image[exfat_cluster_offset(EXFAT_BITMAP_CLUSTER)] = 0b111;
+
+    // Up-case table: ASCII letters only
+    let upcase_table: Vec<u8> = (0..128u8)
+        .flat_map(|character| {
+            let upper = u16::from(character.to_ascii_uppercase());
+            upper.to_le_bytes().to_vec()
+        })
+        .collect();
+    let upcase_offset = exfat_cluster_offset(EXFAT_UPCASE_CLUSTER);
+    image[upcase_offset..upcase_offset + upcase_table.len()].copy_from_slice(&upcase_table);
+
+    // Root directory: the allocation bitmap and up-case table entries
+    let root_offset = exfat_cluster_offset(EXFAT_ROOT_CLUSTER);
+    image[root_offset] = 0x81;
+    write_u32(&mut image, root_offset + 20, EXFAT_BITMAP_CLUSTER);
+    image[root_offset + 24..root_offset + 32].copy_from_slice(&(bitmap_len as u64).to_le_bytes());
+
+    image[root_offset + 32] = 0x82;
+    write_u32(
+        &mut image,
+        root_offset + 36,
+        exfat_checksum(&upcase_table, &[]),
+    );
+    write_u32(&mut image, root_offset + 52, EXFAT_UPCASE_CLUSTER);
+    image[root_offset + 56..root_offset + 64]
+        .copy_from_slice(&(upcase_table.len() as u64).to_le_bytes());
+
+    RamBlockDevice::from_vec(image)
+}
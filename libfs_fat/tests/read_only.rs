@@ -0,0 +1,104 @@
+//! Check that read-only entries can't be modified through ``FileSystemOperations``, on FAT and exFAT volumes.
+
+mod common;
+
+use libfs::{FileAttributes, FileModeFlags, FileSystemError, FileSystemOperations};
+use libfs_fat::{ExFatFileSystem, FatFileSystem};
+
+/// Check that an operation was denied.
+fn assert_denied<T>(result: Result<T, FileSystemError>, operation: &str) {
+    match result {
+        Err(FileSystemError::AccessDenied) => {}
+        Err(error) => panic!("{}: unexpected error {:?}", operation, error),
+        Ok(_) => panic!("{}: unexpected success", operation),
+    }
+}
+
+/// Check that read-only files can only be opened for reading, and can't be deleted or renamed until the attribute is
+/// cleared.
+fn check_read_only_files<T>(fs: &T)
+where
+    T: FileSystemOperations,
+{
+    fs.create_file("/file.txt", 100).unwrap();
+    fs.set_file_attributes("/file.txt", FileAttributes::READ_ONLY)
+        .unwrap();
+    assert_eq!(
+        fs.get_file_attributes("/file.txt").unwrap(),
+        FileAttributes::READ_ONLY
+    );
+
+    let mut file = fs.open_file("/file.txt", FileModeFlags::READABLE).unwrap();
+    assert_eq!(file.get_len().unwrap(), 100);
+    drop(file);
+
+    assert_denied(
+        fs.open_file("/file.txt", FileModeFlags::WRITABLE),
+        "open for writing",
+    );
+    assert_denied(
+        fs.open_file(
+            "/file.txt",
+            FileModeFlags::READABLE | FileModeFlags::APPENDABLE,
+        ),
+        "open for appending",
+    );
+    assert_denied(fs.delete_file("/file.txt"), "delete");
+    assert_denied(fs.rename_file("/file.txt", "/renamed.txt"), "rename");
+
+    // Nothing changed
+    assert_eq!(
+        fs.open_file("/file.txt", FileModeFlags::READABLE)
+            .unwrap()
+            .get_len()
+            .unwrap(),
+        100
+    );
+
+    fs.set_file_attributes("/file.txt", FileAttributes::empty())
+        .unwrap();
+    fs.rename_file("/file.txt", "/renamed.txt").unwrap();
+    fs.open_file("/renamed.txt", FileModeFlags::WRITABLE)
+        .unwrap();
+    fs.delete_file("/renamed.txt").unwrap();
+}
+
+/// Check that read-only directories can still be renamed, the attribute being only meaningful for files.
+fn check_read_only_directories<T>(fs: &T)
+where
+    T: FileSystemOperations,
+{
+    fs.create_directory("/dir").unwrap();
+    fs.set_file_attributes("/dir", FileAttributes::READ_ONLY)
+        .unwrap();
+
+    fs.rename_directory("/dir", "/renamed").unwrap();
+    assert_eq!(
+        fs.get_file_attributes("/renamed").unwrap(),
+        FileAttributes::READ_ONLY
+    );
+}
+
+#[test]
+fn fat_read_only_files_are_protected() {
+    let fs = FatFileSystem::get_raw_partition(common::create_fat_volume()).unwrap();
+    check_read_only_files(&fs);
+}
+
+#[test]
+fn fat_read_only_directories_are_renamable() {
+    let fs = FatFileSystem::get_raw_partition(common::create_fat_volume()).unwrap();
+    check_read_only_directories(&fs);
+}
+
+#[test]
+fn exfat_read_only_files_are_protected() {
+    let fs = ExFatFileSystem::get_raw_partition(common::create_exfat_volume()).unwrap();
+    check_read_only_files(&fs);
+}
+
+#[test]
+fn exfat_read_only_directories_are_renamable() {
+    let fs = ExFatFileSystem::get_raw_partition(common::create_exfat_volume()).unwrap();
+    check_read_only_directories(&fs);
+}
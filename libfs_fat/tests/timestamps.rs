@@ -0,0 +1,117 @@
+//! Check that timestamps set through ``FileSystemOperations`` follow the granularity of FAT directory entries.
+
+mod common;
+
+use libfs::block::RamBlockDevice;
+use libfs::{FileSystemOperations, FileTimeStampRaw, FileTimeStampUpdate};
+use libfs_fat::FatFileSystem;
+
+/// The path of the file used by the tests.
+const FILE: &str = "/file.txt";
+
+/// 2019-07-08 00:00:00, the start of the day of the timestamps set by the tests.
+const DAY: u64 = 1_562_544_000;
+
+/// 2019-07-08 09:10:13, an odd count of seconds.
+const ODD_SECOND: u64 = DAY + 9 * 3600 + 10 * 60 + 13;
+
+/// Create a FAT volume holding the file used by the tests.
+fn create_fs() -> FatFileSystem<RamBlockDevice> {
+    let fs = FatFileSystem::get_raw_partition(common::create_fat_volume()).unwrap();
+    fs.create_file(FILE, 0).unwrap();
+
+    fs
+}
+
+/// Set the given timestamps on the file used by the tests and read them back.
+fn set_timestamps(
+    fs: &FatFileSystem<RamBlockDevice>,
+    timestamps: FileTimeStampUpdate,
+) -> FileTimeStampRaw {
+    fs.set_file_timestamp_raw(FILE, &timestamps).unwrap();
+
+    let result = fs.get_file_timestamp_raw(FILE).unwrap();
+    assert!(result.is_valid);
+    result
+}
+
+#[test]
+fn modification_times_are_rounded_down_to_2_seconds() {
+    let fs = create_fs();
+
+    let result = set_timestamps(
+        &fs,
+        FileTimeStampUpdate {
+            modified_timestamp: Some(ODD_SECOND),
+            ..Default::default()
+        },
+    );
+    assert_eq!(result.modified_timestamp, ODD_SECOND - 1);
+
+    let result = set_timestamps(
+        &fs,
+        FileTimeStampUpdate {
+            modified_timestamp: Some(ODD_SECOND + 1),
+            ..Default::default()
+        },
+    );
+    assert_eq!(result.modified_timestamp, ODD_SECOND + 1);
+}
+
+#[test]
+fn creation_times_keep_odd_seconds() {
+    let fs = create_fs();
+
+    // The 10 ms field of the creation time holds the odd second
+    let result = set_timestamps(
+        &fs,
+        FileTimeStampUpdate {
+            creation_timestamp: Some(ODD_SECOND),
+            ..Default::default()
+        },
+    );
+    assert_eq!(result.creation_timestamp, ODD_SECOND);
+}
+
+#[test]
+fn access_times_only_keep_the_date() {
+    let fs = create_fs();
+
+    let result = set_timestamps(
+        &fs,
+        FileTimeStampUpdate {
+            accessed_timestamp: Some(ODD_SECOND),
+            ..Default::default()
+        },
+    );
+    assert_eq!(result.accessed_timestamp, DAY);
+}
+
+#[test]
+fn timestamps_are_set_individually() {
+    let fs = create_fs();
+
+    let all = set_timestamps(
+        &fs,
+        FileTimeStampUpdate {
+            creation_timestamp: Some(DAY),
+            modified_timestamp: Some(DAY + 2),
+            accessed_timestamp: Some(DAY),
+        },
+    );
+    assert_eq!(all.creation_timestamp, DAY);
+    assert_eq!(all.modified_timestamp, DAY + 2);
+    assert_eq!(all.accessed_timestamp, DAY);
+
+    // Only the modification time changes
+    let result = set_timestamps(
+        &fs,
+        FileTimeStampUpdate {
+            modified_timestamp: Some(ODD_SECOND + 1),
+            ..Default::default()
+        },
+    );
+    assert_eq!(result.creation_timestamp, DAY);
+    assert_eq!(result.modified_timestamp, ODD_SECOND + 1);
+    assert_eq!(result.accessed_timestamp, DAY);
+}